@@ -0,0 +1,35 @@
+//! Compares masking a payload byte by byte with `apply_mask`.
+//!
+//! Run with `cargo bench --bench mask`.
+extern crate websocket;
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use websocket::ws::util::mask::{apply_mask, gen_mask};
+
+const PAYLOAD: usize = 1024 * 1024;
+const ROUNDS: usize = 200;
+
+fn mask_bytes(mask: [u8; 4], data: &mut [u8]) {
+	for (byte, key) in data.iter_mut().zip(mask.iter().cycle()) {
+		*byte ^= key;
+	}
+}
+
+fn run(name: &str, f: fn([u8; 4], &mut [u8])) {
+	let mut data = vec![0x5a; PAYLOAD];
+	let key = gen_mask();
+	let start = Instant::now();
+	for _ in 0..ROUNDS {
+		f(key, black_box(&mut data[..]));
+	}
+	let elapsed = start.elapsed();
+	let mib = (PAYLOAD * ROUNDS) as f64 / (1024.0 * 1024.0);
+	println!("{:>12}: {:8.1} MiB/s", name, mib / elapsed.as_secs_f64());
+}
+
+fn main() {
+	run("byte by byte", mask_bytes);
+	run("apply_mask", apply_mask);
+}
@@ -0,0 +1,96 @@
+//! Counts the allocations made per message by `recv_message` and `recv_borrowed`.
+//!
+//! Run with `cargo bench --bench receive`.
+extern crate websocket;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use websocket::dataframe::{DataFrame, Opcode};
+use websocket::message::OwnedMessage;
+use websocket::receiver::Receiver;
+use websocket::ws::dataframe::DataFrame as DataFrameTrait;
+use websocket::ws::Receiver as ReceiverTrait;
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+		System.alloc(layout)
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		System.dealloc(ptr, layout)
+	}
+
+	unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+		ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+		System.realloc(ptr, layout, new_size)
+	}
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const MESSAGES: usize = 10_000;
+
+fn input(fragments: usize, size: usize) -> Vec<u8> {
+	let mut input = Vec::new();
+	for _ in 0..MESSAGES {
+		for i in 0..fragments {
+			let opcode = if i == 0 {
+				Opcode::Binary
+			} else {
+				Opcode::Continuation
+			};
+			DataFrame::new(i + 1 == fragments, opcode, vec![7; size])
+				.write_to(&mut input, true)
+				.unwrap();
+		}
+	}
+	input
+}
+
+fn run<F>(name: &str, input: &[u8], mut recv: F)
+where
+	F: FnMut(&mut Receiver, &mut &[u8]) -> usize,
+{
+	let mut receiver = Receiver::new(true);
+	let mut reader = input;
+	let mut bytes = 0;
+	let before = ALLOCATIONS.load(Ordering::Relaxed);
+	let start = Instant::now();
+	for _ in 0..MESSAGES {
+		bytes += recv(&mut receiver, &mut reader);
+	}
+	let elapsed = start.elapsed();
+	let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+	println!(
+		"{:<32} {:>8.2} allocations/message {:>10?} ({} bytes)",
+		name,
+		allocations as f64 / MESSAGES as f64,
+		elapsed,
+		bytes
+	);
+}
+
+fn main() {
+	for &(fragments, size) in &[(1, 16), (1, 4096), (4, 1024)] {
+		let input = input(fragments, size);
+		println!("{} fragment(s) of {} bytes:", fragments, size);
+		run("  recv_message", &input, |receiver, reader| match receiver
+			.recv_message(reader)
+			.unwrap()
+		{
+			OwnedMessage::Binary(payload) => payload.len(),
+			_ => unreachable!(),
+		});
+		run("  recv_borrowed", &input, |receiver, reader| {
+			receiver.recv_borrowed(reader).unwrap().payload.len()
+		});
+	}
+}
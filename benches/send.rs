@@ -0,0 +1,86 @@
+//! Counts the writes made per message by `send_message` and compares writing
+//! big payloads with the header in one vectored write to copying them.
+//!
+//! Run with `cargo bench --bench send`.
+extern crate websocket;
+
+use std::io::{self, IoSlice, Write};
+use std::time::Instant;
+
+use websocket::message::OwnedMessage;
+use websocket::sender::Sender;
+use websocket::ws::dataframe::DataFrame;
+use websocket::ws::Sender as SenderTrait;
+
+// throws the data away, counting the calls
+struct Counting(usize);
+
+impl Write for Counting {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.0 += 1;
+		Ok(buf.len())
+	}
+
+	fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+		self.0 += 1;
+		Ok(bufs.iter().map(|buf| buf.len()).sum())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+fn small(masked: bool) {
+	const MESSAGES: usize = 100_000;
+	let message = OwnedMessage::Text("a small chatty message".to_string());
+	let mut sender = Sender::new(masked);
+	let mut writer = Counting(0);
+	let start = Instant::now();
+	for _ in 0..MESSAGES {
+		sender.send_message(&mut writer, &message).unwrap();
+	}
+	println!(
+		"{:<32} {:>8.2} writes/message {:>10?}",
+		if masked {
+			"small messages, masked"
+		} else {
+			"small messages"
+		},
+		writer.0 as f64 / MESSAGES as f64,
+		start.elapsed()
+	);
+}
+
+fn big<F>(name: &str, write: F)
+where
+	F: Fn(&mut Counting, &OwnedMessage),
+{
+	const ROUNDS: usize = 200;
+	let message = OwnedMessage::Binary(vec![7; 1024 * 1024]);
+	let mut writer = Counting(0);
+	let start = Instant::now();
+	for _ in 0..ROUNDS {
+		write(&mut writer, &message);
+	}
+	let elapsed = start.elapsed();
+	println!(
+		"{:<32} {:>8.2} writes/message {:>8.1} MiB/s",
+		name,
+		writer.0 as f64 / ROUNDS as f64,
+		ROUNDS as f64 / elapsed.as_secs_f64()
+	);
+}
+
+fn main() {
+	small(false);
+	small(true);
+	big("1 MiB messages, copied", |writer, message| {
+		let mut data = Vec::with_capacity(message.frame_size(false));
+		message.encode_into(&mut data, false).unwrap();
+		writer.write_all(&data).unwrap();
+	});
+	big("1 MiB messages, vectored", |writer, message| {
+		message.write_to(writer, false).unwrap();
+	});
+}
@@ -23,6 +23,7 @@ fn main() {
 
 		let client = ClientBuilder::new(&url)
 			.unwrap()
+			.enable_deflate()
 			.connect_insecure()
 			.unwrap();
 
@@ -0,0 +1,74 @@
+extern crate hyper;
+extern crate websocket;
+
+use hyper::buffer::BufReader;
+use hyper::http::h1::parse_request;
+use hyper::uri::RequestUri;
+use std::io::Write;
+use std::net::TcpListener;
+use std::thread;
+use websocket::sync::server::upgrade::accept_request;
+use websocket::OwnedMessage;
+
+const INFO: &str = "Connect to ws://127.0.0.1:2794/ws instead.";
+
+fn main() {
+	// Serve plain HTTP and the websocket connections on the same port
+	let listener = TcpListener::bind("127.0.0.1:2794").unwrap();
+
+	for stream in listener.incoming().filter_map(Result::ok) {
+		thread::spawn(move || {
+			let mut reader = BufReader::new(stream);
+			let request = match parse_request(&mut reader) {
+				Ok(request) => request,
+				Err(_) => return,
+			};
+			let mut stream = reader.into_inner();
+
+			// Route on the path, only `/ws` is upgraded
+			let is_ws = match request.subject.1 {
+				RequestUri::AbsolutePath(ref path) => path == "/ws",
+				_ => false,
+			};
+			if !is_ws {
+				let response = format!(
+					"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+					INFO.len(),
+					INFO
+				);
+				stream.write_all(response.as_bytes()).unwrap();
+				return;
+			}
+
+			let mut client = match accept_request(stream, request) {
+				Ok(client) => client,
+				Err((_, e)) => {
+					// invalid handshakes have been answered already
+					println!("Invalid handshake: {}", e);
+					return;
+				}
+			};
+
+			let ip = client.peer_addr().unwrap();
+			println!("Connection from {}", ip);
+
+			loop {
+				let message = match client.recv_message() {
+					Ok(message) => message,
+					Err(_) => return,
+				};
+				match message {
+					OwnedMessage::Close(_) => {
+						let _ = client.send_message(&OwnedMessage::Close(None));
+						println!("Client {} disconnected", ip);
+						return;
+					}
+					OwnedMessage::Ping(data) => {
+						client.send_message(&OwnedMessage::Pong(data)).unwrap();
+					}
+					message => client.send_message(&message).unwrap(),
+				}
+			}
+		});
+	}
+}
@@ -43,16 +43,68 @@
 //! runtime.block_on(send_future).unwrap();
 //! # }
 //! ```
+//!
+//! # Splitting
+//!
+//! Since the client is a `Stream` and a `Sink`, `futures::Stream::split` turns it into
+//! a `ClientSink` and a `ClientStream` which can be moved into separate tasks or
+//! selected over. Both halves share the same codec, so messages sent through the sink
+//! are masked and messages coming out of the stream are decoded as usual.
+//!
+//! The sink applies backpressure: once about 8 KiB of encoded frames are waiting to be
+//! written, `start_send` tries to flush them and hands the message back with
+//! `AsyncSink::NotReady` if the socket can't take them yet, so a fast producer is
+//! slowed down to the speed of the socket instead of buffering without bound.
+//! To pick a different limit wrap the client or its sink in a `BoundedSink`.
+//!
+//! # Detecting dead connections
+//!
+//! Wrapping a client in a `Keepalive` makes it ping the remote endpoint regularly,
+//! if a ping isn't answered in time the stream fails with `WebSocketError::Timeout`.
+//!
+//! ```rust,no_run
+//! # extern crate tokio;
+//! # extern crate futures;
+//! # extern crate websocket;
+//! use websocket::ClientBuilder;
+//! use websocket::async::client::{ClientSink, ClientStream};
+//! use websocket::async::TcpStream;
+//! use websocket::futures::{Future, Stream, Sink};
+//! use websocket::OwnedMessage;
+//! # fn main() {
+//!
+//! let echo = ClientBuilder::new("ws://echo.websocket.org").unwrap()
+//!     .async_connect_insecure()
+//!     .and_then(|(client, _)| {
+//!         let (sink, stream): (ClientSink<TcpStream>, ClientStream<TcpStream>) =
+//!             client.split();
+//!         // send back everything until the server closes the connection
+//!         stream
+//!             .take_while(|m| Ok(!m.is_close()))
+//!             .forward(sink)
+//!     });
+//!
+//! tokio::runtime::Builder::new().build().unwrap().block_on(echo).unwrap();
+//! # }
+//! ```
 
+pub use futures::stream::{SplitSink, SplitStream};
 pub use futures::Future;
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
 use hyper::header::Headers;
+use std::io;
+use std::time::{Duration, Instant};
 pub use tokio_codec::Framed;
 pub use tokio_reactor::Handle;
 pub use tokio_tcp::TcpStream;
+use tokio_timer::Delay;
 
 use codec::ws::MessageCodec;
 use message::OwnedMessage;
+use ratelimit::{RateLimit, RateLimiter};
 use result::WebSocketError;
+use stream::async::Stream as AsyncStream;
+use ws::Message;
 
 #[cfg(feature = "async-ssl")]
 pub use tokio_tls::TlsStream;
@@ -64,6 +116,12 @@ pub use tokio_tls::TlsStream;
 /// these futures.
 pub type Client<S> = Framed<S, MessageCodec<OwnedMessage>>;
 
+/// The sending half of a `Client`, created with `futures::Stream::split`.
+pub type ClientSink<S> = SplitSink<Client<S>>;
+
+/// The receiving half of a `Client`, created with `futures::Stream::split`.
+pub type ClientStream<S> = SplitStream<Client<S>>;
+
 /// A future which will evaluate to a `Client` and a set of hyper `Headers`.
 ///
 /// The `Client` can send and receive websocket messages, and the Headers are
@@ -73,3 +131,682 @@ pub type Client<S> = Framed<S, MessageCodec<OwnedMessage>>;
 /// This crate will not automatically close the connection if the server refused
 /// to use the user protocols given to it, you must check that the server accepted.
 pub type ClientNew<S> = Box<Future<Item = (Client<S>, Headers), Error = WebSocketError> + Send>;
+
+/// A `Sink` of messages which stops accepting new messages once a number of bytes
+/// were sent without being flushed.
+///
+/// When `high_water_mark` bytes of messages are buffered, `start_send` first tries to
+/// flush them and returns `AsyncSink::NotReady` until the underlying sink is done,
+/// this way a producer can't get ahead of the socket by more than the high-water mark.
+/// The size of a message is counted as the size of its frame.
+///
+/// It wraps either a whole `Client`, in which case it is still a `Stream` of messages,
+/// or just the `ClientSink` half.
+///
+/// ```rust,no_run
+/// # extern crate tokio;
+/// # extern crate futures;
+/// # extern crate websocket;
+/// use websocket::ClientBuilder;
+/// use websocket::async::client::BoundedSink;
+/// use websocket::futures::{Future, Stream, Sink};
+/// use websocket::{OwnedMessage, WebSocketError};
+/// # fn main() {
+///
+/// let messages = (0..1000).map(|i| OwnedMessage::Text(i.to_string()));
+/// let send = ClientBuilder::new("ws://echo.websocket.org").unwrap()
+///     .async_connect_insecure()
+///     .and_then(|(client, _)| {
+///         // never get more than 1 KiB ahead of the socket
+///         BoundedSink::new(client, 1024)
+///             .send_all(futures::stream::iter_ok::<_, WebSocketError>(messages))
+///     });
+///
+/// tokio::runtime::Builder::new().build().unwrap().block_on(send).unwrap();
+/// # }
+/// ```
+pub struct BoundedSink<K> {
+	inner: K,
+	high_water_mark: usize,
+	buffered: usize,
+}
+
+impl<K> BoundedSink<K>
+where
+	K: Sink<SinkItem = OwnedMessage>,
+{
+	/// Wrap `inner`, accepting at most `high_water_mark` bytes before they are flushed.
+	pub fn new(inner: K, high_water_mark: usize) -> Self {
+		BoundedSink {
+			inner,
+			high_water_mark,
+			buffered: 0,
+		}
+	}
+
+	/// The number of bytes which may be buffered before messages are refused.
+	pub fn high_water_mark(&self) -> usize {
+		self.high_water_mark
+	}
+
+	/// The number of bytes sent since the underlying sink was last flushed.
+	pub fn buffered(&self) -> usize {
+		self.buffered
+	}
+
+	/// Get a reference to the wrapped sink.
+	pub fn get_ref(&self) -> &K {
+		&self.inner
+	}
+
+	/// Get a mutable reference to the wrapped sink.
+	pub fn get_mut(&mut self) -> &mut K {
+		&mut self.inner
+	}
+
+	/// Unwrap the sink, any buffered messages stay in it.
+	pub fn into_inner(self) -> K {
+		self.inner
+	}
+}
+
+impl<K> Sink for BoundedSink<K>
+where
+	K: Sink<SinkItem = OwnedMessage>,
+{
+	type SinkItem = OwnedMessage;
+	type SinkError = K::SinkError;
+
+	fn start_send(&mut self, item: OwnedMessage) -> StartSend<OwnedMessage, K::SinkError> {
+		if self.buffered >= self.high_water_mark && self.poll_complete()?.is_not_ready() {
+			return Ok(AsyncSink::NotReady(item));
+		}
+		let size = item.message_size(true);
+		let sent = self.inner.start_send(item)?;
+		if sent.is_ready() {
+			self.buffered += size;
+		}
+		Ok(sent)
+	}
+
+	fn poll_complete(&mut self) -> Poll<(), K::SinkError> {
+		let flushed = self.inner.poll_complete()?;
+		if flushed.is_ready() {
+			self.buffered = 0;
+		}
+		Ok(flushed)
+	}
+
+	fn close(&mut self) -> Poll<(), K::SinkError> {
+		let closed = self.inner.close()?;
+		if closed.is_ready() {
+			self.buffered = 0;
+		}
+		Ok(closed)
+	}
+}
+
+impl<K> Stream for BoundedSink<K>
+where
+	K: Stream,
+{
+	type Item = K::Item;
+	type Error = K::Error;
+
+	fn poll(&mut self) -> Poll<Option<K::Item>, K::Error> {
+		self.inner.poll()
+	}
+}
+
+/// A client which regularly pings the remote endpoint and fails once a ping
+/// is not answered in time, so a dead connection is noticed even if nothing
+/// else is sent over it.
+///
+/// Every ping carries a new token (a big endian `u64` counting up) and only a
+/// pong with the token of the last ping counts as an answer, stale pongs are
+/// ignored. All messages, pongs included, are passed on unchanged.
+///
+/// The pings are sent while the stream is polled, the timers need to run on
+/// a tokio runtime.
+///
+/// ```rust,no_run
+/// # extern crate tokio;
+/// # extern crate futures;
+/// # extern crate websocket;
+/// use std::time::Duration;
+/// use websocket::ClientBuilder;
+/// use websocket::async::client::Keepalive;
+/// use websocket::futures::{Future, Stream};
+/// # fn main() {
+///
+/// let messages = ClientBuilder::new("ws://echo.websocket.org").unwrap()
+///     .async_connect_insecure()
+///     .and_then(|(client, _)| {
+///         Keepalive::new(client, Duration::from_secs(30))
+///             .pong_timeout(Duration::from_secs(5))
+///             .for_each(|message| {
+///                 println!("Recv: {:?}", message);
+///                 Ok(())
+///             })
+///     });
+///
+/// tokio::runtime::Builder::new().build().unwrap().block_on(messages).unwrap();
+/// # }
+/// ```
+pub struct Keepalive<K> {
+	inner: K,
+	interval: Duration,
+	pong_timeout: Duration,
+	timer: Delay,
+	token: u64,
+	awaiting_pong: bool,
+	ping: Option<OwnedMessage>,
+	flushing: bool,
+}
+
+impl<K> Keepalive<K>
+where
+	K: Stream<Item = OwnedMessage, Error = WebSocketError>
+		+ Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	/// Wrap `inner`, sending a ping every `interval` once the previous ping has
+	/// been answered. The default pong timeout is 10 seconds.
+	pub fn new(inner: K, interval: Duration) -> Self {
+		Keepalive {
+			inner,
+			interval,
+			pong_timeout: Duration::from_secs(10),
+			timer: Delay::new(Instant::now() + interval),
+			token: 0,
+			awaiting_pong: false,
+			ping: None,
+			flushing: false,
+		}
+	}
+
+	/// How long to wait for the answer to a ping before the stream fails.
+	pub fn pong_timeout(mut self, timeout: Duration) -> Self {
+		self.pong_timeout = timeout;
+		self
+	}
+
+	/// Get a reference to the wrapped client.
+	pub fn get_ref(&self) -> &K {
+		&self.inner
+	}
+
+	/// Get a mutable reference to the wrapped client.
+	pub fn get_mut(&mut self) -> &mut K {
+		&mut self.inner
+	}
+
+	/// Unwrap the client, stopping the pings.
+	pub fn into_inner(self) -> K {
+		self.inner
+	}
+
+	// hands the ping that is due to the client and flushes it, on every
+	// poll until the client is done with it
+	fn send_ping(&mut self) -> Result<(), WebSocketError> {
+		if let Some(ping) = self.ping.take() {
+			if let AsyncSink::NotReady(ping) = self.inner.start_send(ping)? {
+				self.ping = Some(ping);
+				return Ok(());
+			}
+			self.flushing = true;
+		}
+		if self.flushing {
+			self.flushing = self.inner.poll_complete()?.is_not_ready();
+		}
+		Ok(())
+	}
+
+	fn poll_timer(&mut self) -> Poll<(), WebSocketError> {
+		self.timer.poll().map_err(|e| io::Error::other(e).into())
+	}
+}
+
+impl<K> Stream for Keepalive<K>
+where
+	K: Stream<Item = OwnedMessage, Error = WebSocketError>
+		+ Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	type Item = OwnedMessage;
+	type Error = WebSocketError;
+
+	fn poll(&mut self) -> Poll<Option<OwnedMessage>, WebSocketError> {
+		self.send_ping()?;
+
+		let message = self.inner.poll()?;
+		if let Async::Ready(Some(OwnedMessage::Pong(ref token))) = message {
+			if self.awaiting_pong && token[..] == self.token.to_be_bytes()[..] {
+				self.awaiting_pong = false;
+				self.timer.reset(Instant::now() + self.interval);
+			}
+		}
+		if message.is_ready() {
+			return Ok(message);
+		}
+
+		while self.poll_timer()?.is_ready() {
+			if self.awaiting_pong {
+				return Err(WebSocketError::Timeout);
+			}
+			self.token += 1;
+			self.ping = Some(OwnedMessage::Ping(self.token.to_be_bytes().to_vec()));
+			self.awaiting_pong = true;
+			self.timer.reset(Instant::now() + self.pong_timeout);
+			self.send_ping()?;
+		}
+		Ok(Async::NotReady)
+	}
+}
+
+impl<K> Sink for Keepalive<K>
+where
+	K: Stream<Item = OwnedMessage, Error = WebSocketError>
+		+ Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	type SinkItem = OwnedMessage;
+	type SinkError = WebSocketError;
+
+	fn start_send(&mut self, item: OwnedMessage) -> StartSend<OwnedMessage, WebSocketError> {
+		self.send_ping()?;
+		if self.ping.is_some() {
+			return Ok(AsyncSink::NotReady(item));
+		}
+		self.inner.start_send(item)
+	}
+
+	fn poll_complete(&mut self) -> Poll<(), WebSocketError> {
+		self.send_ping()?;
+		if self.ping.is_some() {
+			return Ok(Async::NotReady);
+		}
+		let flushed = self.inner.poll_complete()?;
+		if flushed.is_ready() {
+			self.flushing = false;
+		}
+		Ok(flushed)
+	}
+
+	fn close(&mut self) -> Poll<(), WebSocketError> {
+		self.inner.close()
+	}
+}
+
+/// A client which reads frames no faster than a `RateLimit` allows, see the
+/// `ratelimit` module.
+///
+/// The frames are counted as they are decoded, once the budget is used up
+/// the stream returns `NotReady` until enough of it is back before the next
+/// message is read. The stream fails with `WebSocketError::RateLimitExceeded` if
+/// the peer stays over the limit for longer than `RateLimit::close_after`,
+/// the connection should be closed with status code 1008 then.
+///
+/// The timers need to run on a tokio runtime.
+///
+/// ```rust,no_run
+/// # extern crate tokio;
+/// # extern crate futures;
+/// # extern crate websocket;
+/// use websocket::async::client::RateLimited;
+/// use websocket::async::Server;
+/// use websocket::futures::{Future, Stream};
+/// use websocket::ratelimit::RateLimit;
+/// # fn main() {
+///
+/// let server = Server::bind("127.0.0.1:1234", &tokio::reactor::Handle::default()).unwrap();
+/// let messages = server
+///     .incoming()
+///     .take(1)
+///     .map_err(|e| e.error.into())
+///     .and_then(|(upgrade, _)| upgrade.accept())
+///     .and_then(|(client, _)| {
+///         RateLimited::new(client, RateLimit::frames_per_second(100)).for_each(|message| {
+///             println!("Recv: {:?}", message);
+///             Ok(())
+///         })
+///     })
+///     .for_each(|_| Ok(()));
+///
+/// tokio::runtime::Builder::new().build().unwrap().block_on(messages).unwrap();
+/// # }
+/// ```
+pub struct RateLimited<S> {
+	inner: Client<S>,
+	pause: Option<Delay>,
+}
+
+impl<S> RateLimited<S>
+where
+	S: AsyncStream,
+{
+	/// Wrap `inner`, counting its frames against `limit`.
+	pub fn new(mut inner: Client<S>, limit: RateLimit) -> Self {
+		inner.codec_mut().set_rate_limit(Some(limit));
+		RateLimited { inner, pause: None }
+	}
+
+	/// Get a reference to the wrapped client.
+	pub fn get_ref(&self) -> &Client<S> {
+		&self.inner
+	}
+
+	/// Get a mutable reference to the wrapped client.
+	pub fn get_mut(&mut self) -> &mut Client<S> {
+		&mut self.inner
+	}
+
+	/// Unwrap the client, its frames are still counted but it doesn't wait anymore.
+	pub fn into_inner(self) -> Client<S> {
+		self.inner
+	}
+
+	// waits until the rate limit allows to read the next message
+	fn poll_pause(&mut self) -> Poll<(), WebSocketError> {
+		let ready_at = match self
+			.inner
+			.codec()
+			.rate_limiter()
+			.and_then(RateLimiter::ready_at)
+		{
+			Some(ready_at) if ready_at > Instant::now() => ready_at,
+			_ => {
+				self.pause = None;
+				return Ok(Async::Ready(()));
+			}
+		};
+		let pause = self.pause.get_or_insert_with(|| Delay::new(ready_at));
+		if pause.deadline() != ready_at {
+			pause.reset(ready_at);
+		}
+		let paused = pause.poll().map_err(io::Error::other)?;
+		if paused.is_ready() {
+			self.pause = None;
+		}
+		Ok(paused)
+	}
+}
+
+impl<S> Stream for RateLimited<S>
+where
+	S: AsyncStream,
+{
+	type Item = OwnedMessage;
+	type Error = WebSocketError;
+
+	fn poll(&mut self) -> Poll<Option<OwnedMessage>, WebSocketError> {
+		if self.poll_pause()?.is_not_ready() {
+			return Ok(Async::NotReady);
+		}
+		self.inner.poll()
+	}
+}
+
+impl<S> Sink for RateLimited<S>
+where
+	S: AsyncStream,
+{
+	type SinkItem = OwnedMessage;
+	type SinkError = WebSocketError;
+
+	fn start_send(&mut self, item: OwnedMessage) -> StartSend<OwnedMessage, WebSocketError> {
+		self.inner.start_send(item)
+	}
+
+	fn poll_complete(&mut self) -> Poll<(), WebSocketError> {
+		self.inner.poll_complete()
+	}
+
+	fn close(&mut self) -> Poll<(), WebSocketError> {
+		self.inner.close()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use client::builder::ClientBuilder;
+	use server::async::Server;
+	use tokio::runtime::Builder;
+
+	#[test]
+	fn echo_through_split_halves() {
+		let mut runtime = Builder::new().build().unwrap();
+		let server = Server::bind("127.0.0.1:0", &Handle::default()).unwrap();
+		let addr = server.local_addr().unwrap();
+		let echo = server
+			.incoming()
+			.take(1)
+			.map_err(|e| panic!("invalid connection: {:?}", e.error))
+			.and_then(|(upgrade, _)| upgrade.accept())
+			.for_each(|(client, _)| {
+				let (sink, stream) = client.split();
+				stream
+					.take_while(|m| Ok(!m.is_close()))
+					.forward(sink)
+					.and_then(|(_, sink)| sink.send(OwnedMessage::Close(None)))
+					.map(|_| ())
+			})
+			.map_err(|e| panic!("echo server failed: {:?}", e));
+		runtime.spawn(echo);
+
+		let messages: Vec<_> = (0..100)
+			.map(|i| OwnedMessage::Text(format!("message {}", i)))
+			.collect();
+		let mut outgoing = messages.clone();
+		outgoing.push(OwnedMessage::Close(None));
+		let client = ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.async_connect_insecure()
+			.and_then(move |(client, _)| {
+				let (sink, stream): (ClientSink<TcpStream>, ClientStream<TcpStream>) =
+					client.split();
+				let send = sink.send_all(::futures::stream::iter_ok::<_, WebSocketError>(outgoing));
+				let receive = stream.take_while(|m| Ok(!m.is_close())).collect();
+				send.join(receive).map(|(_, received)| received)
+			});
+
+		assert_eq!(runtime.block_on(client).unwrap(), messages);
+	}
+
+	#[test]
+	#[cfg(feature = "deflate")]
+	fn echo_over_a_compressed_connection() {
+		let mut runtime = Builder::new().build().unwrap();
+		let server = Server::bind("127.0.0.1:0", &Handle::default()).unwrap();
+		let addr = server.local_addr().unwrap();
+		let echo = server
+			.incoming()
+			.take(1)
+			.map_err(|e| panic!("invalid connection: {:?}", e.error))
+			.and_then(|(upgrade, _)| upgrade.enable_deflate().accept())
+			.for_each(|(client, _)| {
+				let (sink, stream) = client.split();
+				stream
+					.take_while(|m| Ok(!m.is_close()))
+					.forward(sink)
+					.and_then(|(_, sink)| sink.send(OwnedMessage::Close(None)))
+					.map(|_| ())
+			})
+			.map_err(|e| panic!("echo server failed: {:?}", e));
+		runtime.spawn(echo);
+
+		let messages: Vec<_> = (0..20)
+			.map(|i| OwnedMessage::Text(format!("compressed message {} ", i).repeat(10)))
+			.collect();
+		let mut outgoing = messages.clone();
+		outgoing.push(OwnedMessage::Close(None));
+		let client = ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.enable_deflate()
+			.async_connect_insecure()
+			.and_then(move |(client, headers)| {
+				let extensions = headers.get::<::header::WebSocketExtensions>().unwrap();
+				assert_eq!(extensions.0[0].name, "permessage-deflate");
+				let (sink, stream) = client.split();
+				let send = sink.send_all(::futures::stream::iter_ok::<_, WebSocketError>(outgoing));
+				let receive = stream.take_while(|m| Ok(!m.is_close())).collect();
+				send.join(receive).map(|(_, received)| received)
+			});
+
+		assert_eq!(runtime.block_on(client).unwrap(), messages);
+	}
+
+	// a sink that needs three polls to flush anything
+	struct SlowSink {
+		buffered: Vec<OwnedMessage>,
+		written: Vec<OwnedMessage>,
+		stalls: usize,
+		most_buffered: usize,
+	}
+
+	impl Sink for SlowSink {
+		type SinkItem = OwnedMessage;
+		type SinkError = WebSocketError;
+
+		fn start_send(&mut self, item: OwnedMessage) -> StartSend<OwnedMessage, WebSocketError> {
+			self.buffered.push(item);
+			self.most_buffered = self.most_buffered.max(self.buffered.len());
+			Ok(AsyncSink::Ready)
+		}
+
+		fn poll_complete(&mut self) -> Poll<(), WebSocketError> {
+			if self.stalls < 2 {
+				self.stalls += 1;
+				return Ok(Async::NotReady);
+			}
+			self.stalls = 0;
+			self.written.append(&mut self.buffered);
+			Ok(Async::Ready(()))
+		}
+	}
+
+	#[test]
+	fn throttles_slow_sink() {
+		let slow = SlowSink {
+			buffered: Vec::new(),
+			written: Vec::new(),
+			stalls: 0,
+			most_buffered: 0,
+		};
+		// every message is a 10 byte masked frame, so at most 6 are buffered
+		let mut sink = BoundedSink::new(slow, 60);
+		let messages: Vec<_> = (0..100).map(|i| OwnedMessage::Binary(vec![i; 4])).collect();
+
+		let mut refused = 0;
+		for message in &messages {
+			let mut message = message.clone();
+			while let AsyncSink::NotReady(m) = sink.start_send(message).unwrap() {
+				refused += 1;
+				message = m;
+			}
+			assert!(sink.buffered() <= sink.high_water_mark());
+		}
+		while sink.poll_complete().unwrap().is_not_ready() {}
+
+		let slow = sink.into_inner();
+		assert!(refused > 0);
+		assert_eq!(slow.most_buffered, 6);
+		assert_eq!(slow.written, messages);
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn keepalive_detects_dead_connections() {
+		use server::sync::Server as SyncServer;
+		use std::thread;
+
+		let mut server = SyncServer::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+		thread::spawn(move || {
+			let mut peer = server.accept().ok().unwrap().accept().unwrap();
+			let ping = peer.recv_message().unwrap();
+			let token = match ping {
+				OwnedMessage::Ping(token) => token,
+				message => panic!("expected a ping, got {:?}", message),
+			};
+			assert_eq!(token, 1u64.to_be_bytes().to_vec());
+			// a stale pong doesn't count as an answer
+			peer.send_message(&OwnedMessage::Pong(b"stale".to_vec()))
+				.unwrap();
+			peer.send_message(&OwnedMessage::Pong(token)).unwrap();
+			// then stop answering
+			while peer.recv_message().is_ok() {}
+		});
+
+		let builder = ClientBuilder::new(&format!("ws://{}", addr)).unwrap();
+		let mut runtime = Builder::new().build().unwrap();
+		let connect = builder
+			.clone()
+			.async_keepalive(ClientBuilder::async_connect_insecure);
+		match runtime.block_on(connect) {
+			Err(WebSocketError::RequestError(_)) => (),
+			other => panic!("expected a RequestError, got {:?}", other.err()),
+		}
+
+		let started = Instant::now();
+		let received = builder
+			.ping_interval(Some(Duration::from_millis(50)))
+			.pong_timeout(Duration::from_millis(100))
+			.async_keepalive(ClientBuilder::async_connect_insecure)
+			.and_then(|(client, _)| client.then(Ok::<_, WebSocketError>).take(3).collect());
+		let received = runtime.block_on(received).unwrap();
+
+		assert_eq!(
+			received[0].as_ref().ok(),
+			Some(&OwnedMessage::Pong(b"stale".to_vec()))
+		);
+		assert_eq!(
+			received[1].as_ref().ok(),
+			Some(&OwnedMessage::Pong(1u64.to_be_bytes().to_vec()))
+		);
+		match received[2] {
+			Err(WebSocketError::Timeout) => (),
+			ref other => panic!("expected a timeout, got {:?}", other),
+		}
+		// two ping intervals and the pong timeout
+		let elapsed = started.elapsed();
+		assert!(elapsed >= Duration::from_millis(200));
+		assert!(elapsed < Duration::from_secs(2));
+	}
+
+	#[test]
+	fn rate_limited_client_is_paced() {
+		use codec::ws::Context;
+		use std::io::Cursor;
+		use stream::async::ReadWritePair;
+		use tokio_codec::Decoder;
+
+		let mut input = Vec::new();
+		for i in 0..30 {
+			OwnedMessage::Binary(vec![i])
+				.serialize(&mut input, false)
+				.unwrap();
+		}
+		let messages = |limit: RateLimit| {
+			let client = MessageCodec::default(Context::Client).framed(ReadWritePair(
+				Cursor::new(input.clone()),
+				Cursor::new(vec![]),
+			));
+			RateLimited::new(client, limit).collect()
+		};
+		let mut runtime = Builder::new().build().unwrap();
+
+		let limit = RateLimit::frames_per_second(100).burst(10);
+		let start = Instant::now();
+		let received = runtime.block_on(messages(limit)).unwrap();
+		let elapsed = start.elapsed();
+		assert_eq!(received.len(), 30);
+		assert_eq!(received[29], OwnedMessage::Binary(vec![29]));
+		// the first 10 frames are a burst, the other 20 come at most 100 per second
+		assert!(elapsed >= Duration::from_millis(180), "took {:?}", elapsed);
+
+		let limit = limit.close_after(Duration::from_millis(50));
+		match runtime.block_on(messages(limit)) {
+			Err(WebSocketError::RateLimitExceeded) => {}
+			other => panic!("expected the rate limit to be exceeded, got {:?}", other),
+		}
+	}
+}
@@ -1,9 +1,12 @@
 //! Everything you need to create a client connection to a websocket.
 
+#[cfg(feature = "deflate")]
+use deflate::DeflateConfig;
 use header::extensions::Extension;
 use header::{Origin, WebSocketExtensions, WebSocketKey, WebSocketProtocol, WebSocketVersion};
-use hyper::header::{Authorization, Basic, Header, HeaderFormat, Headers};
+use hyper::header::{Authorization, Basic, Cookie, Header, HeaderFormat, Headers};
 use hyper::version::HttpVersion;
+use result::{WebSocketError, WebSocketResult};
 use std::borrow::Cow;
 use std::convert::Into;
 pub use url::{ParseError, Url};
@@ -12,16 +15,17 @@ pub use url::{ParseError, Url};
 mod common_imports {
 	pub use header::WebSocketAccept;
 	pub use hyper::buffer::BufReader;
-	pub use hyper::header::{Connection, ConnectionOption, Host, Protocol, ProtocolName, Upgrade};
+	pub use hyper::header::{Connection, ConnectionOption, Protocol, ProtocolName, Upgrade};
 	pub use hyper::http::h1::parse_response;
 	pub use hyper::http::h1::Incoming;
 	pub use hyper::http::RawStatus;
 	pub use hyper::method::Method;
 	pub use hyper::status::StatusCode;
 	pub use hyper::uri::RequestUri;
-	pub use result::{WSUrlErrorKind, WebSocketError, WebSocketResult};
+	pub use result::WSUrlErrorKind;
 	pub use std::net::TcpStream;
 	pub use std::net::ToSocketAddrs;
+	pub(crate) use stream::TcpOptions;
 	pub use stream::{self, Stream};
 	pub use unicase::UniCase;
 	pub use url::Position;
@@ -31,22 +35,48 @@ use self::common_imports::*;
 
 #[cfg(feature = "sync")]
 use super::sync::Client;
+#[cfg(feature = "sync")]
+use std::io::{self, Read, Write};
+#[cfg(feature = "sync")]
+use std::net::SocketAddr as SyncSocketAddr;
+#[cfg(all(feature = "sync", unix))]
+use std::os::unix::net::UnixStream;
+#[cfg(all(feature = "sync", unix))]
+use std::path::Path;
+#[cfg(feature = "sync")]
+use std::sync::mpsc::{self, RecvTimeoutError};
+#[cfg(feature = "sync")]
+use std::thread;
+#[cfg(any(feature = "sync", feature = "async"))]
+use std::time::Duration;
+#[cfg(feature = "sync")]
+use std::time::Instant;
+#[cfg(feature = "sync")]
+use stream::sync::AsTcpStream;
 
-#[cfg(feature = "sync-ssl")]
+#[cfg(any(
+	feature = "sync-ssl",
+	all(feature = "sync-rustls", not(feature = "native-tls"))
+))]
 use stream::sync::NetworkStream;
 
 #[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
 use native_tls::TlsConnector;
 #[cfg(feature = "sync-ssl")]
 use native_tls::TlsStream;
+#[cfg(all(feature = "sync-rustls", not(feature = "native-tls")))]
+use tls::{self, TlsConnector, TlsStream};
 
 #[cfg(feature = "async")]
 mod async_imports {
 	pub use super::super::async;
+	pub use bytes::BytesMut;
 	pub use codec::ws::{Context, MessageCodec};
 	pub use futures::future;
 	pub use futures::Stream as FutureStream;
 	pub use futures::{Future, IntoFuture, Sink};
+	pub use message::OwnedMessage;
+	pub use std::net::SocketAddr;
 	pub use tokio_codec::FramedParts;
 	pub use tokio_codec::{Decoder, Framed};
 	pub use tokio_reactor::Handle;
@@ -58,6 +88,21 @@ mod async_imports {
 #[cfg(feature = "async")]
 use self::async_imports::*;
 
+// headers which are set by the handshake itself and cannot be customized
+const HANDSHAKE_HEADERS: &[&str] = &["Sec-WebSocket-Key", "Upgrade", "Connection"];
+
+// upper bound on the size of a proxy's response to a CONNECT request
+#[cfg(feature = "sync")]
+const MAX_PROXY_RESPONSE_SIZE: usize = 8 * 1024;
+
+// how long connecting to one address may take before the next one is tried
+#[cfg(feature = "sync")]
+const DEFAULT_ATTEMPT_TIMEOUT: Duration = Duration::from_millis(250);
+
+// how long one of the raced connection attempts may take without a `connect_timeout`
+#[cfg(feature = "sync")]
+const MAX_ATTEMPT_TIME: Duration = Duration::from_secs(60);
+
 /// Build clients with a builder-style API
 /// This makes it easy to create and configure a websocket
 /// connection:
@@ -110,6 +155,120 @@ pub struct ClientBuilder<'u> {
 	headers: Headers,
 	version_set: bool,
 	key_set: bool,
+	#[cfg(feature = "deflate")]
+	deflate: Option<DeflateConfig>,
+	proxy: Option<String>,
+	proxy_auth: Option<Basic>,
+	host: Option<String>,
+	#[cfg(any(feature = "sync", feature = "async"))]
+	tcp: TcpOptions,
+	#[cfg(feature = "async")]
+	reactor: Option<Handle>,
+	#[cfg(feature = "sync")]
+	connect_timeout: Option<Duration>,
+	#[cfg(feature = "sync")]
+	attempt_timeout: Duration,
+	#[cfg(feature = "sync")]
+	max_redirects: usize,
+	#[cfg(feature = "sync")]
+	read_buffer_size: Option<usize>,
+	#[cfg(feature = "sync")]
+	write_buffer_size: usize,
+	#[cfg(feature = "sync")]
+	auto_close: bool,
+	#[cfg(feature = "sync")]
+	strict: bool,
+	#[cfg(any(feature = "sync", feature = "async"))]
+	ping_interval: Option<Duration>,
+	#[cfg(any(feature = "sync", feature = "async"))]
+	pong_timeout: Duration,
+	#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
+	tls_domain: Option<String>,
+	#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
+	accept_invalid_certs: bool,
+	#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
+	identity: Option<TlsIdentity>,
+}
+
+/// A client certificate and its private key, presented to servers which
+/// require TLS client authentication, see `ClientBuilder::identity`.
+///
+/// The identity is parsed by whichever TLS implementation is compiled in
+/// when connecting, an invalid identity makes the connect methods fail.
+#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
+#[derive(Clone)]
+pub struct TlsIdentity {
+	kind: IdentityKind,
+}
+
+#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
+#[derive(Clone)]
+enum IdentityKind {
+	Pem {
+		cert_chain: Vec<u8>,
+		key: Vec<u8>,
+	},
+	#[cfg(feature = "native-tls")]
+	Pkcs12 {
+		der: Vec<u8>,
+		password: String,
+	},
+}
+
+#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
+impl TlsIdentity {
+	/// Create an identity from a PEM encoded certificate chain, starting with the
+	/// client certificate, and a PEM encoded PKCS #8 private key.
+	pub fn from_pem(cert_chain: &[u8], key: &[u8]) -> Self {
+		TlsIdentity {
+			kind: IdentityKind::Pem {
+				cert_chain: cert_chain.to_vec(),
+				key: key.to_vec(),
+			},
+		}
+	}
+
+	/// Create an identity from a DER encoded PKCS #12 archive protected by `password`.
+	/// This is only supported by `native-tls`.
+	#[cfg(feature = "native-tls")]
+	pub fn from_pkcs12(der: &[u8], password: &str) -> Self {
+		TlsIdentity {
+			kind: IdentityKind::Pkcs12 {
+				der: der.to_vec(),
+				password: password.to_string(),
+			},
+		}
+	}
+
+	#[cfg(feature = "native-tls")]
+	fn to_native(&self) -> WebSocketResult<::native_tls::Identity> {
+		let identity = match self.kind {
+			IdentityKind::Pem {
+				ref cert_chain,
+				ref key,
+			} => ::native_tls::Identity::from_pkcs8(cert_chain, key)?,
+			IdentityKind::Pkcs12 {
+				ref der,
+				ref password,
+			} => ::native_tls::Identity::from_pkcs12(der, password)?,
+		};
+		Ok(identity)
+	}
+}
+
+// the private key stays out of logs
+#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
+impl ::std::fmt::Debug for TlsIdentity {
+	fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+		let format = match self.kind {
+			IdentityKind::Pem { .. } => "PEM",
+			#[cfg(feature = "native-tls")]
+			IdentityKind::Pkcs12 { .. } => "PKCS #12",
+		};
+		fmt.debug_struct("TlsIdentity")
+			.field("format", &format)
+			.finish()
+	}
 }
 
 impl<'u> ClientBuilder<'u> {
@@ -157,11 +316,48 @@ impl<'u> ClientBuilder<'u> {
 			version_set: false,
 			key_set: false,
 			headers: Headers::new(),
+			#[cfg(feature = "deflate")]
+			deflate: None,
+			proxy: None,
+			proxy_auth: None,
+			host: None,
+			#[cfg(any(feature = "sync", feature = "async"))]
+			tcp: TcpOptions::default(),
+			#[cfg(feature = "async")]
+			reactor: None,
+			#[cfg(feature = "sync")]
+			connect_timeout: None,
+			#[cfg(feature = "sync")]
+			attempt_timeout: DEFAULT_ATTEMPT_TIMEOUT,
+			#[cfg(feature = "sync")]
+			max_redirects: 0,
+			#[cfg(feature = "sync")]
+			read_buffer_size: None,
+			#[cfg(feature = "sync")]
+			write_buffer_size: 0,
+			#[cfg(feature = "sync")]
+			auto_close: false,
+			#[cfg(feature = "sync")]
+			strict: false,
+			#[cfg(any(feature = "sync", feature = "async"))]
+			ping_interval: None,
+			#[cfg(any(feature = "sync", feature = "async"))]
+			pong_timeout: Duration::from_secs(10),
+			#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
+			tls_domain: None,
+			#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
+			accept_invalid_certs: false,
+			#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
+			identity: None,
 		}
 	}
 
 	/// Adds a user-defined protocol to the handshake, the server will be
-	/// given a list of these protocols and will send back the ones it accepts.
+	/// given a list of these protocols and will send back the one it chose.
+	///
+	/// The protocols are offered in the order they were added, so add the
+	/// most preferred one first. Connecting fails with a `ResponseError`
+	/// if the server chooses a protocol which was not offered.
 	///
 	/// ```rust
 	/// # use websocket::ClientBuilder;
@@ -285,11 +481,383 @@ impl<'u> ClientBuilder<'u> {
 		self
 	}
 
+	/// Offer the `permessage-deflate` extension, if the server accepts it
+	/// all messages will be compressed.
+	///
+	/// Both the synchronous and the asynchronous clients offer the extension.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("ws://compressed.biz").unwrap()
+	///     .enable_deflate();
+	/// ```
+	#[cfg(feature = "deflate")]
+	pub fn enable_deflate(self) -> Self {
+		self.enable_deflate_with(&DeflateConfig::default())
+	}
+
+	/// Offer the `permessage-deflate` extension with the given preferences,
+	/// e.g. to bound the memory used for compression by every connection.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// use websocket::deflate::DeflateConfig;
+	///
+	/// // forget the LZ77 window after every message and only keep 1KB of it
+	/// let config = DeflateConfig {
+	///     server_no_context_takeover: true,
+	///     client_no_context_takeover: true,
+	///     server_max_window_bits: 10,
+	///     client_max_window_bits: 10,
+	/// };
+	/// let builder = ClientBuilder::new("ws://compressed.biz").unwrap()
+	///     .enable_deflate_with(&config);
+	/// ```
+	#[cfg(feature = "deflate")]
+	pub fn enable_deflate_with(mut self, config: &DeflateConfig) -> Self {
+		self.deflate = Some(*config);
+		self
+	}
+
+	/// Connect through an HTTP proxy listening on `address`, e.g. `"proxy.corp:3128"`.
+	///
+	/// Before the handshake the proxy is asked to open a tunnel to the websocket
+	/// host with an HTTP `CONNECT` request, TLS and the handshake then go through
+	/// that tunnel. If the proxy refuses, connecting fails with a
+	/// `StatusCodeError` holding the status the proxy answered with.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("wss://behind.the.firewall").unwrap()
+	///     .proxy("proxy.corp:3128")
+	///     .proxy_auth("user".to_string(), Some("password".to_string()));
+	/// ```
+	pub fn proxy<A>(mut self, address: A) -> Self
+	where
+		A: Into<String>,
+	{
+		self.proxy = Some(address.into());
+		self
+	}
+
+	/// Authenticate with the proxy set by `proxy` using Basic authentication,
+	/// the credentials are sent in the `Proxy-Authorization` header of the
+	/// `CONNECT` request.
+	pub fn proxy_auth(mut self, username: String, password: Option<String>) -> Self {
+		self.proxy_auth = Some(Basic { username, password });
+		self
+	}
+
+	/// Connect directly instead of through a proxy, this also removes
+	/// any proxy credentials.
+	pub fn clear_proxy(mut self) -> Self {
+		self.proxy = None;
+		self.proxy_auth = None;
+		self
+	}
+
+	/// Whether to set `TCP_NODELAY` on the connection, so small messages are sent
+	/// right away instead of being held back by Nagle's algorithm.
+	///
+	/// This is on by default, older versions left the system's default
+	/// (Nagle's algorithm on). It applies to the connect methods which open the
+	/// TCP connection themselves, i.e. not to `connect_on`, and is set as soon as
+	/// the connection is established, before the handshake.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("ws://bulk.transfer").unwrap()
+	///     .nodelay(false);
+	/// ```
+	#[cfg(any(feature = "sync", feature = "async"))]
+	pub fn nodelay(mut self, nodelay: bool) -> Self {
+		self.tcp.nodelay = nodelay;
+		self
+	}
+
+	/// Enable `SO_KEEPALIVE` on the connection, the system starts probing the
+	/// peer once the connection was idle for `idle`. `None` (the default)
+	/// leaves the option off. Like `nodelay` this is set before the handshake.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// use std::time::Duration;
+	///
+	/// let builder = ClientBuilder::new("ws://quiet.server").unwrap()
+	///     .keepalive(Some(Duration::from_secs(60)));
+	/// ```
+	#[cfg(any(feature = "sync", feature = "async"))]
+	pub fn keepalive(mut self, idle: Option<Duration>) -> Self {
+		self.tcp.keepalive = idle;
+		self
+	}
+
+	/// Register the connections opened by the asynchronous connect methods with
+	/// the reactor of `handle`, for applications running several runtimes or an
+	/// executor of their own.
+	///
+	/// By default a connection is registered with the reactor of the runtime
+	/// polling the future, or a fallback reactor running in a background thread
+	/// if there is none. The connect futures never spawn any tasks.
+	///
+	/// ```rust
+	/// # extern crate tokio;
+	/// # extern crate websocket;
+	/// # use websocket::ClientBuilder;
+	/// # fn main() {
+	/// use tokio::reactor::Reactor;
+	///
+	/// let reactor = Reactor::new().unwrap();
+	/// let builder = ClientBuilder::new("ws://127.0.0.1:1234").unwrap()
+	///     .reactor(&reactor.handle());
+	/// # }
+	/// ```
+	#[cfg(feature = "async")]
+	pub fn reactor(mut self, handle: &Handle) -> Self {
+		self.reactor = Some(handle.clone());
+		self
+	}
+
+	/// Give up connecting if the handshake did not complete within `timeout`,
+	/// this covers connecting the TCP stream, the TLS handshake and the HTTP
+	/// upgrade. When the time runs out the connect methods fail with
+	/// `WebSocketError::Timeout`.
+	///
+	/// This only applies to the synchronous connect methods, the stream's
+	/// read and write timeouts are used during the handshake and reset afterwards.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// use std::time::Duration;
+	///
+	/// let builder = ClientBuilder::new("ws://slow.server").unwrap()
+	///     .connect_timeout(Duration::from_secs(10));
+	/// ```
+	#[cfg(feature = "sync")]
+	pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+		self.connect_timeout = Some(timeout);
+		self
+	}
+
+	/// How long connecting to one address of the host may take before the next
+	/// address is tried alongside it, 250 milliseconds by default.
+	///
+	/// Hosts with several addresses, e.g. IPv4 and IPv6 ones, are connected to
+	/// like "Happy Eyeballs" (RFC 8305) does: the address families take turns and
+	/// a new attempt starts whenever the previous one failed or took longer than
+	/// `timeout`. The first connection made is used for the handshake and the
+	/// others are closed as they come in, so a broken address family doesn't hold
+	/// up the connection. Hosts with a single address are connected to directly.
+	///
+	/// Each attempt gives up once the `connect_timeout` expires, or after a minute
+	/// without one.
+	///
+	/// This only applies to the synchronous connect methods, the asynchronous ones
+	/// try the addresses one after another.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// use std::time::Duration;
+	///
+	/// let builder = ClientBuilder::new("ws://dual.stack.server").unwrap()
+	///     .connect_attempt_timeout(Duration::from_millis(100));
+	/// ```
+	#[cfg(feature = "sync")]
+	pub fn connect_attempt_timeout(mut self, timeout: Duration) -> Self {
+		self.attempt_timeout = timeout;
+		self
+	}
+
+	/// Follow up to `max_redirects` redirects (`301`, `302`, `307` or `308`)
+	/// during the handshake, by default redirects are not followed and fail with
+	/// `WebSocketError::StatusCodeError`.
+	///
+	/// The handshake is sent again to the `Location` of the response, which decides
+	/// whether TLS is used (`ws`/`http` or `wss`/`https`). Redirects from a secure
+	/// to an insecure URL are refused and the `Authorization` and `Cookie` headers
+	/// are dropped when redirected to another host. `connect_insecure` and
+	/// `connect_secure` refuse redirects to a URL needing the other transport with
+	/// a `ResponseError`. A redirect after the last one allowed fails like any other
+	/// unexpected status code.
+	///
+	/// This only applies to the synchronous connect methods which open the
+	/// connection themselves, i.e. not to `connect_on`.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("ws://moved.server").unwrap()
+	///     .max_redirects(3);
+	/// ```
+	#[cfg(feature = "sync")]
+	pub fn max_redirects(mut self, max_redirects: usize) -> Self {
+		self.max_redirects = max_redirects;
+		self
+	}
+
+	/// The size of the buffer the client reads from the stream into, 4096 bytes
+	/// by default.
+	///
+	/// Every read from the stream fills as much of the buffer as is available,
+	/// so a small buffer saves memory on constrained devices at the cost of more
+	/// reads, and a big one saves reads for bulk transfers. Frames bigger than the
+	/// buffer are still received, their payload is read straight from the stream.
+	/// The buffer may grow while the handshake response is read if the response
+	/// does not fit, it is shrunk back to `size` afterwards.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("ws://tiny.device").unwrap()
+	///     .read_buffer_size(256)
+	///     .write_buffer_size(256);
+	/// ```
+	#[cfg(feature = "sync")]
+	pub fn read_buffer_size(mut self, size: usize) -> Self {
+		self.read_buffer_size = Some(size);
+		self
+	}
+
+	/// The size of the buffer the client encodes outgoing frames into, by default
+	/// every frame is encoded into a buffer of its own.
+	/// See `Sender::set_write_buffer_size` for the trade-offs.
+	#[cfg(feature = "sync")]
+	pub fn write_buffer_size(mut self, size: usize) -> Self {
+		self.write_buffer_size = size;
+		self
+	}
+
+	/// Answer the close message of the server with a close message of the
+	/// same status code, see `Client::set_auto_close`. This is disabled by default.
+	#[cfg(feature = "sync")]
+	pub fn auto_close(mut self, auto_close: bool) -> Self {
+		self.auto_close = auto_close;
+		self
+	}
+
+	/// Ping the server every `interval` once the previous ping was answered, the
+	/// connection fails once a ping is not answered within the pong timeout.
+	/// The clients of `connect`, `connect_insecure` and `connect_secure` are set up
+	/// with `Client::set_ping_interval`, asynchronous ones are wrapped in a
+	/// `Keepalive` by `async_keepalive`. By default no pings are sent.
+	#[cfg(any(feature = "sync", feature = "async"))]
+	pub fn ping_interval(mut self, interval: Option<Duration>) -> Self {
+		self.ping_interval = interval;
+		self
+	}
+
+	/// How long to wait for the answer to a ping, see `ping_interval`.
+	/// By default this is 10 seconds.
+	#[cfg(any(feature = "sync", feature = "async"))]
+	pub fn pong_timeout(mut self, timeout: Duration) -> Self {
+		self.pong_timeout = timeout;
+		self
+	}
+
+	/// Enforce every requirement of RFC 6455 on the server, see `Client::set_strict`.
+	/// The checks which `strict` turns on can be turned off again on the client.
+	/// Only the synchronous clients of `connect`, `connect_insecure` and
+	/// `connect_secure` are set up, the asynchronous codec does not know these
+	/// checks. This is disabled by default.
+	///
+	/// ```rust,no_run
+	/// # use websocket::ClientBuilder;
+	/// let mut client = ClientBuilder::new("ws://127.0.0.1:9001/runCase?case=1&agent=rust-websocket")
+	///     .unwrap()
+	///     .strict(true)
+	///     .connect_insecure()
+	///     .unwrap();
+	/// // but let lengths be encoded in more bytes than needed
+	/// client.set_minimal_lengths(false);
+	/// ```
+	#[cfg(feature = "sync")]
+	pub fn strict(mut self, strict: bool) -> Self {
+		self.strict = strict;
+		self
+	}
+
+	/// Send `host` as the `Host` header of the handshake instead of the URL's
+	/// authority, e.g. for split-horizon DNS setups where the server is reached
+	/// under another name than the one it serves. The connection is still opened
+	/// to the URL's host, the override is dropped when redirected to another host.
+	///
+	/// By default the header is the URL's host, followed by the port unless it
+	/// is the default port of the scheme, with IPv6 addresses in brackets.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("ws://10.0.0.7:8080/chat").unwrap()
+	///     .host("chat.example.com");
+	/// ```
+	pub fn host<H>(mut self, host: H) -> Self
+	where
+		H: Into<String>,
+	{
+		self.host = Some(host.into());
+		self
+	}
+
+	/// Use `domain` instead of the URL's host as the server name of TLS
+	/// connections, it is sent with SNI and the server's certificate must be
+	/// valid for it. This is useful when connecting to an IP address or an
+	/// alias of the host the certificate was issued for.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("wss://10.0.0.7:8443").unwrap()
+	///     .tls_domain("chat.example.com");
+	/// ```
+	#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
+	pub fn tls_domain<D>(mut self, domain: D) -> Self
+	where
+		D: Into<String>,
+	{
+		self.tls_domain = Some(domain.into());
+		self
+	}
+
+	/// Accept any certificate the server presents, including expired,
+	/// self-signed and ones issued for another host.
+	///
+	/// **Warning:** this disables the verification which protects connections
+	/// against man-in-the-middle attacks, only ever use it in test environments.
+	/// It only applies when no `TlsConnector` is passed to the connect method,
+	/// a custom connector must be configured on its own.
+	#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
+	pub fn danger_accept_invalid_certs(mut self, accept_invalid_certs: bool) -> Self {
+		self.accept_invalid_certs = accept_invalid_certs;
+		self
+	}
+
+	/// Present a client certificate to servers which require TLS client
+	/// authentication (mutual TLS).
+	///
+	/// Like `danger_accept_invalid_certs` this only applies when no `TlsConnector`
+	/// is passed to the connect method, a custom connector must be given the
+	/// identity on its own. If the server rejects the certificate connecting fails
+	/// with `WebSocketError::TlsSessionError` (or `TlsHandshakeFailure` if it is
+	/// rejected during the `native-tls` handshake).
+	///
+	/// ```rust,no_run
+	/// # use websocket::ClientBuilder;
+	/// use websocket::client::builder::TlsIdentity;
+	///
+	/// let cert_chain = std::fs::read("client.pem").unwrap();
+	/// let key = std::fs::read("client.key.pem").unwrap();
+	/// let client = ClientBuilder::new("wss://mtls.example.com").unwrap()
+	///     .identity(TlsIdentity::from_pem(&cert_chain, &key))
+	///     .connect_secure(None)
+	///     .unwrap();
+	/// ```
+	#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
+	pub fn identity(mut self, identity: TlsIdentity) -> Self {
+		self.identity = Some(identity);
+		self
+	}
+
 	/// Add a custom `Sec-WebSocket-Key` header.
 	/// Use this only if you know what you're doing, and this almost
 	/// never has to be used.
 	pub fn key(mut self, key: [u8; 16]) -> Self {
-		self.headers.set(WebSocketKey(key));
+		self.headers.set(WebSocketKey::from_bytes(key));
 		self.key_set = true;
 		self
 	}
@@ -301,12 +869,22 @@ impl<'u> ClientBuilder<'u> {
 		self
 	}
 
-	/// Set the version of the Websocket connection.
+	/// Set the version of the Websocket connection, sent as it is in the
+	/// `Sec-WebSocket-Version` header. Version 13 is sent by default.
 	/// Currently this library only supports version 13 (from RFC6455),
 	/// but one could use this library to create the handshake then use an
-	/// implementation of another websocket version.
-	pub fn version(mut self, version: WebSocketVersion) -> Self {
-		self.headers.set(version);
+	/// implementation of another websocket version, or send another version
+	/// to test how a server answers it.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("ws://localhost:1234").unwrap().version(8);
+	/// ```
+	pub fn version<V>(mut self, version: V) -> Self
+	where
+		V: Into<WebSocketVersion>,
+	{
+		self.headers.set(version.into());
 		self.version_set = true;
 		self
 	}
@@ -333,9 +911,63 @@ impl<'u> ClientBuilder<'u> {
 		self
 	}
 
+	/// Send cookies with the handshake, e.g. the session cookies of a previous
+	/// HTTP login. All cookies are sent in a single `Cookie` header, together with
+	/// the ones added before.
+	///
+	/// The `Set-Cookie` headers of the server's response can be found with
+	/// `Client::cookies` after connecting. The cookies are not sent anymore
+	/// once a redirect leads to another host.
+	///
+	/// A name which is empty or contains a `;` or `=`, a value which contains
+	/// a `;` and control characters like CR and LF fail with a `RequestError`.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// use websocket::header::Cookie;
+	///
+	/// let builder = ClientBuilder::new("wss://chat.example.com").unwrap()
+	///     .cookies(vec![("SID", "31d4d96e407aad42"), ("lang", "en-US")])
+	///     .unwrap();
+	///
+	/// let cookies = &builder.get_header::<Cookie>().unwrap().0;
+	/// assert_eq!(cookies, &["SID=31d4d96e407aad42", "lang=en-US"]);
+	/// ```
+	pub fn cookies<I, N, V>(mut self, cookies: I) -> WebSocketResult<Self>
+	where
+		I: IntoIterator<Item = (N, V)>,
+		N: AsRef<str>,
+		V: AsRef<str>,
+	{
+		let mut valid = Vec::new();
+		for (name, value) in cookies {
+			let (name, value) = (name.as_ref(), value.as_ref());
+			let invalid = |c: char| c == ';' || c.is_control();
+			if name.is_empty()
+				|| name.contains(|c| invalid(c) || c == '=')
+				|| value.contains(invalid)
+			{
+				return Err(WebSocketError::RequestError("Invalid cookie name or value"));
+			}
+			valid.push(format!("{}={}", name, value));
+		}
+		let mut cookies = valid;
+		if cookies.is_empty() {
+			return Ok(self);
+		}
+		upsert_header!(self.headers; Cookie; {
+			Some(c) => c.0.append(&mut cookies),
+			None => Cookie(cookies)
+		});
+		Ok(self)
+	}
+
 	/// This is a catch all to add random headers to your handshake,
 	/// the process here is more manual.
 	///
+	/// The `Sec-WebSocket-Key`, `Upgrade` and `Connection` headers are required
+	/// by the handshake and are skipped, use `key` to set a custom key.
+	///
 	/// ```rust
 	/// # use websocket::ClientBuilder;
 	/// # use websocket::header::{Headers, Authorization};
@@ -349,7 +981,11 @@ impl<'u> ClientBuilder<'u> {
 	/// # assert!(hds == &"let me in".to_string());
 	/// ```
 	pub fn custom_headers(mut self, custom_headers: &Headers) -> Self {
-		self.headers.extend(custom_headers.iter());
+		self.headers.extend(custom_headers.iter().filter(|header| {
+			!HANDSHAKE_HEADERS
+				.iter()
+				.any(|name| header.name().eq_ignore_ascii_case(name))
+		}));
 		self
 	}
 
@@ -390,20 +1026,22 @@ impl<'u> ClientBuilder<'u> {
 	/// let message = Message::text("m337 47 7pm");
 	/// client.send_message(&message).unwrap();
 	/// ```
-	#[cfg(feature = "sync-ssl")]
+	#[cfg(any(
+		feature = "sync-ssl",
+		all(feature = "sync-rustls", not(feature = "native-tls"))
+	))]
 	pub fn connect(
 		&mut self,
 		ssl_config: Option<TlsConnector>,
-	) -> WebSocketResult<Client<Box<NetworkStream + Send>>> {
-		let tcp_stream = self.establish_tcp(None)?;
-
-		let boxed_stream: Box<NetworkStream + Send> = if self.is_secure_url() {
-			Box::new(self.wrap_ssl(tcp_stream, ssl_config)?)
-		} else {
-			Box::new(tcp_stream)
-		};
-
-		self.connect_on(boxed_stream)
+	) -> WebSocketResult<Client<Box<dyn NetworkStream + Send>>> {
+		self.connect_with(None, |builder, tcp_stream| {
+			let boxed_stream: Box<dyn NetworkStream + Send> = if builder.is_secure_url() {
+				Box::new(builder.wrap_ssl(tcp_stream, ssl_config.clone())?)
+			} else {
+				Box::new(tcp_stream)
+			};
+			Ok(boxed_stream)
+		})
 	}
 
 	/// Create an insecure (plain TCP) connection to the client.
@@ -422,25 +1060,44 @@ impl<'u> ClientBuilder<'u> {
 	/// ```
 	#[cfg(feature = "sync")]
 	pub fn connect_insecure(&mut self) -> WebSocketResult<Client<TcpStream>> {
-		let tcp_stream = self.establish_tcp(Some(false))?;
-
-		self.connect_on(tcp_stream)
+		self.connect_with(Some(false), |_, tcp_stream| Ok(tcp_stream))
 	}
 
 	/// Create an SSL connection to the sever.
 	/// This will only use an `TlsStream`, this is useful
 	/// when you want to be sure to connect over SSL or when you want access
 	/// to the `TlsStream` functions (without having to go through a `Box`).
-	#[cfg(feature = "sync-ssl")]
+	#[cfg(any(
+		feature = "sync-ssl",
+		all(feature = "sync-rustls", not(feature = "native-tls"))
+	))]
 	pub fn connect_secure(
 		&mut self,
 		ssl_config: Option<TlsConnector>,
 	) -> WebSocketResult<Client<TlsStream<TcpStream>>> {
-		let tcp_stream = self.establish_tcp(Some(true))?;
-
-		let ssl_stream = self.wrap_ssl(tcp_stream, ssl_config)?;
+		self.connect_with(Some(true), |builder, tcp_stream| {
+			builder.wrap_ssl(tcp_stream, ssl_config.clone())
+		})
+	}
 
-		self.connect_on(ssl_stream)
+	/// Connect to a websocket server listening on the Unix domain socket at `path`.
+	///
+	/// The URL is only used for the handshake request, i.e. its path and the
+	/// `Host` header (`localhost` if the URL has no host).
+	///
+	/// ```rust,no_run
+	/// # use websocket::ClientBuilder;
+	/// let mut client = ClientBuilder::new("ws://localhost/chat").unwrap()
+	///     .connect_unix("/run/chat/ws.sock")
+	///     .unwrap();
+	/// ```
+	#[cfg(all(feature = "sync", unix))]
+	pub fn connect_unix<P>(&mut self, path: P) -> WebSocketResult<Client<UnixStream>>
+	where
+		P: AsRef<Path>,
+	{
+		let stream = UnixStream::connect(path)?;
+		self.connect_on(stream)
 	}
 
 	/// Connects to a websocket server on any stream you would like.
@@ -449,6 +1106,10 @@ impl<'u> ClientBuilder<'u> {
 	///  - Logging Middle-ware
 	///  - SSH
 	///
+	/// The server's `Sec-WebSocket-Accept` must be derived from the key that was
+	/// sent, otherwise connecting fails with
+	/// `WebSocketError::ResponseError("invalid Sec-WebSocket-Accept")`.
+	///
 	/// ```rust
 	/// # use websocket::ClientBuilder;
 	/// use websocket::sync::stream::ReadWritePair;
@@ -473,23 +1134,111 @@ impl<'u> ClientBuilder<'u> {
 	/// assert!(text.contains("dGhlIHNhbXBsZSBub25jZQ=="), "{}", text);
 	/// ```
 	#[cfg(feature = "sync")]
-	pub fn connect_on<S>(&mut self, mut stream: S) -> WebSocketResult<Client<S>>
+	pub fn connect_on<S>(&mut self, stream: S) -> WebSocketResult<Client<S>>
 	where
 		S: Stream,
 	{
-		// send request
+		self.handshake_on(stream, false)?
+			.map_err(|(status, _)| WebSocketError::StatusCodeError(status))
+	}
+
+	// does the handshake, the status and location of a redirect are returned
+	// instead of an error if `follow_redirects` is set
+	#[cfg(feature = "sync")]
+	fn handshake_on<S>(
+		&mut self,
+		mut stream: S,
+		follow_redirects: bool,
+	) -> WebSocketResult<Result<Client<S>, (StatusCode, String)>>
+	where
+		S: Stream,
+	{
+		#[cfg(feature = "deflate")]
+		self.add_deflate_offer();
+
+		// send request
 		let resource = self.build_request();
 		let data = format!("GET {} {}\r\n{}\r\n", resource, self.version, self.headers);
 		stream.write_all(data.as_bytes())?;
 
 		// wait for a response
-		let mut reader = BufReader::new(stream);
+		let mut reader = match self.read_buffer_size {
+			Some(size) => BufReader::with_capacity(stream, size),
+			None => BufReader::new(stream),
+		};
 		let response = parse_response(&mut reader)?;
 
+		let status = StatusCode::from_u16(response.subject.0);
+		if follow_redirects && is_redirect(status) {
+			return match response.headers.get_raw("Location") {
+				Some(location) if location.len() == 1 => {
+					let location = String::from_utf8_lossy(&location[0]).into_owned();
+					Ok(Err((status, location)))
+				}
+				_ => Err(WebSocketError::ResponseError(
+					"Redirect without a valid Location header",
+				)),
+			};
+		}
+
 		// validate
 		self.validate(&response)?;
 
-		Ok(Client::unchecked(reader, response.headers, true, false))
+		#[cfg(feature = "deflate")]
+		let deflate = self.accept_deflate(&response.headers)?;
+
+		if let Some(size) = self.read_buffer_size {
+			reader = shrink_buffer(reader, size);
+		}
+		let mut client = Client::unchecked(reader, response.headers, true, false);
+		client.set_write_buffer_size(self.write_buffer_size);
+		client.set_auto_close(self.auto_close);
+		if self.strict {
+			client.set_strict(true);
+		}
+		#[cfg(feature = "deflate")]
+		{
+			if let Some(config) = deflate {
+				client.use_deflate(&config);
+			}
+		}
+		Ok(Ok(client))
+	}
+
+	// points the builder at the target of a redirect, unless it needs another
+	// transport than the one given by `secure`
+	#[cfg(feature = "sync")]
+	fn redirect(&mut self, location: &str, secure: Option<bool>) -> WebSocketResult<()> {
+		let mut url = self.url.join(location)?;
+		let scheme = match url.scheme() {
+			"ws" | "http" => "ws",
+			"wss" | "https" => "wss",
+			_ => {
+				return Err(WebSocketError::WebSocketUrlError(
+					WSUrlErrorKind::InvalidScheme,
+				));
+			}
+		};
+		if scheme == "ws" && (self.url.scheme() == "wss" || self.url.scheme() == "https") {
+			return Err(WebSocketError::ResponseError(
+				"Redirect from a secure to an insecure URL",
+			));
+		}
+		if secure.is_some_and(|secure| secure != (scheme == "wss")) {
+			return Err(WebSocketError::ResponseError(
+				"Redirect to a URL needing another transport",
+			));
+		}
+		url.set_scheme(scheme).ok();
+		url.set_fragment(None);
+		if url.origin() != self.url.origin() {
+			// credentials are only meant for the origin they were given for
+			self.headers.remove::<Authorization<Basic>>();
+			self.headers.remove::<Cookie>();
+			self.host = None;
+		}
+		self.url = Cow::Owned(url);
+		Ok(())
 	}
 
 	/// Connect to a websocket server asynchronously.
@@ -544,13 +1293,7 @@ impl<'u> ClientBuilder<'u> {
 		// connect to the tcp stream
 		let tcp_stream = self.async_tcpstream(None);
 
-		let builder = ClientBuilder {
-			url: Cow::Owned(self.url.into_owned()),
-			version: self.version,
-			headers: self.headers,
-			version_set: self.version_set,
-			key_set: self.key_set,
-		};
+		let builder = self.into_owned();
 
 		// check if we should connect over ssl or not
 		if builder.is_secure_url() {
@@ -563,6 +1306,7 @@ impl<'u> ClientBuilder<'u> {
 			};
 			// secure connection, wrap with ssl
 			let future = tcp_stream
+				.and_then(before_tls)
 				.and_then(move |s| connector.connect(&host, s).map_err(Into::into))
 				.and_then(move |stream| {
 					let stream: Box<stream::async::Stream + Send> = Box::new(stream);
@@ -571,9 +1315,9 @@ impl<'u> ClientBuilder<'u> {
 			Box::new(future)
 		} else {
 			// insecure connection, connect normally
-			let future = tcp_stream.and_then(move |stream| {
+			let future = tcp_stream.and_then(move |(stream, read_buf)| {
 				let stream: Box<stream::async::Stream + Send> = Box::new(stream);
-				builder.async_connect_on(stream)
+				builder.async_connect_buffered(stream, read_buf)
 			});
 			Box::new(future)
 		}
@@ -629,18 +1373,13 @@ impl<'u> ClientBuilder<'u> {
 			}
 		};
 
-		let builder = ClientBuilder {
-			url: Cow::Owned(self.url.into_owned()),
-			version: self.version,
-			headers: self.headers,
-			version_set: self.version_set,
-			key_set: self.key_set,
-		};
+		let builder = self.into_owned();
 
 		// put it all together
 		let future = tcp_stream
+			.and_then(before_tls)
 			.and_then(move |s| connector.connect(&host, s).map_err(Into::into))
-			.and_then(move |stream| builder.async_connect_on(stream));
+			.and_then(move |stream| builder.async_connect_on(stream).map_err(tls_session_error));
 		Box::new(future)
 	}
 
@@ -680,15 +1419,65 @@ impl<'u> ClientBuilder<'u> {
 	pub fn async_connect_insecure(self) -> async::ClientNew<async::TcpStream> {
 		let tcp_stream = self.async_tcpstream(Some(false));
 
-		let builder = ClientBuilder {
-			url: Cow::Owned(self.url.into_owned()),
-			version: self.version,
-			headers: self.headers,
-			version_set: self.version_set,
-			key_set: self.key_set,
-		};
+		let builder = self.into_owned();
+
+		let future = tcp_stream
+			.and_then(move |(stream, read_buf)| builder.async_connect_buffered(stream, read_buf));
+		Box::new(future)
+	}
 
-		let future = tcp_stream.and_then(move |stream| builder.async_connect_on(stream));
+	/// Connects with `connect`, e.g. `ClientBuilder::async_connect_insecure`, and
+	/// wraps the client in a `Keepalive` pinging the server as set with
+	/// `ping_interval` and `pong_timeout`. Without a ping interval this fails
+	/// with a `RequestError`.
+	///
+	/// ```rust,no_run
+	/// # extern crate tokio;
+	/// # extern crate websocket;
+	/// use std::time::Duration;
+	/// use websocket::futures::{Future, Stream};
+	/// use websocket::ClientBuilder;
+	/// # fn main() {
+	///
+	/// let messages = ClientBuilder::new("ws://echo.websocket.org")
+	///     .unwrap()
+	///     .ping_interval(Some(Duration::from_secs(30)))
+	///     .async_keepalive(ClientBuilder::async_connect_insecure)
+	///     .and_then(|(client, _)| {
+	///         client.for_each(|message| {
+	///             println!("Recv: {:?}", message);
+	///             Ok(())
+	///         })
+	///     });
+	///
+	/// tokio::runtime::Builder::new().build().unwrap().block_on(messages).unwrap();
+	/// # }
+	/// ```
+	#[cfg(feature = "async")]
+	pub fn async_keepalive<S, F>(
+		self,
+		connect: F,
+	) -> Box<
+		dyn Future<Item = (async::Keepalive<async::Client<S>>, Headers), Error = WebSocketError>
+			+ Send,
+	>
+	where
+		S: stream::async::Stream + Send + 'static,
+		F: FnOnce(Self) -> async::ClientNew<S>,
+	{
+		let interval = match self.ping_interval {
+			Some(interval) => interval,
+			None => {
+				return Box::new(future::err(WebSocketError::RequestError(
+					"No ping interval was set",
+				)))
+			}
+		};
+		let pong_timeout = self.pong_timeout;
+		let future = connect(self).map(move |(client, headers)| {
+			let client = async::Keepalive::new(client, interval).pong_timeout(pong_timeout);
+			(client, headers)
+		});
 		Box::new(future)
 	}
 
@@ -727,6 +1516,7 @@ impl<'u> ClientBuilder<'u> {
 	///
 	/// let client = ClientBuilder::new("wss://test.ws").unwrap()
 	///     .key(b"the sample nonce".clone())
+	///     .add_protocol("proto-metheus")
 	///     .async_connect_on(ReadWritePair(input, output))
 	///     .map(|(_, headers)| {
 	///         let proto: &WebSocketProtocol = headers.get().unwrap();
@@ -740,15 +1530,22 @@ impl<'u> ClientBuilder<'u> {
 	where
 		S: stream::async::Stream + Send + 'static,
 	{
-		let mut builder = ClientBuilder {
-			url: Cow::Owned(self.url.into_owned()),
-			version: self.version,
-			headers: self.headers,
-			version_set: self.version_set,
-			key_set: self.key_set,
-		};
+		self.async_connect_buffered(stream, BytesMut::new())
+	}
+
+	// connects on a stream whose first bytes were read into `read_buf` already
+	#[cfg(feature = "async")]
+	fn async_connect_buffered<S>(self, stream: S, read_buf: BytesMut) -> async::ClientNew<S>
+	where
+		S: stream::async::Stream + Send + 'static,
+	{
+		let mut builder = self.into_owned();
+		#[cfg(feature = "deflate")]
+		builder.add_deflate_offer();
 		let resource = builder.build_request();
-		let framed = ::codec::http::HttpClientCodec.framed(stream);
+		let mut parts = FramedParts::new(stream, ::codec::http::HttpClientCodec);
+		parts.read_buf = read_buf;
+		let framed = Framed::from_parts(parts);
 		let request = Incoming {
 			version: builder.version,
 			headers: builder.headers.clone(),
@@ -767,11 +1564,14 @@ impl<'u> ClientBuilder<'u> {
 					.ok_or(WebSocketError::ProtocolError(
 						"Connection closed before handshake could complete.",
 					))
-					.and_then(|message| builder.validate(&message).map(|()| (message, stream)))
+					.and_then(|message| {
+						builder.validate(&message)?;
+						let codec = builder.message_codec(&message.headers)?;
+						Ok((message, stream, codec))
+					})
 			})
 			// output the final client and metadata
-			.map(|(message, stream)| {
-				let codec = MessageCodec::default(Context::Client);
+			.map(|(message, stream, codec)| {
 				let client = update_framed_codec(stream, codec);
 				(client, message.headers)
 			});
@@ -779,43 +1579,149 @@ impl<'u> ClientBuilder<'u> {
 		Box::new(future)
 	}
 
+	/// Take ownership of the URL, so the builder can outlive it.
+	pub fn into_owned(self) -> ClientBuilder<'static> {
+		ClientBuilder {
+			url: Cow::Owned(self.url.into_owned()),
+			version: self.version,
+			headers: self.headers,
+			version_set: self.version_set,
+			key_set: self.key_set,
+			#[cfg(feature = "deflate")]
+			deflate: self.deflate,
+			proxy: self.proxy,
+			proxy_auth: self.proxy_auth,
+			host: self.host,
+			#[cfg(any(feature = "sync", feature = "async"))]
+			tcp: self.tcp,
+			#[cfg(feature = "async")]
+			reactor: self.reactor,
+			#[cfg(feature = "sync")]
+			connect_timeout: self.connect_timeout,
+			#[cfg(feature = "sync")]
+			attempt_timeout: self.attempt_timeout,
+			#[cfg(feature = "sync")]
+			max_redirects: self.max_redirects,
+			#[cfg(feature = "sync")]
+			read_buffer_size: self.read_buffer_size,
+			#[cfg(feature = "sync")]
+			write_buffer_size: self.write_buffer_size,
+			#[cfg(feature = "sync")]
+			auto_close: self.auto_close,
+			#[cfg(feature = "sync")]
+			strict: self.strict,
+			#[cfg(any(feature = "sync", feature = "async"))]
+			ping_interval: self.ping_interval,
+			#[cfg(any(feature = "sync", feature = "async"))]
+			pong_timeout: self.pong_timeout,
+			#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
+			tls_domain: self.tls_domain,
+			#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
+			accept_invalid_certs: self.accept_invalid_certs,
+			#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
+			identity: self.identity,
+		}
+	}
+
+	// the stream comes with the bytes a proxy sent after its response
 	#[cfg(feature = "async")]
 	fn async_tcpstream(
 		&self,
 		secure: Option<bool>,
-	) -> Box<future::Future<Item = TcpStreamNew, Error = WebSocketError> + Send> {
-		// get the address to connect to, return an error future if ther's a problem
-		let address = match self
-			.extract_host_port(secure)
-			.and_then(|p| Ok(p.to_socket_addrs()?))
-		{
-			Ok(mut s) => match s.next() {
-				Some(a) => a,
-				None => {
-					return Box::new(
-						Err(WebSocketError::WebSocketUrlError(
-							WSUrlErrorKind::NoHostName,
-						))
-						.into_future(),
-					);
-				}
-			},
+	) -> Box<dyn future::Future<Item = (TcpStreamNew, BytesMut), Error = WebSocketError> + Send> {
+		// get the addresses to connect to, return an error future if ther's a problem
+		let addresses: WebSocketResult<Vec<SocketAddr>> = match self.proxy {
+			Some(ref proxy) => proxy
+				.as_str()
+				.to_socket_addrs()
+				.map(Iterator::collect)
+				.map_err(Into::into),
+			None => self
+				.extract_host_port(secure)
+				.and_then(|p| Ok(p.to_socket_addrs()?.collect())),
+		};
+		let addresses = match addresses {
+			Ok(ref a) if a.is_empty() => {
+				return Box::new(
+					Err(WebSocketError::WebSocketUrlError(
+						WSUrlErrorKind::NoHostName,
+					))
+					.into_future(),
+				);
+			}
+			Ok(a) => a,
 			Err(e) => return Box::new(Err(e).into_future()),
 		};
 
 		// connect a tcp stream
-		Box::new(TcpStreamNew::connect(&address).map_err(Into::into))
+		let tcp = self.tcp;
+		let tcp_stream = connect_any(addresses, self.reactor.clone()).and_then(move |stream| {
+			tcp.apply_async(&stream)?;
+			Ok(stream)
+		});
+		if self.proxy.is_none() {
+			return Box::new(tcp_stream.map(|stream| (stream, BytesMut::new())));
+		}
+
+		// open a tunnel to the websocket host through the proxy
+		let (target, headers) = match self.proxy_request(secure) {
+			Ok(r) => r,
+			Err(e) => return Box::new(Err(e).into_future()),
+		};
+		let request = Incoming {
+			version: HttpVersion::Http11,
+			headers,
+			subject: (Method::Connect, RequestUri::Authority(target)),
+		};
+		let future = tcp_stream
+			.and_then(|stream| {
+				::codec::http::HttpClientCodec
+					.framed(stream)
+					.send(request)
+					.map_err(Into::into)
+			})
+			.and_then(|framed| framed.into_future().map_err(|e| e.0.into()))
+			.and_then(|(response, framed)| {
+				let response = response.ok_or(WebSocketError::ProtocolError(
+					"Connection closed before the proxy responded",
+				))?;
+				check_proxy_response(&response)?;
+				let parts = framed.into_parts();
+				Ok((parts.io, parts.read_buf))
+			});
+		Box::new(future)
 	}
 
 	#[cfg(any(feature = "sync", feature = "async"))]
-	fn build_request(&mut self) -> String {
-		// enter host if available (unix sockets don't have hosts)
-		if let Some(host) = self.url.host_str() {
-			self.headers.set(Host {
-				hostname: host.to_string(),
-				port: self.url.port(),
-			});
+	fn proxy_request(&self, secure: Option<bool>) -> WebSocketResult<(String, Headers)> {
+		let target = self.extract_host_port(secure)?.to_string();
+		let mut headers = Headers::new();
+		headers.set_raw("Host", vec![target.clone().into_bytes()]);
+		if let Some(ref auth) = self.proxy_auth {
+			let credentials = format!(
+				"{}:{}",
+				auth.username,
+				auth.password.as_ref().map_or("", |p| p.as_str())
+			);
+			let auth = format!("Basic {}", base64::encode(&credentials));
+			headers.set_raw("Proxy-Authorization", vec![auth.into_bytes()]);
 		}
+		Ok((target, headers))
+	}
+
+	#[cfg(any(feature = "sync", feature = "async"))]
+	fn build_request(&mut self) -> String {
+		// enter host if available, HTTP/1.1 requires one so URLs without
+		// a host (e.g. of unix sockets) use `localhost`
+		// the port is left out if it is the default port of the scheme, `Host`
+		// would also leave out 80 and 443 for the other scheme
+		let hostname = self.url.host_str().unwrap_or("localhost");
+		let host = match (self.host.as_ref(), self.url.port()) {
+			(Some(host), _) => host.clone(),
+			(None, Some(port)) => format!("{}:{}", hostname, port),
+			(None, None) => hostname.to_string(),
+		};
+		self.headers.set_raw("Host", vec![host.into_bytes()]);
 
 		// handle username/password from URL
 		if !self.url.username().is_empty() {
@@ -865,12 +1771,49 @@ impl<'u> ClientBuilder<'u> {
 				"Request Sec-WebSocket-Key was invalid",
 			))?;
 
-		if response.headers.get() != Some(&(WebSocketAccept::new(key))) {
+		let accepted = match response.headers.get::<WebSocketAccept>() {
+			Some(accept) => accept.ct_eq(&key.accept()),
+			None => false,
+		};
+		if !accepted {
 			return Err(WebSocketError::ResponseError(
-				"Sec-WebSocket-Accept is invalid",
+				"invalid Sec-WebSocket-Accept",
 			));
 		}
 
+		if let Some(chosen) = response.headers.get::<WebSocketProtocol>() {
+			let offered = self.headers.get::<WebSocketProtocol>();
+			if chosen.0.len() != 1 {
+				return Err(WebSocketError::ResponseError(
+					"Sec-WebSocket-Protocol must contain a single protocol",
+				));
+			}
+			if !offered.is_some_and(|offered| offered.0.contains(&chosen.0[0])) {
+				return Err(WebSocketError::ResponseError(
+					"Sec-WebSocket-Protocol was not offered",
+				));
+			}
+		}
+
+		if response
+			.headers
+			.get_raw("Sec-WebSocket-Extensions")
+			.is_some()
+		{
+			let accepted = response.headers.get::<WebSocketExtensions>().ok_or(
+				WebSocketError::ResponseError("Sec-WebSocket-Extensions could not be parsed"),
+			)?;
+			let offered = self.headers.get::<WebSocketExtensions>();
+			let was_offered = |name: &str| {
+				offered.is_some_and(|offered| offered.0.iter().any(|e| e.name == name))
+			};
+			if !accepted.0.iter().all(|e| was_offered(&e.name)) {
+				return Err(WebSocketError::ResponseError(
+					"Sec-WebSocket-Extensions was not offered",
+				));
+			}
+		}
+
 		if response.headers.get()
 			!= Some(
 				&(Upgrade(vec![Protocol {
@@ -897,10 +1840,54 @@ impl<'u> ClientBuilder<'u> {
 		Ok(())
 	}
 
+	#[cfg(all(any(feature = "sync", feature = "async"), feature = "deflate"))]
+	fn add_deflate_offer(&mut self) {
+		let offer = match self.deflate {
+			Some(ref config) => config.offer(),
+			None => return,
+		};
+		upsert_header!(self.headers; WebSocketExtensions; {
+			Some(exts) => if !exts.0.iter().any(|e| e.name == ::deflate::EXTENSION_NAME) {
+				exts.0.push(offer)
+			},
+			None => WebSocketExtensions(vec![offer])
+		});
+	}
+
+	#[cfg(all(any(feature = "sync", feature = "async"), feature = "deflate"))]
+	fn accept_deflate(&self, response: &Headers) -> WebSocketResult<Option<DeflateConfig>> {
+		let config = match self.deflate {
+			Some(ref config) => config,
+			None => return Ok(None),
+		};
+		let extension = response
+			.get::<WebSocketExtensions>()
+			.and_then(|e| e.0.iter().find(|e| e.name == ::deflate::EXTENSION_NAME));
+		match extension {
+			Some(extension) => config.accept_response(extension).map(Some),
+			None => Ok(None),
+		}
+	}
+
+	// the codec of an asynchronous client, compressing if deflate was accepted
+	#[cfg(all(feature = "async", feature = "deflate"))]
+	fn message_codec(&self, response: &Headers) -> WebSocketResult<MessageCodec<OwnedMessage>> {
+		let mut codec = MessageCodec::default(Context::Client);
+		if let Some(config) = self.accept_deflate(response)? {
+			codec.use_deflate(&config);
+		}
+		Ok(codec)
+	}
+
+	#[cfg(all(feature = "async", not(feature = "deflate")))]
+	fn message_codec(&self, _response: &Headers) -> WebSocketResult<MessageCodec<OwnedMessage>> {
+		Ok(MessageCodec::default(Context::Client))
+	}
+
 	/// Check whether the given URL uses a secure scheme, e.g. `wss` or `https`.
 	/// Note that `https` is not intended scheme for web sockets, but
 	/// it's still reasonable to wrap TLS if it is encountered.
-	#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+	#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
 	fn is_secure_url(&self) -> bool {
 		let scheme = self.url.scheme();
 		scheme == "wss" || scheme == "https"
@@ -928,27 +1915,143 @@ impl<'u> ClientBuilder<'u> {
 		})?)
 	}
 
+	// connects the TCP stream, lets `wrap` add TLS on top of it and does
+	// the handshake, all within the `connect_timeout` if there is one
+	#[cfg(feature = "sync")]
+	fn connect_with<S, F>(
+		&mut self,
+		secure: Option<bool>,
+		mut wrap: F,
+	) -> WebSocketResult<Client<S>>
+	where
+		S: Stream + AsTcpStream,
+		F: FnMut(&Self, TcpStream) -> WebSocketResult<S>,
+	{
+		let deadline = self.connect_timeout.map(|t| Instant::now() + t);
+		let mut handshake = |builder: &mut Self| {
+			let mut redirects = 0;
+			loop {
+				let tcp_stream = builder.establish_tcp(secure, deadline)?;
+				let stream = wrap(builder, tcp_stream)?;
+				if let Some(deadline) = deadline {
+					set_timeouts(stream.as_tcp(), Some(time_left(deadline)?))?;
+				}
+				let follow = redirects < builder.max_redirects;
+				let mut client = match builder.handshake_on(stream, follow)? {
+					Ok(client) => client,
+					Err((_, location)) => {
+						builder.redirect(&location, secure)?;
+						redirects += 1;
+						continue;
+					}
+				};
+				if deadline.is_some() {
+					set_timeouts(client.stream_ref().as_tcp(), None)?;
+				}
+				if builder.ping_interval.is_some() {
+					client.set_pong_timeout(builder.pong_timeout);
+					client.set_ping_interval(builder.ping_interval);
+				}
+				return Ok(client);
+			}
+		};
+		match handshake(self) {
+			Err(ref e) if deadline.is_some() && is_timeout(e) => Err(WebSocketError::Timeout),
+			#[cfg(any(feature = "sync-ssl", feature = "sync-rustls"))]
+			Err(e) if secure.unwrap_or_else(|| self.is_secure_url()) => Err(tls_session_error(e)),
+			result => result,
+		}
+	}
+
+	#[cfg(feature = "sync")]
+	fn establish_tcp(
+		&mut self,
+		secure: Option<bool>,
+		deadline: Option<Instant>,
+	) -> WebSocketResult<TcpStream> {
+		let mut stream = match self.proxy {
+			Some(ref proxy) => connect_tcp(proxy.as_str(), deadline, self.attempt_timeout)?,
+			None => connect_tcp(
+				self.extract_host_port(secure)?,
+				deadline,
+				self.attempt_timeout,
+			)?,
+		};
+		self.tcp.apply(&stream)?;
+		if self.proxy.is_some() {
+			self.tunnel(&mut stream, secure)?;
+		}
+		Ok(stream)
+	}
+
 	#[cfg(feature = "sync")]
-	fn establish_tcp(&mut self, secure: Option<bool>) -> WebSocketResult<TcpStream> {
-		Ok(TcpStream::connect(self.extract_host_port(secure)?)?)
+	fn tunnel(&self, stream: &mut TcpStream, secure: Option<bool>) -> WebSocketResult<()> {
+		let (target, headers) = self.proxy_request(secure)?;
+		let data = format!(
+			"{} {} {}\r\n{}\r\n",
+			Method::Connect,
+			target,
+			HttpVersion::Http11,
+			headers
+		);
+		stream.write_all(data.as_bytes())?;
+
+		// read byte by byte, everything after the response belongs to the tunnel
+		let mut head = Vec::new();
+		let mut byte = [0; 1];
+		while !head.ends_with(b"\r\n\r\n") {
+			if head.len() >= MAX_PROXY_RESPONSE_SIZE {
+				return Err(WebSocketError::ResponseError("Proxy response is too large"));
+			}
+			stream.read_exact(&mut byte)?;
+			head.push(byte[0]);
+		}
+		let response = parse_response(&mut BufReader::new(&head[..]))?;
+		check_proxy_response(&response)
 	}
 
-	#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+	#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
 	fn extract_host_ssl_conn(
 		&self,
 		connector: Option<TlsConnector>,
 	) -> WebSocketResult<(&str, TlsConnector)> {
-		let host = match self.url.host_str() {
-			Some(h) => h,
+		// the server name used for SNI and to verify the certificate
+		let host = match self.tls_domain {
+			Some(ref domain) => domain.as_str(),
+			// IPv6 hosts are bracketed in URLs
+			None => match self.url.host_str() {
+				Some(h) => h.trim_start_matches('[').trim_end_matches(']'),
+				None => {
+					return Err(WebSocketError::WebSocketUrlError(
+						WSUrlErrorKind::NoHostName,
+					));
+				}
+			},
+		};
+		#[cfg(feature = "native-tls")]
+		let connector = match connector {
+			Some(c) => c,
 			None => {
-				return Err(WebSocketError::WebSocketUrlError(
-					WSUrlErrorKind::NoHostName,
-				));
+				let mut builder = TlsConnector::builder();
+				builder.danger_accept_invalid_certs(self.accept_invalid_certs);
+				if let Some(ref identity) = self.identity {
+					builder.identity(identity.to_native()?);
+				}
+				builder.build()?
 			}
 		};
+		#[cfg(not(feature = "native-tls"))]
 		let connector = match connector {
 			Some(c) => c,
-			None => TlsConnector::builder().build()?,
+			None => {
+				let identity = self.identity.as_ref().map(|identity| match identity.kind {
+					IdentityKind::Pem {
+						ref cert_chain,
+						ref key,
+					} => (&cert_chain[..], &key[..]),
+				});
+				tls::client_connector(self.accept_invalid_certs, identity)?
+			}
 		};
 		Ok((host, connector))
 	}
@@ -963,40 +2066,1455 @@ impl<'u> ClientBuilder<'u> {
 		let ssl_stream = connector.connect(host, tcp_stream)?;
 		Ok(ssl_stream)
 	}
+
+	#[cfg(all(feature = "sync-rustls", not(feature = "native-tls")))]
+	fn wrap_ssl(
+		&self,
+		tcp_stream: TcpStream,
+		connector: Option<TlsConnector>,
+	) -> WebSocketResult<TlsStream<TcpStream>> {
+		let (host, connector) = self.extract_host_ssl_conn(connector)?;
+		Ok(tls::connect(connector, host, tcp_stream)?)
+	}
 }
 
-mod tests {
-	#[test]
-	fn build_client_with_protocols() {
-		use super::*;
-		let builder = ClientBuilder::new("ws://127.0.0.1:8080/hello/world")
-			.unwrap()
-			.add_protocol("protobeard");
+// tries the addresses one after the other until a connection succeeds,
+// like the synchronous `TcpStream::connect`
+#[cfg(feature = "async")]
+fn connect_any(
+	mut addresses: Vec<SocketAddr>,
+	reactor: Option<Handle>,
+) -> Box<dyn future::Future<Item = TcpStreamNew, Error = WebSocketError> + Send> {
+	let address = addresses.remove(0);
+	let connect = match reactor {
+		Some(ref handle) => connect_on_reactor(&address, handle),
+		None => Box::new(TcpStreamNew::connect(&address)),
+	};
+	Box::new(connect.or_else(move |e| {
+		if addresses.is_empty() {
+			Box::new(Err(e.into()).into_future())
+		} else {
+			connect_any(addresses, reactor)
+		}
+	}))
+}
 
-		let protos = &builder.headers.get::<WebSocketProtocol>().unwrap().0;
-		assert!(protos.contains(&"protobeard".to_string()));
-		assert!(protos.len() == 1);
+#[cfg(feature = "async")]
+fn connect_on_reactor(
+	address: &SocketAddr,
+	handle: &Handle,
+) -> Box<dyn future::Future<Item = TcpStreamNew, Error = ::std::io::Error> + Send> {
+	use socket2::{Domain, Socket, Type};
+
+	let socket = Socket::new(Domain::for_address(*address), Type::STREAM, None);
+	// the socket has to be bound before connecting on windows
+	#[cfg(windows)]
+	let socket = socket.and_then(|socket| {
+		let any: SocketAddr = if address.is_ipv4() {
+			"0.0.0.0:0"
+		} else {
+			"[::]:0"
+		}
+		.parse()
+		.unwrap();
+		socket.bind(&any.into())?;
+		Ok(socket)
+	});
+	match socket.map(TcpStream::from) {
+		Ok(stream) => Box::new(TcpStreamNew::connect_std(stream, address, handle)),
+		Err(e) => Box::new(Err(e).into_future()),
+	}
+}
 
-		let builder = ClientBuilder::new("ws://example.org/hello")
-			.unwrap()
-			.add_protocol("rust-websocket")
-			.clear_protocols()
-			.add_protocols(vec!["electric", "boogaloo"]);
+#[cfg(feature = "sync")]
+fn is_redirect(status: StatusCode) -> bool {
+	matches!(
+		status,
+		StatusCode::MovedPermanently
+			| StatusCode::Found
+			| StatusCode::TemporaryRedirect
+			| StatusCode::PermanentRedirect
+	)
+}
 
-		let protos = &builder.headers.get::<WebSocketProtocol>().unwrap().0;
+// the buffer grows to fit the response head, keep only the bytes after it
+#[cfg(feature = "sync")]
+fn shrink_buffer<S: Read>(reader: BufReader<S>, size: usize) -> BufReader<S> {
+	let (stream, buf, pos, cap) = reader.into_parts();
+	if buf.len() <= size || cap - pos > size {
+		return BufReader::from_parts(stream, buf, pos, cap);
+	}
+	let mut shrunk = vec![0; size];
+	shrunk[..cap - pos].copy_from_slice(&buf[pos..cap]);
+	BufReader::from_parts(stream, shrunk, 0, cap - pos)
+}
 
-		assert!(protos.contains(&"boogaloo".to_string()));
-		assert!(protos.contains(&"electric".to_string()));
-		assert!(!protos.contains(&"rust-websocket".to_string()));
+#[cfg(feature = "sync")]
+fn connect_tcp<A>(
+	address: A,
+	deadline: Option<Instant>,
+	attempt_timeout: Duration,
+) -> io::Result<TcpStream>
+where
+	A: ToSocketAddrs,
+{
+	let addresses: Vec<_> = address.to_socket_addrs()?.collect();
+	let stream = match (addresses.len(), deadline) {
+		(0, _) => {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"could not resolve to any addresses",
+			));
+		}
+		(1, None) => TcpStream::connect(addresses[0])?,
+		(1, Some(deadline)) => {
+			TcpStream::connect_timeout(&addresses[0], connect_time_left(deadline)?)?
+		}
+		_ => race(interleave(addresses), deadline, attempt_timeout)?,
+	};
+	if let Some(deadline) = deadline {
+		set_timeouts(&stream, Some(time_left(deadline)?))?;
 	}
+	Ok(stream)
+}
 
-	#[test]
-	fn build_client_with_username_password() {
-		use super::*;
-		let mut builder = ClientBuilder::new("ws://john:pswd@127.0.0.1:8080/hello").unwrap();
-		let _request = builder.build_request();
-		let auth = builder.headers.get::<Authorization<Basic>>().unwrap();
-		assert!(auth.username == "john");
-		assert_eq!(auth.password, Some("pswd".to_owned()));
+// starts connecting to the next address whenever the previous attempts failed or
+// took longer than `attempt_timeout`, the first connection made is returned
+#[cfg(feature = "sync")]
+fn race(
+	addresses: Vec<SyncSocketAddr>,
+	deadline: Option<Instant>,
+	attempt_timeout: Duration,
+) -> io::Result<TcpStream> {
+	let (results, received) = mpsc::channel();
+	let mut addresses = addresses.into_iter().peekable();
+	let mut pending = 0;
+	let mut error = None;
+	loop {
+		if let Some(address) = addresses.next() {
+			let results = results.clone();
+			thread::spawn(move || {
+				let stream = match deadline {
+					Some(deadline) => connect_time_left(deadline),
+					None => Ok(MAX_ATTEMPT_TIME),
+				}
+				.and_then(|t| TcpStream::connect_timeout(&address, t));
+				// the connection is closed if another one won
+				let _ = results.send(stream);
+			});
+			pending += 1;
+		}
+
+		let result = if addresses.peek().is_some() {
+			received.recv_timeout(attempt_timeout)
+		} else {
+			match deadline {
+				Some(deadline) => received.recv_timeout(connect_time_left(deadline)?),
+				None => received.recv().map_err(|_| RecvTimeoutError::Disconnected),
+			}
+		};
+		match result {
+			Ok(Ok(stream)) => return Ok(stream),
+			Ok(Err(e)) => {
+				error = Some(e);
+				pending -= 1;
+				if pending == 0 && addresses.peek().is_none() {
+					break;
+				}
+			}
+			Err(RecvTimeoutError::Timeout) => {
+				if addresses.peek().is_none() {
+					return Err(connect_timed_out());
+				}
+			}
+			Err(RecvTimeoutError::Disconnected) => break,
+		}
+	}
+	Err(error.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "could not connect")))
+}
+
+// alternates between the address families, starting with the first address
+#[cfg(feature = "sync")]
+fn interleave(addresses: Vec<SyncSocketAddr>) -> Vec<SyncSocketAddr> {
+	let first_is_v6 = addresses[0].is_ipv6();
+	let (mut first, mut second): (Vec<_>, Vec<_>) = addresses
+		.into_iter()
+		.partition(|address| address.is_ipv6() == first_is_v6);
+	let mut interleaved = Vec::with_capacity(first.len() + second.len());
+	first.reverse();
+	second.reverse();
+	while !first.is_empty() || !second.is_empty() {
+		interleaved.extend(first.pop());
+		interleaved.extend(second.pop());
+	}
+	interleaved
+}
+
+#[cfg(feature = "sync")]
+fn set_timeouts(stream: &TcpStream, timeout: Option<Duration>) -> io::Result<()> {
+	stream.set_read_timeout(timeout)?;
+	stream.set_write_timeout(timeout)
+}
+
+#[cfg(feature = "sync")]
+fn time_left(deadline: Instant) -> io::Result<Duration> {
+	let now = Instant::now();
+	if now >= deadline {
+		return Err(io::Error::new(
+			io::ErrorKind::TimedOut,
+			"WebSocket handshake timed out",
+		));
+	}
+	Ok(deadline - now)
+}
+
+#[cfg(feature = "sync")]
+fn connect_time_left(deadline: Instant) -> io::Result<Duration> {
+	time_left(deadline).map_err(|_| connect_timed_out())
+}
+
+#[cfg(feature = "sync")]
+fn connect_timed_out() -> io::Error {
+	io::Error::new(io::ErrorKind::TimedOut, "TCP connect timed out")
+}
+
+// whether this error was caused by a read or write timeout
+#[cfg(feature = "sync")]
+fn is_timeout(error: &WebSocketError) -> bool {
+	let error = match *error {
+		WebSocketError::IoError(ref e) => e,
+		WebSocketError::HttpError(::hyper::Error::Io(ref e)) => e,
+		#[cfg(feature = "sync-ssl")]
+		WebSocketError::TlsHandshakeInterruption => return true,
+		_ => return false,
+	};
+	error.kind() == io::ErrorKind::WouldBlock || error.kind() == io::ErrorKind::TimedOut
+}
+
+// tells errors of the TLS session apart from those of the connection
+#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
+fn tls_session_error(error: WebSocketError) -> WebSocketError {
+	match error {
+		WebSocketError::IoError(e) | WebSocketError::HttpError(::hyper::Error::Io(e))
+			if is_tls_session_error(&e) =>
+		{
+			WebSocketError::TlsSessionError(e)
+		}
+		error => error,
+	}
+}
+
+// `native-tls` wraps the errors of its backend in I/O errors, errors of
+// the connection itself are passed on as they are
+#[cfg(all(
+	feature = "native-tls",
+	not(any(target_os = "windows", target_vendor = "apple"))
+))]
+fn is_tls_session_error(error: &::std::io::Error) -> bool {
+	error
+		.get_ref()
+		.is_some_and(|e| e.is::<::openssl::ssl::Error>())
+}
+
+// the other backends are not dependencies of this crate, their errors
+// come with an unspecified kind
+#[cfg(all(
+	feature = "native-tls",
+	any(target_os = "windows", target_vendor = "apple")
+))]
+fn is_tls_session_error(error: &::std::io::Error) -> bool {
+	error.kind() == ::std::io::ErrorKind::Other && error.get_ref().is_some()
+}
+
+#[cfg(all(feature = "sync-rustls", not(feature = "native-tls")))]
+fn is_tls_session_error(error: &::std::io::Error) -> bool {
+	tls::is_session_error(error)
+}
+
+// nothing may come before the TLS handshake, which the client starts
+#[cfg(feature = "async-ssl")]
+fn before_tls((stream, read_buf): (TcpStreamNew, BytesMut)) -> WebSocketResult<TcpStreamNew> {
+	if !read_buf.is_empty() {
+		return Err(WebSocketError::ProtocolError(
+			"The proxy sent data before the TLS handshake",
+		));
+	}
+	Ok(stream)
+}
+
+#[cfg(any(feature = "sync", feature = "async"))]
+fn check_proxy_response(response: &Incoming<RawStatus>) -> WebSocketResult<()> {
+	let status = StatusCode::from_u16(response.subject.0);
+	if status != StatusCode::Ok {
+		return Err(WebSocketError::StatusCodeError(status));
+	}
+	Ok(())
+}
+
+mod tests {
+	#[test]
+	fn build_client_with_protocols() {
+		use super::*;
+		let builder = ClientBuilder::new("ws://127.0.0.1:8080/hello/world")
+			.unwrap()
+			.add_protocol("protobeard");
+
+		let protos = &builder.headers.get::<WebSocketProtocol>().unwrap().0;
+		assert!(protos.contains(&"protobeard".to_string()));
+		assert!(protos.len() == 1);
+
+		let builder = ClientBuilder::new("ws://example.org/hello")
+			.unwrap()
+			.add_protocol("rust-websocket")
+			.clear_protocols()
+			.add_protocols(vec!["electric", "boogaloo"]);
+
+		let protos = &builder.headers.get::<WebSocketProtocol>().unwrap().0;
+
+		assert!(protos.contains(&"boogaloo".to_string()));
+		assert!(protos.contains(&"electric".to_string()));
+		assert!(!protos.contains(&"rust-websocket".to_string()));
+	}
+
+	#[test]
+	fn build_client_with_username_password() {
+		use super::*;
+		let mut builder = ClientBuilder::new("ws://john:pswd@127.0.0.1:8080/hello").unwrap();
+		let _request = builder.build_request();
+		let auth = builder.headers.get::<Authorization<Basic>>().unwrap();
+		assert!(auth.username == "john");
+		assert_eq!(auth.password, Some("pswd".to_owned()));
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn connect_checks_accept() {
+		use super::*;
+		use std::io::Cursor;
+		use stream::sync::ReadWritePair;
+
+		let response = |accept: &str| {
+			format!(
+				"HTTP/1.1 101 Switching Protocols\r\n\
+				 Upgrade: websocket\r\n\
+				 Connection: Upgrade\r\n\
+				 Sec-WebSocket-Accept: {}\r\n\r\n",
+				accept
+			)
+			.into_bytes()
+		};
+		let connect = |accept: &str| {
+			ClientBuilder::new("ws://127.0.0.1:8080")
+				.unwrap()
+				.key(*b"the sample nonce")
+				.connect_on(ReadWritePair(
+					Cursor::new(response(accept)),
+					Cursor::new(Vec::new()),
+				))
+		};
+
+		assert!(connect("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=").is_ok());
+		match connect("AAAAAAAAAAAAAAAAAAAAAAAAAAA=") {
+			Err(WebSocketError::ResponseError("invalid Sec-WebSocket-Accept")) => (),
+			_ => panic!("invalid accept must be rejected"),
+		}
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn protocols_are_offered_in_order() {
+		use super::*;
+		use std::io::Cursor;
+		use stream::sync::ReadWritePair;
+
+		let connect = |chosen: Option<&str>| {
+			let protocol = chosen.map_or(String::new(), |p| {
+				format!("Sec-WebSocket-Protocol: {}\r\n", p)
+			});
+			let response = format!(
+				"HTTP/1.1 101 Switching Protocols\r\n\
+				 Upgrade: websocket\r\n\
+				 Connection: Upgrade\r\n\
+				 {}\
+				 Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n",
+				protocol
+			);
+			ClientBuilder::new("ws://127.0.0.1:8080")
+				.unwrap()
+				.key(*b"the sample nonce")
+				.add_protocol("graphql-ws")
+				.add_protocols(vec!["chat", "json"])
+				.connect_on(ReadWritePair(
+					Cursor::new(response.into_bytes()),
+					Cursor::new(Vec::new()),
+				))
+		};
+
+		let client = connect(Some("chat")).unwrap();
+		assert_eq!(client.protocol(), Some("chat"));
+		let request = String::from_utf8((client.into_stream().0).1.into_inner()).unwrap();
+		assert!(
+			request.contains("Sec-WebSocket-Protocol: graphql-ws, chat, json\r\n"),
+			"{}",
+			request
+		);
+
+		assert_eq!(connect(None).unwrap().protocol(), None);
+		match connect(Some("mqtt")) {
+			Err(WebSocketError::ResponseError("Sec-WebSocket-Protocol was not offered")) => (),
+			other => panic!("expected a handshake error, got {:?}", other.map(|_| ())),
+		}
+		match connect(Some("chat, json")) {
+			Err(WebSocketError::ResponseError(_)) => (),
+			other => panic!("expected a handshake error, got {:?}", other.map(|_| ())),
+		}
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn accepted_extensions_are_exposed() {
+		use super::*;
+		use header::extensions::Parameter;
+		use std::io::Cursor;
+		use stream::sync::ReadWritePair;
+
+		let connect = |accepted: &str| {
+			let response = format!(
+				"HTTP/1.1 101 Switching Protocols\r\n\
+				 Upgrade: websocket\r\n\
+				 Connection: Upgrade\r\n\
+				 Sec-WebSocket-Extensions: {}\r\n\
+				 Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n",
+				accepted
+			);
+			ClientBuilder::new("ws://127.0.0.1:8080")
+				.unwrap()
+				.key(*b"the sample nonce")
+				.add_extension(
+					"permessage-deflate; client_max_window_bits"
+						.parse()
+						.unwrap(),
+				)
+				.add_extension(Extension::new("x-webkit-deflate-frame".to_string()))
+				.connect_on(ReadWritePair(
+					Cursor::new(response.into_bytes()),
+					Cursor::new(Vec::new()),
+				))
+		};
+
+		let client = connect("permessage-deflate; server_no_context_takeover").unwrap();
+		let extensions = client.extensions();
+		assert_eq!(extensions.len(), 1);
+		assert_eq!(extensions[0].name, "permessage-deflate");
+		assert_eq!(
+			extensions[0].params,
+			vec![Parameter::new(
+				"server_no_context_takeover".to_string(),
+				None
+			)]
+		);
+
+		match connect("permessage-deflate, x-unknown") {
+			Err(WebSocketError::ResponseError("Sec-WebSocket-Extensions was not offered")) => (),
+			other => panic!("expected a handshake error, got {:?}", other.map(|_| ())),
+		}
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn servers_with_a_wrong_accept_are_rejected() {
+		use super::*;
+		use sha1::Sha1;
+		use std::io::{BufRead, BufReader as StdBufReader, Write};
+		use std::net::TcpListener;
+		use std::thread;
+
+		// hashes the key without the GUID
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		thread::spawn(move || {
+			for stream in listener.incoming() {
+				let mut reader = StdBufReader::new(stream.unwrap());
+				let (mut line, mut key) = (String::new(), String::new());
+				while line != "\r\n" {
+					line.clear();
+					reader.read_line(&mut line).unwrap();
+					if let Some(value) = line.strip_prefix("Sec-WebSocket-Key:") {
+						key = value.trim().to_string();
+					}
+				}
+				let mut sha1 = Sha1::new();
+				sha1.update(key.as_bytes());
+				let response = format!(
+					"HTTP/1.1 101 Switching Protocols\r\n\
+					 Upgrade: websocket\r\n\
+					 Connection: Upgrade\r\n\
+					 Sec-WebSocket-Accept: {}\r\n\r\n",
+					base64::encode(&sha1.digest().bytes())
+				);
+				reader.get_mut().write_all(response.as_bytes()).unwrap();
+			}
+		});
+		let url = format!("ws://{}", addr);
+
+		match ClientBuilder::new(&url).unwrap().connect_insecure() {
+			Err(WebSocketError::ResponseError("invalid Sec-WebSocket-Accept")) => (),
+			other => panic!("expected an invalid accept, got {:?}", other.map(|_| ())),
+		}
+
+		#[cfg(feature = "async")]
+		{
+			use tokio::runtime::Builder;
+
+			let connect = ClientBuilder::new(&url).unwrap().async_connect_insecure();
+			match Builder::new().build().unwrap().block_on(connect) {
+				Err(WebSocketError::ResponseError("invalid Sec-WebSocket-Accept")) => (),
+				other => panic!("expected an invalid accept, got {:?}", other.map(|_| ())),
+			}
+		}
+	}
+
+	// answers the first `redirects` connections with a redirect to `/chat`,
+	// the next one is accepted if it asks for `/chat`
+	#[cfg(all(test, feature = "sync"))]
+	fn redirecting_server(redirects: usize) -> ::std::net::SocketAddr {
+		use server::upgrade::sync::IntoWs;
+		use std::io::{BufRead, BufReader as StdBufReader, Write};
+		use std::net::TcpListener;
+		use std::thread;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		thread::spawn(move || {
+			for _ in 0..redirects {
+				let (stream, _) = listener.accept().unwrap();
+				let mut reader = StdBufReader::new(stream);
+				let mut line = String::new();
+				while line != "\r\n" {
+					line.clear();
+					reader.read_line(&mut line).unwrap();
+				}
+				let response = "HTTP/1.1 302 Found\r\n\
+				                Location: /chat\r\n\
+				                Content-Length: 0\r\n\r\n";
+				reader.get_mut().write_all(response.as_bytes()).unwrap();
+			}
+			let (stream, _) = listener.accept().unwrap();
+			let upgrade = stream.into_ws().ok().unwrap();
+			assert_eq!(upgrade.uri(), "/chat");
+			let mut client = upgrade.accept().ok().unwrap();
+			client
+				.send_message(&::message::Message::text("moved"))
+				.unwrap();
+		});
+		addr
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn connect_follows_redirects() {
+		use super::*;
+		use message::OwnedMessage;
+
+		let addr = redirecting_server(1);
+		let mut client = ClientBuilder::new(&format!("ws://{}/", addr))
+			.unwrap()
+			.max_redirects(1)
+			.connect_insecure()
+			.unwrap();
+		let message = client.recv_message().unwrap();
+		assert_eq!(message, OwnedMessage::Text("moved".to_string()));
+
+		// without redirects the status code is an error
+		let addr = redirecting_server(1);
+		match ClientBuilder::new(&format!("ws://{}/", addr))
+			.unwrap()
+			.connect_insecure()
+		{
+			Err(WebSocketError::StatusCodeError(StatusCode::Found)) => (),
+			_ => panic!("redirects are not followed by default"),
+		}
+
+		// redirect loops end after `max_redirects`
+		let addr = redirecting_server(3);
+		match ClientBuilder::new(&format!("ws://{}/", addr))
+			.unwrap()
+			.max_redirects(2)
+			.connect_insecure()
+		{
+			Err(WebSocketError::StatusCodeError(StatusCode::Found)) => (),
+			_ => panic!("only two redirects may be followed"),
+		}
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn redirects_keep_the_transport() {
+		use super::*;
+		use std::io::{BufRead, BufReader as StdBufReader, Write};
+		use std::net::TcpListener;
+		use std::thread;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let server = thread::spawn(move || {
+			let (stream, _) = listener.accept().unwrap();
+			let mut reader = StdBufReader::new(stream);
+			let mut line = String::new();
+			while line != "\r\n" {
+				line.clear();
+				reader.read_line(&mut line).unwrap();
+			}
+			let response = format!(
+				"HTTP/1.1 302 Found\r\nLocation: wss://{}/chat\r\nContent-Length: 0\r\n\r\n",
+				addr
+			);
+			reader.get_mut().write_all(response.as_bytes()).unwrap();
+		});
+
+		// a plain TCP connection can not follow the redirect to wss
+		match ClientBuilder::new(&format!("ws://{}/", addr))
+			.unwrap()
+			.max_redirects(1)
+			.connect_insecure()
+		{
+			Err(WebSocketError::ResponseError(_)) => (),
+			other => panic!("expected a response error, got {:?}", other.map(|_| ())),
+		}
+		server.join().unwrap();
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn redirect_targets() {
+		use super::*;
+
+		let mut builder = ClientBuilder::new("ws://user:secret@a.test/chat").unwrap();
+		builder.build_request();
+		builder = builder.cookies(vec![("SID", "1")]).unwrap();
+		builder.redirect("/same-host", None).unwrap();
+		assert!(builder.get_header::<Cookie>().is_some());
+		builder
+			.redirect("ws://a.test:8080/other-port", None)
+			.unwrap();
+		assert!(builder.get_header::<Authorization<Basic>>().is_none());
+		assert!(builder.get_header::<Cookie>().is_none());
+
+		builder = builder.cookies(vec![("SID", "1")]).unwrap();
+		builder.redirect("https://b.test/other#top", None).unwrap();
+		assert_eq!(builder.url.as_str(), "wss://b.test/other");
+		assert!(builder.get_header::<Authorization<Basic>>().is_none());
+		assert!(builder.get_header::<Cookie>().is_none());
+
+		match builder.redirect("ws://b.test/", None) {
+			Err(WebSocketError::ResponseError(_)) => (),
+			_ => panic!("redirects must not downgrade to ws"),
+		}
+		match builder.redirect("ftp://b.test/", None) {
+			Err(WebSocketError::WebSocketUrlError(WSUrlErrorKind::InvalidScheme)) => (),
+			_ => panic!("only websocket and http URLs can be followed"),
+		}
+
+		// a refused redirect leaves the builder untouched
+		match builder.redirect("/plain", Some(false)) {
+			Err(WebSocketError::ResponseError(_)) => (),
+			_ => panic!("a plain connection can not follow a redirect to wss"),
+		}
+		assert_eq!(builder.url.as_str(), "wss://b.test/other");
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn custom_headers_are_sent() {
+		use super::*;
+		use hyper::header::UserAgent;
+		use std::io::Cursor;
+		use stream::sync::ReadWritePair;
+
+		let mut headers = Headers::new();
+		headers.set(UserAgent("rust-websocket-test".to_string()));
+		headers.set_raw("X-Api-Token", vec![b"secret".to_vec()]);
+		headers.set_raw(
+			"sec-websocket-key",
+			vec![b"AAAAAAAAAAAAAAAAAAAAAA==".to_vec()],
+		);
+		headers.set_raw("Upgrade", vec![b"h2c".to_vec()]);
+		headers.set_raw("Connection", vec![b"close".to_vec()]);
+
+		let response = b"HTTP/1.1 101 Switching Protocols\r\n\
+			Upgrade: websocket\r\n\
+			Connection: Upgrade\r\n\
+			Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n";
+		let client = ClientBuilder::new("ws://127.0.0.1:8080")
+			.unwrap()
+			.key(*b"the sample nonce")
+			.custom_headers(&headers)
+			.connect_on(ReadWritePair(
+				Cursor::new(response.to_vec()),
+				Cursor::new(Vec::new()),
+			))
+			.unwrap();
+
+		let request = (client.into_stream().0).1.into_inner();
+		let request = String::from_utf8(request).unwrap();
+		assert!(request.contains("User-Agent: rust-websocket-test\r\n"));
+		assert!(request.contains("X-Api-Token: secret\r\n"));
+		assert!(request.contains("Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n"));
+		assert!(request.contains("Upgrade: websocket\r\n"));
+		assert!(request.contains("Connection: Upgrade\r\n"));
+		assert!(!request.contains("AAAAAAAAAAAAAAAAAAAAAA=="));
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn cookies_are_sent_and_received() {
+		use super::*;
+		use std::io::Cursor;
+		use stream::sync::ReadWritePair;
+
+		let response = b"HTTP/1.1 101 Switching Protocols\r\n\
+			Upgrade: websocket\r\n\
+			Connection: Upgrade\r\n\
+			Set-Cookie: SID=5e2a; Path=/; HttpOnly\r\n\
+			Set-Cookie: seen=1\r\n\
+			Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n";
+		let client = ClientBuilder::new("ws://127.0.0.1:8080")
+			.unwrap()
+			.key(*b"the sample nonce")
+			.cookies(vec![("SID", "31d4d96e407aad42")])
+			.unwrap()
+			.cookies(vec![("lang".to_string(), "en-US".to_string())])
+			.unwrap()
+			.cookies(Vec::<(&str, &str)>::new())
+			.unwrap()
+			.connect_on(ReadWritePair(
+				Cursor::new(response.to_vec()),
+				Cursor::new(Vec::new()),
+			))
+			.unwrap();
+
+		assert_eq!(client.cookies(), ["SID=5e2a; Path=/; HttpOnly", "seen=1"]);
+		let request = (client.into_stream().0).1.into_inner();
+		let request = String::from_utf8(request).unwrap();
+		assert!(request.contains("\r\nCookie: SID=31d4d96e407aad42; lang=en-US\r\n"));
+
+		for &(name, value) in &[
+			("", "empty"),
+			("a=b", "c"),
+			("SID", "1; admin=1"),
+			("SID", "1\r\nX-Injected: 1"),
+			("S\nID", "1"),
+		] {
+			match ClientBuilder::new("ws://127.0.0.1:8080")
+				.unwrap()
+				.cookies(vec![(name, value)])
+			{
+				Err(WebSocketError::RequestError(_)) => (),
+				_ => panic!("{:?}={:?} is not a valid cookie", name, value),
+			}
+		}
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn small_buffers_reassemble_large_frames() {
+		use super::*;
+		use dataframe::{DataFrame, Opcode};
+		use message::OwnedMessage;
+		use std::io::{self, Cursor};
+		use stream::sync::ReadWritePair;
+		use ws::dataframe::DataFrame as DataFrameTrait;
+
+		// hands out at most 7 bytes per read
+		struct Trickle(Cursor<Vec<u8>>);
+		impl Read for Trickle {
+			fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+				let len = buf.len().min(7);
+				self.0.read(&mut buf[..len])
+			}
+		}
+
+		let mut input = b"HTTP/1.1 101 Switching Protocols\r\n\
+			Upgrade: websocket\r\n\
+			Connection: Upgrade\r\n\
+			Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n"
+			.to_vec();
+		let big: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+		DataFrame::new(true, Opcode::Binary, big.clone())
+			.write_to(&mut input, false)
+			.unwrap();
+		for (i, part) in ["fragmented ", "text ", "message"].iter().enumerate() {
+			let opcode = if i == 0 {
+				Opcode::Text
+			} else {
+				Opcode::Continuation
+			};
+			DataFrame::new(i == 2, opcode, part.as_bytes().to_vec())
+				.write_to(&mut input, false)
+				.unwrap();
+		}
+
+		let mut client = ClientBuilder::new("ws://127.0.0.1:8080")
+			.unwrap()
+			.key(*b"the sample nonce")
+			.read_buffer_size(16)
+			.write_buffer_size(64)
+			.connect_on(ReadWritePair(
+				Trickle(Cursor::new(input)),
+				Cursor::new(Vec::new()),
+			))
+			.unwrap();
+		assert_eq!(client.recv_message().unwrap(), OwnedMessage::Binary(big));
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Text("fragmented text message".to_string())
+		);
+	}
+
+	#[test]
+	fn default_ports_and_host_header() {
+		use super::*;
+		let cases = [
+			("ws://example.com/chat", None, 80, "example.com"),
+			("wss://example.com/chat", None, 443, "example.com"),
+			("ws://example.com:80", None, 80, "example.com"),
+			("wss://example.com:443", None, 443, "example.com"),
+			("ws://example.com:8080", None, 8080, "example.com:8080"),
+			("wss://example.com:80", None, 80, "example.com:80"),
+			("ws://[::1]/path", None, 80, "[::1]"),
+			("ws://[::1]:8080/path", None, 8080, "[::1]:8080"),
+		];
+		for &(url, secure, port, host) in cases.iter() {
+			let mut builder = ClientBuilder::new(url).unwrap();
+			assert_eq!(builder.extract_host_port(secure).unwrap().port, port);
+			builder.build_request();
+			assert_eq!(
+				builder.headers.get_raw("Host").unwrap(),
+				&[host.as_bytes().to_vec()][..],
+				"{}",
+				url
+			);
+		}
+
+		// an explicit host wins, custom headers don't
+		let mut headers = Headers::new();
+		headers.set_raw("Host", vec![b"custom.example.com".to_vec()]);
+		let mut builder = ClientBuilder::new("ws://10.0.0.7:8080")
+			.unwrap()
+			.custom_headers(&headers);
+		builder.build_request();
+		assert_eq!(
+			builder.headers.get_raw("Host").unwrap(),
+			&[b"10.0.0.7:8080".to_vec()][..]
+		);
+		let mut builder = builder.host("chat.example.com");
+		builder.build_request();
+		assert_eq!(
+			builder.headers.get_raw("Host").unwrap(),
+			&[b"chat.example.com".to_vec()][..]
+		);
+		let address = builder.extract_host_port(None).unwrap().to_string();
+		assert_eq!(address, "10.0.0.7:8080");
+
+		let builder = ClientBuilder::new("ws://[::1]:8080").unwrap();
+		let address = builder.extract_host_port(None).unwrap();
+		let address: Vec<_> = address.to_socket_addrs().unwrap().collect();
+		assert_eq!(address, ["[::1]:8080".parse().unwrap()]);
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn connect_to_ipv6_literal() {
+		use super::*;
+		use hyper::header::Host;
+		use server::sync::Server;
+		use std::thread;
+
+		let mut server = match Server::bind("[::1]:0") {
+			Ok(server) => server,
+			// no IPv6 loopback
+			Err(_) => return,
+		};
+		let addr = server.local_addr().unwrap();
+		let peer = thread::spawn(move || {
+			let upgrade = server.accept().ok().unwrap();
+			let host = upgrade.request.headers.get::<Host>().unwrap().to_string();
+			upgrade.accept().unwrap();
+			host
+		});
+		ClientBuilder::new(&format!("ws://{}/path", addr))
+			.unwrap()
+			.connect_insecure()
+			.unwrap();
+		assert_eq!(peer.join().unwrap(), format!("[::1]:{}", addr.port()));
+	}
+
+	#[cfg(feature = "async")]
+	#[test]
+	fn async_connect_tries_every_address() {
+		use super::*;
+		use std::net::TcpListener;
+		use tokio::runtime::Builder;
+
+		let live = TcpListener::bind("127.0.0.1:0").unwrap();
+		let dead = TcpListener::bind("127.0.0.1:0")
+			.unwrap()
+			.local_addr()
+			.unwrap();
+		let addresses = vec![dead, live.local_addr().unwrap()];
+
+		let mut runtime = Builder::new().build().unwrap();
+		let stream = runtime.block_on(connect_any(addresses, None)).unwrap();
+		assert_eq!(stream.peer_addr().unwrap(), live.local_addr().unwrap());
+		assert!(runtime.block_on(connect_any(vec![dead], None)).is_err());
+	}
+
+	// a proxy that tunnels a single connection to a websocket echo server,
+	// returns the server and proxy addresses and the CONNECT request it received
+	#[cfg(all(test, feature = "sync"))]
+	fn mock_proxy(
+		status: &'static str,
+	) -> (
+		::std::net::SocketAddr,
+		::std::net::SocketAddr,
+		::std::sync::mpsc::Receiver<String>,
+	) {
+		use server::sync::Server;
+		use std::io::{copy, Read, Write};
+		use std::net::{TcpListener, TcpStream};
+		use std::sync::mpsc::channel;
+		use std::thread;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let server_addr = server.local_addr().unwrap();
+		thread::spawn(move || {
+			if let Ok(upgrade) = server.accept() {
+				let mut client = upgrade.accept().unwrap();
+				let message = client.recv_message().unwrap();
+				client.send_message(&message).unwrap();
+			}
+		});
+
+		let proxy = TcpListener::bind("127.0.0.1:0").unwrap();
+		let proxy_addr = proxy.local_addr().unwrap();
+		let (tx, rx) = channel();
+		thread::spawn(move || {
+			let (mut client, _) = proxy.accept().unwrap();
+			let mut head = Vec::new();
+			let mut byte = [0; 1];
+			while !head.ends_with(b"\r\n\r\n") {
+				client.read_exact(&mut byte).unwrap();
+				head.push(byte[0]);
+			}
+			tx.send(String::from_utf8(head).unwrap()).unwrap();
+			client.write_all(status.as_bytes()).unwrap();
+			if !status.starts_with("HTTP/1.1 200") {
+				return;
+			}
+
+			let upstream = TcpStream::connect(server_addr).unwrap();
+			let (mut client_read, mut upstream_write) =
+				(client.try_clone().unwrap(), upstream.try_clone().unwrap());
+			thread::spawn(move || copy(&mut client_read, &mut upstream_write));
+			let (mut upstream_read, mut client_write) = (upstream, client);
+			let _ = copy(&mut upstream_read, &mut client_write);
+		});
+
+		(server_addr, proxy_addr, rx)
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn connect_through_proxy() {
+		use super::*;
+		use message::OwnedMessage;
+
+		let (server_addr, proxy_addr, requests) =
+			mock_proxy("HTTP/1.1 200 Connection established\r\n\r\n");
+		let mut client = ClientBuilder::new(&format!("ws://{}", server_addr))
+			.unwrap()
+			.proxy(proxy_addr.to_string())
+			.proxy_auth("user".to_string(), Some("secret".to_string()))
+			.connect_insecure()
+			.unwrap();
+
+		let request = requests.recv().unwrap();
+		assert!(request.starts_with(&format!("CONNECT {} HTTP/1.1\r\n", server_addr)));
+		assert!(request.contains(&format!("Host: {}\r\n", server_addr)));
+		assert!(request.contains("Proxy-Authorization: Basic dXNlcjpzZWNyZXQ=\r\n"));
+
+		client
+			.send_message(&OwnedMessage::Text("through the tunnel".to_string()))
+			.unwrap();
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Text("through the tunnel".to_string())
+		);
+	}
+
+	#[cfg(all(feature = "sync", feature = "async"))]
+	#[test]
+	fn async_connect_through_proxy() {
+		use super::*;
+		use message::OwnedMessage;
+		use tokio::runtime::Builder;
+
+		let (server_addr, proxy_addr, _requests) =
+			mock_proxy("HTTP/1.1 200 Connection established\r\n\r\n");
+		let client = ClientBuilder::new(&format!("ws://{}", server_addr))
+			.unwrap()
+			.proxy(proxy_addr.to_string())
+			.async_connect_insecure()
+			.and_then(|(s, _)| s.send(OwnedMessage::Text("hallo".to_string())))
+			.and_then(|s| s.into_future().map_err(|e| e.0))
+			.map(|(m, _)| m);
+
+		let mut runtime = Builder::new().build().unwrap();
+		assert_eq!(
+			runtime.block_on(client).unwrap(),
+			Some(OwnedMessage::Text("hallo".to_string()))
+		);
+	}
+
+	#[cfg(feature = "async")]
+	#[test]
+	fn async_proxy_keeps_the_bytes_after_its_response() {
+		use super::*;
+		use message::OwnedMessage;
+		use std::io::{Read, Write};
+		use std::net::TcpListener;
+		use std::thread;
+		use tokio::runtime::Builder;
+
+		// the handshake response and a message come with the proxy's response
+		let proxy = TcpListener::bind("127.0.0.1:0").unwrap();
+		let proxy_addr = proxy.local_addr().unwrap();
+		thread::spawn(move || {
+			let (mut client, _) = proxy.accept().unwrap();
+			let mut head = Vec::new();
+			let mut byte = [0; 1];
+			while !head.ends_with(b"\r\n\r\n") {
+				client.read_exact(&mut byte).unwrap();
+				head.push(byte[0]);
+			}
+			let mut response = b"HTTP/1.1 200 Connection established\r\n\r\n\
+				HTTP/1.1 101 Switching Protocols\r\n\
+				Upgrade: websocket\r\n\
+				Connection: Upgrade\r\n\
+				Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+				\r\n"
+				.to_vec();
+			response.extend_from_slice(&[0x81, 0x02, b'h', b'i']);
+			client.write_all(&response).unwrap();
+			let _ = client.read_to_end(&mut Vec::new());
+		});
+
+		let client = ClientBuilder::new("ws://ws.example.test")
+			.unwrap()
+			.key(*b"the sample nonce")
+			.proxy(proxy_addr.to_string())
+			.async_connect_insecure()
+			.and_then(|(s, _)| s.into_future().map_err(|e| e.0))
+			.map(|(m, _)| m);
+
+		let mut runtime = Builder::new().build().unwrap();
+		assert_eq!(
+			runtime.block_on(client).unwrap(),
+			Some(OwnedMessage::Text("hi".to_string()))
+		);
+	}
+
+	#[cfg(all(feature = "sync", feature = "async"))]
+	#[test]
+	fn async_connect_on_a_reactor_of_its_own() {
+		use super::*;
+		use futures::Future;
+		use message::OwnedMessage;
+		use server::sync::Server;
+		use std::thread;
+		use tokio::reactor::Reactor;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+		thread::spawn(move || {
+			let mut client = server.accept().ok().unwrap().accept().unwrap();
+			let message = client.recv_message().unwrap();
+			client.send_message(&message).unwrap();
+		});
+
+		// no runtime at all, the future is driven on this thread
+		// and the reactor runs in the background
+		let reactor = Reactor::new().unwrap();
+		let handle = reactor.handle();
+		let _background = reactor.background().unwrap();
+		let client = ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.reactor(&handle)
+			.async_connect_insecure()
+			.and_then(|(s, _)| s.send(OwnedMessage::Text("hallo".to_string())))
+			.and_then(|s| s.into_future().map_err(|e| e.0))
+			.map(|(m, _)| m);
+		assert_eq!(
+			client.wait().unwrap(),
+			Some(OwnedMessage::Text("hallo".to_string()))
+		);
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn proxy_refusing_connect() {
+		use super::*;
+
+		let (server_addr, proxy_addr, _requests) =
+			mock_proxy("HTTP/1.1 407 Proxy Authentication Required\r\n\r\n");
+		let result = ClientBuilder::new(&format!("ws://{}", server_addr))
+			.unwrap()
+			.proxy(proxy_addr.to_string())
+			.connect_insecure();
+		match result {
+			Err(WebSocketError::StatusCodeError(StatusCode::ProxyAuthenticationRequired)) => (),
+			_ => panic!("a refused CONNECT must fail"),
+		}
+
+		// only a 200 means the tunnel is open
+		let (server_addr, proxy_addr, _requests) = mock_proxy("HTTP/1.1 204 No Content\r\n\r\n");
+		let result = ClientBuilder::new(&format!("ws://{}", server_addr))
+			.unwrap()
+			.proxy(proxy_addr.to_string())
+			.connect_insecure();
+		match result {
+			Err(WebSocketError::StatusCodeError(StatusCode::NoContent)) => (),
+			_ => panic!("a CONNECT answered with another 2xx must fail"),
+		}
+	}
+
+	// a TLS server with a certificate for `ws.example.test`, which
+	// accepts handshakes until the test ends
+	#[cfg(all(test, feature = "sync-ssl"))]
+	fn tls_server() -> ::std::net::SocketAddr {
+		use native_tls::{Identity, TlsAcceptor};
+		use server::sync::Server;
+		use std::thread;
+
+		let identity = include_bytes!("fixtures/ws.example.test.p12");
+		let identity = Identity::from_pkcs12(identity, "websocket").unwrap();
+		let acceptor = TlsAcceptor::new(identity).unwrap();
+		let mut server = Server::bind_secure("127.0.0.1:0", acceptor).unwrap();
+		let addr = server.local_addr().unwrap();
+		thread::spawn(move || loop {
+			if let Ok(upgrade) = server.accept() {
+				let _ = upgrade.accept();
+			}
+		});
+		addr
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	#[test]
+	fn connect_with_tls_domain() {
+		use super::*;
+		use native_tls::Certificate;
+
+		let addr = tls_server();
+		let connector = || {
+			let cert = include_bytes!("fixtures/ws.example.test.der");
+			let cert = Certificate::from_der(cert).unwrap();
+			Some(
+				TlsConnector::builder()
+					.add_root_certificate(cert)
+					.build()
+					.unwrap(),
+			)
+		};
+		let builder = ClientBuilder::new(&format!("wss://{}", addr)).unwrap();
+
+		// the certificate is not valid for the IP address
+		assert!(builder.clone().connect_secure(connector()).is_err());
+		assert!(builder
+			.clone()
+			.tls_domain("ws.example.test")
+			.connect_secure(connector())
+			.is_ok());
+		assert!(builder
+			.clone()
+			.tls_domain("other.example.test")
+			.connect_secure(connector())
+			.is_err());
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	#[test]
+	fn connect_accepting_invalid_certs() {
+		use super::*;
+
+		let addr = tls_server();
+		let builder = ClientBuilder::new(&format!("wss://{}", addr)).unwrap();
+
+		assert!(builder.clone().connect_secure(None).is_err());
+		assert!(builder
+			.clone()
+			.danger_accept_invalid_certs(true)
+			.connect_secure(None)
+			.is_ok());
+	}
+
+	// an echo server over TLS which requires a certificate
+	// for `client.example.test` from its clients, built with openssl
+	// as `native-tls` can't ask for client certificates
+	#[cfg(all(
+		test,
+		feature = "sync-ssl",
+		not(any(target_os = "windows", target_vendor = "apple"))
+	))]
+	fn mutual_tls_server() -> ::std::net::SocketAddr {
+		use openssl::pkey::PKey;
+		use openssl::ssl::{HandshakeError, SslAcceptor, SslMethod, SslVerifyMode};
+		use openssl::x509::X509;
+		use server::upgrade::sync::IntoWs;
+		use std::io;
+		use std::net::TcpListener;
+		use std::thread;
+
+		let cert = X509::from_der(include_bytes!("fixtures/ws.example.test.der")).unwrap();
+		let key = PKey::private_key_from_der(include_bytes!("fixtures/ws.example.test.key.der"));
+		let client_cert = X509::from_pem(include_bytes!("fixtures/client.example.test.pem"));
+		let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+		acceptor.set_certificate(&cert).unwrap();
+		acceptor.set_private_key(&key.unwrap()).unwrap();
+		acceptor
+			.cert_store_mut()
+			.add_cert(client_cert.unwrap())
+			.unwrap();
+		acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+		let acceptor = acceptor.build();
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		thread::spawn(move || {
+			for stream in listener.incoming() {
+				let stream = match acceptor.accept(stream.unwrap()) {
+					Ok(stream) => stream,
+					Err(HandshakeError::Failure(mut stream)) => {
+						// read on until the client hangs up, so it gets the alert
+						// instead of a reset connection
+						let _ = io::copy(stream.get_mut(), &mut io::sink());
+						continue;
+					}
+					Err(_) => continue,
+				};
+				let mut client = match stream.into_ws() {
+					Ok(upgrade) => upgrade.accept().unwrap(),
+					Err(_) => continue,
+				};
+				if let Ok(message) = client.recv_message() {
+					let _ = client.send_message(&message);
+				}
+			}
+		});
+		addr
+	}
+
+	#[cfg(all(
+		feature = "sync-ssl",
+		not(any(target_os = "windows", target_vendor = "apple"))
+	))]
+	#[test]
+	fn connect_with_client_certificate() {
+		use super::*;
+		use message::OwnedMessage;
+
+		let addr = mutual_tls_server();
+		let builder = ClientBuilder::new(&format!("wss://{}", addr))
+			.unwrap()
+			.danger_accept_invalid_certs(true);
+		let cert = include_bytes!("fixtures/client.example.test.pem");
+		let key = include_bytes!("fixtures/client.example.test.key.pem");
+		let pkcs12 = include_bytes!("fixtures/client.example.test.p12");
+		let identities = vec![
+			TlsIdentity::from_pem(cert, key),
+			TlsIdentity::from_pkcs12(pkcs12, "websocket"),
+		];
+
+		for identity in identities {
+			let mut client = builder
+				.clone()
+				.identity(identity)
+				.connect_secure(None)
+				.unwrap();
+			let message = OwnedMessage::Text("mutual".to_string());
+			client.send_message(&message).unwrap();
+			assert_eq!(client.recv_message().unwrap(), message);
+		}
+
+		match builder.clone().connect_secure(None) {
+			Err(WebSocketError::TlsSessionError(_)) => {}
+			other => panic!(
+				"expected the certificate to be required, got {:?}",
+				other.err()
+			),
+		}
+		let wrong_key = include_bytes!("fixtures/ws.example.test.p12");
+		let result = builder
+			.clone()
+			.identity(TlsIdentity::from_pkcs12(wrong_key, "wrong password"))
+			.connect_secure(None);
+		assert!(result.is_err());
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn connect_timeout_expires() {
+		use super::*;
+		use std::net::TcpListener;
+		use std::thread;
+
+		// accepts the connection but never answers the handshake
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		thread::spawn(move || {
+			let (_stream, _) = listener.accept().unwrap();
+			thread::sleep(Duration::from_secs(10));
+		});
+
+		let start = Instant::now();
+		let result = ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.connect_timeout(Duration::from_millis(200))
+			.connect_insecure();
+		match result {
+			Err(WebSocketError::Timeout) => (),
+			Err(e) => panic!("expected the handshake to time out, got {:?}", e),
+			Ok(_) => panic!("expected the handshake to time out"),
+		}
+		assert!(start.elapsed() < Duration::from_secs(5));
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn connect_timeout_is_reset() {
+		use super::*;
+		use server::sync::Server;
+		use std::thread;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+		thread::spawn(move || {
+			let _client = server.accept().ok().unwrap().accept().unwrap();
+			thread::sleep(Duration::from_secs(10));
+		});
+
+		let client = ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.connect_timeout(Duration::from_secs(5))
+			.connect_insecure()
+			.unwrap();
+		assert_eq!(client.stream_ref().read_timeout().unwrap(), None);
+		assert_eq!(client.stream_ref().write_timeout().unwrap(), None);
+	}
+
+	// Linux drops the connections beyond a full backlog instead of refusing them
+	#[cfg(all(feature = "sync", target_os = "linux"))]
+	#[test]
+	fn dead_address_family_is_skipped() {
+		use super::*;
+		use socket2::{Domain, Socket, Type};
+		use std::net::{SocketAddr, TcpListener};
+
+		// a full backlog leaves further connections hanging, like a dead route
+		let listen = |socket: Socket| {
+			let address: SocketAddr = "[::1]:0".parse().unwrap();
+			socket.bind(&address.into())?;
+			socket.listen(0)?;
+			Ok(TcpListener::from(socket))
+		};
+		let dead = match Socket::new(Domain::IPV6, Type::STREAM, None).and_then(listen) {
+			Ok(dead) => dead,
+			// no IPv6 loopback
+			Err(_) => return,
+		};
+		let dead_addr = dead.local_addr().unwrap();
+		let _queued = TcpStream::connect(dead_addr).unwrap();
+		let live = TcpListener::bind("127.0.0.1:0").unwrap();
+		let live_addr = live.local_addr().unwrap();
+
+		let start = Instant::now();
+		let stream = connect_tcp(
+			&[dead_addr, live_addr][..],
+			Some(start + Duration::from_secs(10)),
+			Duration::from_millis(50),
+		)
+		.unwrap();
+		assert_eq!(stream.peer_addr().unwrap(), live_addr);
+		assert!(start.elapsed() < Duration::from_secs(1));
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn connect_with_ping_interval() {
+		use super::*;
+		use message::OwnedMessage;
+		use server::sync::Server;
+		use std::thread;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+		thread::spawn(move || {
+			let mut peer = server.accept().ok().unwrap().accept().unwrap();
+			match peer.recv_message().unwrap() {
+				OwnedMessage::Ping(token) => peer.send_message(&OwnedMessage::Pong(token)),
+				message => panic!("expected a ping, got {:?}", message),
+			}
+			.unwrap();
+		});
+
+		let mut client = ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.ping_interval(Some(Duration::from_millis(20)))
+			.connect_insecure()
+			.unwrap();
+		match client.recv_message().unwrap() {
+			OwnedMessage::Pong(_) => (),
+			message => panic!("expected the pong, got {:?}", message),
+		}
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn connections_are_nodelay() {
+		use super::*;
+		use server::sync::Server;
+		use std::sync::mpsc::channel;
+		use std::thread;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+		let (tx, rx) = channel();
+		thread::spawn(move || {
+			for _ in 0..2 {
+				let upgrade = server.accept().ok().unwrap();
+				tx.send(upgrade.stream.nodelay().unwrap()).unwrap();
+				let _client = upgrade.accept().unwrap();
+			}
+		});
+
+		let client = ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.connect_insecure()
+			.unwrap();
+		assert!(client.stream_ref().nodelay().unwrap());
+		assert!(rx.recv().unwrap());
+
+		let client = ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.nodelay(false)
+			.keepalive(Some(Duration::from_secs(30)))
+			.connect_insecure()
+			.unwrap();
+		assert!(!client.stream_ref().nodelay().unwrap());
+		let socket = ::socket2::SockRef::from(client.stream_ref());
+		assert!(socket.keepalive().unwrap());
+		#[cfg(not(windows))]
+		assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(30));
+		assert!(rx.recv().unwrap());
 	}
 }
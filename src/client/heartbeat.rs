@@ -0,0 +1,166 @@
+//! Pluggable keepalive strategies for the synchronous client.
+//!
+//! A `Heartbeat` is driven while the client waits for the next message or data
+//! frame: it is told about everything that arrives and ticks when it asked to,
+//! so it can send pings, application level heartbeat messages or nothing at all.
+//! `Client::set_ping_interval` installs the default `PingPongHeartbeat`.
+//!
+//! ```rust,no_run
+//! # use std::time::Duration;
+//! use websocket::client::heartbeat::{Action, Heartbeat, HeartbeatSender};
+//! use websocket::{ClientBuilder, OwnedMessage};
+//!
+//! // says hello every 30 seconds, the server doesn't answer pings
+//! struct Hello;
+//!
+//! impl Heartbeat for Hello {
+//!     fn on_start(&mut self) -> Action {
+//!         Action::TickAfter(Duration::from_secs(30))
+//!     }
+//!
+//!     fn on_tick(&mut self, sender: &mut HeartbeatSender) -> Action {
+//!         sender.send_message(&OwnedMessage::Text("hello".to_string()));
+//!         Action::TickAfter(Duration::from_secs(30))
+//!     }
+//!
+//!     fn on_message(&mut self, _message: &OwnedMessage) -> Action {
+//!         Action::Continue
+//!     }
+//! }
+//!
+//! let mut client = ClientBuilder::new("ws://127.0.0.1:1234")
+//!     .unwrap()
+//!     .connect_insecure()
+//!     .unwrap();
+//! client.set_heartbeat(Some(Box::new(Hello)));
+//! ```
+
+use std::io::Write;
+use std::time::Duration;
+
+use dataframe::DataFrame;
+use message::OwnedMessage;
+use result::WebSocketError;
+use sender::Sender;
+use ws;
+use ws::sender::Sender as SenderTrait;
+
+/// What the client does after a `Heartbeat` was called.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+	/// Keep the tick scheduled so far, after `on_tick` this means no more
+	/// ticks until another call schedules one.
+	Continue,
+	/// Call `on_tick` after this long, replacing the tick scheduled so far.
+	TickAfter(Duration),
+	/// Give up on the connection: a close message is sent, the connection is
+	/// shut down and receiving fails with `WebSocketError::Timeout`.
+	Disconnect,
+}
+
+/// A keepalive strategy, see the module documentation.
+///
+/// The heartbeat only runs while `recv_message` or `recv_dataframe` wait,
+/// with the read timeout of the stream and not in nonblocking mode.
+pub trait Heartbeat: Send {
+	/// Called when the heartbeat is installed, by default nothing is scheduled.
+	fn on_start(&mut self) -> Action {
+		Action::Continue
+	}
+
+	/// Called when the scheduled tick is due, messages sent through `sender`
+	/// go out right away.
+	fn on_tick(&mut self, sender: &mut HeartbeatSender) -> Action;
+
+	/// Called with every message received by `recv_message`, pings answered
+	/// automatically included.
+	fn on_message(&mut self, message: &OwnedMessage) -> Action;
+
+	/// Called with every data frame received by `recv_dataframe`,
+	/// by default this does nothing.
+	fn on_frame(&mut self, _frame: &DataFrame) -> Action {
+		Action::Continue
+	}
+}
+
+/// Sends messages for a `Heartbeat` on the connection it runs on.
+pub struct HeartbeatSender<'a> {
+	sender: &'a mut Sender,
+	writer: &'a mut dyn Write,
+	error: Option<WebSocketError>,
+}
+
+impl<'a> HeartbeatSender<'a> {
+	pub(crate) fn new(sender: &'a mut Sender, writer: &'a mut dyn Write) -> Self {
+		HeartbeatSender {
+			sender,
+			writer,
+			error: None,
+		}
+	}
+
+	/// Sends a message. If this fails the receiving method which drove the
+	/// heartbeat fails with the error once the tick is over and the messages
+	/// sent after it are dropped.
+	pub fn send_message<M>(&mut self, message: &M)
+	where
+		M: ws::Message,
+	{
+		if self.error.is_none() {
+			self.error = self.sender.send_message(&mut self.writer, message).err();
+		}
+	}
+
+	pub(crate) fn into_error(self) -> Option<WebSocketError> {
+		self.error
+	}
+}
+
+/// Sends a ping whenever nothing was received for `interval` and gives up on
+/// the connection if nothing arrives within `pong_timeout` after a ping.
+/// Anything received counts as an answer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PingPongHeartbeat {
+	interval: Duration,
+	pong_timeout: Duration,
+	ping_sent: bool,
+}
+
+impl PingPongHeartbeat {
+	/// Ping after `interval` and wait `pong_timeout` for the answer.
+	pub fn new(interval: Duration, pong_timeout: Duration) -> Self {
+		PingPongHeartbeat {
+			interval,
+			pong_timeout,
+			ping_sent: false,
+		}
+	}
+
+	fn seen(&mut self) -> Action {
+		self.ping_sent = false;
+		Action::TickAfter(self.interval)
+	}
+}
+
+impl Heartbeat for PingPongHeartbeat {
+	fn on_start(&mut self) -> Action {
+		self.seen()
+	}
+
+	fn on_tick(&mut self, sender: &mut HeartbeatSender) -> Action {
+		if self.ping_sent {
+			return Action::Disconnect;
+		}
+		sender.send_message(&OwnedMessage::Ping(Vec::new()));
+		self.ping_sent = true;
+		Action::TickAfter(self.pong_timeout)
+	}
+
+	fn on_message(&mut self, _message: &OwnedMessage) -> Action {
+		self.seen()
+	}
+
+	fn on_frame(&mut self, _frame: &DataFrame) -> Action {
+		self.seen()
+	}
+}
@@ -2,7 +2,8 @@
 //!
 //! This crate is split up into a synchronous and asynchronous half.
 //! These can be turned on and off by switching the `sync` and `async` features
-//! on and off (plus `sync-ssl` and `async-ssl` for SSL connections).
+//! on and off (plus `sync-ssl` and `async-ssl` for SSL connections, or `sync-rustls`
+//! to use `rustls` for synchronous SSL connections instead).
 //!
 //! In general pick a style you would like to write in and use `ClientBuilder`
 //! to create your websocket connections. Use the `.async_connect` functions to create
@@ -21,3 +22,9 @@ pub mod async;
 
 #[cfg(feature = "sync")]
 pub mod sync;
+
+#[cfg(feature = "sync")]
+pub mod reconnect;
+
+#[cfg(feature = "sync")]
+pub mod heartbeat;
@@ -0,0 +1,368 @@
+//! A synchronous client which reconnects when its connection is lost.
+//!
+//! ```rust,no_run
+//! # use std::time::Duration;
+//! use websocket::client::reconnect::{Backoff, ReconnectingClient};
+//! use websocket::{ClientBuilder, OwnedMessage};
+//!
+//! let builder = ClientBuilder::new("ws://127.0.0.1:1234").unwrap().add_protocol("chat");
+//! let mut client = ReconnectingClient::insecure(builder)
+//!     .unwrap()
+//!     .backoff(Backoff::new(Duration::from_millis(100), Duration::from_secs(30)))
+//!     .on_disconnect(|error| println!("Connection lost: {}", error))
+//!     .on_reconnect(|client| {
+//!         // the server forgot about us, subscribe again
+//!         client.send_message(&OwnedMessage::Text("subscribe".to_string()))
+//!     });
+//!
+//! loop {
+//!     let message = client.recv_message().unwrap();
+//!     println!("Recv: {:?}", message);
+//! }
+//! ```
+
+use rand::{self, Rng};
+use std::cmp;
+use std::io;
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use super::builder::ClientBuilder;
+use super::sync::Client;
+use message::OwnedMessage;
+use result::{WebSocketError, WebSocketResult};
+use stream::sync::Stream;
+use ws;
+
+/// How long to wait before each attempt to reconnect.
+///
+/// The first attempt waits `base`, every further attempt waits twice as long
+/// as the one before, up to `max`. With some jitter each delay is shortened by
+/// a random fraction, so clients which lost their connection at the same time
+/// don't all come back at once.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Backoff {
+	base: Duration,
+	max: Duration,
+	jitter: f64,
+	max_attempts: Option<u32>,
+}
+
+impl Backoff {
+	/// Create a backoff starting at `base` and growing up to `max`,
+	/// without jitter and retrying forever.
+	pub fn new(base: Duration, max: Duration) -> Self {
+		Backoff {
+			base,
+			max,
+			jitter: 0.0,
+			max_attempts: None,
+		}
+	}
+
+	/// Shorten every delay by a random fraction of at most `jitter`,
+	/// which is clamped between 0 and 1.
+	pub fn jitter(mut self, jitter: f64) -> Self {
+		self.jitter = jitter.clamp(0.0, 1.0);
+		self
+	}
+
+	/// Give up after `attempts` failed attempts in a row, `None` retries forever.
+	pub fn max_attempts(mut self, attempts: Option<u32>) -> Self {
+		self.max_attempts = attempts;
+		self
+	}
+
+	/// The delay before the given attempt, starting at 0.
+	pub fn delay(&self, attempt: u32) -> Duration {
+		let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+		let delay = self
+			.base
+			.checked_mul(factor)
+			.map_or(self.max, |delay| cmp::min(delay, self.max));
+		if self.jitter == 0.0 {
+			return delay;
+		}
+		let jitter: f64 = rand::thread_rng().gen();
+		delay.mul_f64(1.0 - jitter * self.jitter)
+	}
+}
+
+impl Default for Backoff {
+	/// Starts at 500 milliseconds and grows up to 30 seconds, with a jitter of 0.5.
+	fn default() -> Self {
+		Backoff::new(Duration::from_millis(500), Duration::from_secs(30)).jitter(0.5)
+	}
+}
+
+/// A client which reconnects with the `ClientBuilder` it was created from when
+/// its connection is lost, so the URL, headers and protocols are the same
+/// for every connection.
+///
+/// Reading a message fails over to a new connection when the connection is closed
+/// or an I/O error (other than a timeout) occurs, a message which fails to be
+/// sent is sent again on the new connection. Other errors, e.g. a protocol
+/// error, are returned as they are.
+///
+/// Messages sent to the old connection which the server didn't receive are lost,
+/// use `on_reconnect` to bring the new connection up to date.
+pub struct ReconnectingClient<S>
+where
+	S: Stream,
+{
+	client: Client<S>,
+	builder: ClientBuilder<'static>,
+	connect: Connect<S>,
+	backoff: Backoff,
+	on_disconnect: Option<OnDisconnect>,
+	on_reconnect: Option<OnReconnect<S>>,
+}
+
+type Connect<S> = Box<dyn FnMut(&mut ClientBuilder<'static>) -> WebSocketResult<Client<S>>>;
+type OnDisconnect = Box<dyn FnMut(&WebSocketError)>;
+type OnReconnect<S> = Box<dyn FnMut(&mut Client<S>) -> WebSocketResult<()>>;
+
+impl ReconnectingClient<TcpStream> {
+	/// Connect with `ClientBuilder::connect_insecure`.
+	pub fn insecure(builder: ClientBuilder) -> WebSocketResult<Self> {
+		ReconnectingClient::new(builder, |builder| builder.connect_insecure())
+	}
+}
+
+impl<S> ReconnectingClient<S>
+where
+	S: Stream,
+{
+	/// Connect right away with `connect`, which is called with the builder
+	/// again for every reconnection, e.g.
+	/// `|builder| builder.connect(None)` for plain or secure connections.
+	pub fn new<F>(builder: ClientBuilder, mut connect: F) -> WebSocketResult<Self>
+	where
+		F: FnMut(&mut ClientBuilder<'static>) -> WebSocketResult<Client<S>> + 'static,
+	{
+		let mut builder = builder.into_owned();
+		let client = connect(&mut builder)?;
+		Ok(ReconnectingClient {
+			client,
+			builder,
+			connect: Box::new(connect),
+			backoff: Backoff::default(),
+			on_disconnect: None,
+			on_reconnect: None,
+		})
+	}
+
+	/// Set the delays between attempts to reconnect, see `Backoff::default`.
+	pub fn backoff(mut self, backoff: Backoff) -> Self {
+		self.backoff = backoff;
+		self
+	}
+
+	/// Call `callback` with the error which made the connection fail,
+	/// before reconnecting.
+	pub fn on_disconnect<F>(mut self, callback: F) -> Self
+	where
+		F: FnMut(&WebSocketError) + 'static,
+	{
+		self.on_disconnect = Some(Box::new(callback));
+		self
+	}
+
+	/// Call `callback` with every new connection before it is used, e.g. to
+	/// send again what the server needs to know. An error makes the connection
+	/// fail like any other and another connection is tried.
+	pub fn on_reconnect<F>(mut self, callback: F) -> Self
+	where
+		F: FnMut(&mut Client<S>) -> WebSocketResult<()> + 'static,
+	{
+		self.on_reconnect = Some(Box::new(callback));
+		self
+	}
+
+	/// The current connection.
+	pub fn client(&mut self) -> &mut Client<S> {
+		&mut self.client
+	}
+
+	/// The builder used to reconnect.
+	pub fn builder(&self) -> &ClientBuilder<'static> {
+		&self.builder
+	}
+
+	/// Reads the next message, reconnecting if the connection was lost.
+	pub fn recv_message(&mut self) -> WebSocketResult<OwnedMessage> {
+		loop {
+			match self.client.recv_message() {
+				Err(e) => {
+					if !is_disconnect(&e) {
+						return Err(e);
+					}
+					self.reconnect(Some(e))?;
+				}
+				Ok(message) => return Ok(message),
+			}
+		}
+	}
+
+	/// Sends a message, sending it again on a new connection if the
+	/// connection was lost.
+	pub fn send_message<M>(&mut self, message: &M) -> WebSocketResult<()>
+	where
+		M: ws::Message,
+	{
+		loop {
+			match self.client.send_message(message) {
+				Err(e) => {
+					if !is_disconnect(&e) {
+						return Err(e);
+					}
+					self.reconnect(Some(e))?;
+				}
+				Ok(()) => return Ok(()),
+			}
+		}
+	}
+
+	/// Drops the current connection and connects again, the error is passed on to
+	/// the `on_disconnect` callback.
+	///
+	/// This fails with the error of the last attempt if `Backoff::max_attempts`
+	/// attempts failed in a row.
+	pub fn reconnect(&mut self, error: Option<WebSocketError>) -> WebSocketResult<()> {
+		if let (Some(callback), Some(error)) = (self.on_disconnect.as_mut(), error.as_ref()) {
+			callback(error);
+		}
+
+		let mut attempt = 0;
+		loop {
+			thread::sleep(self.backoff.delay(attempt));
+			let mut result = (self.connect)(&mut self.builder);
+			if let (Ok(client), Some(callback)) = (result.as_mut(), self.on_reconnect.as_mut()) {
+				if let Err(e) = callback(client) {
+					result = Err(e);
+				}
+			}
+			match result {
+				Ok(client) => {
+					self.client = client;
+					return Ok(());
+				}
+				Err(e) => {
+					attempt += 1;
+					if self.backoff.max_attempts.is_some_and(|max| attempt >= max) {
+						return Err(e);
+					}
+				}
+			}
+		}
+	}
+}
+
+// whether the connection is gone, timeouts leave it usable
+fn is_disconnect(error: &WebSocketError) -> bool {
+	match *error {
+		WebSocketError::NoDataAvailable | WebSocketError::IncompleteMessage => true,
+		WebSocketError::IoError(ref e) => !matches!(
+			e.kind(),
+			io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted
+		),
+		_ => false,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use header::Headers;
+	use server::sync::Server;
+	use std::cell::RefCell;
+	use std::rc::Rc;
+	use std::sync::mpsc::channel;
+
+	#[test]
+	fn backoff_doubles_up_to_max() {
+		let backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(100));
+		let delays: Vec<_> = (0..6).map(|attempt| backoff.delay(attempt)).collect();
+		let millis = |ms: &[u64]| ms.iter().map(|&ms| Duration::from_millis(ms)).collect();
+		let expected: Vec<_> = millis(&[10, 20, 40, 80, 100, 100]);
+		assert_eq!(delays, expected);
+		assert_eq!(backoff.delay(100), Duration::from_millis(100));
+
+		let backoff = backoff.jitter(0.5);
+		for _ in 0..100 {
+			let delay = backoff.delay(1);
+			assert!(delay >= Duration::from_millis(10) && delay <= Duration::from_millis(20));
+		}
+	}
+
+	#[test]
+	fn reconnects_after_the_connection_drops() {
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+		let (tx, rx) = channel();
+		let peer = thread::spawn(move || {
+			for round in 0..2 {
+				let upgrade = server.accept().ok().unwrap();
+				let token = upgrade.request.headers.get_raw("X-Token").unwrap()[0].clone();
+				tx.send((upgrade.protocols().to_vec(), token)).unwrap();
+				let mut client = upgrade.use_protocol("chat").accept().unwrap();
+				if round == 0 {
+					// drop the connection without a close handshake
+					client.recv_message().unwrap();
+					continue;
+				}
+				assert_eq!(
+					client.recv_message().unwrap(),
+					OwnedMessage::Text("hello again".to_string())
+				);
+				client
+					.send_message(&OwnedMessage::Text("welcome back".to_string()))
+					.unwrap();
+				let message = client.recv_message().unwrap();
+				client.send_message(&message).unwrap();
+			}
+		});
+
+		let mut headers = Headers::new();
+		headers.set_raw("X-Token", vec![b"secret".to_vec()]);
+		let builder = ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.add_protocol("chat")
+			.custom_headers(&headers);
+		let events = Rc::new(RefCell::new(Vec::new()));
+		let (disconnects, reconnects) = (events.clone(), events.clone());
+		let mut client = ReconnectingClient::insecure(builder)
+			.unwrap()
+			.backoff(
+				Backoff::new(Duration::from_millis(10), Duration::from_millis(100))
+					.max_attempts(Some(5)),
+			)
+			.on_disconnect(move |_| disconnects.borrow_mut().push("disconnect"))
+			.on_reconnect(move |client| {
+				reconnects.borrow_mut().push("reconnect");
+				client.send_message(&OwnedMessage::Text("hello again".to_string()))
+			});
+
+		client
+			.send_message(&OwnedMessage::Text("one".to_string()))
+			.unwrap();
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Text("welcome back".to_string())
+		);
+		assert_eq!(*events.borrow(), vec!["disconnect", "reconnect"]);
+		assert_eq!(client.client().protocol(), Some("chat"));
+
+		let message = OwnedMessage::Text("two".to_string());
+		client.send_message(&message).unwrap();
+		assert_eq!(client.recv_message().unwrap(), message);
+		peer.join().unwrap();
+
+		for _ in 0..2 {
+			let (protocols, token) = rx.recv().unwrap();
+			assert_eq!(protocols, vec!["chat".to_string()]);
+			assert_eq!(token, b"secret".to_vec());
+		}
+	}
+}
@@ -1,26 +1,38 @@
 //! Contains the WebSocket client.
 use hyper::buffer::BufReader;
-use hyper::header::Headers;
+use hyper::header::{Headers, SetCookie};
+use std::cell::Cell;
+use std::cmp;
 use std::io::Result as IoResult;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use std::net::SocketAddr;
 use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use dataframe::DataFrame;
+use super::heartbeat::{Action, Heartbeat, HeartbeatSender, PingPongHeartbeat};
+use dataframe::{DataFrame, DataFrameBuilder, Opcode};
+#[cfg(feature = "deflate")]
+use deflate::DeflateConfig;
 use header::extensions::Extension;
 use header::{WebSocketExtensions, WebSocketProtocol};
-use message::OwnedMessage;
-use result::WebSocketResult;
-use stream::sync::{AsTcpStream, Shutdown, Splittable, Stream};
+use message::{CloseCode, CloseData, Message, OwnedMessage};
+use metrics::Metrics;
+use observer::FrameObserver;
+use ratelimit::RateLimit;
+use result::{WebSocketError, WebSocketResult};
+use server::ConnectionSlot;
+use stream::sync::{AsTcpStream, Shutdown, SocketAddrs, Splittable, Stream};
 use ws;
 use ws::receiver::Receiver as ReceiverTrait;
 use ws::receiver::{DataFrameIterator, MessageIterator};
 use ws::sender::Sender as SenderTrait;
+use ws::util::mask::MaskGenerator;
 
-pub use receiver::Reader;
 use receiver::Receiver;
+pub use receiver::{MessageReader, Reader};
 use sender::Sender;
-pub use sender::Writer;
+pub use sender::{MessageWriter, Writer};
 use ws::dataframe::DataFrame as DataFrameable;
 
 /// Represents a WebSocket client, which can send and receive messages/data frames.
@@ -60,6 +72,55 @@ where
 	headers: Headers,
 	sender: Sender,
 	receiver: Receiver,
+	auto_pong: bool,
+	auto_close: bool,
+	close_on_error: bool,
+	nonblocking: Cell<bool>,
+	close_timeout: Duration,
+	read_timeout: Cell<Option<Duration>>,
+	keepalive: Option<Keepalive<S>>,
+	pong_timeout: Duration,
+}
+
+// how many seconds to wait for the answer to a close message
+const DEFAULT_CLOSE_TIMEOUT: u64 = 5;
+
+// how many seconds to wait for the answer to a keepalive ping
+const DEFAULT_PONG_TIMEOUT: u64 = 10;
+
+// the state of the heartbeat, see `Client::set_heartbeat`
+struct Keepalive<S> {
+	heartbeat: Box<dyn Heartbeat>,
+	due: Option<Instant>,
+	// the interval of the pings sent by `set_ping_interval`
+	ping_interval: Option<Duration>,
+	tcp: fn(&S) -> &TcpStream,
+}
+
+impl<S> Keepalive<S> {
+	fn new(mut heartbeat: Box<dyn Heartbeat>, tcp: fn(&S) -> &TcpStream) -> Self {
+		let action = heartbeat.on_start();
+		let mut keepalive = Keepalive {
+			heartbeat,
+			due: None,
+			ping_interval: None,
+			tcp,
+		};
+		keepalive.apply(action, Instant::now());
+		keepalive
+	}
+
+	// returns whether the connection is to be given up on
+	fn apply(&mut self, action: Action, now: Instant) -> bool {
+		match action {
+			Action::Continue => false,
+			Action::TickAfter(after) => {
+				self.due = Some(now + after);
+				false
+			}
+			Action::Disconnect => true,
+		}
+	}
 }
 
 impl Client<TcpStream> {
@@ -78,24 +139,33 @@ impl Client<TcpStream> {
 
 impl<S> Client<S>
 where
-	S: AsTcpStream + Stream,
+	S: SocketAddrs + Stream,
 {
-	/// Shuts down the client connection, will cause all pending and future IO to
-	/// return immediately with an appropriate value.
-	pub fn shutdown(&self) -> IoResult<()> {
-		self.stream.get_ref().as_tcp().shutdown(Shutdown::Both)
-	}
-
 	/// See [`TcpStream::peer_addr`]
 	/// (https://doc.rust-lang.org/std/net/struct.TcpStream.html#method.peer_addr).
+	///
+	/// This is available for every stream backed by a `TcpStream`, TLS streams
+	/// included. Unix sockets and in-memory streams fail with an error of kind
+	/// `Unsupported`, see `SocketAddrs`.
 	pub fn peer_addr(&self) -> IoResult<SocketAddr> {
-		self.stream.get_ref().as_tcp().peer_addr()
+		self.stream.get_ref().peer_addr()
 	}
 
 	/// See [`TcpStream::local_addr`]
 	/// (https://doc.rust-lang.org/std/net/struct.TcpStream.html#method.local_addr).
 	pub fn local_addr(&self) -> IoResult<SocketAddr> {
-		self.stream.get_ref().as_tcp().local_addr()
+		self.stream.get_ref().local_addr()
+	}
+}
+
+impl<S> Client<S>
+where
+	S: AsTcpStream + Stream,
+{
+	/// Shuts down the client connection, will cause all pending and future IO to
+	/// return immediately with an appropriate value.
+	pub fn shutdown(&self) -> IoResult<()> {
+		self.stream.get_ref().as_tcp().shutdown(Shutdown::Both)
 	}
 
 	/// See [`TcpStream::set_nodelay`]
@@ -105,8 +175,235 @@ where
 	}
 
 	/// Changes whether the stream is in nonblocking mode.
+	///
+	/// In nonblocking mode the receiving methods fail with an `IoError` of kind
+	/// `WouldBlock` when no complete data frame is available yet. This error can
+	/// be retried once the stream is readable again, partially received frames
+	/// and messages are kept until the rest arrives.
+	///
+	/// Sending is not resumable, if a `WouldBlock` error occurs while sending
+	/// the data frame may have been partially written.
 	pub fn set_nonblocking(&self, nonblocking: bool) -> IoResult<()> {
-		self.stream.get_ref().as_tcp().set_nonblocking(nonblocking)
+		self.stream
+			.get_ref()
+			.as_tcp()
+			.set_nonblocking(nonblocking)?;
+		self.nonblocking.set(nonblocking);
+		Ok(())
+	}
+
+	/// Performs the closing handshake without a status code, see `close_with`.
+	pub fn close(&mut self) -> WebSocketResult<()> {
+		self.close_handshake(OwnedMessage::Close(None))
+	}
+
+	/// Performs the closing handshake, this sends a close message with `data`,
+	/// waits for the close message of the remote endpoint and then shuts down
+	/// the connection. Other messages received in the meantime are discarded.
+	///
+	/// If the remote endpoint does not answer within the close timeout
+	/// (see `set_close_timeout`) the connection is shut down anyway and
+	/// `WebSocketError::Timeout` is returned.
+	///
+	/// If a close message was sent already, e.g. by `set_auto_close`, the
+	/// connection is only shut down.
+	///
+	/// ```rust,no_run
+	/// use websocket::message::CloseCode;
+	/// use websocket::{ClientBuilder, CloseData};
+	/// let mut client = ClientBuilder::new("ws://localhost:3000")
+	///     .unwrap()
+	///     .connect_insecure()
+	///     .unwrap();
+	///
+	/// let goodbye = CloseData::new(CloseCode::NormalClosure, "Goodbye").unwrap();
+	/// client.close_with(goodbye).unwrap();
+	/// ```
+	pub fn close_with(&mut self, data: CloseData) -> WebSocketResult<()> {
+		self.close_handshake(OwnedMessage::Close(Some(data)))
+	}
+
+	/// How long `close` and `close_with` wait for the remote endpoint
+	/// to answer the close message, by default this is 5 seconds.
+	pub fn set_close_timeout(&mut self, timeout: Duration) {
+		self.close_timeout = timeout;
+	}
+
+	/// Send data messages with payloads larger than `size` bytes as several
+	/// frames, see `Sender::set_fragment_size`. By default messages are not fragmented.
+	///
+	/// ```rust,no_run
+	/// use websocket::{ClientBuilder, Message};
+	/// let mut client = ClientBuilder::new("ws://localhost:3000")
+	///     .unwrap()
+	///     .connect_insecure()
+	///     .unwrap();
+	///
+	/// client.set_fragment_size(Some(64 * 1024));
+	/// client.send_message(&Message::binary(vec![0; 1024 * 1024])).unwrap();
+	/// ```
+	pub fn set_fragment_size(&mut self, size: Option<usize>) {
+		self.sender.set_fragment_size(size);
+	}
+
+	/// Keep an idle connection alive by sending a ping whenever nothing was
+	/// received for `interval`, `None` (the default) disables the pings.
+	/// This installs a `PingPongHeartbeat`, see `set_heartbeat`.
+	///
+	/// If the remote endpoint doesn't answer within the pong timeout (see
+	/// `set_pong_timeout`) the connection is considered dead: a close message is sent,
+	/// the connection is shut down and `WebSocketError::Timeout` is returned.
+	///
+	/// The pings are sent while `recv_message` or `recv_dataframe` wait for the
+	/// next message, which is done with the read timeout of the stream, and
+	/// not in nonblocking mode. The pongs are returned like any other message.
+	///
+	/// ```rust,no_run
+	/// use std::time::Duration;
+	/// use websocket::ClientBuilder;
+	/// let mut client = ClientBuilder::new("ws://localhost:3000")
+	///     .unwrap()
+	///     .connect_insecure()
+	///     .unwrap();
+	///
+	/// client.set_ping_interval(Some(Duration::from_secs(30)));
+	/// loop {
+	///     let message = client.recv_message().unwrap();
+	///     println!("Recv: {:?}", message);
+	/// }
+	/// ```
+	pub fn set_ping_interval(&mut self, interval: Option<Duration>) {
+		let pong_timeout = self.pong_timeout;
+		self.set_heartbeat(interval.map(|interval| {
+			Box::new(PingPongHeartbeat::new(interval, pong_timeout)) as Box<dyn Heartbeat>
+		}));
+		if let Some(ref mut keepalive) = self.keepalive {
+			keepalive.ping_interval = interval;
+		}
+	}
+
+	/// How long to wait for the answer to a keepalive ping before the connection
+	/// is closed, see `set_ping_interval`. By default this is 10 seconds.
+	pub fn set_pong_timeout(&mut self, timeout: Duration) {
+		self.pong_timeout = timeout;
+		let interval = self.keepalive.as_ref().and_then(|k| k.ping_interval);
+		if interval.is_some() {
+			self.set_ping_interval(interval);
+		}
+	}
+
+	/// Drive `heartbeat` while waiting for messages, see the `heartbeat` module.
+	/// `None` (the default) removes the heartbeat, like `set_ping_interval(None)`.
+	///
+	/// The heartbeat's `on_start` is called right away, it ticks while
+	/// `recv_message` or `recv_dataframe` wait. Once it returns `Action::Disconnect`
+	/// a close message is sent, the connection is shut down and
+	/// `WebSocketError::Timeout` is returned.
+	pub fn set_heartbeat(&mut self, heartbeat: Option<Box<dyn Heartbeat>>) {
+		self.keepalive = heartbeat.map(|heartbeat| Keepalive::new(heartbeat, S::as_tcp));
+	}
+
+	fn close_handshake(&mut self, close: OwnedMessage) -> WebSocketResult<()> {
+		let result = if self.sender.close_sent() {
+			Ok(())
+		} else {
+			self.send_message(&close).and_then(|()| self.await_close())
+		};
+		let shutdown = self.shutdown();
+		result?;
+		match shutdown {
+			// the remote endpoint may have closed the connection already
+			Err(ref e) if e.kind() == io::ErrorKind::NotConnected => Ok(()),
+			shutdown => Ok(shutdown?),
+		}
+	}
+
+	fn await_close(&mut self) -> WebSocketResult<()> {
+		self.set_nonblocking(false)?;
+		let deadline = Instant::now() + self.close_timeout;
+		loop {
+			let now = Instant::now();
+			if now >= deadline {
+				return Err(WebSocketError::Timeout);
+			}
+			self.set_read_timeout(Some(deadline - now))?;
+			if self.recv_dataframe()?.opcode == Opcode::Close {
+				return Ok(());
+			}
+		}
+	}
+
+	/// See [`TcpStream::set_read_timeout`]
+	/// (https://doc.rust-lang.org/std/net/struct.TcpStream.html#method.set_read_timeout).
+	///
+	/// Once the timeout expires the receiving methods fail with `WebSocketError::Timeout`.
+	/// The timeout may strike in the middle of a data frame, the part of the
+	/// frame which was already read is kept and receiving can be retried.
+	pub fn set_read_timeout(&self, timeout: Option<Duration>) -> IoResult<()> {
+		self.stream.get_ref().as_tcp().set_read_timeout(timeout)?;
+		self.read_timeout.set(timeout);
+		Ok(())
+	}
+
+	/// See [`TcpStream::set_write_timeout`]
+	/// (https://doc.rust-lang.org/std/net/struct.TcpStream.html#method.set_write_timeout).
+	///
+	/// Once the timeout expires the sending methods fail with `WebSocketError::Timeout`,
+	/// a data frame may have been partially written and the connection should be closed.
+	pub fn set_write_timeout(&self, timeout: Option<Duration>) -> IoResult<()> {
+		self.stream.get_ref().as_tcp().set_write_timeout(timeout)
+	}
+
+	/// Reads a single message like `recv_message`, but gives up with `Ok(None)`
+	/// if no complete message arrived within `timeout`.
+	///
+	/// The read timeout of the stream is only changed while waiting, the one set
+	/// with `set_read_timeout` is restored before this returns. The part of a message
+	/// which arrived in time is kept and the next call resumes with it.
+	/// In nonblocking mode this fails right away like `recv_message`.
+	///
+	/// ```rust,no_run
+	/// use std::time::Duration;
+	/// use websocket::ClientBuilder;
+	/// let mut client = ClientBuilder::new("ws://localhost:3000")
+	///     .unwrap()
+	///     .connect_insecure()
+	///     .unwrap();
+	///
+	/// match client.recv_message_timeout(Duration::from_secs(1)).unwrap() {
+	///     Some(message) => println!("Recv: {:?}", message),
+	///     None => println!("Nothing yet"),
+	/// }
+	/// ```
+	pub fn recv_message_timeout(
+		&mut self,
+		timeout: Duration,
+	) -> WebSocketResult<Option<OwnedMessage>> {
+		let previous = self.read_timeout.get();
+		let alive = self.keepalive.is_some();
+		let deadline = Instant::now() + timeout;
+		let result = loop {
+			let now = Instant::now();
+			if now >= deadline {
+				break Ok(None);
+			}
+			if let Err(e) = self.set_read_timeout(Some(deadline - now)) {
+				break Err(e.into());
+			}
+			match self.recv_message() {
+				// a failed keepalive is reported as a timeout as well
+				Err(WebSocketError::Timeout) if alive && self.keepalive.is_none() => {
+					break Err(WebSocketError::Timeout);
+				}
+				Err(WebSocketError::Timeout) => continue,
+				result => break result.map(Some),
+			}
+		};
+
+		let restored = self.set_read_timeout(previous);
+		let message = result?;
+		restored?;
+		Ok(message)
 	}
 }
 
@@ -125,36 +422,397 @@ where
 		out_mask: bool,
 		in_mask: bool,
 	) -> Self {
+		let sender = Sender::new(out_mask); // true
+		let mut receiver = Receiver::new(in_mask); // false
+		receiver.set_metrics(sender.metrics().clone());
 		Client {
 			headers,
 			stream,
-			sender: Sender::new(out_mask),    // true
-			receiver: Receiver::new(in_mask), // false
+			sender,
+			receiver,
+			auto_pong: false,
+			auto_close: false,
+			close_on_error: false,
+			nonblocking: Cell::new(false),
+			close_timeout: Duration::from_secs(DEFAULT_CLOSE_TIMEOUT),
+			read_timeout: Cell::new(None),
+			keepalive: None,
+			pong_timeout: Duration::from_secs(DEFAULT_PONG_TIMEOUT),
 		}
 	}
 
+	/// Answer pings automatically, when enabled `recv_message` replies to
+	/// every ping with a pong carrying the same payload and only returns the
+	/// other messages. This is disabled by default.
+	///
+	/// Pings with a payload over 125 bytes fail with a `ProtocolError`
+	/// and are not answered.
+	///
+	/// Note that this only applies to `recv_message`, the iterators and the
+	/// halves of a split client still return pings.
+	pub fn set_auto_pong(&mut self, auto_pong: bool) {
+		self.auto_pong = auto_pong;
+	}
+
+	/// Answer close messages automatically, when enabled `recv_message`
+	/// replies to a close message with a close message carrying the same status
+	/// code, or 1000 if it had none. The close message is still returned, it is
+	/// the last one of the connection and the client should be dropped or
+	/// `close`d, which then only shuts it down. This is disabled by default.
+	///
+	/// No reply is sent if this end sent a close message already.
+	pub fn set_auto_close(&mut self, auto_close: bool) {
+		self.auto_close = auto_close;
+	}
+
+	/// Close the connection when the peer breaks the protocol, as RFC 6455 asks
+	/// for: when receiving fails with an error which has a `close_code`, e.g.
+	/// 1002 for a `ProtocolError` or 1007 for invalid UTF-8 text, a close frame
+	/// with that status code is sent before the error is returned. The client
+	/// should be dropped then. This is disabled by default, the error is only
+	/// returned.
+	///
+	/// No close frame is sent if this end sent one already.
+	pub fn set_close_on_error(&mut self, close_on_error: bool) {
+		self.close_on_error = close_on_error;
+	}
+
+	/// Reject received frames whose payload length is not encoded in the fewest
+	/// bytes possible, see `Receiver::set_minimal_lengths`. By default they are
+	/// accepted.
+	pub fn set_minimal_lengths(&mut self, minimal_lengths: bool) {
+		self.receiver.set_minimal_lengths(minimal_lengths);
+	}
+
+	/// Enforce every requirement of RFC 6455 on the peer, e.g. to pass the
+	/// Autobahn test suite. This turns on `set_minimal_lengths`,
+	/// `set_close_on_error`, `set_auto_pong` and `set_auto_close`, or off again
+	/// with `false`, they can still be changed on their own afterwards.
+	///
+	/// The other checks are always done: reserved bits and opcodes, the size and
+	/// fragmentation of control frames, masking, UTF-8 of text messages and close
+	/// reasons and the validity of close codes.
+	pub fn set_strict(&mut self, strict: bool) {
+		self.set_minimal_lengths(strict);
+		self.set_close_on_error(strict);
+		self.set_auto_pong(strict);
+		self.set_auto_close(strict);
+	}
+
+	/// Limit the total payload size of received messages, see
+	/// `Receiver::set_max_message_size`. By default the size is not limited.
+	pub fn set_max_message_size(&mut self, max_message_size: Option<usize>) {
+		self.receiver.set_max_message_size(max_message_size);
+	}
+
+	/// Limit the payload size of received data frames, see
+	/// `Receiver::set_max_frame_size`. By default the size is not limited.
+	pub fn set_max_frame_size(&mut self, max_frame_size: Option<usize>) {
+		self.receiver.set_max_frame_size(max_frame_size);
+	}
+
+	/// Limit the number of frames of a received message, see
+	/// `Receiver::set_max_fragments`. By default the number is not limited.
+	pub fn set_max_fragments(&mut self, max_fragments: Option<usize>) {
+		self.receiver.set_max_fragments(max_fragments);
+	}
+
+	/// Allow received data frames to have the reserved bits RSV1, RSV2 and RSV3
+	/// set, see `Receiver::set_reserved_bits`. This is meant for extensions
+	/// implemented on top of `recv_dataframe`, which returns the bits of every
+	/// frame, `permessage-deflate` sets up RSV1 itself.
+	pub fn set_reserved_bits(&mut self, reserved_bits: [bool; 3]) {
+		self.receiver.set_reserved_bits(reserved_bits);
+	}
+
+	/// Limit how fast frames are received, see `Receiver::set_rate_limit`.
+	///
+	/// A peer which exceeds the limit for longer than `RateLimit::close_after` is sent
+	/// a close frame with status code 1008 and receiving fails with
+	/// `WebSocketError::RateLimitExceeded`, the client should be dropped then.
+	pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+		self.receiver.set_rate_limit(limit);
+	}
+
+	/// Tell `observer` about every frame read from and written to the connection,
+	/// see the `observer` module. The halves of a split client keep it.
+	pub fn set_frame_observer(&mut self, observer: Option<Arc<dyn FrameObserver>>) {
+		self.receiver.set_frame_observer(observer.clone());
+		self.sender.set_frame_observer(observer);
+	}
+
+	/// The counters of the messages and frames sent and received, see the
+	/// `metrics` module. The halves of a split client keep counting in them.
+	pub fn metrics(&self) -> Arc<Metrics> {
+		self.sender.metrics().clone()
+	}
+
+	/// Mask the frames sent from now on with keys from `generator` instead of
+	/// random ones, see `Sender::set_mask_generator`. This is meant for tests
+	/// which check the exact bytes written, keys should be random otherwise.
+	pub fn set_mask_generator(&mut self, generator: Option<Box<dyn MaskGenerator>>) {
+		self.sender.set_mask_generator(generator);
+	}
+
+	/// Encode outgoing frames into a buffer of `size` bytes which is reused,
+	/// see `Sender::set_write_buffer_size`. By default every frame is encoded
+	/// into a buffer of its own.
+	pub fn set_write_buffer_size(&mut self, size: usize) {
+		self.sender.set_write_buffer_size(size);
+	}
+
+	/// Queue outgoing frames and write only what the stream takes, failing with
+	/// `WouldBlock` once more than `limit` bytes are waiting, see
+	/// `Sender::set_write_high_water_mark`. This is meant for nonblocking mode,
+	/// where `flush` writes the rest.
+	pub fn set_write_high_water_mark(&mut self, limit: Option<usize>) {
+		self.sender.set_write_high_water_mark(limit);
+	}
+
+	// compresses and inflates messages with the parameters of `permessage-deflate`
+	// negotiated during the handshake
+	#[cfg(feature = "deflate")]
+	pub(crate) fn use_deflate(&mut self, config: &DeflateConfig) {
+		let is_server = !self.sender.is_masked();
+		self.sender
+			.set_compressor(Some(config.compressor(is_server)));
+		self.receiver
+			.set_decompressor(Some(config.decompressor(is_server)));
+	}
+
+	/// If `permessage-deflate` was negotiated, send data messages smaller than
+	/// `threshold` bytes uncompressed. By default messages under
+	/// `deflate::DEFAULT_COMPRESSION_THRESHOLD` bytes are not compressed.
+	#[cfg(feature = "deflate")]
+	pub fn set_compression_threshold(&mut self, threshold: usize) {
+		self.sender.set_compression_threshold(threshold);
+	}
+
+	/// Count the connection against a server's connection limit until the client,
+	/// or its `Reader` once split, is dropped.
+	pub(crate) fn hold_slot(&mut self, slot: ConnectionSlot) {
+		self.receiver.hold_slot(slot);
+	}
+
+	/// Sends a ping carrying `data`, the remote endpoint answers it with a pong
+	/// carrying the same data.
+	///
+	/// Control frames hold at most 125 bytes, a longer payload fails with a
+	/// `DataFrameError` and nothing is sent.
+	///
+	/// ```rust,no_run
+	/// use websocket::ClientBuilder;
+	/// let mut client = ClientBuilder::new("ws://localhost:3000")
+	///     .unwrap()
+	///     .connect_insecure()
+	///     .unwrap();
+	///
+	/// client.ping(&b"are you there?"[..]).unwrap();
+	/// assert!(client.ping(vec![0; 126]).is_err());
+	/// ```
+	pub fn ping<D>(&mut self, data: D) -> WebSocketResult<()>
+	where
+		D: Into<Vec<u8>>,
+	{
+		let frame = DataFrameBuilder::new(Opcode::Ping).payload(data).build()?;
+		self.send_dataframe(&frame)
+	}
+
+	/// Sends a pong carrying `data`, e.g. an unsolicited pong as a heartbeat
+	/// which is not answered. Like `ping` this fails for more than 125 bytes.
+	pub fn pong<D>(&mut self, data: D) -> WebSocketResult<()>
+	where
+		D: Into<Vec<u8>>,
+	{
+		let frame = DataFrameBuilder::new(Opcode::Pong).payload(data).build()?;
+		self.send_dataframe(&frame)
+	}
+
 	/// Sends a single data frame to the remote endpoint.
 	pub fn send_dataframe<D>(&mut self, dataframe: &D) -> WebSocketResult<()>
 	where
 		D: DataFrameable,
 	{
-		self.sender.send_dataframe(self.stream.get_mut(), dataframe)
+		self.sender
+			.send_dataframe(self.stream.get_mut(), dataframe)
+			.map_err(|e| self.timeout_error(e))
+	}
+
+	/// Forwards a data frame as it is, see `Sender::send_dataframe_raw`.
+	pub fn send_dataframe_raw(&mut self, frame: &DataFrame) -> WebSocketResult<()> {
+		self.sender
+			.send_dataframe_raw(self.stream.get_mut(), frame)
+			.map_err(|e| self.timeout_error(e))
 	}
 
 	/// Sends a single message to the remote endpoint.
+	///
+	/// The message is written and the stream is flushed before this returns,
+	/// together with the messages buffered by `send_message_buffered`.
 	pub fn send_message<M>(&mut self, message: &M) -> WebSocketResult<()>
 	where
 		M: ws::Message,
 	{
-		self.sender.send_message(self.stream.get_mut(), message)
+		self.sender
+			.send_message(self.stream.get_mut(), message)
+			.map_err(|e| self.timeout_error(e))
+	}
+
+	/// Encodes a message without writing it, the buffered messages are written
+	/// together by `flush` or before the next message sent with `send_message`.
+	/// See `Sender::send_message_buffered`.
+	///
+	/// ```rust,no_run
+	/// use websocket::{ClientBuilder, OwnedMessage};
+	///
+	/// let mut client = ClientBuilder::new("ws://localhost:3000")
+	///     .unwrap()
+	///     .connect_insecure()
+	///     .unwrap();
+	///
+	/// for name in &["orders", "trades", "quotes"] {
+	///     client
+	///         .send_message_buffered(&OwnedMessage::Text(format!("subscribe {}", name)))
+	///         .unwrap();
+	/// }
+	/// client.flush().unwrap();
+	/// let reply = client.recv_message().unwrap();
+	/// ```
+	pub fn send_message_buffered<M>(&mut self, message: &M) -> WebSocketResult<()>
+	where
+		M: ws::Message,
+	{
+		self.sender.send_message_buffered(message)
+	}
+
+	/// Writes the messages buffered by `send_message_buffered` and flushes the stream.
+	pub fn flush(&mut self) -> WebSocketResult<()> {
+		self.sender
+			.flush(self.stream.get_mut())
+			.map_err(|e| self.timeout_error(e))
+	}
+
+	/// Starts a text or binary message which is sent piece by piece,
+	/// see `Sender::message_writer`.
+	///
+	/// ```rust,no_run
+	/// use std::fs::File;
+	/// use std::io;
+	/// use websocket::dataframe::Opcode;
+	/// use websocket::ClientBuilder;
+	///
+	/// let mut client = ClientBuilder::new("ws://localhost:3000")
+	///     .unwrap()
+	///     .connect_insecure()
+	///     .unwrap();
+	///
+	/// let mut file = File::open("upload.bin").unwrap();
+	/// let mut message = client.message_writer(Opcode::Binary).unwrap();
+	/// io::copy(&mut file, &mut message).unwrap();
+	/// message.finish().unwrap();
+	/// ```
+	pub fn message_writer<'a>(
+		&'a mut self,
+		opcode: Opcode,
+	) -> WebSocketResult<MessageWriter<'a, S>> {
+		self.sender.message_writer(self.stream.get_mut(), opcode)
 	}
 
 	/// Reads a single data frame from the remote endpoint.
+	///
+	/// Frames are returned as they arrive, with their FIN and reserved bits,
+	/// so the fragments of a message and the control frames sent in between
+	/// them are seen in order. The payload is unmasked but not reassembled,
+	/// decompressed or validated as UTF-8.
+	///
+	/// Frames which are masked when they shouldn't be, or the other way round,
+	/// fail with a protocol error, as do frames with reserved bits which were not
+	/// allowed with `set_reserved_bits`.
 	pub fn recv_dataframe(&mut self) -> WebSocketResult<DataFrame> {
-		self.receiver.recv_dataframe(&mut self.stream)
+		self.recv_alive(
+			|receiver, stream| receiver.recv_dataframe(stream),
+			|heartbeat, frame| heartbeat.on_frame(frame),
+		)
+	}
+
+	/// Reads a data frame to forward it, see `Receiver::recv_dataframe_raw`.
+	pub fn recv_dataframe_raw(&mut self) -> WebSocketResult<DataFrame> {
+		self.recv_alive(
+			|receiver, stream| receiver.recv_dataframe_raw(stream),
+			|heartbeat, frame| heartbeat.on_frame(frame),
+		)
+	}
+
+	/// Starts reading the next message piece by piece as its frames arrive,
+	/// see `Receiver::message_reader`.
+	///
+	/// ```rust,no_run
+	/// use std::fs::File;
+	/// use std::io;
+	/// use websocket::ClientBuilder;
+	///
+	/// let mut client = ClientBuilder::new("ws://localhost:3000")
+	///     .unwrap()
+	///     .connect_insecure()
+	///     .unwrap();
+	///
+	/// let mut file = File::create("download.bin").unwrap();
+	/// let mut message = client.message_reader().unwrap();
+	/// io::copy(&mut message, &mut file).unwrap();
+	/// ```
+	pub fn message_reader<'a>(&'a mut self) -> WebSocketResult<MessageReader<'a, BufReader<S>>> {
+		self.receiver.message_reader(&mut self.stream)
 	}
 
-	/// Returns an iterator over incoming data frames.
+	/// Reads a single message without copying its payload, see `Receiver::recv_borrowed`.
+	///
+	/// The type of the message is in `opcode`, the payload of a text message has been
+	/// validated as UTF-8. Pings are not answered automatically and no keepalive
+	/// pings are sent while waiting.
+	///
+	/// ```rust,no_run
+	/// use websocket::message::Type;
+	/// use websocket::ClientBuilder;
+	///
+	/// let mut client = ClientBuilder::new("ws://localhost:3000")
+	///     .unwrap()
+	///     .connect_insecure()
+	///     .unwrap();
+	///
+	/// let message = client.recv_borrowed().unwrap();
+	/// match message.opcode {
+	///     Type::Text => println!("text: {}", message.as_text().unwrap()),
+	///     Type::Binary => println!("{} bytes", message.payload.len()),
+	///     _ => {}
+	/// }
+	/// ```
+	pub fn recv_borrowed<'a>(&'a mut self) -> WebSocketResult<Message<'a>> {
+		self.receiver.recv_borrowed(&mut self.stream)
+	}
+
+	/// Reads and discards the next message, see `Receiver::skip_message`.
+	///
+	/// The control messages received meanwhile are returned, pings are not
+	/// answered automatically.
+	pub fn skip_message(&mut self) -> WebSocketResult<Vec<OwnedMessage>> {
+		self.receiver.skip_message(&mut self.stream)
+	}
+
+	/// Returns an iterator over incoming data frames, see `recv_dataframe`.
+	///
+	/// ```rust,no_run
+	/// use websocket::ClientBuilder;
+	///
+	/// let mut client = ClientBuilder::new("ws://localhost:3000")
+	///     .unwrap()
+	///     .connect_insecure()
+	///     .unwrap();
+	///
+	/// for frame in client.incoming_dataframes() {
+	///     let frame = frame.unwrap();
+	///     println!("{:?} fin={} {} bytes", frame.opcode, frame.finished, frame.data.len());
+	/// }
+	/// ```
 	pub fn incoming_dataframes(&mut self) -> DataFrameIterator<Receiver, BufReader<S>> {
 		self.receiver.incoming_dataframes(&mut self.stream)
 	}
@@ -173,7 +831,146 @@ where
 	/// let response = client.recv_message().unwrap();
 	/// ```
 	pub fn recv_message(&mut self) -> WebSocketResult<OwnedMessage> {
-		self.receiver.recv_message(&mut self.stream)
+		loop {
+			let message = self.recv_alive(
+				|receiver, stream| receiver.recv_message(stream),
+				|heartbeat, message| heartbeat.on_message(message),
+			)?;
+			match message {
+				OwnedMessage::Ping(data) if self.auto_pong => {
+					self.send_message(&OwnedMessage::Pong(data))?;
+				}
+				OwnedMessage::Close(data) if self.auto_close && !self.sender.close_sent() => {
+					let code = data
+						.as_ref()
+						.map(|data| CloseCode::from_u16(data.status_code))
+						.unwrap_or(CloseCode::NormalClosure);
+					let reply = CloseData::new(code, "").ok();
+					self.send_message(&OwnedMessage::Close(reply))?;
+					return Ok(OwnedMessage::Close(data));
+				}
+				message => return Ok(message),
+			}
+		}
+	}
+
+	// receives with `recv`, driving the heartbeat while waiting
+	fn recv_alive<T, F>(
+		&mut self,
+		mut recv: F,
+		seen: fn(&mut dyn Heartbeat, &T) -> Action,
+	) -> WebSocketResult<T>
+	where
+		F: FnMut(&mut Receiver, &mut BufReader<S>) -> WebSocketResult<T>,
+	{
+		let tcp = match self.keepalive {
+			Some(ref keepalive) if !self.nonblocking.get() => keepalive.tcp,
+			_ => {
+				return recv(&mut self.receiver, &mut self.stream).map_err(|e| {
+					let e = self.timeout_error(e);
+					self.close_on_failure(e)
+				});
+			}
+		};
+
+		let read_deadline = self.read_timeout.get().map(|t| Instant::now() + t);
+		let result = loop {
+			let now = Instant::now();
+			let due = self.keepalive.as_ref().and_then(|keepalive| keepalive.due);
+			if due.is_some_and(|due| now >= due) {
+				let keepalive = self.keepalive.as_mut().unwrap();
+				keepalive.due = None;
+				let (action, error) = {
+					let mut sender = HeartbeatSender::new(&mut self.sender, self.stream.get_mut());
+					let action = keepalive.heartbeat.on_tick(&mut sender);
+					(action, sender.into_error())
+				};
+				if let Some(e) = error {
+					break Err(e);
+				}
+				if keepalive.apply(action, now) {
+					break Err(self.keepalive_failed(tcp));
+				}
+				continue;
+			}
+
+			let wake_up = match (read_deadline, due) {
+				(Some(read_deadline), _) if read_deadline <= now => {
+					break Err(WebSocketError::Timeout)
+				}
+				(Some(read_deadline), Some(due)) => Some(cmp::min(read_deadline, due)),
+				(read_deadline, due) => read_deadline.or(due),
+			};
+			let timeout = wake_up.map(|wake_up| wake_up - now);
+			if let Err(e) = tcp(self.stream.get_ref()).set_read_timeout(timeout) {
+				break Err(e.into());
+			}
+			match recv(&mut self.receiver, &mut self.stream).map_err(|e| self.timeout_error(e)) {
+				Err(WebSocketError::Timeout) => continue,
+				Ok(received) => {
+					let keepalive = self.keepalive.as_mut().unwrap();
+					let action = seen(&mut *keepalive.heartbeat, &received);
+					if keepalive.apply(action, Instant::now()) {
+						break Err(self.keepalive_failed(tcp));
+					}
+					break Ok(received);
+				}
+				Err(e) => break Err(e),
+			}
+		};
+
+		let restored = tcp(self.stream.get_ref()).set_read_timeout(self.read_timeout.get());
+		let received = result.map_err(|e| self.close_on_failure(e))?;
+		restored?;
+		Ok(received)
+	}
+
+	// the heartbeat gave up, so does the connection
+	fn keepalive_failed(&mut self, tcp: fn(&S) -> &TcpStream) -> WebSocketError {
+		self.keepalive = None;
+		if !self.sender.close_sent() {
+			let _ = self.send_message(&OwnedMessage::Close(None));
+		}
+		let _ = tcp(self.stream.get_ref()).shutdown(Shutdown::Both);
+		WebSocketError::Timeout
+	}
+
+	// a peer which stayed over its rate limit is told why it is let go, with
+	// `set_close_on_error` so is a peer which broke the protocol, and all
+	// peers are once their server is going away
+	fn close_on_failure(&mut self, error: WebSocketError) -> WebSocketError {
+		// the server shut down the receiving side, see `close_connections`
+		if let Some(close) = self.receiver.going_away() {
+			if !self.sender.close_sent() {
+				let _ = self.send_message(&close);
+			}
+			return error;
+		}
+		let abusive = matches!(error, WebSocketError::RateLimitExceeded);
+		let code = match error.close_code() {
+			Some(code) if abusive || self.close_on_error => code,
+			_ => return error,
+		};
+		if !self.sender.close_sent() {
+			let reason = if abusive { "Rate limit exceeded" } else { "" };
+			let close = CloseData::new(code, reason).ok();
+			let _ = self.send_message(&OwnedMessage::Close(close));
+		}
+		error
+	}
+
+	// read and write timeouts are reported as `WouldBlock` or `TimedOut`
+	// depending on the platform, in nonblocking mode `WouldBlock` is expected
+	fn timeout_error(&self, error: WebSocketError) -> WebSocketError {
+		match error {
+			WebSocketError::IoError(ref e)
+				if e.kind() == io::ErrorKind::TimedOut
+					|| (e.kind() == io::ErrorKind::WouldBlock && !self.nonblocking.get()) =>
+			{
+				WebSocketError::Timeout
+			}
+			error => error,
+		}
 	}
 
 	/// Access the headers that were sent in the server's handshake response.
@@ -182,10 +979,43 @@ where
 		&self.headers
 	}
 
-	/// **If you supplied a protocol, you must check that it was accepted by
-	/// the server** using this function.
-	/// This is not done automatically because the terms of accepting a protocol
-	/// can get complicated, especially if some protocols depend on others, etc.
+	/// The headers of the handshake response, the same as `headers`.
+	///
+	/// ```rust,no_run
+	/// # use websocket::ClientBuilder;
+	/// let client = ClientBuilder::new("ws://127.0.0.1:1234").unwrap()
+	///     .connect_insecure()
+	///     .unwrap();
+	///
+	/// if let Some(server) = client.response_headers().get_raw("X-Server-Id") {
+	///     println!("connected to {}", String::from_utf8_lossy(&server[0]));
+	/// }
+	/// ```
+	pub fn response_headers(&self) -> &Headers {
+		&self.headers
+	}
+
+	/// The protocol the server selected in its handshake response, if any.
+	///
+	/// This is always one of the protocols the client offered,
+	/// connecting fails if the server chooses another one.
+	pub fn protocol(&self) -> Option<&str> {
+		self.protocols().first().map(|p| p as &str)
+	}
+
+	/// The cookies the server set with its handshake response, one for each
+	/// `Set-Cookie` header including its attributes.
+	pub fn cookies(&self) -> &[String] {
+		self.headers
+			.get::<SetCookie>()
+			.map(|c| c.0.as_slice())
+			.unwrap_or(&[])
+	}
+
+	/// The protocols the server selected in its handshake response, see `protocol`.
+	///
+	/// **If you supplied a protocol, check that the server accepted one**, a server
+	/// may also not choose any protocol at all.
 	///
 	/// ```rust,no_run
 	/// # use websocket::ClientBuilder;
@@ -204,9 +1034,12 @@ where
 			.unwrap_or(&[])
 	}
 
-	/// If you supplied a protocol, be sure to check if it was accepted by the
-	/// server here. Since no extensions are implemented out of the box yet, using
-	/// one will require its own implementation.
+	/// The extensions accepted by the server along with their parameters,
+	/// the handshake fails if the server accepts an extension which was not offered.
+	///
+	/// If you supplied an extension, be sure to check if it was accepted by the
+	/// server here. Apart from `permessage-deflate` no extensions are implemented
+	/// out of the box, using one will require its own implementation.
 	pub fn extensions(&self) -> &[Extension] {
 		self.headers
 			.get::<WebSocketExtensions>()
@@ -229,6 +1062,29 @@ where
 		self.stream.get_ref()
 	}
 
+	/// Get a reference to the underlying stream, the same as `stream_ref`.
+	pub fn get_ref(&self) -> &S {
+		self.stream.get_ref()
+	}
+
+	/// Get a mutable reference to the underlying stream.
+	/// Useful to be able to set options on the stream.
+	///
+	/// Reading from or writing to the stream directly will likely corrupt the
+	/// websocket connection, since data may already be buffered by the client.
+	///
+	/// ```rust,no_run
+	/// # use websocket::ClientBuilder;
+	/// let mut client = ClientBuilder::new("ws://double.down").unwrap()
+	///     .connect_insecure()
+	///     .unwrap();
+	///
+	/// client.get_mut().set_nodelay(true).unwrap();
+	/// ```
+	pub fn get_mut(&mut self) -> &mut S {
+		self.stream.get_mut()
+	}
+
 	/// Get a handle to the writable portion of this stream.
 	/// This can be used to write custom extensions.
 	///
@@ -290,10 +1146,21 @@ where
 	/// bytes might already be read from the stream when this is called,
 	/// these buffered bytes are returned in the form
 	///
-	/// `(byte_buffer: Vec<u8>, buffer_capacity: usize, buffer_position: usize)`
-	pub fn into_stream(self) -> (S, Option<(Vec<u8>, usize, usize)>) {
+	/// `(byte_buffer: Vec<u8>, buffer_position: usize, buffer_capacity: usize)`
+	///
+	/// where `&byte_buffer[buffer_position..buffer_capacity]` are the bytes
+	/// which were not consumed yet. This includes the start of a data frame which
+	/// was only partially received, but not the fragments of an incomplete message.
+	pub fn into_stream(mut self) -> (S, Option<(Vec<u8>, usize, usize)>) {
+		let partial = self.receiver.take_partial_frame();
 		let (stream, buf, pos, cap) = self.stream.into_parts();
-		(stream, Some((buf, pos, cap)))
+		if partial.is_empty() {
+			return (stream, Some((buf, pos, cap)));
+		}
+		let mut unread = partial;
+		unread.extend_from_slice(&buf[pos..cap]);
+		let len = unread.len();
+		(stream, Some((unread, 0, len)))
 	}
 
 	/// Returns an iterator over incoming messages.
@@ -344,6 +1211,13 @@ where
 	/// Split this client into its constituent Sender and Receiver pair.
 	///
 	/// This allows the Sender and Receiver to be sent to different threads.
+	/// For a `TcpStream` both halves are handles to the same socket, created with
+	/// `TcpStream::try_clone`. TLS streams can not be duplicated, so clients
+	/// over TLS don't have this method.
+	///
+	/// The two halves don't coordinate with each other: the close handshake,
+	/// i.e. answering a received close with one of your own, and any other reply to
+	/// a received message has to be done by the caller through the `Writer`.
 	///
 	///```no_run
 	///# extern crate websocket;
@@ -386,3 +1260,827 @@ where
 		))
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use dataframe::Opcode;
+	use message::CloseCode;
+	use std::io::Cursor;
+	use stream::sync::ReadWritePair;
+
+	type TestClient = Client<ReadWritePair<Cursor<Vec<u8>>, Cursor<Vec<u8>>>>;
+
+	fn client(frames: &[DataFrame]) -> TestClient {
+		let mut input = Vec::new();
+		for frame in frames {
+			frame.write_to(&mut input, false).unwrap();
+		}
+		raw_client(input)
+	}
+
+	fn raw_client(input: Vec<u8>) -> TestClient {
+		let stream = ReadWritePair(Cursor::new(input), Cursor::new(Vec::new()));
+		Client::unchecked(BufReader::new(stream), Headers::new(), true, false)
+	}
+
+	#[test]
+	fn dataframes_keep_their_boundaries() {
+		let frames = vec![
+			DataFrame::new(false, Opcode::Text, b"hel".to_vec()),
+			DataFrame::new(true, Opcode::Ping, b"ping".to_vec()),
+			DataFrame::new(false, Opcode::Continuation, b"lo ".to_vec()),
+			DataFrame::new(true, Opcode::Continuation, b"world".to_vec()),
+			DataFrame::new(true, Opcode::Binary, vec![1, 2]),
+		];
+		let mut client = client(&frames);
+		let received: Vec<DataFrame> = client
+			.incoming_dataframes()
+			.take(frames.len())
+			.map(Result::unwrap)
+			.collect();
+		assert_eq!(received, frames);
+		assert!(client.recv_dataframe().is_err());
+	}
+
+	#[test]
+	fn dataframes_report_reserved_bits() {
+		// "hello" compressed with permessage-deflate
+		let compressed = vec![0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00];
+		let mut frame = DataFrame::new(true, Opcode::Text, compressed.clone());
+		frame.reserved = [true, false, false];
+
+		match client(&[frame.clone()]).recv_dataframe() {
+			Err(WebSocketError::ProtocolError(_)) => (),
+			other => panic!("RSV1 was not negotiated, got {:?}", other),
+		}
+
+		let mut client = client(&[frame]);
+		client.set_reserved_bits([true, false, false]);
+		let received = client.recv_dataframe().unwrap();
+		assert!(received.finished);
+		assert_eq!(received.reserved, [true, false, false]);
+		assert_eq!(received.opcode, Opcode::Text);
+		assert_eq!(received.data, compressed);
+	}
+
+	#[test]
+	fn auto_pong_answers_pings() {
+		let mut client = client(&[
+			DataFrame::new(true, Opcode::Ping, b"are you there?".to_vec()),
+			DataFrame::new(true, Opcode::Text, b"hi".to_vec()),
+		]);
+		client.set_auto_pong(true);
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Text("hi".to_string())
+		);
+
+		let written = (client.into_stream().0).1.into_inner();
+		let pong = DataFrame::read_dataframe(&mut &written[..], true).unwrap();
+		assert_eq!(pong.opcode, Opcode::Pong);
+		assert_eq!(pong.data, b"are you there?".to_vec());
+	}
+
+	#[test]
+	fn metrics_count_the_exchange() {
+		let mut client = client(&[
+			DataFrame::new(true, Opcode::Ping, b"ping".to_vec()),
+			DataFrame::new(false, Opcode::Text, b"hel".to_vec()),
+			DataFrame::new(true, Opcode::Continuation, b"lo".to_vec()),
+			DataFrame::new(true, Opcode::Binary, vec![0; 200]),
+			DataFrame::new(false, Opcode::Binary, vec![0; 50]),
+		]);
+		client.set_auto_pong(true);
+		let metrics = client.metrics();
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Text("hello".to_string())
+		);
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Binary(vec![0; 200])
+		);
+		client
+			.send_message(&OwnedMessage::Text("hi".to_string()))
+			.unwrap();
+		client
+			.send_message(&OwnedMessage::Binary(vec![1; 300]))
+			.unwrap();
+		client
+			.send_message(&OwnedMessage::Ping(Vec::new()))
+			.unwrap();
+
+		assert_eq!(metrics.messages_received(), 2);
+		assert_eq!(metrics.pings_received(), 1);
+		assert_eq!(metrics.pongs_received(), 0);
+		assert_eq!(metrics.bytes_received(), 6 + 5 + 4 + 204);
+		assert_eq!(metrics.messages_sent(), 2);
+		assert_eq!(metrics.pings_sent(), 1);
+		assert_eq!(metrics.pongs_sent(), 1);
+		assert_eq!(metrics.reassembly_buffer_len(), 0);
+
+		// the last message never ends
+		assert!(client.recv_message().is_err());
+		assert_eq!(metrics.reassembly_buffer_len(), 50);
+		let written = (client.into_stream().0).1.into_inner();
+		assert_eq!(metrics.bytes_sent(), written.len());
+	}
+
+	#[test]
+	fn frame_observer_sees_the_exchange() {
+		use std::sync::Mutex;
+
+		#[derive(Default)]
+		struct Events(Mutex<Vec<String>>);
+
+		impl Events {
+			fn push(&self, direction: &str, frame: &DataFrame, masked: bool) {
+				let event = format!(
+					"{} {:?} fin={} len={} masked={}",
+					direction,
+					frame.opcode,
+					frame.finished,
+					frame.data.len(),
+					masked
+				);
+				self.0.lock().unwrap().push(event);
+			}
+		}
+
+		impl FrameObserver for Events {
+			fn on_read(&self, frame: &DataFrame, masked: bool) {
+				self.push("read", frame, masked);
+			}
+
+			fn on_write(&self, frame: &DataFrame, masked: bool) {
+				self.push("write", frame, masked);
+			}
+		}
+
+		let mut client = client(&[
+			DataFrame::new(true, Opcode::Ping, b"ping".to_vec()),
+			DataFrame::new(false, Opcode::Text, b"hel".to_vec()),
+			DataFrame::new(true, Opcode::Continuation, b"lo".to_vec()),
+		]);
+		let events = Arc::new(Events::default());
+		client.set_frame_observer(Some(events.clone()));
+		client.set_auto_pong(true);
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Text("hello".to_string())
+		);
+		client
+			.send_message(&OwnedMessage::Binary(vec![1, 2, 3]))
+			.unwrap();
+
+		assert_eq!(
+			*events.0.lock().unwrap(),
+			vec![
+				"read Ping fin=true len=4 masked=false",
+				"write Pong fin=true len=4 masked=true",
+				"read Text fin=false len=3 masked=false",
+				"read Continuation fin=true len=2 masked=false",
+				"write Binary fin=true len=3 masked=true",
+			]
+		);
+
+		// the frames are written as they were observed
+		let written = (client.into_stream().0).1.into_inner();
+		let mut reader = &written[..];
+		let pong = DataFrame::read_dataframe(&mut reader, true).unwrap();
+		assert_eq!(pong, DataFrame::new(true, Opcode::Pong, b"ping".to_vec()));
+		let binary = DataFrame::read_dataframe(&mut reader, true).unwrap();
+		assert_eq!(binary, DataFrame::new(true, Opcode::Binary, vec![1, 2, 3]));
+		assert!(reader.is_empty());
+	}
+
+	#[test]
+	fn borrowed_messages_report_their_type() {
+		use message::Type;
+
+		let mut close = vec![0x03, 0xe8];
+		close.extend_from_slice(b"bye");
+		let mut client = client(&[
+			DataFrame::new(true, Opcode::Text, "hé".as_bytes().to_vec()),
+			DataFrame::new(true, Opcode::Binary, vec![0xff, 0]),
+			DataFrame::new(true, Opcode::Ping, vec![1]),
+			DataFrame::new(true, Opcode::Pong, vec![2]),
+			DataFrame::new(true, Opcode::Close, close),
+			DataFrame::new(true, Opcode::Text, vec![0xff]),
+		]);
+		let expected: Vec<(Type, &[u8])> = vec![
+			(Type::Text, "hé".as_bytes()),
+			(Type::Binary, &[0xff, 0]),
+			(Type::Ping, &[1]),
+			(Type::Pong, &[2]),
+			(Type::Close, b"bye"),
+		];
+		for (opcode, payload) in expected {
+			let message = client.recv_borrowed().unwrap();
+			assert_eq!(message.opcode, opcode);
+			assert_eq!(&*message.payload, payload);
+			let text = if opcode == Type::Text {
+				Some("hé")
+			} else {
+				None
+			};
+			assert_eq!(message.as_text(), text);
+		}
+
+		// text is only handed out once it is known to be valid
+		match client.recv_borrowed() {
+			Err(WebSocketError::Utf8Error(_)) => {}
+			other => panic!("expected invalid UTF-8, got {:?}", other.err()),
+		}
+	}
+
+	#[test]
+	fn pings_are_returned_by_default() {
+		let mut client = client(&[DataFrame::new(true, Opcode::Ping, vec![1, 2])]);
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Ping(vec![1, 2])
+		);
+	}
+
+	#[test]
+	fn auto_pong_rejects_large_pings() {
+		// a ping with a 126 byte payload, which cannot be written with a DataFrame
+		let mut input = vec![0x89, 126, 0, 126];
+		input.extend_from_slice(&[0; 126]);
+		let mut client = raw_client(input);
+		client.set_auto_pong(true);
+		match client.recv_message() {
+			Err(WebSocketError::ProtocolError(_)) => {}
+			other => panic!("expected a protocol error, got {:?}", other),
+		}
+		assert!((client.into_stream().0).1.into_inner().is_empty());
+	}
+
+	#[test]
+	fn auto_pong_echoes_the_largest_payload() {
+		let payload: Vec<u8> = (0..125).collect();
+		let mut client = client(&[
+			DataFrame::new(true, Opcode::Ping, payload.clone()),
+			DataFrame::new(true, Opcode::Text, b"hi".to_vec()),
+		]);
+		client.set_auto_pong(true);
+		client.recv_message().unwrap();
+
+		// a single masked frame with a 7 bit length
+		let written = (client.into_stream().0).1.into_inner();
+		assert_eq!(written.len(), 2 + 4 + 125);
+		assert_eq!(written[1], 0x80 | 125);
+		let pong = DataFrame::read_dataframe(&mut &written[..], true).unwrap();
+		assert_eq!(pong.opcode, Opcode::Pong);
+		assert_eq!(pong.data, payload);
+	}
+
+	#[test]
+	fn ping_and_pong_check_the_payload_length() {
+		let mut client = client(&[]);
+		client.ping(&b"ping"[..]).unwrap();
+		client.pong(vec![1; 125]).unwrap();
+		for result in [client.ping(vec![0; 126]), client.pong(vec![0; 200])] {
+			match result {
+				Err(WebSocketError::DataFrameError(_)) => (),
+				other => panic!("expected a data frame error, got {:?}", other),
+			}
+		}
+
+		let written = (client.into_stream().0).1.into_inner();
+		let mut reader = &written[..];
+		let ping = DataFrame::read_dataframe(&mut reader, true).unwrap();
+		assert_eq!(ping, DataFrame::new(true, Opcode::Ping, b"ping".to_vec()));
+		let pong = DataFrame::read_dataframe(&mut reader, true).unwrap();
+		assert_eq!(pong, DataFrame::new(true, Opcode::Pong, vec![1; 125]));
+		assert!(reader.is_empty());
+	}
+
+	#[test]
+	fn read_timeout_is_reported() {
+		use std::net::TcpListener;
+		use std::thread;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let peer = thread::spawn(move || listener.accept().unwrap().0);
+		let stream = TcpStream::connect(addr).unwrap();
+		let _peer = peer.join().unwrap();
+
+		let mut client = Client::unchecked(BufReader::new(stream), Headers::new(), true, false);
+		client
+			.set_read_timeout(Some(Duration::from_millis(50)))
+			.unwrap();
+		client
+			.set_write_timeout(Some(Duration::from_secs(1)))
+			.unwrap();
+		assert!(client.stream_ref().read_timeout().unwrap().is_some());
+		assert!(client.stream_ref().write_timeout().unwrap().is_some());
+		match client.recv_message() {
+			Err(WebSocketError::Timeout) => (),
+			_ => panic!("a read timeout must be reported as a timeout"),
+		}
+	}
+
+	#[test]
+	fn recv_message_timeout_resumes() {
+		use std::net::TcpListener;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		let mut peer = listener.accept().unwrap().0;
+		let mut client = Client::unchecked(BufReader::new(stream), Headers::new(), true, false);
+
+		let timeout = Duration::from_millis(50);
+		assert_eq!(client.recv_message_timeout(timeout).unwrap(), None);
+		assert_eq!(client.stream_ref().read_timeout().unwrap(), None);
+
+		let mut frame = Vec::new();
+		DataFrame::new(true, Opcode::Text, b"worth the wait".to_vec())
+			.write_to(&mut frame, false)
+			.unwrap();
+		let (first, second) = frame.split_at(5);
+		peer.write_all(first).unwrap();
+		assert_eq!(client.recv_message_timeout(timeout).unwrap(), None);
+
+		// the previous read timeout is kept
+		client
+			.set_read_timeout(Some(Duration::from_secs(5)))
+			.unwrap();
+		peer.write_all(second).unwrap();
+		assert_eq!(
+			client.recv_message_timeout(Duration::from_secs(5)).unwrap(),
+			Some(OwnedMessage::Text("worth the wait".to_string()))
+		);
+		assert_eq!(
+			client.stream_ref().read_timeout().unwrap(),
+			Some(Duration::from_secs(5))
+		);
+	}
+
+	#[test]
+	fn nonblocking_reads_resume() {
+		use std::net::TcpListener;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		let mut peer = listener.accept().unwrap().0;
+
+		let mut client = Client::unchecked(BufReader::new(stream), Headers::new(), true, false);
+		client.set_nonblocking(true).unwrap();
+
+		let mut frame = Vec::new();
+		DataFrame::new(true, Opcode::Text, b"half and half".to_vec())
+			.write_to(&mut frame, false)
+			.unwrap();
+		let (first, second) = frame.split_at(6);
+
+		peer.write_all(first).unwrap();
+		let mut would_block = 0;
+		loop {
+			match client.recv_message() {
+				Err(WebSocketError::IoError(ref e)) if e.kind() == io::ErrorKind::WouldBlock => {
+					would_block += 1;
+					if would_block == 1 {
+						peer.write_all(second).unwrap();
+					}
+				}
+				Ok(message) => {
+					assert_eq!(message, OwnedMessage::Text("half and half".to_string()));
+					break;
+				}
+				Err(e) => panic!("unexpected error {:?}", e),
+			}
+		}
+		assert!(would_block > 0);
+	}
+
+	// connects a client to a server which hands its side of the connection to `peer`
+	fn connected<F>(peer: F) -> (Client<TcpStream>, ::std::thread::JoinHandle<()>)
+	where
+		F: FnOnce(Client<TcpStream>) + Send + 'static,
+	{
+		use client::builder::ClientBuilder;
+		use server::sync::Server;
+		use std::thread;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+		let handle = thread::spawn(move || peer(server.accept().ok().unwrap().accept().unwrap()));
+		let client = ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.connect_insecure()
+			.unwrap();
+		(client, handle)
+	}
+
+	#[test]
+	fn selected_protocol() {
+		use client::builder::ClientBuilder;
+		use server::sync::Server;
+		use std::thread;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+		let peer = thread::spawn(move || {
+			let upgrade = server.accept().ok().unwrap();
+			assert_eq!(upgrade.protocols(), ["superchat", "chat"]);
+			let mut headers = Headers::new();
+			headers.set_raw("X-Server-Id", vec![b"7".to_vec()]);
+			upgrade
+				.use_protocol("chat")
+				.accept_with(&headers)
+				.ok()
+				.unwrap();
+		});
+		let client = ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.add_protocols(vec!["superchat", "chat"])
+			.connect_insecure()
+			.unwrap();
+		peer.join().unwrap();
+
+		assert_eq!(client.protocol(), Some("chat"));
+		let headers = client.response_headers();
+		assert_eq!(headers.get_raw("X-Server-Id"), Some(&[b"7".to_vec()][..]));
+	}
+
+	#[test]
+	fn close_handshake() {
+		let (mut client, peer) = connected(|mut peer| {
+			peer.send_message(&OwnedMessage::Text("discarded".to_string()))
+				.unwrap();
+			match peer.recv_message().unwrap() {
+				OwnedMessage::Close(data) => {
+					assert_eq!(
+						data,
+						Some(CloseData::new(CloseCode::NormalClosure, "bye").unwrap())
+					);
+					peer.send_message(&OwnedMessage::Close(data)).unwrap();
+				}
+				message => panic!("expected a close message, got {:?}", message),
+			}
+			match peer.recv_message() {
+				Err(WebSocketError::NoDataAvailable) => (),
+				other => panic!("the connection must be shut down, got {:?}", other),
+			}
+		});
+
+		client
+			.close_with(CloseData::new(CloseCode::NormalClosure, "bye").unwrap())
+			.unwrap();
+		peer.join().unwrap();
+	}
+
+	#[test]
+	fn auto_close_answers_once() {
+		let (client, peer) = connected(|mut peer| {
+			let going_away = CloseData::new(CloseCode::GoingAway, "bye").unwrap();
+			peer.send_message(&OwnedMessage::Close(Some(going_away)))
+				.unwrap();
+			assert_eq!(
+				peer.recv_message().unwrap(),
+				OwnedMessage::Close(Some(CloseData::new(CloseCode::GoingAway, "").unwrap()))
+			);
+			match peer.recv_message() {
+				Err(WebSocketError::NoDataAvailable) => (),
+				other => panic!("expected a single close message, got {:?}", other),
+			}
+		});
+		let mut client = client;
+		client.set_auto_close(true);
+
+		match client.recv_message().unwrap() {
+			OwnedMessage::Close(Some(data)) => assert_eq!(data.status_code, 1001),
+			message => panic!("expected a close message, got {:?}", message),
+		}
+		client.close().unwrap();
+		peer.join().unwrap();
+	}
+
+	#[test]
+	fn close_is_not_answered_by_default() {
+		let mut client = client(&[DataFrame::new(true, Opcode::Close, vec![0x03, 0xe9])]);
+		match client.recv_message().unwrap() {
+			OwnedMessage::Close(Some(data)) => assert_eq!(data.status_code, 1001),
+			message => panic!("expected a close message, got {:?}", message),
+		}
+		assert!((client.into_stream().0).1.into_inner().is_empty());
+	}
+
+	#[test]
+	fn strict_mode_closes_like_autobahn_expects() {
+		let mut big_ping = vec![0x89, 0x7e, 0x00, 0x7e];
+		big_ping.extend_from_slice(&[0; 126]);
+		// the number of the Autobahn case, what is received and the close code sent
+		let cases: &[(&str, &[u8], Option<u16>)] = &[
+			("1.1.1", &[0x81, 0x00], None),
+			("2.5", &big_ping, Some(1002)),
+			("3.1", &[0xc1, 0x02, b'h', b'i'], Some(1002)),
+			("4.1.1", &[0x83, 0x00], Some(1002)),
+			("4.2.1", &[0x8b, 0x00], Some(1002)),
+			(
+				"5.1",
+				&[0x09, 0x02, b'p', b'i', 0x80, 0x02, b'n', b'g'],
+				Some(1002),
+			),
+			("5.9", &[0x80, 0x02, b'h', b'i'], Some(1002)),
+			("5.18", &[0x01, 0x01, b'h', 0x81, 0x01, b'i'], Some(1002)),
+			("6.3.1", &[0x81, 0x02, 0xce, 0xff], Some(1007)),
+			("7.3.2", &[0x88, 0x01, 0x03], Some(1002)),
+			("7.5.1", &[0x88, 0x04, 0x03, 0xe8, 0xff, 0xfe], Some(1007)),
+			("7.9.1", &[0x88, 0x02, 0x00, 0x00], Some(1002)),
+			// not cases of the suite, masked frames and lengths in too many bytes
+			(
+				"masked",
+				&[0x81, 0x82, 1, 2, 3, 4, b'h' ^ 1, b'i' ^ 2],
+				Some(1002),
+			),
+			("length", &[0x81, 0x7e, 0x00, 0x02, b'h', b'i'], Some(1002)),
+		];
+		for &(case, input, expected) in cases {
+			let mut client = raw_client(input.to_vec());
+			client.set_strict(true);
+			let result = client.recv_message();
+			let written = (client.into_stream().0).1.into_inner();
+			let close = match expected {
+				Some(close) => close,
+				None => {
+					assert!(result.is_ok(), "case {}: {:?}", case, result);
+					continue;
+				}
+			};
+			assert!(result.is_err(), "case {} must fail", case);
+			let frame = DataFrame::read_dataframe(&mut &written[..], true).unwrap();
+			assert_eq!(frame.opcode, Opcode::Close, "case {}", case);
+			assert_eq!(frame.data, close.to_be_bytes(), "case {}", case);
+		}
+
+		// pings are answered (case 2.2)
+		let mut client = raw_client(vec![0x89, 0x02, b'h', b'i', 0x81, 0x00]);
+		client.set_strict(true);
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Text(String::new())
+		);
+		let written = (client.into_stream().0).1.into_inner();
+		let pong = DataFrame::read_dataframe(&mut &written[..], true).unwrap();
+		assert_eq!(pong, DataFrame::new(true, Opcode::Pong, b"hi".to_vec()));
+
+		// by default the length is accepted and failures are only returned
+		let mut client = raw_client(vec![0x81, 0x7e, 0x00, 0x02, b'h', b'i']);
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Text("hi".to_string())
+		);
+		let mut client = raw_client(vec![0xc1, 0x02, b'h', b'i']);
+		assert!(client.recv_message().is_err());
+		assert!((client.into_stream().0).1.into_inner().is_empty());
+
+		// the checks of strict mode can be turned off on their own
+		let mut client = raw_client(vec![0x81, 0x7e, 0x00, 0x02, b'h', b'i']);
+		client.set_strict(true);
+		client.set_minimal_lengths(false);
+		assert!(client.recv_message().is_ok());
+	}
+
+	#[test]
+	fn close_handshake_times_out() {
+		let (mut client, peer) = connected(|mut peer| {
+			// read the close message but never answer it
+			match peer.recv_message().unwrap() {
+				OwnedMessage::Close(None) => (),
+				message => panic!("expected a close message, got {:?}", message),
+			}
+			match peer.recv_message() {
+				Err(WebSocketError::NoDataAvailable) => (),
+				other => panic!("the connection must be shut down, got {:?}", other),
+			}
+		});
+
+		client.set_close_timeout(Duration::from_millis(100));
+		let start = Instant::now();
+		match client.close() {
+			Err(WebSocketError::Timeout) => (),
+			other => panic!("expected a timeout, got {:?}", other),
+		}
+		assert!(start.elapsed() < Duration::from_secs(5));
+		peer.join().unwrap();
+	}
+
+	#[test]
+	fn socket_addresses() {
+		use client::builder::ClientBuilder;
+		use server::sync::Server;
+		use std::sync::mpsc::channel;
+		use std::thread;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+		let (tx, rx) = channel();
+		let peer = thread::spawn(move || {
+			let upgrade = server.accept().ok().unwrap();
+			let upgrade_addr = upgrade.peer_addr().unwrap();
+			let client = upgrade.accept().unwrap();
+			tx.send((
+				upgrade_addr,
+				client.peer_addr().unwrap(),
+				client.local_addr().unwrap(),
+			))
+			.unwrap();
+		});
+
+		let client = ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.connect_insecure()
+			.unwrap();
+		let (upgrade_addr, server_peer, server_local) = rx.recv().unwrap();
+		peer.join().unwrap();
+
+		assert_eq!(client.peer_addr().unwrap(), addr);
+		assert_eq!(server_local, addr);
+		assert_eq!(server_peer, client.local_addr().unwrap());
+		assert_eq!(upgrade_addr, client.local_addr().unwrap());
+	}
+
+	#[test]
+	fn socket_addresses_need_a_socket() {
+		let client = raw_client(Vec::new());
+		let error = client.peer_addr().unwrap_err();
+		assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+		let error = client.local_addr().unwrap_err();
+		assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+	}
+
+	#[test]
+	fn stream_access() {
+		use std::net::TcpListener;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		let mut client = Client::unchecked(BufReader::new(stream), Headers::new(), true, false);
+
+		client.get_mut().set_nodelay(true).unwrap();
+		assert!(client.get_ref().nodelay().unwrap());
+		client.get_mut().set_nodelay(false).unwrap();
+		assert!(!client.stream_ref().nodelay().unwrap());
+	}
+
+	#[test]
+	fn into_stream_keeps_unread_bytes() {
+		let mut input = Vec::new();
+		DataFrame::new(true, Opcode::Text, b"first".to_vec())
+			.write_to(&mut input, false)
+			.unwrap();
+		let mut second = Vec::new();
+		DataFrame::new(true, Opcode::Text, b"second".to_vec())
+			.write_to(&mut second, false)
+			.unwrap();
+		input.extend_from_slice(&second);
+
+		let mut client = raw_client(input);
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Text("first".to_string())
+		);
+		let (_, buffer) = client.into_stream();
+		let (buf, pos, cap) = buffer.unwrap();
+		assert_eq!(&buf[pos..cap], &second[..]);
+	}
+
+	#[test]
+	fn split_halves_run_concurrently() {
+		use std::thread;
+
+		let (client, peer) = connected(|mut peer| loop {
+			let message = peer.recv_message().unwrap();
+			peer.send_message(&message).unwrap();
+			if message.is_close() {
+				break;
+			}
+		});
+		let (mut reader, mut writer) = client.split().unwrap();
+		let received = thread::spawn(move || {
+			let mut received = Vec::new();
+			for message in reader.incoming_messages() {
+				match message.unwrap() {
+					OwnedMessage::Close(_) => break,
+					message => received.push(message),
+				}
+			}
+			received
+		});
+
+		let messages: Vec<_> = (0..50)
+			.map(|i| OwnedMessage::Text(format!("message {}", i)))
+			.collect();
+		for message in &messages {
+			writer.send_message(message).unwrap();
+		}
+		writer.send_message(&OwnedMessage::Close(None)).unwrap();
+
+		assert_eq!(received.join().unwrap(), messages);
+		peer.join().unwrap();
+	}
+
+	#[test]
+	fn keepalive_pings() {
+		let interval = Duration::from_millis(50);
+		let (mut client, peer) = connected(move |mut peer| {
+			let mut pings = Vec::new();
+			loop {
+				let frame = peer.recv_dataframe().unwrap();
+				match frame.opcode {
+					Opcode::Ping => pings.push(Instant::now()),
+					Opcode::Close => break,
+					opcode => panic!("unexpected {:?} frame", opcode),
+				}
+				// answer the first two pings only
+				if pings.len() <= 2 {
+					peer.send_message(&OwnedMessage::Pong(frame.data)).unwrap();
+				}
+			}
+			assert_eq!(pings.len(), 3);
+			for pair in pings.windows(2) {
+				assert!(pair[1] - pair[0] >= interval);
+			}
+		});
+		client.set_ping_interval(Some(interval));
+		client.set_pong_timeout(Duration::from_millis(100));
+		client
+			.set_read_timeout(Some(Duration::from_secs(5)))
+			.unwrap();
+
+		let started = Instant::now();
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Pong(Vec::new())
+		);
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Pong(Vec::new())
+		);
+		assert!(started.elapsed() >= interval * 2);
+		match client.recv_message() {
+			Err(WebSocketError::Timeout) => (),
+			_ => panic!("a missing pong must close the connection"),
+		}
+		assert!(started.elapsed() < Duration::from_secs(5));
+		peer.join().unwrap();
+		assert!(client.recv_message().is_err());
+	}
+
+	#[test]
+	fn custom_heartbeat_ticks() {
+		use std::sync::mpsc::{channel, Sender as ChannelSender};
+
+		// sends an application level heartbeat on every tick
+		struct Beat {
+			sent: u32,
+			received: ChannelSender<OwnedMessage>,
+		}
+
+		impl Heartbeat for Beat {
+			fn on_start(&mut self) -> Action {
+				Action::TickAfter(Duration::from_millis(20))
+			}
+
+			fn on_tick(&mut self, sender: &mut HeartbeatSender) -> Action {
+				self.sent += 1;
+				sender.send_message(&OwnedMessage::Text(format!("beat {}", self.sent)));
+				Action::TickAfter(Duration::from_millis(20))
+			}
+
+			fn on_message(&mut self, message: &OwnedMessage) -> Action {
+				self.received.send(message.clone()).unwrap();
+				Action::Continue
+			}
+		}
+
+		let (mut client, peer) = connected(|mut peer| {
+			for i in 1..=3 {
+				assert_eq!(
+					peer.recv_message().unwrap(),
+					OwnedMessage::Text(format!("beat {}", i))
+				);
+			}
+			peer.send_message(&OwnedMessage::Text("enough".to_string()))
+				.unwrap();
+		});
+		let (tx, rx) = channel();
+		client.set_heartbeat(Some(Box::new(Beat {
+			sent: 0,
+			received: tx,
+		})));
+		client
+			.set_read_timeout(Some(Duration::from_secs(5)))
+			.unwrap();
+
+		let message = OwnedMessage::Text("enough".to_string());
+		assert_eq!(client.recv_message().unwrap(), message);
+		assert_eq!(rx.try_recv().unwrap(), message);
+		peer.join().unwrap();
+	}
+}
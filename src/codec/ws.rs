@@ -11,18 +11,23 @@ use std::borrow::Borrow;
 use std::io::Cursor;
 use std::marker::PhantomData;
 use std::mem;
+use std::time::Instant;
 
 use bytes::BufMut;
 use bytes::BytesMut;
 use tokio_codec::Decoder;
 use tokio_codec::Encoder;
 
-use dataframe::DataFrame;
+use dataframe::{self, DataFrame, Opcode};
+#[cfg(feature = "deflate")]
+use deflate::{Compressor, Decompressor, DeflateConfig};
 use message::OwnedMessage;
+use ratelimit::{RateLimit, RateLimiter};
 use result::WebSocketError;
 use ws::dataframe::DataFrame as DataFrameTrait;
 use ws::message::Message as MessageTrait;
-use ws::util::header::read_header;
+use ws::util::header::{read_header, DataFrameFlags};
+use ws::util::utf8::Utf8Validator;
 
 /// Even though a websocket connection may look perfectly symmetrical
 /// in reality there are small differences between clients and servers.
@@ -58,6 +63,8 @@ pub enum Context {
 /// clients and the `Server` to make servers.
 pub struct DataFrameCodec<D> {
 	is_server: bool,
+	limiter: Option<RateLimiter>,
+	reserved_bits: [bool; 3],
 	frame_type: PhantomData<D>,
 }
 
@@ -82,17 +89,44 @@ impl<D> DataFrameCodec<D> {
 	pub fn new(context: Context) -> DataFrameCodec<D> {
 		DataFrameCodec {
 			is_server: context == Context::Server,
+			limiter: None,
+			reserved_bits: [false; 3],
 			frame_type: PhantomData,
 		}
 	}
-}
 
-impl<D> Decoder for DataFrameCodec<D> {
-	type Item = DataFrame;
-	type Error = WebSocketError;
+	/// Count the decoded frames against a rate limit, decoding fails with
+	/// `WebSocketError::RateLimitExceeded` if the peer stays over it for longer
+	/// than `RateLimit::close_after`.
+	///
+	/// The codec itself never waits, wrap the client in
+	/// `async::client::RateLimited` to be paced by the limit.
+	pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+		self.limiter = limit.map(RateLimiter::new);
+	}
+
+	/// The state of the rate limit, if there is one.
+	pub(crate) fn rate_limiter(&self) -> Option<&RateLimiter> {
+		self.limiter.as_ref()
+	}
+
+	/// Declare which of the reserved bits RSV1, RSV2 and RSV3 are used by
+	/// the negotiated extensions. Decoding a frame with any other reserved
+	/// bit set is a protocol error. By default no reserved bits are allowed.
+	pub fn set_reserved_bits(&mut self, reserved_bits: [bool; 3]) {
+		self.reserved_bits = reserved_bits;
+	}
+}
 
+impl<D> DataFrameCodec<D> {
+	// fails with `MessageTooBig` as soon as the header of a data frame announces
+	// a payload of more than `max_len` bytes, before waiting for the payload
 	// TODO: do not retry to read the header on each new data (keep a buffer)
-	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+	fn decode_frame(
+		&mut self,
+		src: &mut BytesMut,
+		max_len: Option<u64>,
+	) -> Result<Option<DataFrame>, WebSocketError> {
 		let (header, bytes_read) = {
 			// we'll make a fake reader and keep track of the bytes read
 			let mut reader = Cursor::new(src.as_ref());
@@ -106,6 +140,24 @@ impl<D> Decoder for DataFrameCodec<D> {
 
 			(header, reader.position())
 		};
+		dataframe::check_masking(&header, self.is_server)?;
+		let reserved = [
+			header.flags.contains(DataFrameFlags::RSV1),
+			header.flags.contains(DataFrameFlags::RSV2),
+			header.flags.contains(DataFrameFlags::RSV3),
+		];
+		let unexpected = reserved
+			.iter()
+			.zip(self.reserved_bits.iter())
+			.any(|(&set, &allowed)| set && !allowed);
+		if unexpected {
+			return Err(WebSocketError::ProtocolError(
+				"Unsupported reserved bits received",
+			));
+		}
+		if header.opcode < 8 && max_len.is_some_and(|max| header.len > max) {
+			return Err(WebSocketError::MessageTooBig);
+		}
 
 		// check if we have enough bytes to continue
 		if header.len + bytes_read > src.len() as u64 {
@@ -115,6 +167,9 @@ impl<D> Decoder for DataFrameCodec<D> {
 		// TODO: using usize is not the right thing here (can be larger)
 		let _ = src.split_to(bytes_read as usize);
 		let body = src.split_to(header.len as usize).to_vec();
+		if let Some(ref mut limiter) = self.limiter {
+			limiter.take(body.len(), Instant::now())?;
+		}
 
 		// construct a dataframe
 		Ok(Some(DataFrame::read_dataframe_body(
@@ -125,6 +180,15 @@ impl<D> Decoder for DataFrameCodec<D> {
 	}
 }
 
+impl<D> Decoder for DataFrameCodec<D> {
+	type Item = DataFrame;
+	type Error = WebSocketError;
+
+	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+		self.decode_frame(src, None)
+	}
+}
+
 impl<D> Encoder for DataFrameCodec<D>
 where
 	D: Borrow<DataFrameTrait>,
@@ -199,6 +263,13 @@ where
 {
 	buffer: Vec<DataFrame>,
 	dataframe_codec: DataFrameCodec<DataFrame>,
+	utf8: Utf8Validator,
+	max_message_size: Option<usize>,
+	// kept for the whole connection, the peers may take over their context
+	#[cfg(feature = "deflate")]
+	compressor: Option<Compressor>,
+	#[cfg(feature = "deflate")]
+	decompressor: Option<Decompressor>,
 	message_type: PhantomData<fn(M)>,
 }
 
@@ -230,9 +301,61 @@ where
 		MessageCodec {
 			buffer: Vec::new(),
 			dataframe_codec: DataFrameCodec::new(context),
+			utf8: Utf8Validator::new(),
+			max_message_size: None,
+			#[cfg(feature = "deflate")]
+			compressor: None,
+			#[cfg(feature = "deflate")]
+			decompressor: None,
 			message_type: PhantomData,
 		}
 	}
+
+	// compresses and inflates messages with the parameters of `permessage-deflate`
+	// negotiated during the handshake
+	#[cfg(feature = "deflate")]
+	pub(crate) fn use_deflate(&mut self, config: &DeflateConfig) {
+		let is_server = self.dataframe_codec.is_server;
+		// the decompressor consumes the RSV1 bit
+		self.dataframe_codec.reserved_bits[0] = true;
+		self.compressor = Some(config.compressor(is_server));
+		self.decompressor = Some(config.decompressor(is_server));
+	}
+
+	/// If `permessage-deflate` was negotiated, send data messages smaller than
+	/// `threshold` bytes uncompressed, see `sync::Client::set_compression_threshold`.
+	#[cfg(feature = "deflate")]
+	pub fn set_compression_threshold(&mut self, threshold: usize) {
+		if let Some(ref mut compressor) = self.compressor {
+			compressor.set_threshold(threshold);
+		}
+	}
+
+	/// Limit the total payload size of a received message, including all of its
+	/// fragments, decoding a bigger message fails with `WebSocketError::MessageTooBig`.
+	/// Compressed messages are limited while they are inflated.
+	/// By default the size of messages is not limited.
+	pub fn set_max_message_size(&mut self, max_message_size: Option<usize>) {
+		self.max_message_size = max_message_size;
+	}
+
+	/// Allow received frames to have the reserved bits RSV1, RSV2 and RSV3 set,
+	/// see `DataFrameCodec::set_reserved_bits`. `permessage-deflate` sets up
+	/// RSV1 itself.
+	pub fn set_reserved_bits(&mut self, reserved_bits: [bool; 3]) {
+		self.dataframe_codec.set_reserved_bits(reserved_bits);
+	}
+
+	/// Count the received frames against a rate limit,
+	/// see `DataFrameCodec::set_rate_limit`.
+	pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+		self.dataframe_codec.set_rate_limit(limit);
+	}
+
+	/// The state of the rate limit, if there is one.
+	pub(crate) fn rate_limiter(&self) -> Option<&RateLimiter> {
+		self.dataframe_codec.rate_limiter()
+	}
 }
 
 impl<M> Decoder for MessageCodec<M>
@@ -243,9 +366,33 @@ where
 	type Error = WebSocketError;
 
 	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-		while let Some(frame) = self.dataframe_codec.decode(src)? {
+		loop {
+			// the fragments received so far count against the size of the message
+			let max_len = self.max_message_size.map(|max| {
+				let size: usize = self.buffer.iter().map(|f| f.data.len()).sum();
+				max.saturating_sub(size) as u64
+			});
+			let frame = match self.dataframe_codec.decode_frame(src, max_len) {
+				Ok(Some(frame)) => frame,
+				Ok(None) => return Ok(None),
+				Err(e) => {
+					self.utf8 = Utf8Validator::new();
+					self.buffer.clear();
+					return Err(e);
+				}
+			};
 			let is_first = self.buffer.is_empty();
 			let finished = frame.finished;
+			if frame.reserved[0]
+				&& self.is_decompressing()
+				&& (frame.opcode as u8 == 0 || frame.opcode as u8 >= 8)
+			{
+				self.buffer.clear();
+				self.utf8 = Utf8Validator::new();
+				return Err(WebSocketError::ProtocolError(
+					"RSV1 set on a control or continuation frame",
+				));
+			}
 
 			match frame.opcode as u8 {
 				// continuation code
@@ -260,6 +407,8 @@ where
 				}
 				// data frame
 				1...7 if !is_first => {
+					self.buffer.clear();
+					self.utf8 = Utf8Validator::new();
 					return Err(WebSocketError::ProtocolError(
 						"Unexpected data frame opcode",
 					));
@@ -270,13 +419,85 @@ where
 				}
 			};
 
+			// validate text messages as they arrive
+			let compressed = self.buffer[0].reserved[0] && self.is_decompressing();
+			if self.buffer[0].opcode == Opcode::Text && !compressed {
+				let last = &self.buffer[self.buffer.len() - 1];
+				let mut result = self.utf8.feed(&last.data);
+				if finished {
+					result = result.and_then(|()| self.utf8.finish());
+				}
+				if let Err(e) = result {
+					self.utf8 = Utf8Validator::new();
+					self.buffer.clear();
+					return Err(e.into());
+				}
+			}
+
 			if finished {
 				let buffer = mem::replace(&mut self.buffer, Vec::new());
+				let buffer = self.inflate(buffer)?;
 				return Ok(Some(OwnedMessage::from_dataframes(buffer)?));
 			}
 		}
+	}
+}
 
-		Ok(None)
+impl<M> MessageCodec<M>
+where
+	M: MessageTrait,
+{
+	// compressed messages are validated once inflated
+	#[cfg(feature = "deflate")]
+	fn inflate(&mut self, frames: Vec<DataFrame>) -> Result<Vec<DataFrame>, WebSocketError> {
+		match self.decompressor {
+			Some(ref mut decompressor) => {
+				decompressor.decompress_frames(frames, self.max_message_size)
+			}
+			None => Ok(frames),
+		}
+	}
+
+	#[cfg(feature = "deflate")]
+	fn is_decompressing(&self) -> bool {
+		self.decompressor.is_some()
+	}
+
+	#[cfg(not(feature = "deflate"))]
+	fn is_decompressing(&self) -> bool {
+		false
+	}
+
+	#[cfg(not(feature = "deflate"))]
+	fn inflate(&mut self, frames: Vec<DataFrame>) -> Result<Vec<DataFrame>, WebSocketError> {
+		Ok(frames)
+	}
+
+	#[cfg(feature = "deflate")]
+	fn encode_compressed(&mut self, item: &M, dst: &mut BytesMut) -> Result<bool, WebSocketError> {
+		let compressor = match self.compressor {
+			Some(ref mut compressor) => compressor,
+			None => return Ok(false),
+		};
+
+		let masked = !self.dataframe_codec.is_server;
+		for frame in compressor.compress_frames(item.dataframes()?)? {
+			let frame_size = frame.frame_size(masked);
+			if frame_size > dst.remaining_mut() {
+				dst.reserve(frame_size);
+			}
+			frame.write_to(&mut dst.writer(), masked)?;
+		}
+		Ok(true)
+	}
+
+	#[cfg(not(feature = "deflate"))]
+	fn encode_compressed(
+		&mut self,
+		_item: &M,
+		_dst: &mut BytesMut,
+	) -> Result<bool, WebSocketError> {
+		Ok(false)
 	}
 }
 
@@ -288,6 +509,9 @@ where
 	type Error = WebSocketError;
 
 	fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+		if self.encode_compressed(&item, dst)? {
+			return Ok(());
+		}
 		let masked = !self.dataframe_codec.is_server;
 		let frame_size = item.message_size(masked);
 		if frame_size > dst.remaining_mut() {
@@ -428,4 +652,250 @@ mod tests {
 
 		runtime.block_on(f).unwrap();
 	}
+
+	#[test]
+	fn message_codec_validates_utf8_early() {
+		let mut codec = MessageCodec::<OwnedMessage>::default(Context::Client);
+		let mut src = BytesMut::new();
+		let mut input = Vec::new();
+		DataFrame::new(false, Opcode::Text, vec![0xe2, 0x82])
+			.write_to(&mut input, false)
+			.unwrap();
+		src.extend_from_slice(&input);
+		assert!(codec.decode(&mut src).unwrap().is_none());
+
+		let mut input = Vec::new();
+		DataFrame::new(false, Opcode::Continuation, vec![0x28])
+			.write_to(&mut input, false)
+			.unwrap();
+		src.extend_from_slice(&input);
+		match codec.decode(&mut src) {
+			Err(WebSocketError::Utf8Error(_)) => (),
+			other => panic!("expected Utf8Error, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn codecs_check_masking_before_the_payload() {
+		for &(context, header) in &[
+			(Context::Server, [0x82, 100]),
+			(Context::Client, [0x82, 0x80 | 100]),
+		] {
+			let mut src = BytesMut::from(&header[..]);
+			src.extend_from_slice(&[1, 2, 3, 4]);
+			match MessageCodec::<OwnedMessage>::default(context).decode(&mut src) {
+				Err(WebSocketError::ProtocolError(_)) => (),
+				other => panic!("expected a protocol error, got {:?}", other),
+			}
+		}
+	}
+
+	#[test]
+	fn codecs_reject_reserved_bits() {
+		for i in 0..3 {
+			let mut frame = DataFrame::new(true, Opcode::Binary, vec![1, 2, 3]);
+			frame.reserved[i] = true;
+			let mut input = Vec::new();
+			frame.write_to(&mut input, false).unwrap();
+
+			let mut src = BytesMut::from(&input[..]);
+			match MessageCodec::<OwnedMessage>::default(Context::Client).decode(&mut src) {
+				Err(WebSocketError::ProtocolError(_)) => (),
+				other => panic!("expected ProtocolError, got {:?}", other),
+			}
+
+			let mut allowed = [false; 3];
+			allowed[i] = true;
+			let mut codec = DataFrameCodec::default(Context::Client);
+			codec.set_reserved_bits(allowed);
+			let mut src = BytesMut::from(&input[..]);
+			assert_eq!(codec.decode(&mut src).unwrap(), Some(frame));
+		}
+	}
+
+	#[test]
+	fn message_codec_validates_utf8_with_rsv1_but_no_decompressor() {
+		let mut frame = DataFrame::new(true, Opcode::Text, vec![0xff]);
+		frame.reserved[0] = true;
+		let mut input = Vec::new();
+		frame.write_to(&mut input, false).unwrap();
+
+		let mut codec = MessageCodec::<OwnedMessage>::default(Context::Client);
+		codec.set_reserved_bits([true, false, false]);
+		let mut src = BytesMut::from(&input[..]);
+		match codec.decode(&mut src) {
+			Err(WebSocketError::Utf8Error(_)) => (),
+			other => panic!("expected Utf8Error, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn message_codec_checks_fragment_sequence() {
+		let mut codec = MessageCodec::<OwnedMessage>::default(Context::Client);
+		let mut src = BytesMut::new();
+		let mut input = Vec::new();
+		DataFrame::new(true, Opcode::Continuation, vec![])
+			.write_to(&mut input, false)
+			.unwrap();
+		src.extend_from_slice(&input);
+		match codec.decode(&mut src) {
+			Err(WebSocketError::ProtocolError(_)) => (),
+			other => panic!("expected a protocol error, got {:?}", other),
+		}
+
+		let mut input = Vec::new();
+		DataFrame::new(false, Opcode::Text, vec![0xce])
+			.write_to(&mut input, false)
+			.unwrap();
+		DataFrame::new(true, Opcode::Text, vec![])
+			.write_to(&mut input, false)
+			.unwrap();
+		DataFrame::new(true, Opcode::Text, b"ok".to_vec())
+			.write_to(&mut input, false)
+			.unwrap();
+		src.extend_from_slice(&input);
+		match codec.decode(&mut src) {
+			Err(WebSocketError::ProtocolError(_)) => (),
+			other => panic!("expected a protocol error, got {:?}", other),
+		}
+		assert_eq!(
+			codec.decode(&mut src).unwrap(),
+			Some(OwnedMessage::Text("ok".to_string()))
+		);
+	}
+
+	#[test]
+	fn message_codec_limits_message_size() {
+		let mut codec = MessageCodec::<OwnedMessage>::default(Context::Client);
+		codec.set_max_message_size(Some(4));
+		let mut input = Vec::new();
+		DataFrame::new(false, Opcode::Binary, vec![1, 2, 3])
+			.write_to(&mut input, false)
+			.unwrap();
+		DataFrame::new(true, Opcode::Continuation, vec![4, 5])
+			.write_to(&mut input, false)
+			.unwrap();
+		let mut src = BytesMut::from(&input[..]);
+		match codec.decode(&mut src) {
+			Err(WebSocketError::MessageTooBig) => (),
+			other => panic!("expected MessageTooBig, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn message_codec_limits_message_size_before_the_payload() {
+		let mut codec = MessageCodec::<OwnedMessage>::default(Context::Client);
+		codec.set_max_message_size(Some(4));
+		let mut input = Vec::new();
+		DataFrame::new(false, Opcode::Binary, vec![1, 2, 3])
+			.write_to(&mut input, false)
+			.unwrap();
+		// a continuation announcing 2^62 bytes, none of which are sent
+		input.extend_from_slice(&[0x80, 127, 0x40, 0, 0, 0, 0, 0, 0, 0]);
+		let mut src = BytesMut::from(&input[..]);
+		match codec.decode(&mut src) {
+			Err(WebSocketError::MessageTooBig) => (),
+			other => panic!("expected MessageTooBig, got {:?}", other),
+		}
+	}
+
+	#[test]
+	#[cfg(feature = "deflate")]
+	fn message_codec_limits_inflated_messages() {
+		let mut client = MessageCodec::<OwnedMessage>::default(Context::Client);
+		client.use_deflate(&DeflateConfig::default());
+		client.set_max_message_size(Some(1 << 16));
+
+		// a megabyte of zeros compressed to a few KiB
+		let mut server = MessageCodec::<OwnedMessage>::default(Context::Server);
+		server.use_deflate(&DeflateConfig::default());
+		let mut src = BytesMut::new();
+		server
+			.encode(OwnedMessage::Binary(vec![0; 1 << 20]), &mut src)
+			.unwrap();
+		assert!(src.len() < 1 << 16);
+		match client.decode(&mut src) {
+			Err(WebSocketError::MessageTooBig) => (),
+			other => panic!("expected MessageTooBig, got {:?}", other.map(|_| ())),
+		}
+	}
+
+	#[test]
+	#[cfg(feature = "deflate")]
+	fn message_codec_rejects_rsv1_on_control_and_continuation_frames() {
+		let mut ping = DataFrame::new(true, Opcode::Ping, vec![]);
+		ping.reserved[0] = true;
+		let mut continuation = DataFrame::new(true, Opcode::Continuation, vec![]);
+		continuation.reserved[0] = true;
+		let messages = vec![
+			vec![ping],
+			vec![
+				DataFrame::new(false, Opcode::Text, b"a".to_vec()),
+				continuation,
+			],
+		];
+
+		for frames in messages {
+			let mut codec = MessageCodec::<OwnedMessage>::default(Context::Server);
+			codec.use_deflate(&DeflateConfig::default());
+			let mut src = BytesMut::new();
+			for frame in frames {
+				frame.write_to(&mut (&mut src).writer(), true).unwrap();
+			}
+			match codec.decode(&mut src) {
+				Err(WebSocketError::ProtocolError(_)) => (),
+				other => panic!("expected ProtocolError, got {:?}", other),
+			}
+		}
+	}
+
+	#[test]
+	#[cfg(feature = "deflate")]
+	fn message_codec_keeps_the_deflate_context() {
+		let config = DeflateConfig::default();
+		let mut client = MessageCodec::<OwnedMessage>::default(Context::Client);
+		let mut server = MessageCodec::<OwnedMessage>::default(Context::Server);
+		client.use_deflate(&config);
+		server.use_deflate(&config);
+
+		let message = OwnedMessage::Text("compress me, ".repeat(20));
+		let mut sizes = Vec::new();
+		for _ in 0..3 {
+			let mut wire = BytesMut::new();
+			client.encode(message.clone(), &mut wire).unwrap();
+			assert!(wire[0] & 0x40 != 0, "RSV1 should be set");
+			sizes.push(wire.len());
+
+			// the message arrives a byte at a time
+			let mut src = BytesMut::new();
+			let mut decoded = None;
+			for byte in wire.iter() {
+				assert_eq!(decoded, None);
+				src.extend_from_slice(&[*byte]);
+				decoded = server.decode(&mut src).unwrap();
+			}
+			assert_eq!(decoded, Some(message.clone()));
+		}
+		// later messages refer back to the earlier ones
+		assert!(sizes[0] < message.message_size(true));
+		assert!(sizes[1] < sizes[0]);
+
+		// small and control messages are sent as they are
+		let mut wire = BytesMut::new();
+		server
+			.encode(OwnedMessage::Ping(vec![1, 2, 3]), &mut wire)
+			.unwrap();
+		server
+			.encode(OwnedMessage::Text("hi".to_string()), &mut wire)
+			.unwrap();
+		assert_eq!(wire[0] & 0x40, 0);
+		assert_eq!(
+			client.decode(&mut wire).unwrap(),
+			Some(OwnedMessage::Ping(vec![1, 2, 3]))
+		);
+		assert_eq!(
+			client.decode(&mut wire).unwrap(),
+			Some(OwnedMessage::Text("hi".to_string()))
+		);
+	}
 }
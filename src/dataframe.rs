@@ -1,11 +1,14 @@
 //! Module containing the default implementation of data frames.
 use result::{WebSocketError, WebSocketResult};
+use std::cmp;
 use std::io::{self, Read, Write};
 use ws::dataframe::DataFrame as DataFrameable;
 use ws::util::header as dfh;
 use ws::util::header::DataFrameHeader;
 use ws::util::mask;
 
+const MAX_INITIAL_CAPACITY: u64 = 64 * 1024;
+
 /// Represents a WebSocket data frame.
 ///
 /// The data held in a DataFrame is never masked.
@@ -43,7 +46,7 @@ impl DataFrame {
 	/// take many of these and create a websocket message.
 	pub fn read_dataframe_body(
 		header: DataFrameHeader,
-		body: Vec<u8>,
+		mut body: Vec<u8>,
 		should_be_masked: bool,
 	) -> WebSocketResult<Self> {
 		let finished = header.flags.contains(dfh::DataFrameFlags::FIN);
@@ -54,23 +57,15 @@ impl DataFrame {
 			header.flags.contains(dfh::DataFrameFlags::RSV3),
 		];
 
-		let opcode = Opcode::new(header.opcode).expect("Invalid header opcode!");
+		let opcode = Opcode::from_u8(header.opcode)?;
 
+		check_masking(&header, should_be_masked)?;
 		let data = match header.mask {
 			Some(mask) => {
-				if !should_be_masked {
-					return Err(WebSocketError::DataFrameError(
-						"Expected unmasked data frame",
-					));
-				}
-				mask::mask_data(mask, &body)
-			}
-			None => {
-				if should_be_masked {
-					return Err(WebSocketError::DataFrameError("Expected masked data frame"));
-				}
+				mask::apply_mask(mask, &mut body);
 				body
 			}
+			None => body,
 		};
 
 		Ok(DataFrame {
@@ -83,12 +78,63 @@ impl DataFrame {
 
 	/// Reads a DataFrame from a Reader.
 	pub fn read_dataframe<R>(reader: &mut R, should_be_masked: bool) -> WebSocketResult<Self>
+	where
+		R: Read,
+	{
+		DataFrame::read_dataframe_with_limit(reader, should_be_masked, None)
+	}
+
+	/// Reads a DataFrame from a Reader, failing with a `DataFrameError` before
+	/// anything is allocated for the payload if the length announced in the header
+	/// is bigger than `max_len`. `None` reads frames of any length like `read_dataframe`.
+	///
+	/// Only the header of a rejected frame is read from `reader`, so the frames
+	/// following it can't be read anymore.
+	///
+	/// ```rust
+	/// # use websocket::dataframe::DataFrame;
+	/// // a binary frame claiming a payload of 2^63 - 1 bytes
+	/// let huge = [0x82, 0x7f, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+	/// assert!(DataFrame::read_dataframe_with_limit(&mut &huge[..], false, Some(1 << 20)).is_err());
+	/// ```
+	pub fn read_dataframe_with_limit<R>(
+		reader: &mut R,
+		should_be_masked: bool,
+		max_len: Option<usize>,
+	) -> WebSocketResult<Self>
 	where
 		R: Read,
 	{
 		let header = dfh::read_header(reader)?;
+		DataFrame::read_payload_with_limit(header, reader, should_be_masked, max_len)
+	}
+
+	/// Reads the payload of the frame whose `header` was read already from `reader`,
+	/// checking it like `read_dataframe_with_limit`.
+	pub(crate) fn read_payload_with_limit<R>(
+		header: DataFrameHeader,
+		reader: &mut R,
+		should_be_masked: bool,
+		max_len: Option<usize>,
+	) -> WebSocketResult<Self>
+	where
+		R: Read,
+	{
+		Opcode::from_u8(header.opcode)?;
+		check_masking(&header, should_be_masked)?;
+
+		if let Some(max_len) = max_len {
+			if header.len > max_len as u64 {
+				return Err(WebSocketError::DataFrameError(
+					"Data frame payload exceeds the maximum frame size",
+				));
+			}
+		}
 
-		let mut data: Vec<u8> = Vec::with_capacity(header.len as usize);
+		// the length is untrusted, only reserve enough memory for a reasonably sized
+		// payload and let the buffer grow as the actual data arrives
+		let capacity = cmp::min(header.len, MAX_INITIAL_CAPACITY) as usize;
+		let mut data: Vec<u8> = Vec::with_capacity(capacity);
 		let read = reader.take(header.len).read_to_end(&mut data)?;
 		if (read as u64) < header.len {
 			return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete payload").into());
@@ -129,6 +175,116 @@ impl DataFrameable for DataFrame {
 	fn take_payload(self) -> Vec<u8> {
 		self.data
 	}
+
+	fn payload_slice(&self) -> Option<&[u8]> {
+		Some(&self.data)
+	}
+}
+
+/// Builds a `DataFrame` with full control over its opcode, FIN and RSV bits
+/// and payload, e.g. to test how a peer handles edge cases of the protocol
+/// or to implement an extension.
+///
+/// The frame is sent as it is with `send_dataframe`, `build` only refuses
+/// frames which no endpoint may send.
+///
+/// ```rust
+/// use websocket::dataframe::{DataFrameBuilder, Opcode};
+///
+/// let first = DataFrameBuilder::new(Opcode::Text)
+///     .finished(false)
+///     .reserved([false, true, false])
+///     .payload("Hello, ")
+///     .build()
+///     .unwrap();
+/// let last = DataFrameBuilder::new(Opcode::Continuation)
+///     .payload("World!")
+///     .build()
+///     .unwrap();
+/// assert!(!first.finished && first.reserved[1] && last.finished);
+///
+/// // control frames can't be fragmented
+/// assert!(DataFrameBuilder::new(Opcode::Ping).finished(false).build().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataFrameBuilder {
+	frame: DataFrame,
+}
+
+impl DataFrameBuilder {
+	/// Start a frame with `opcode`, by default it is the last frame of its message,
+	/// has no reserved bits set and an empty payload.
+	pub fn new(opcode: Opcode) -> Self {
+		DataFrameBuilder {
+			frame: DataFrame::new(true, opcode, Vec::new()),
+		}
+	}
+
+	/// Set whether this is the last frame of its message (the FIN bit).
+	pub fn finished(mut self, finished: bool) -> Self {
+		self.frame.finished = finished;
+		self
+	}
+
+	/// Set the RSV1, RSV2 and RSV3 bits.
+	pub fn reserved(mut self, reserved: [bool; 3]) -> Self {
+		self.frame.reserved = reserved;
+		self
+	}
+
+	/// Set the unmasked payload.
+	pub fn payload<P>(mut self, payload: P) -> Self
+	where
+		P: Into<Vec<u8>>,
+	{
+		self.frame.data = payload.into();
+		self
+	}
+
+	/// Create the frame, failing with `WebSocketError::DataFrameError` if it is
+	/// a fragmented control frame, a control frame with more than 125 bytes of
+	/// payload or a close frame with a payload too short for its status code.
+	pub fn build(self) -> WebSocketResult<DataFrame> {
+		let frame = self.frame;
+		if frame.opcode as u8 >= 8 {
+			if !frame.finished {
+				return Err(WebSocketError::DataFrameError(
+					"Control frames must not be fragmented",
+				));
+			}
+			if frame.data.len() > 125 {
+				return Err(WebSocketError::DataFrameError(
+					"Control frame payloads must not be longer than 125 bytes",
+				));
+			}
+		}
+		if frame.opcode == Opcode::Close && frame.data.len() == 1 {
+			return Err(WebSocketError::DataFrameError(
+				"Close frame payloads must start with a status code",
+			));
+		}
+		Ok(frame)
+	}
+}
+
+/// Checks that a data frame is masked as it should be for the receiving end,
+/// frames sent by clients must be masked and frames sent by servers must not.
+/// `should_be_masked` is true when receiving as a server.
+///
+/// A violation is a `WebSocketError::ProtocolError`.
+pub(crate) fn check_masking(
+	header: &DataFrameHeader,
+	should_be_masked: bool,
+) -> WebSocketResult<()> {
+	match (header.mask, should_be_masked) {
+		(Some(_), false) => Err(WebSocketError::ProtocolError(
+			"Masked data frame received from a server",
+		)),
+		(None, true) => Err(WebSocketError::ProtocolError(
+			"Unmasked data frame received from a client",
+		)),
+		_ => Ok(()),
+	}
 }
 
 /// Represents a WebSocket data frame opcode
@@ -194,6 +350,31 @@ impl Opcode {
 			_ => return None,
 		})
 	}
+
+	/// Attempts to form an Opcode from a nibble, failing with a protocol error for
+	/// the opcodes reserved for further frame types (3 to 7 and 11 to 15).
+	pub fn from_u8(op: u8) -> WebSocketResult<Opcode> {
+		match Opcode::new(op) {
+			Some(opcode) if opcode.is_reserved() => {
+				Err(WebSocketError::ProtocolError("Reserved opcode received"))
+			}
+			Some(opcode) => Ok(opcode),
+			None => Err(WebSocketError::DataFrameError("Invalid opcode")),
+		}
+	}
+
+	/// Whether this opcode is reserved for further non-control or control frames.
+	pub fn is_reserved(self) -> bool {
+		!matches!(
+			self,
+			Opcode::Continuation
+				| Opcode::Text
+				| Opcode::Binary
+				| Opcode::Close
+				| Opcode::Ping
+				| Opcode::Pong
+		)
+	}
 }
 
 #[cfg(all(feature = "nightly", test))]
@@ -237,6 +418,26 @@ mod tests {
 		assert_eq!(more_payload.unwrap().data, payload);
 	}
 
+	#[test]
+	fn read_with_limit() {
+		let mut huge = vec![0x82u8, 0x7f, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+		huge.extend_from_slice(&[0; 16]);
+		let mut reader = &huge[..];
+		match DataFrame::read_dataframe_with_limit(&mut reader, false, Some(1 << 20)) {
+			Err(WebSocketError::DataFrameError(_)) => (),
+			other => panic!("expected the frame to be rejected, got {:?}", other),
+		}
+		// the payload is left alone
+		assert_eq!(reader.len(), 16);
+
+		let normal = [0x81u8, 0x05, b'h', b'e', b'l', b'l', b'o'];
+		for limit in &[Some(5), None] {
+			let frame = DataFrame::read_dataframe_with_limit(&mut &normal[..], false, *limit);
+			assert_eq!(frame.unwrap().data, b"hello");
+		}
+		assert!(DataFrame::read_dataframe_with_limit(&mut &normal[..], false, Some(4)).is_err());
+	}
+
 	#[bench]
 	fn bench_read_dataframe(b: &mut Bencher) {
 		let data = b"The quick brown fox jumps over the lazy dog";
@@ -282,4 +483,73 @@ mod tests {
 			dataframe.write_to(&mut writer, false).unwrap();
 		});
 	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn build_fragmented_and_reserved_frames() {
+		use receiver::Receiver;
+		use sender::Sender;
+		use ws::receiver::Receiver as ReceiverTrait;
+		use ws::sender::Sender as SenderTrait;
+
+		let frames = vec![
+			DataFrameBuilder::new(Opcode::Binary)
+				.finished(false)
+				.payload(vec![1, 2])
+				.build()
+				.unwrap(),
+			DataFrameBuilder::new(Opcode::Ping)
+				.payload("ping")
+				.build()
+				.unwrap(),
+			DataFrameBuilder::new(Opcode::Continuation)
+				.payload(vec![3])
+				.build()
+				.unwrap(),
+		];
+		let mut wire = Vec::new();
+		let mut sender = Sender::new(true);
+		for frame in &frames {
+			sender.send_dataframe(&mut wire, frame).unwrap();
+		}
+		let mut receiver = Receiver::new(true);
+		let mut stream = &wire[..];
+		for frame in &frames {
+			assert_eq!(&receiver.recv_dataframe(&mut stream).unwrap(), frame);
+		}
+
+		// reserved bits are written as they are
+		let frame = DataFrameBuilder::new(Opcode::Text)
+			.reserved([false, true, true])
+			.payload("x")
+			.build()
+			.unwrap();
+		let mut wire = Vec::new();
+		frame.write_to(&mut wire, false).unwrap();
+		assert_eq!(wire, vec![0xb1, 0x01, b'x']);
+		assert!(Receiver::new(false).recv_dataframe(&mut &wire[..]).is_err());
+		let mut receiver = Receiver::new(false);
+		receiver.set_reserved_bits([false, true, true]);
+		assert_eq!(receiver.recv_dataframe(&mut &wire[..]).unwrap(), frame);
+	}
+
+	#[test]
+	fn build_refuses_invalid_control_frames() {
+		let invalid = vec![
+			DataFrameBuilder::new(Opcode::Ping).finished(false),
+			DataFrameBuilder::new(Opcode::Pong).payload(vec![0; 126]),
+			DataFrameBuilder::new(Opcode::Close).payload(vec![3]),
+		];
+		for builder in invalid {
+			match builder.clone().build() {
+				Err(WebSocketError::DataFrameError(_)) => (),
+				other => panic!("{:?} must be refused, got {:?}", builder, other),
+			}
+		}
+		assert!(DataFrameBuilder::new(Opcode::Pong)
+			.payload(vec![0; 125])
+			.build()
+			.is_ok());
+		assert!(DataFrameBuilder::new(Opcode::Close).build().is_ok());
+	}
 }
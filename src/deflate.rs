@@ -0,0 +1,807 @@
+//! An implementation of the `permessage-deflate` extension
+//! ([RFC 7692](https://tools.ietf.org/html/rfc7692)).
+//!
+//! Compression is negotiated during the handshake: a client offers it with
+//! `ClientBuilder::enable_deflate` and a server accepts the offer by calling
+//! `enable_deflate` on the upgrade request. Both have an `enable_deflate_with`
+//! variant taking a `DeflateConfig`, e.g. to disable context takeover. Once
+//! negotiated, the `Sender` compresses outgoing data messages and the `Receiver`
+//! inflates incoming messages that have the RSV1 bit set, so users keep sending
+//! and receiving plain messages.
+//! Asynchronous clients do the same in their `MessageCodec`.
+//!
+//! The types in this module are only needed when negotiating the extension
+//! by hand, e.g. when performing a custom handshake.
+use std::cmp;
+use std::io;
+
+use dataframe::{DataFrame, Opcode};
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+use header::extensions::{Extension, Parameter};
+use result::{WebSocketError, WebSocketResult};
+
+/// The name of this extension in the `Sec-WebSocket-Extensions` header.
+pub const EXTENSION_NAME: &str = "permessage-deflate";
+
+const SERVER_NO_CONTEXT_TAKEOVER: &str = "server_no_context_takeover";
+const CLIENT_NO_CONTEXT_TAKEOVER: &str = "client_no_context_takeover";
+const SERVER_MAX_WINDOW_BITS: &str = "server_max_window_bits";
+const CLIENT_MAX_WINDOW_BITS: &str = "client_max_window_bits";
+
+/// Messages with a smaller payload (in bytes) are not compressed by default,
+/// see `Compressor::set_threshold`.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 64;
+
+const MIN_WINDOW_BITS: u8 = 8;
+const MAX_WINDOW_BITS: u8 = 15;
+// zlib cannot compress raw deflate streams with an 8 bit window, so we never
+// agree to use one for our own messages
+const MIN_COMPRESS_WINDOW_BITS: u8 = 9;
+
+// the tail of an empty deflate block which is stripped from every compressed
+// message and appended again before inflating it (RFC 7692 7.2.1)
+const TRAILER: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+/// The parameters of a `permessage-deflate` session.
+///
+/// When offering or accepting the extension these are the preferences of the
+/// local endpoint, after negotiation they describe what both sides agreed on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeflateConfig {
+	/// The server resets its compression context after every message.
+	pub server_no_context_takeover: bool,
+	/// The client resets its compression context after every message.
+	pub client_no_context_takeover: bool,
+	/// The size (as a base 2 logarithm) of the server's LZ77 window.
+	pub server_max_window_bits: u8,
+	/// The size (as a base 2 logarithm) of the client's LZ77 window.
+	pub client_max_window_bits: u8,
+}
+
+impl Default for DeflateConfig {
+	fn default() -> Self {
+		DeflateConfig {
+			server_no_context_takeover: false,
+			client_no_context_takeover: false,
+			server_max_window_bits: MAX_WINDOW_BITS,
+			client_max_window_bits: MAX_WINDOW_BITS,
+		}
+	}
+}
+
+/// The parameters exactly as they appear in a header.
+#[derive(Default)]
+struct Params {
+	server_no_context_takeover: bool,
+	client_no_context_takeover: bool,
+	server_max_window_bits: Option<u8>,
+	client_max_window_bits: Option<Option<u8>>,
+}
+
+impl Params {
+	/// Returns `None` if the parameters are invalid, in which case the
+	/// whole extension must be declined.
+	fn parse(extension: &Extension) -> Option<Params> {
+		let mut params = Params::default();
+		for param in &extension.params {
+			let value = param.value.as_ref().map(|v| v.trim_matches('"'));
+			match (&param.name as &str, value) {
+				(SERVER_NO_CONTEXT_TAKEOVER, None) if !params.server_no_context_takeover => {
+					params.server_no_context_takeover = true;
+				}
+				(CLIENT_NO_CONTEXT_TAKEOVER, None) if !params.client_no_context_takeover => {
+					params.client_no_context_takeover = true;
+				}
+				(SERVER_MAX_WINDOW_BITS, Some(bits)) if params.server_max_window_bits.is_none() => {
+					params.server_max_window_bits = Some(parse_window_bits(bits)?);
+				}
+				(CLIENT_MAX_WINDOW_BITS, bits) if params.client_max_window_bits.is_none() => {
+					params.client_max_window_bits = Some(match bits {
+						Some(bits) => Some(parse_window_bits(bits)?),
+						None => None,
+					});
+				}
+				_ => return None,
+			}
+		}
+		Some(params)
+	}
+}
+
+fn parse_window_bits(bits: &str) -> Option<u8> {
+	match bits.parse() {
+		Ok(bits) if (MIN_WINDOW_BITS..=MAX_WINDOW_BITS).contains(&bits) => Some(bits),
+		_ => None,
+	}
+}
+
+fn window_bits_param(name: &str, bits: u8) -> Parameter {
+	Parameter::new(name.to_string(), Some(bits.to_string()))
+}
+
+impl DeflateConfig {
+	/// The extension a client sends to offer compression with these preferences.
+	pub fn offer(&self) -> Extension {
+		let mut extension = Extension::new(EXTENSION_NAME.to_string());
+		if self.server_no_context_takeover {
+			extension
+				.params
+				.push(Parameter::new(SERVER_NO_CONTEXT_TAKEOVER.to_string(), None));
+		}
+		if self.client_no_context_takeover {
+			extension
+				.params
+				.push(Parameter::new(CLIENT_NO_CONTEXT_TAKEOVER.to_string(), None));
+		}
+		if self.server_max_window_bits < MAX_WINDOW_BITS {
+			extension.params.push(window_bits_param(
+				SERVER_MAX_WINDOW_BITS,
+				self.server_max_window_bits,
+			));
+		}
+		// always tell the server we can limit our window
+		let client_max_window_bits = own_window_bits(self.client_max_window_bits);
+		extension
+			.params
+			.push(if client_max_window_bits < MAX_WINDOW_BITS {
+				window_bits_param(CLIENT_MAX_WINDOW_BITS, client_max_window_bits)
+			} else {
+				Parameter::new(CLIENT_MAX_WINDOW_BITS.to_string(), None)
+			});
+		extension
+	}
+
+	/// Picks the first acceptable offer out of the extensions a client requested,
+	/// using `self` as the server's preferences.
+	///
+	/// Returns the parameters of the session, use `response` to get the
+	/// extension which must be sent back to the client.
+	pub fn negotiate(&self, offers: &[Extension]) -> Option<DeflateConfig> {
+		offers
+			.iter()
+			.filter(|offer| offer.name == EXTENSION_NAME)
+			.filter_map(Params::parse)
+			.filter_map(|offer| {
+				let client_max_window_bits = match offer.client_max_window_bits {
+					Some(bits) => {
+						cmp::min(bits.unwrap_or(MAX_WINDOW_BITS), self.client_max_window_bits)
+					}
+					// the client cannot limit its window, so we have to accept any
+					None if self.client_max_window_bits < MAX_WINDOW_BITS => return None,
+					None => MAX_WINDOW_BITS,
+				};
+				let server_max_window_bits = cmp::min(
+					offer.server_max_window_bits.unwrap_or(MAX_WINDOW_BITS),
+					own_window_bits(self.server_max_window_bits),
+				);
+				if server_max_window_bits < MIN_COMPRESS_WINDOW_BITS {
+					return None;
+				}
+				Some(DeflateConfig {
+					server_no_context_takeover: offer.server_no_context_takeover
+						|| self.server_no_context_takeover,
+					client_no_context_takeover: offer.client_no_context_takeover
+						|| self.client_no_context_takeover,
+					server_max_window_bits,
+					client_max_window_bits,
+				})
+			})
+			.next()
+	}
+
+	/// The extension a server sends back to accept a negotiated session.
+	pub fn response(&self) -> Extension {
+		let mut extension = Extension::new(EXTENSION_NAME.to_string());
+		if self.server_no_context_takeover {
+			extension
+				.params
+				.push(Parameter::new(SERVER_NO_CONTEXT_TAKEOVER.to_string(), None));
+		}
+		if self.client_no_context_takeover {
+			extension
+				.params
+				.push(Parameter::new(CLIENT_NO_CONTEXT_TAKEOVER.to_string(), None));
+		}
+		if self.server_max_window_bits < MAX_WINDOW_BITS {
+			extension.params.push(window_bits_param(
+				SERVER_MAX_WINDOW_BITS,
+				self.server_max_window_bits,
+			));
+		}
+		if self.client_max_window_bits < MAX_WINDOW_BITS {
+			extension.params.push(window_bits_param(
+				CLIENT_MAX_WINDOW_BITS,
+				self.client_max_window_bits,
+			));
+		}
+		extension
+	}
+
+	/// Checks the extension a server responded with against the offer made
+	/// with `self`, and returns the parameters of the session.
+	pub fn accept_response(&self, response: &Extension) -> WebSocketResult<DeflateConfig> {
+		let params = Params::parse(response).ok_or(WebSocketError::ResponseError(
+			"Invalid permessage-deflate parameters",
+		))?;
+
+		if self.server_no_context_takeover && !params.server_no_context_takeover {
+			return Err(WebSocketError::ResponseError(
+				"Server did not accept server_no_context_takeover",
+			));
+		}
+
+		let server_max_window_bits = params.server_max_window_bits.unwrap_or(MAX_WINDOW_BITS);
+		if server_max_window_bits > self.server_max_window_bits {
+			return Err(WebSocketError::ResponseError(
+				"Server did not accept server_max_window_bits",
+			));
+		}
+
+		let client_max_window_bits = own_window_bits(self.client_max_window_bits);
+		let client_max_window_bits = match params.client_max_window_bits {
+			Some(Some(bits)) if bits < MIN_COMPRESS_WINDOW_BITS => {
+				return Err(WebSocketError::ResponseError(
+					"Server requested an unsupported client_max_window_bits",
+				));
+			}
+			Some(Some(bits)) => cmp::min(bits, client_max_window_bits),
+			Some(None) => {
+				return Err(WebSocketError::ResponseError(
+					"client_max_window_bits must have a value",
+				));
+			}
+			None => client_max_window_bits,
+		};
+
+		Ok(DeflateConfig {
+			server_no_context_takeover: params.server_no_context_takeover,
+			client_no_context_takeover: params.client_no_context_takeover
+				|| self.client_no_context_takeover,
+			server_max_window_bits,
+			client_max_window_bits,
+		})
+	}
+
+	/// Reads the parameters of an already negotiated session, such as the one
+	/// a server put in its own handshake response.
+	pub fn from_extension(extension: &Extension) -> Option<DeflateConfig> {
+		if extension.name != EXTENSION_NAME {
+			return None;
+		}
+		Params::parse(extension).map(|params| DeflateConfig {
+			server_no_context_takeover: params.server_no_context_takeover,
+			client_no_context_takeover: params.client_no_context_takeover,
+			server_max_window_bits: params.server_max_window_bits.unwrap_or(MAX_WINDOW_BITS),
+			client_max_window_bits: params
+				.client_max_window_bits
+				.and_then(|bits| bits)
+				.unwrap_or(MAX_WINDOW_BITS),
+		})
+	}
+
+	/// Create the compressor for outgoing messages of an endpoint.
+	pub fn compressor(&self, is_server: bool) -> Compressor {
+		if is_server {
+			Compressor::new(self.server_max_window_bits, self.server_no_context_takeover)
+		} else {
+			Compressor::new(self.client_max_window_bits, self.client_no_context_takeover)
+		}
+	}
+
+	/// Create the decompressor for incoming messages of an endpoint.
+	pub fn decompressor(&self, is_server: bool) -> Decompressor {
+		if is_server {
+			Decompressor::new(self.client_max_window_bits, self.client_no_context_takeover)
+		} else {
+			Decompressor::new(self.server_max_window_bits, self.server_no_context_takeover)
+		}
+	}
+}
+
+// the window we are willing to compress with when a peer limits it to `bits`
+fn own_window_bits(bits: u8) -> u8 {
+	cmp::max(bits, MIN_COMPRESS_WINDOW_BITS)
+}
+
+// zlib does not support 8 bit windows for raw deflate streams, a 9 bit
+// window can decode everything an 8 bit window can, but not the other way around.
+fn zlib_window_bits(bits: u8) -> u8 {
+	cmp::max(bits, MIN_WINDOW_BITS + 1)
+}
+
+fn is_data(opcode: Opcode) -> bool {
+	opcode == Opcode::Text || opcode == Opcode::Binary
+}
+
+/// Compresses the payloads of outgoing messages.
+#[derive(Debug)]
+pub struct Compressor {
+	compress: Compress,
+	no_context_takeover: bool,
+	threshold: usize,
+}
+
+impl Compressor {
+	/// Create a compressor using an LZ77 window of `2^window_bits` bytes.
+	/// With `no_context_takeover` every message is compressed independently.
+	///
+	/// Panics if `window_bits` is not between 9 and 15, zlib cannot compress
+	/// with an 8 bit window.
+	pub fn new(window_bits: u8, no_context_takeover: bool) -> Compressor {
+		Compressor {
+			compress: Compress::new_with_window_bits(Compression::default(), false, window_bits),
+			no_context_takeover,
+			threshold: DEFAULT_COMPRESSION_THRESHOLD,
+		}
+	}
+
+	/// Send messages with a payload smaller than `threshold` bytes uncompressed,
+	/// as compressing them usually makes them bigger.
+	/// By default this is `DEFAULT_COMPRESSION_THRESHOLD`, 0 compresses every message.
+	pub fn set_threshold(&mut self, threshold: usize) {
+		self.threshold = threshold;
+	}
+
+	/// The size below which messages are not compressed, see `set_threshold`.
+	pub fn threshold(&self) -> usize {
+		self.threshold
+	}
+
+	/// Compress the payload of a single message.
+	pub fn compress(&mut self, data: &[u8]) -> WebSocketResult<Vec<u8>> {
+		let mut output = Vec::with_capacity(data.len() / 2 + 64);
+		let start = self.compress.total_in();
+
+		loop {
+			let consumed = (self.compress.total_in() - start) as usize;
+			if output.len() == output.capacity() {
+				output.reserve(data.len() - consumed + 64);
+			}
+			self.compress
+				.compress_vec(&data[consumed..], &mut output, FlushCompress::Sync)
+				.map_err(io::Error::other)?;
+
+			let consumed = (self.compress.total_in() - start) as usize;
+			// a full output buffer means there might be more output pending
+			if consumed == data.len() && output.len() < output.capacity() {
+				break;
+			}
+		}
+
+		if output.ends_with(&TRAILER) {
+			let len = output.len() - TRAILER.len();
+			output.truncate(len);
+		}
+		if output.is_empty() {
+			// an empty message is sent as a single empty block (RFC 7692 7.2.3.6)
+			output.push(0x00);
+		}
+
+		if self.no_context_takeover {
+			self.compress.reset();
+		}
+		Ok(output)
+	}
+
+	/// Compress the frames forming one message, data messages are turned into a
+	/// single frame with the RSV1 bit set while control frames and messages smaller
+	/// than the threshold are left untouched.
+	pub fn compress_frames(&mut self, frames: Vec<DataFrame>) -> WebSocketResult<Vec<DataFrame>> {
+		let opcode = match frames.first() {
+			Some(frame) if is_data(frame.opcode) => frame.opcode,
+			_ => return Ok(frames),
+		};
+		let len = frames.iter().map(|f| f.data.len()).sum();
+		if len < self.threshold {
+			return Ok(frames);
+		}
+
+		let mut payload = Vec::with_capacity(len);
+		for frame in frames {
+			payload.extend_from_slice(&frame.data);
+		}
+
+		let mut frame = DataFrame::new(true, opcode, self.compress(&payload)?);
+		frame.reserved[0] = true;
+		Ok(vec![frame])
+	}
+}
+
+/// Inflates the payloads of incoming compressed messages.
+#[derive(Debug)]
+pub struct Decompressor {
+	decompress: Decompress,
+	no_context_takeover: bool,
+	// the stream of a message being discarded ended before the message
+	ended: bool,
+}
+
+impl Decompressor {
+	/// Create a decompressor for a peer using an LZ77 window of `2^window_bits` bytes.
+	/// With `no_context_takeover` the peer compresses every message independently.
+	pub fn new(window_bits: u8, no_context_takeover: bool) -> Decompressor {
+		Decompressor {
+			decompress: Decompress::new_with_window_bits(false, zlib_window_bits(window_bits)),
+			no_context_takeover,
+			ended: false,
+		}
+	}
+
+	/// Inflate the payload of a single message, failing with `MessageTooBig` as
+	/// soon as it inflates to more than `max_len` bytes. `None` inflates messages
+	/// of any size.
+	///
+	/// A message which is too big leaves the context of the peer incomplete,
+	/// the connection should be closed afterwards.
+	pub fn decompress(&mut self, data: &[u8], max_len: Option<usize>) -> WebSocketResult<Vec<u8>> {
+		let mut input = Vec::with_capacity(data.len() + TRAILER.len());
+		input.extend_from_slice(data);
+		input.extend_from_slice(&TRAILER);
+
+		// never allocate more than one byte over the limit, which tells that it is exceeded
+		let limit = max_len.map_or(usize::MAX, |max| max.saturating_add(1));
+		let mut output = Vec::with_capacity(cmp::min(data.len() * 2 + 64, limit));
+		let start = self.decompress.total_in();
+		let mut finished = false;
+
+		loop {
+			let consumed = (self.decompress.total_in() - start) as usize;
+			if output.len() == output.capacity() {
+				output.reserve_exact(cmp::min(output.len() + 64, limit - output.len()));
+			}
+			let status = self
+				.decompress
+				.decompress_vec(&input[consumed..], &mut output, FlushDecompress::Sync)
+				.map_err(|_| WebSocketError::ProtocolError("Invalid compressed payload"))?;
+
+			if output.len() >= limit {
+				self.decompress.reset(false);
+				return Err(WebSocketError::MessageTooBig);
+			}
+			let consumed = (self.decompress.total_in() - start) as usize;
+			if status == Status::StreamEnd {
+				// the peer ended the stream, anything after the final block is ignored
+				finished = true;
+				break;
+			}
+			if consumed == input.len() && output.len() < output.capacity() {
+				break;
+			}
+			if status == Status::BufError && output.len() < output.capacity() {
+				return Err(WebSocketError::ProtocolError("Invalid compressed payload"));
+			}
+		}
+
+		if finished || self.no_context_takeover {
+			self.decompress.reset(false);
+		}
+		Ok(output)
+	}
+
+	/// Inflate a part of a compressed message and throw the output away, so the
+	/// context stays in sync with the peer when a message is skipped. The parts
+	/// of a message are passed in order, `finished` is set for the last one.
+	pub fn discard(&mut self, data: &[u8], finished: bool) -> WebSocketResult<()> {
+		if !self.ended {
+			self.discard_input(data)?;
+		}
+		if finished {
+			if !self.ended {
+				self.discard_input(&TRAILER)?;
+			}
+			if self.ended || self.no_context_takeover {
+				self.decompress.reset(false);
+			}
+			self.ended = false;
+		}
+		Ok(())
+	}
+
+	fn discard_input(&mut self, mut input: &[u8]) -> WebSocketResult<()> {
+		let mut output = [0; 4096];
+		loop {
+			let (total_in, total_out) = (self.decompress.total_in(), self.decompress.total_out());
+			let status = self
+				.decompress
+				.decompress(input, &mut output, FlushDecompress::Sync)
+				.map_err(|_| WebSocketError::ProtocolError("Invalid compressed payload"))?;
+			input = &input[(self.decompress.total_in() - total_in) as usize..];
+			let produced = (self.decompress.total_out() - total_out) as usize;
+			if status == Status::StreamEnd {
+				// the peer ended the stream, anything after the final block is ignored
+				self.ended = true;
+				return Ok(());
+			}
+			if input.is_empty() && produced < output.len() {
+				return Ok(());
+			}
+			if status == Status::BufError && produced == 0 {
+				return Err(WebSocketError::ProtocolError("Invalid compressed payload"));
+			}
+		}
+	}
+
+	/// Inflate the frames forming one message. If the first frame of a data message
+	/// has the RSV1 bit set, the message is turned into a single uncompressed frame
+	/// of at most `max_len` bytes, see `decompress`. Any other message is returned
+	/// untouched.
+	pub fn decompress_frames(
+		&mut self,
+		frames: Vec<DataFrame>,
+		max_len: Option<usize>,
+	) -> WebSocketResult<Vec<DataFrame>> {
+		let opcode = match frames.first() {
+			Some(frame) if is_data(frame.opcode) && frame.reserved[0] => frame.opcode,
+			_ => return Ok(frames),
+		};
+
+		let mut payload = Vec::with_capacity(frames.iter().map(|f| f.data.len()).sum());
+		for (i, frame) in frames.into_iter().enumerate() {
+			if i > 0 && frame.reserved[0] {
+				return Err(WebSocketError::ProtocolError(
+					"RSV1 set on a continuation frame",
+				));
+			}
+			payload.extend_from_slice(&frame.data);
+		}
+
+		let mut frame = DataFrame::new(true, opcode, self.decompress(&payload, max_len)?);
+		frame.reserved = [false; 3];
+		Ok(vec![frame])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::str::FromStr;
+
+	fn offer(s: &str) -> Extension {
+		Extension::from_str(s).unwrap()
+	}
+
+	#[test]
+	fn round_trip_with_context_takeover() {
+		let config = DeflateConfig::default();
+		let mut compressor = config.compressor(false);
+		let mut decompressor = config.decompressor(true);
+
+		let message = b"Hello, hello, hello, is there anybody in there?";
+		let first = compressor.compress(message).unwrap();
+		let second = compressor.compress(message).unwrap();
+		// the second message can refer back to the first one
+		assert!(second.len() < first.len());
+
+		assert_eq!(decompressor.decompress(&first, None).unwrap(), &message[..]);
+		assert_eq!(
+			decompressor.decompress(&second, None).unwrap(),
+			&message[..]
+		);
+	}
+
+	#[test]
+	fn no_context_takeover_messages_stand_alone() {
+		let server = DeflateConfig {
+			server_no_context_takeover: true,
+			client_no_context_takeover: true,
+			server_max_window_bits: 10,
+			client_max_window_bits: 10,
+		};
+		let client = DeflateConfig::default();
+		let session = server.negotiate(&[client.offer()]).unwrap();
+		assert_eq!(session, server);
+		assert_eq!(client.accept_response(&session.response()).unwrap(), server);
+
+		let message = b"Hello, hello, hello, is there anybody in there?";
+		for &is_server in &[true, false] {
+			let mut compressor = session.compressor(is_server);
+			let first = compressor.compress(message).unwrap();
+			for _ in 0..3 {
+				let compressed = compressor.compress(message).unwrap();
+				assert_eq!(compressed, first);
+				// a fresh decompressor has none of the previous messages
+				let mut decompressor = Decompressor::new(10, false);
+				assert_eq!(
+					decompressor.decompress(&compressed, None).unwrap(),
+					&message[..]
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn small_messages_are_not_compressed() {
+		let mut compressor = Compressor::new(15, false);
+		assert_eq!(compressor.threshold(), DEFAULT_COMPRESSION_THRESHOLD);
+		let short = vec![DataFrame::new(true, Opcode::Text, b"hi".to_vec())];
+		assert_eq!(compressor.compress_frames(short.clone()).unwrap(), short);
+
+		let long = vec![DataFrame::new(true, Opcode::Text, vec![b'a'; 100])];
+		let compressed = compressor.compress_frames(long).unwrap();
+		assert!(compressed[0].reserved[0]);
+		assert!(compressed[0].data.len() < 100);
+
+		compressor.set_threshold(0);
+		let compressed = compressor.compress_frames(short).unwrap();
+		assert!(compressed[0].reserved[0]);
+	}
+
+	#[test]
+	fn round_trip_empty_message() {
+		let mut compressor = Compressor::new(15, false);
+		let mut decompressor = Decompressor::new(15, false);
+
+		let compressed = compressor.compress(b"").unwrap();
+		assert_eq!(compressed, vec![0x00]);
+		assert!(decompressor
+			.decompress(&compressed, None)
+			.unwrap()
+			.is_empty());
+		assert!(decompressor.decompress(&[], None).unwrap().is_empty());
+	}
+
+	#[test]
+	fn rfc_example_message() {
+		// RFC 7692 7.2.3.1, "Hello" compressed
+		let mut decompressor = Decompressor::new(15, false);
+		let data = [0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00];
+		assert_eq!(decompressor.decompress(&data, None).unwrap(), b"Hello");
+	}
+
+	#[test]
+	fn decompress_fragmented_message() {
+		let mut compressor = Compressor::new(15, false);
+		let compressed = compressor.compress(b"a fragmented message").unwrap();
+		let (head, tail) = compressed.split_at(compressed.len() / 2);
+
+		let mut first = DataFrame::new(false, Opcode::Text, head.to_vec());
+		first.reserved[0] = true;
+		let last = DataFrame::new(true, Opcode::Continuation, tail.to_vec());
+
+		let mut decompressor = Decompressor::new(15, false);
+		let frames = decompressor
+			.decompress_frames(vec![first, last], None)
+			.unwrap();
+		assert_eq!(frames.len(), 1);
+		assert_eq!(frames[0].opcode, Opcode::Text);
+		assert_eq!(frames[0].reserved, [false; 3]);
+		assert_eq!(frames[0].data, b"a fragmented message".to_vec());
+	}
+
+	#[test]
+	fn uncompressed_messages_pass_through() {
+		let mut decompressor = Decompressor::new(15, false);
+		let frames = vec![DataFrame::new(true, Opcode::Binary, vec![1, 2, 3])];
+		assert_eq!(
+			decompressor
+				.decompress_frames(frames.clone(), None)
+				.unwrap(),
+			frames
+		);
+
+		let mut compressor = Compressor::new(15, false);
+		let ping = vec![DataFrame::new(true, Opcode::Ping, vec![1, 2, 3])];
+		assert_eq!(compressor.compress_frames(ping.clone()).unwrap(), ping);
+	}
+
+	#[test]
+	fn server_negotiates_first_valid_offer() {
+		let offers = vec![
+			offer("permessage-deflate; server_max_window_bits=20"),
+			offer("permessage-deflate; client_max_window_bits; server_no_context_takeover"),
+		];
+		let config = DeflateConfig::default().negotiate(&offers).unwrap();
+		assert!(config.server_no_context_takeover);
+		assert_eq!(config.client_max_window_bits, 15);
+		assert_eq!(
+			config.response().to_string(),
+			"permessage-deflate; server_no_context_takeover"
+		);
+
+		assert!(DeflateConfig::default()
+			.negotiate(&[offer("permessage-deflate; foo")])
+			.is_none());
+		// we cannot compress with an 8 bit window
+		assert!(DeflateConfig::default()
+			.negotiate(&[offer("permessage-deflate; server_max_window_bits=8")])
+			.is_none());
+		assert!(DeflateConfig::default()
+			.negotiate(&[offer("x-webkit-deflate-frame")])
+			.is_none());
+	}
+
+	#[test]
+	fn client_checks_response() {
+		let config = DeflateConfig::default();
+		assert_eq!(
+			config.offer().to_string(),
+			"permessage-deflate; client_max_window_bits"
+		);
+
+		let accepted = config
+			.accept_response(&offer(
+				"permessage-deflate; server_max_window_bits=10; client_no_context_takeover",
+			))
+			.unwrap();
+		assert_eq!(accepted.server_max_window_bits, 10);
+		assert!(accepted.client_no_context_takeover);
+
+		assert!(config
+			.accept_response(&offer("permessage-deflate; client_max_window_bits"))
+			.is_err());
+		assert!(config
+			.accept_response(&offer("permessage-deflate; unknown"))
+			.is_err());
+		assert!(config
+			.accept_response(&offer("permessage-deflate; client_max_window_bits=8"))
+			.is_err());
+	}
+
+	#[test]
+	fn own_window_is_never_8_bits() {
+		let config = DeflateConfig {
+			server_max_window_bits: 8,
+			client_max_window_bits: 8,
+			..DeflateConfig::default()
+		};
+		assert_eq!(
+			config.offer().to_string(),
+			"permessage-deflate; server_max_window_bits=8; client_max_window_bits=9"
+		);
+
+		let session = config
+			.negotiate(&[offer("permessage-deflate; client_max_window_bits")])
+			.unwrap();
+		assert_eq!(session.server_max_window_bits, 9);
+		assert_eq!(session.client_max_window_bits, 8);
+
+		let mut compressor = session.compressor(true);
+		let mut decompressor = session.decompressor(false);
+		let message = b"Hello, hello, hello, is there anybody in there?";
+		let compressed = compressor.compress(message).unwrap();
+		assert_eq!(
+			decompressor.decompress(&compressed, None).unwrap(),
+			&message[..]
+		);
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn sender_and_receiver_round_trip() {
+		use message::OwnedMessage;
+		use receiver::Receiver;
+		use sender::Sender;
+		use ws::receiver::Receiver as ReceiverTrait;
+		use ws::sender::Sender as SenderTrait;
+		use ws::util::header::{read_header, DataFrameFlags};
+
+		let config = DeflateConfig::default();
+		let mut sender = Sender::new(true);
+		sender.set_compressor(Some(config.compressor(false)));
+		let mut receiver = Receiver::new(true);
+		receiver.set_decompressor(Some(config.decompressor(true)));
+
+		// short messages are sent uncompressed in between compressed ones
+		let long = "compress me, ".repeat(10);
+		let messages = vec![
+			(OwnedMessage::Text(long.clone()), true),
+			(OwnedMessage::Ping(vec![1, 2, 3]), false),
+			(OwnedMessage::Binary(vec![]), false),
+			(OwnedMessage::Text("compress me".to_string()), false),
+			(OwnedMessage::Text(long), true),
+		];
+		let mut wire = Vec::new();
+		for (message, _) in &messages {
+			sender.send_message(&mut wire, message).unwrap();
+		}
+
+		let mut reader = &wire[..];
+		for (message, compressed) in messages {
+			let mut peek = reader;
+			let header = read_header(&mut peek).unwrap();
+			assert_eq!(header.flags.contains(DataFrameFlags::RSV1), compressed);
+			let received: OwnedMessage = receiver.recv_message(&mut reader).unwrap();
+			assert_eq!(received, message);
+		}
+	}
+}
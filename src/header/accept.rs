@@ -7,10 +7,14 @@ use result::{WebSocketError, WebSocketResult};
 use sha1::Sha1;
 use std::fmt::{self, Debug};
 use std::str::FromStr;
+use ws::util::ct_eq;
 
 static MAGIC_GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
 
 /// Represents a Sec-WebSocket-Accept header
+///
+/// The `PartialEq` implementation is not constant time, the handshake
+/// validation uses `ct_eq` instead.
 #[derive(PartialEq, Clone, Copy)]
 pub struct WebSocketAccept([u8; 20]);
 
@@ -54,6 +58,10 @@ impl WebSocketAccept {
 		let bytes = sha1.digest().bytes();
 		WebSocketAccept(bytes)
 	}
+	/// Compare with another WebSocketAccept in constant time
+	pub fn ct_eq(&self, other: &WebSocketAccept) -> bool {
+		ct_eq(&self.0, &other.0)
+	}
 	/// Return the Base64 encoding of this WebSocketAccept
 	pub fn serialize(&self) -> String {
 		let WebSocketAccept(accept) = *self;
@@ -110,6 +118,15 @@ mod tests {
 		assert!(accept.is_err()); // > 20 bytes
 	}
 
+	#[test]
+	fn test_header_accept_ct_eq() {
+		let key = FromStr::from_str("dGhlIHNhbXBsZSBub25jZQ==").unwrap();
+		let accept = WebSocketAccept::new(&key);
+		let expected = WebSocketAccept::from_str("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=").unwrap();
+		assert!(accept.ct_eq(&expected));
+		assert!(!WebSocketAccept::new(&WebSocketKey::new()).ct_eq(&expected));
+	}
+
 	#[bench]
 	fn bench_header_accept_new(b: &mut test::Bencher) {
 		let key = WebSocketKey::new();
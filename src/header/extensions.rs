@@ -1,7 +1,7 @@
 //! Provides the Sec-WebSocket-Extensions header.
 
 use hyper;
-use hyper::header::parsing::{fmt_comma_delimited, from_comma_delimited};
+use hyper::header::parsing::fmt_comma_delimited;
 use hyper::header::{Header, HeaderFormat};
 use result::{WebSocketError, WebSocketResult};
 use std::fmt;
@@ -9,10 +9,12 @@ use std::ops::Deref;
 use std::str::FromStr;
 
 const INVALID_EXTENSION: &str = "Invalid Sec-WebSocket-Extensions extension name";
-
-// TODO: check if extension name is valid according to spec
+const INVALID_PARAMETER: &str = "Invalid Sec-WebSocket-Extensions extension parameter";
 
 /// Represents a Sec-WebSocket-Extensions header
+///
+/// The extensions are kept in the order they appear in the header, which for an
+/// offer is the order of preference.
 #[derive(PartialEq, Clone, Debug)]
 pub struct WebSocketExtensions(pub Vec<Extension>);
 
@@ -47,23 +49,16 @@ impl FromStr for Extension {
 	type Err = WebSocketError;
 
 	fn from_str(s: &str) -> WebSocketResult<Extension> {
-		let mut ext = s.split(';').map(str::trim);
-		Ok(Extension {
-			name: match ext.next() {
-				Some(x) => x.to_string(),
-				None => return Err(WebSocketError::ProtocolError(INVALID_EXTENSION)),
-			},
-			params: ext
-				.map(|x| {
-					let mut pair = x.splitn(1, '=').map(|x| x.trim().to_string());
-
-					Parameter {
-						name: pair.next().unwrap(),
-						value: pair.next(),
-					}
-				})
-				.collect(),
-		})
+		let mut ext = split_unquoted(s, ';').into_iter().map(str::trim);
+		let name = match ext.next() {
+			Some(x) if is_token(x) => x.to_string(),
+			_ => return Err(WebSocketError::ProtocolError(INVALID_EXTENSION)),
+		};
+		let params = ext
+			.filter(|x| !x.is_empty())
+			.map(str::parse)
+			.collect::<WebSocketResult<_>>()?;
+		Ok(Extension { name, params })
 	}
 }
 
@@ -93,23 +88,117 @@ impl Parameter {
 	}
 }
 
+impl FromStr for Parameter {
+	type Err = WebSocketError;
+
+	/// Parses `name` or `name=value`, where the value may be a quoted string.
+	fn from_str(s: &str) -> WebSocketResult<Parameter> {
+		let mut pair = s.splitn(2, '=').map(str::trim);
+		let name = match pair.next() {
+			Some(x) if is_token(x) => x.to_string(),
+			_ => return Err(WebSocketError::ProtocolError(INVALID_PARAMETER)),
+		};
+		let value = match pair.next() {
+			Some(x) if x.starts_with('"') => Some(unquote(x)?),
+			Some(x) if is_token(x) => Some(x.to_string()),
+			Some(_) => return Err(WebSocketError::ProtocolError(INVALID_PARAMETER)),
+			None => None,
+		};
+		Ok(Parameter { name, value })
+	}
+}
+
 impl fmt::Display for Parameter {
+	/// Values which are not tokens are written as quoted strings.
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		write!(f, "{}", self.name)?;
-		if let Some(ref x) = self.value {
-			write!(f, "={}", x)?;
+		match self.value {
+			Some(ref x) if is_token(x) => write!(f, "={}", x)?,
+			Some(ref x) => {
+				write!(f, "=\"")?;
+				for c in x.chars() {
+					if c == '"' || c == '\\' {
+						write!(f, "\\")?;
+					}
+					write!(f, "{}", c)?;
+				}
+				write!(f, "\"")?;
+			}
+			None => (),
 		}
 		Ok(())
 	}
 }
 
+// a token as defined in RFC 7230 section 3.2.6
+fn is_token(s: &str) -> bool {
+	!s.is_empty()
+		&& s.bytes()
+			.all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+// splits at `separator` unless it is inside a quoted string
+fn split_unquoted(s: &str, separator: char) -> Vec<&str> {
+	let mut parts = Vec::new();
+	let mut start = 0;
+	let mut quoted = false;
+	let mut escaped = false;
+	for (i, c) in s.char_indices() {
+		match c {
+			_ if escaped => escaped = false,
+			'\\' if quoted => escaped = true,
+			'"' => quoted = !quoted,
+			c if c == separator && !quoted => {
+				parts.push(&s[start..i]);
+				start = i + 1;
+			}
+			_ => (),
+		}
+	}
+	parts.push(&s[start..]);
+	parts
+}
+
+// the contents of a quoted string, which must not be empty
+fn unquote(s: &str) -> WebSocketResult<String> {
+	if s.len() < 2 || !s.ends_with('"') {
+		return Err(WebSocketError::ProtocolError(INVALID_PARAMETER));
+	}
+	let mut value = String::with_capacity(s.len() - 2);
+	let mut chars = s[1..s.len() - 1].chars();
+	while let Some(c) = chars.next() {
+		match c {
+			'\\' => match chars.next() {
+				Some(c) => value.push(c),
+				None => return Err(WebSocketError::ProtocolError(INVALID_PARAMETER)),
+			},
+			'"' => return Err(WebSocketError::ProtocolError(INVALID_PARAMETER)),
+			c => value.push(c),
+		}
+	}
+	if value.is_empty() {
+		return Err(WebSocketError::ProtocolError(INVALID_PARAMETER));
+	}
+	Ok(value)
+}
+
 impl Header for WebSocketExtensions {
 	fn header_name() -> &'static str {
 		"Sec-WebSocket-Extensions"
 	}
 
 	fn parse_header(raw: &[Vec<u8>]) -> hyper::Result<WebSocketExtensions> {
-		from_comma_delimited(raw).map(WebSocketExtensions)
+		let mut extensions = Vec::new();
+		for line in raw {
+			let line = ::std::str::from_utf8(line).map_err(|_| hyper::Error::Header)?;
+			for ext in split_unquoted(line, ',') {
+				let ext = ext.trim();
+				if !ext.is_empty() {
+					extensions.push(ext.parse().map_err(|_| hyper::Error::Header)?);
+				}
+			}
+		}
+		Ok(WebSocketExtensions(extensions))
 	}
 }
 
@@ -147,6 +236,84 @@ mod tests {
 		);
 	}
 
+	fn parse(value: &str) -> WebSocketExtensions {
+		Header::parse_header(&[value.as_bytes().to_vec()][..]).unwrap()
+	}
+
+	#[test]
+	fn test_header_extensions_round_trip() {
+		// offers sent by Chrome and Firefox, and the answer of nginx
+		// proxying to a server with permessage-deflate
+		for value in &[
+			"permessage-deflate; client_max_window_bits",
+			"permessage-deflate",
+			"permessage-deflate; server_no_context_takeover; client_max_window_bits=15",
+			"permessage-deflate; client_max_window_bits=10; server_no_context_takeover, permessage-deflate",
+		] {
+			assert_eq!(&parse(value).to_string(), value);
+		}
+	}
+
+	#[test]
+	fn test_header_extensions_params() {
+		let extensions = parse(
+			"permessage-deflate; client_max_window_bits=10; server_no_context_takeover, x-webkit-deflate-frame",
+		);
+		assert_eq!(
+			extensions.0,
+			vec![
+				Extension {
+					name: "permessage-deflate".to_string(),
+					params: vec![
+						Parameter::new(
+							"client_max_window_bits".to_string(),
+							Some("10".to_string())
+						),
+						Parameter::new("server_no_context_takeover".to_string(), None),
+					],
+				},
+				Extension::new("x-webkit-deflate-frame".to_string()),
+			]
+		);
+	}
+
+	#[test]
+	fn test_header_extensions_quoted() {
+		let extensions = parse("foo; a=\"10\"; b=\"x, y; z\", bar; c=\"\\\"\"");
+		assert_eq!(extensions.len(), 2);
+		assert_eq!(extensions[0].params[0].value, Some("10".to_string()));
+		assert_eq!(extensions[0].params[1].value, Some("x, y; z".to_string()));
+		assert_eq!(extensions[1].params[0].value, Some("\"".to_string()));
+		// only values that aren't tokens stay quoted
+		assert_eq!(
+			extensions.to_string(),
+			"foo; a=10; b=\"x, y; z\", bar; c=\"\\\"\""
+		);
+	}
+
+	#[test]
+	fn test_header_extensions_multiple_lines() {
+		let value = vec![b"foo".to_vec(), b"bar; baz=1,".to_vec()];
+		let extensions: WebSocketExtensions = Header::parse_header(&value[..]).unwrap();
+		assert_eq!(extensions.to_string(), "foo, bar; baz=1");
+	}
+
+	#[test]
+	fn test_header_extensions_invalid() {
+		for value in &[
+			"foo bar",
+			"; a",
+			"foo; a=\"",
+			"foo; a=\"\"",
+			"foo; a b=1",
+			"foo; a=b c",
+		] {
+			let raw = vec![value.as_bytes().to_vec()];
+			let result: hyper::Result<WebSocketExtensions> = Header::parse_header(&raw[..]);
+			assert!(result.is_err(), "{} should be rejected", value);
+		}
+	}
+
 	#[bench]
 	fn bench_header_extensions_parse(b: &mut test::Bencher) {
 		let value = vec![b"foo, bar; baz; qux=quux".to_vec()];
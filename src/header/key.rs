@@ -1,8 +1,9 @@
 use base64;
+use header::WebSocketAccept;
 use hyper;
 use hyper::header::parsing::from_one_raw_str;
 use hyper::header::{Header, HeaderFormat};
-use rand;
+use rand::{self, Rng};
 use result::{WebSocketError, WebSocketResult};
 use std::fmt::{self, Debug};
 use std::str::FromStr;
@@ -42,14 +43,30 @@ impl FromStr for WebSocketKey {
 impl WebSocketKey {
 	/// Generate a new, random WebSocketKey
 	pub fn new() -> WebSocketKey {
-		let key = rand::random();
-		WebSocketKey(key)
+		WebSocketKey::new_with(&mut rand::thread_rng())
+	}
+	/// Generate a new WebSocketKey using the given random number generator
+	pub fn new_with<R: Rng>(rng: &mut R) -> WebSocketKey {
+		WebSocketKey(rng.gen())
+	}
+	/// Create a WebSocketKey from the given bytes
+	pub fn from_bytes(bytes: [u8; 16]) -> WebSocketKey {
+		WebSocketKey(bytes)
+	}
+	/// Return the bytes of this WebSocketKey
+	pub fn as_bytes(&self) -> &[u8; 16] {
+		&self.0
 	}
 	/// Return the Base64 encoding of this WebSocketKey
 	pub fn serialize(&self) -> String {
 		let WebSocketKey(key) = *self;
 		base64::encode(&key)
 	}
+	/// Compute the Sec-WebSocket-Accept value a server has to respond
+	/// with to accept a handshake using this key.
+	pub fn accept(&self) -> WebSocketAccept {
+		WebSocketAccept::new(self)
+	}
 }
 
 impl Header for WebSocketKey {
@@ -100,6 +117,38 @@ mod tests {
 		assert!(key.is_err()); // > 16 bytes
 	}
 
+	#[test]
+	fn test_header_key_from_bytes() {
+		let key = WebSocketKey::from_bytes(*b"the sample nonce");
+		assert_eq!(key.serialize(), "dGhlIHNhbXBsZSBub25jZQ==");
+		assert_eq!(key.as_bytes(), b"the sample nonce");
+		assert_eq!(WebSocketKey::from_str(&key.serialize()).unwrap(), key);
+	}
+
+	#[test]
+	fn test_header_key_new_with() {
+		use rand::rngs::StdRng;
+		use rand::SeedableRng;
+
+		let mut first = StdRng::seed_from_u64(1337);
+		let mut second = StdRng::seed_from_u64(1337);
+		assert_eq!(
+			WebSocketKey::new_with(&mut first),
+			WebSocketKey::new_with(&mut second)
+		);
+		assert_ne!(
+			WebSocketKey::new_with(&mut first),
+			WebSocketKey::new_with(&mut StdRng::seed_from_u64(7))
+		);
+	}
+
+	#[test]
+	fn test_header_key_accept() {
+		// the sample handshake from RFC 6455 section 1.3
+		let key = WebSocketKey::from_str("dGhlIHNhbXBsZSBub25jZQ==").unwrap();
+		assert_eq!(key.accept().serialize(), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+	}
+
 	#[bench]
 	fn bench_header_key_new(b: &mut test::Bencher) {
 		b.iter(|| {
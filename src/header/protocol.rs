@@ -7,6 +7,9 @@ use std::ops::Deref;
 // TODO: only allow valid protocol names to be added
 
 /// Represents a Sec-WebSocket-Protocol header
+///
+/// Parsing the header fails unless it lists at least one protocol and every
+/// protocol is an HTTP token, i.e. made of letters, digits and ``!#$%&'*+-.^_`|~``.
 #[derive(PartialEq, Clone, Debug)]
 pub struct WebSocketProtocol(pub Vec<String>);
 
@@ -23,10 +26,22 @@ impl Header for WebSocketProtocol {
 	}
 
 	fn parse_header(raw: &[Vec<u8>]) -> hyper::Result<WebSocketProtocol> {
-		from_comma_delimited(raw).map(WebSocketProtocol)
+		let protocols: Vec<String> = from_comma_delimited(raw)?;
+		if protocols.is_empty() || !protocols.iter().all(|p| is_token(p)) {
+			return Err(hyper::Error::Header);
+		}
+		Ok(WebSocketProtocol(protocols))
 	}
 }
 
+// whether `value` is a token as defined by RFC 7230 section 3.2.6
+fn is_token(value: &str) -> bool {
+	!value.is_empty()
+		&& value
+			.bytes()
+			.all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
 impl HeaderFormat for WebSocketProtocol {
 	fn fmt_header(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
 		let WebSocketProtocol(ref value) = *self;
@@ -21,6 +21,15 @@ impl fmt::Debug for WebSocketVersion {
 	}
 }
 
+impl From<u8> for WebSocketVersion {
+	fn from(version: u8) -> Self {
+		match version {
+			13 => WebSocketVersion::WebSocket13,
+			_ => WebSocketVersion::Unknown(version.to_string()),
+		}
+	}
+}
+
 impl Header for WebSocketVersion {
 	fn header_name() -> &'static str {
 		"Sec-WebSocket-Version"
@@ -33,6 +33,12 @@
 //! See the `Server` struct documentation for more information. The `bind()` and
 //! `bind_secure()` functions will bind the server to the given `SocketAddr`.
 //!
+//! # TLS
+//! Secure connections use the platform's TLS library through `native-tls` with the
+//! `sync-ssl` and `async-ssl` features. Synchronous connections can use `rustls`
+//! instead by enabling `sync-rustls` without these features, see the `tls` module.
+//! If `native-tls` is enabled it always takes precedence over `rustls`.
+//!
 //! # Extending Rust-WebSocket
 //! The `ws` module contains the traits and functions used by Rust-WebSocket at a lower
 //! level. Their usage is explained in the module documentation.
@@ -40,13 +46,24 @@ extern crate base64;
 extern crate byteorder;
 #[cfg(feature = "async")]
 extern crate bytes;
+#[cfg(feature = "deflate")]
+extern crate flate2;
 #[cfg(feature = "async")]
 pub extern crate futures;
 extern crate hyper;
 #[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
 extern crate native_tls;
+#[cfg(all(
+	feature = "openssl",
+	not(any(target_os = "windows", target_vendor = "apple"))
+))]
+extern crate openssl;
 extern crate rand;
+#[cfg(all(feature = "sync-rustls", not(feature = "native-tls")))]
+pub extern crate rustls;
 extern crate sha1;
+#[cfg(any(feature = "sync", feature = "async"))]
+extern crate socket2;
 #[cfg(test)]
 extern crate tokio;
 #[cfg(feature = "async")]
@@ -57,10 +74,14 @@ extern crate tokio_io;
 extern crate tokio_reactor;
 #[cfg(feature = "async")]
 extern crate tokio_tcp;
+#[cfg(feature = "async")]
+extern crate tokio_timer;
 #[cfg(feature = "async-ssl")]
 extern crate tokio_tls;
 extern crate unicase;
 pub extern crate url;
+#[cfg(all(feature = "sync-rustls", not(feature = "native-tls")))]
+extern crate webpki_roots;
 
 #[macro_use]
 extern crate bitflags;
@@ -73,22 +94,28 @@ macro_rules! upsert_header {
 		if $headers.has::<$header>() {
 			if let Some($pat) = $headers.get_mut::<$header>() {
 				$some_match
-				}
+			}
 		} else {
 			$headers.set($default);
-			}
-		}};
+		}
+	}};
 }
 
 pub mod dataframe;
 pub mod header;
 pub mod message;
+pub mod metrics;
+pub mod observer;
+pub mod ratelimit;
 pub mod result;
 pub mod ws;
 
 #[cfg(feature = "async")]
 pub mod codec;
 
+#[cfg(feature = "deflate")]
+pub mod deflate;
+
 #[cfg(feature = "sync")]
 pub mod receiver;
 #[cfg(feature = "sync")]
@@ -97,21 +124,26 @@ pub mod sender;
 pub mod client;
 pub mod server;
 pub mod stream;
+#[cfg(feature = "sync")]
+pub mod testing;
+#[cfg(all(feature = "sync-rustls", not(feature = "native-tls")))]
+pub mod tls;
 
 /// A collection of handy synchronous-only parts of the crate.
 #[cfg(feature = "sync")]
 pub mod sync {
 	pub use sender;
-	pub use sender::Writer;
+	pub use sender::{Broadcast, MessageWriter, Writer};
 
 	pub use receiver;
-	pub use receiver::Reader;
+	pub use receiver::{MessageReader, Reader};
 
 	pub use stream::sync as stream;
 	pub use stream::sync::Stream;
 
 	/// A collection of handy synchronous-only parts of the `server` module.
 	pub mod server {
+		pub use server::shutdown::ShutdownTrigger;
 		pub use server::sync::*;
 		pub use server::upgrade::sync as upgrade;
 		pub use server::upgrade::sync::IntoWs;
@@ -122,6 +154,8 @@ pub mod sync {
 	/// A collection of handy synchronous-only parts of the `client` module.
 	pub mod client {
 		pub use client::builder::ClientBuilder;
+		pub use client::heartbeat::{Heartbeat, PingPongHeartbeat};
+		pub use client::reconnect::{Backoff, ReconnectingClient};
 		pub use client::sync::*;
 	}
 	pub use client::sync::Client;
@@ -142,6 +176,7 @@ pub mod async {
 	/// A collection of handy asynchronous-only parts of the `server` module.
 	pub mod server {
 		pub use server::async::*;
+		pub use server::shutdown::{ShutdownSignal, ShutdownTrigger};
 		pub use server::upgrade::async as upgrade;
 		pub use server::upgrade::async::IntoWs;
 		pub use server::upgrade::async::Upgrade;
@@ -164,6 +199,7 @@ pub mod async {
 }
 
 pub use self::client::builder::ClientBuilder;
+pub use self::message::CloseCode;
 pub use self::message::CloseData;
 pub use self::message::Message;
 pub use self::message::OwnedMessage;
@@ -1,6 +1,6 @@
 //! Module containing the default implementation for messages.
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use dataframe::Opcode;
+use dataframe::{DataFrame, Opcode};
 use result::{WebSocketError, WebSocketResult};
 use std::borrow::Cow;
 use std::io;
@@ -8,7 +8,6 @@ use std::io::Write;
 use std::str::from_utf8;
 use ws;
 use ws::dataframe::DataFrame as DataFrameTrait;
-use ws::util::bytes_to_string;
 
 const FALSE_RESERVED_BITS: &[bool; 3] = &[false; 3];
 
@@ -119,6 +118,19 @@ impl<'a> Message<'a> {
 		Message::new(Type::Pong, None, data.into())
 	}
 
+	/// The payload of a text message as a string, `None` for other messages
+	/// and text which is not valid UTF-8.
+	///
+	/// Messages received with `Receiver::recv_borrowed` are validated already,
+	/// together with `opcode` this is enough to dispatch a message by its type
+	/// without copying it.
+	pub fn as_text(&self) -> Option<&str> {
+		match self.opcode {
+			Type::Text => from_utf8(&self.payload).ok(),
+			_ => None,
+		}
+	}
+
 	// TODO: change this to match conventions
 	#[cfg_attr(feature = "cargo-clippy", allow(wrong_self_convention))]
 	/// Convert a ping message to a pong, keeping the data.
@@ -131,6 +143,14 @@ impl<'a> Message<'a> {
 			Err(())
 		}
 	}
+
+	/// Convert this message into the bytes of its payload, the close code of a close
+	/// message is written before its reason.
+	///
+	/// An owned payload is moved out without being copied, unless this is a close message.
+	pub fn into_bytes(self) -> Vec<u8> {
+		self.take_payload()
+	}
 }
 
 impl<'a> ws::dataframe::DataFrame for Message<'a> {
@@ -172,6 +192,24 @@ impl<'a> ws::dataframe::DataFrame for Message<'a> {
 			self.payload.into_owned()
 		}
 	}
+
+	fn payload_slice(&self) -> Option<&[u8]> {
+		match self.cd_status_code {
+			Some(_) => None,
+			None => Some(&self.payload),
+		}
+	}
+}
+
+// the frame of a message, made of its opcode and payload
+fn single_dataframe<D>(message: &D) -> WebSocketResult<DataFrame>
+where
+	D: DataFrameTrait,
+{
+	let opcode = Opcode::from_u8(message.opcode())?;
+	let mut data = Vec::with_capacity(message.size());
+	message.write_payload(&mut data)?;
+	Ok(DataFrame::new(true, opcode, data))
 }
 
 impl<'a> ws::Message for Message<'a> {
@@ -214,30 +252,63 @@ impl<'a> ws::Message for Message<'a> {
 			data.append(&mut dataframe.take_payload());
 		}
 
-		if opcode == Some(Opcode::Text) {
-			if let Err(e) = from_utf8(data.as_slice()) {
+		match opcode {
+			Some(opcode) => Message::from_payload(opcode, Cow::Owned(data)),
+			None => Err(WebSocketError::ProtocolError("Unsupported opcode received")),
+		}
+	}
+
+	/// A message is sent in a single frame
+	fn dataframes(&self) -> WebSocketResult<Vec<DataFrame>> {
+		Ok(vec![single_dataframe(self)?])
+	}
+}
+
+impl<'a> Message<'a> {
+	/// Create a message from the whole received payload of a message,
+	/// validating text and close messages.
+	pub(crate) fn from_payload(opcode: Opcode, mut data: Cow<'a, [u8]>) -> WebSocketResult<Self> {
+		if opcode == Opcode::Text {
+			if let Err(e) = from_utf8(&data) {
 				return Err(e.into());
 			}
 		}
 
 		let msg = match opcode {
-			Some(Opcode::Text) => Message {
-				opcode: Type::Text,
-				cd_status_code: None,
-				payload: Cow::Owned(data),
-			},
-			Some(Opcode::Binary) => Message::binary(data),
-			Some(Opcode::Close) => {
+			Opcode::Text => Message::new(Type::Text, None, data),
+			Opcode::Binary => Message::new(Type::Binary, None, data),
+			Opcode::Close => {
+				if data.len() == 1 {
+					return Err(WebSocketError::ProtocolError(
+						"Close payload must be empty or start with a close code",
+					));
+				}
 				if !data.is_empty() {
 					let status_code = (&data[..]).read_u16::<BigEndian>()?;
-					let reason = bytes_to_string(&data[2..])?;
-					Message::close_because(status_code, reason)
+					let code = CloseCode::from_u16(status_code);
+					if code.is_reserved() {
+						return Err(WebSocketError::ProtocolError(
+							"Reserved close code received",
+						));
+					}
+					if !code.is_valid() {
+						return Err(WebSocketError::ProtocolError("Invalid close code received"));
+					}
+					from_utf8(&data[2..])?;
+					let reason = match data {
+						Cow::Borrowed(data) => Cow::Borrowed(&data[2..]),
+						Cow::Owned(ref mut data) => {
+							data.drain(..2);
+							Cow::Owned(::std::mem::take(data))
+						}
+					};
+					Message::new(Type::Close, Some(status_code), reason)
 				} else {
 					Message::close()
 				}
 			}
-			Some(Opcode::Ping) => Message::ping(data),
-			Some(Opcode::Pong) => Message::pong(data),
+			Opcode::Ping => Message::new(Type::Ping, None, data),
+			Opcode::Pong => Message::new(Type::Pong, None, data),
 			_ => return Err(WebSocketError::ProtocolError("Unsupported opcode received")),
 		};
 		Ok(msg)
@@ -342,6 +413,51 @@ impl OwnedMessage {
 			_ => false,
 		}
 	}
+
+	/// Convert this message into the bytes of its payload, the close code of a close
+	/// message is written before its reason.
+	///
+	/// The payload of a message other than a close message is moved out without being copied.
+	///
+	///```rust
+	///# use websocket::OwnedMessage;
+	///let text = "hello".to_string();
+	///let ptr = text.as_ptr();
+	///let bytes = OwnedMessage::Text(text).into_bytes();
+	///assert_eq!(bytes, b"hello");
+	///assert_eq!(bytes.as_ptr(), ptr);
+	///```
+	pub fn into_bytes(self) -> Vec<u8> {
+		self.take_payload()
+	}
+
+	/// Take the text out of a text message, any other message is given back.
+	pub fn into_text(self) -> Result<String, Self> {
+		match self {
+			OwnedMessage::Text(text) => Ok(text),
+			message => Err(message),
+		}
+	}
+
+	/// Take the data out of a binary message, any other message is given back.
+	pub fn into_binary(self) -> Result<Vec<u8>, Self> {
+		match self {
+			OwnedMessage::Binary(data) => Ok(data),
+			message => Err(message),
+		}
+	}
+
+	/// A `Message` borrowing the payload of this message, e.g. to send it
+	/// without giving it up.
+	///
+	///```rust
+	///# use websocket::{Message, OwnedMessage};
+	///let message = OwnedMessage::Text("hello".to_string());
+	///assert_eq!(message.as_borrowed(), Message::text("hello"));
+	///```
+	pub fn as_borrowed<'a>(&'a self) -> Message<'a> {
+		Message::from(self)
+	}
 }
 
 impl ws::Message for OwnedMessage {
@@ -362,6 +478,11 @@ impl ws::Message for OwnedMessage {
 	{
 		Ok(Message::from_dataframes(frames)?.into())
 	}
+
+	/// A message is sent in a single frame
+	fn dataframes(&self) -> WebSocketResult<Vec<DataFrame>> {
+		Ok(vec![single_dataframe(self)?])
+	}
 }
 
 impl ws::dataframe::DataFrame for OwnedMessage {
@@ -434,6 +555,16 @@ impl ws::dataframe::DataFrame for OwnedMessage {
 			},
 		}
 	}
+
+	fn payload_slice(&self) -> Option<&[u8]> {
+		match *self {
+			OwnedMessage::Text(ref txt) => Some(txt.as_bytes()),
+			OwnedMessage::Binary(ref data)
+			| OwnedMessage::Ping(ref data)
+			| OwnedMessage::Pong(ref data) => Some(data),
+			OwnedMessage::Close(_) => None,
+		}
+	}
 }
 
 impl From<String> for OwnedMessage {
@@ -448,17 +579,35 @@ impl From<Vec<u8>> for OwnedMessage {
 	}
 }
 
+impl<'a> From<String> for Message<'a> {
+	fn from(text: String) -> Self {
+		Message::text(text)
+	}
+}
+
+impl<'a> From<Vec<u8>> for Message<'a> {
+	fn from(buf: Vec<u8>) -> Self {
+		Message::binary(buf)
+	}
+}
+
+// reuses an owned payload if it is valid UTF-8
+fn into_string(payload: Cow<[u8]>) -> String {
+	match payload {
+		Cow::Owned(bytes) => String::from_utf8(bytes)
+			.unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()),
+		Cow::Borrowed(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+	}
+}
+
 impl<'m> From<Message<'m>> for OwnedMessage {
 	fn from(message: Message<'m>) -> Self {
 		match message.opcode {
-			Type::Text => {
-				let convert = String::from_utf8_lossy(&message.payload).into_owned();
-				OwnedMessage::Text(convert)
-			}
+			Type::Text => OwnedMessage::Text(into_string(message.payload)),
 			Type::Close => match message.cd_status_code {
 				Some(code) => OwnedMessage::Close(Some(CloseData {
 					status_code: code,
-					reason: String::from_utf8_lossy(&message.payload).into_owned(),
+					reason: into_string(message.payload),
 				})),
 				None => OwnedMessage::Close(None),
 			},
@@ -484,6 +633,30 @@ impl<'m> From<OwnedMessage> for Message<'m> {
 	}
 }
 
+impl<'a, 'm> From<&'a Message<'m>> for OwnedMessage {
+	fn from(message: &'a Message<'m>) -> Self {
+		OwnedMessage::from(message.clone())
+	}
+}
+
+impl<'m> From<&'m OwnedMessage> for Message<'m> {
+	fn from(message: &'m OwnedMessage) -> Self {
+		match *message {
+			OwnedMessage::Text(ref txt) => Message::text(&txt[..]),
+			OwnedMessage::Binary(ref bin) => Message::binary(&bin[..]),
+			OwnedMessage::Close(Some(ref c)) => {
+				Message::close_because(c.status_code, &c.reason[..])
+			}
+			OwnedMessage::Close(None) => Message::close(),
+			OwnedMessage::Ping(ref data) => Message::ping(&data[..]),
+			OwnedMessage::Pong(ref data) => Message::pong(&data[..]),
+		}
+	}
+}
+
+// a control frame payload is at most 125 bytes, two of them hold the code
+const MAX_CLOSE_REASON: usize = 123;
+
 /// Represents data contained in a Close message
 #[derive(Eq, PartialEq, Clone, Debug)]
 pub struct CloseData {
@@ -494,12 +667,42 @@ pub struct CloseData {
 }
 
 impl CloseData {
-	/// Create a new CloseData object
-	pub fn new(status_code: u16, reason: String) -> CloseData {
-		CloseData {
-			status_code,
-			reason,
+	/// Create a new CloseData object.
+	///
+	/// This fails if the code may not be sent in a Close frame, or if the reason
+	/// is longer than the 123 bytes which fit in a Close frame along with the code.
+	///
+	///```rust
+	///# use websocket::message::{CloseCode, CloseData};
+	///let data = CloseData::new(CloseCode::GoingAway, "restarting").unwrap();
+	///assert_eq!(data.status_code, 1001);
+	///
+	///assert!(CloseData::new(CloseCode::NormalClosure, "x".repeat(124)).is_err());
+	///assert!(CloseData::new(CloseCode::AbnormalClosure, "").is_err());
+	///```
+	pub fn new<R>(code: CloseCode, reason: R) -> WebSocketResult<CloseData>
+	where
+		R: Into<String>,
+	{
+		let reason = reason.into();
+		if !code.is_valid() {
+			return Err(WebSocketError::DataFrameError(
+				"Close code can not be sent in a Close frame",
+			));
+		}
+		if reason.len() > MAX_CLOSE_REASON {
+			return Err(WebSocketError::DataFrameError(
+				"Close reason does not fit in a Close frame",
+			));
 		}
+		Ok(CloseData {
+			status_code: code.to_u16(),
+			reason,
+		})
+	}
+	/// The status-code of the CloseData as a `CloseCode`
+	pub fn code(&self) -> CloseCode {
+		CloseCode::from_u16(self.status_code)
 	}
 	/// Convert this into a vector of bytes
 	pub fn into_bytes(self) -> io::Result<Vec<u8>> {
@@ -512,6 +715,119 @@ impl CloseData {
 	}
 }
 
+/// Status codes of a Close message, as registered in
+/// [RFC 6455](https://tools.ietf.org/html/rfc6455#section-7.4).
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Hash)]
+pub enum CloseCode {
+	/// 1000, the purpose of the connection has been fulfilled
+	NormalClosure,
+	/// 1001, the endpoint is going away, e.g. a server shutting down
+	GoingAway,
+	/// 1002, the endpoint received a frame violating the protocol
+	ProtocolError,
+	/// 1003, the endpoint received a type of data it cannot accept
+	UnsupportedData,
+	/// 1005, no status code was present, must not be sent in a Close frame
+	NoStatusReceived,
+	/// 1006, the connection was closed without a Close frame, must not be
+	/// sent in a Close frame
+	AbnormalClosure,
+	/// 1007, the endpoint received data inconsistent with the message type,
+	/// e.g. non UTF-8 data in a text message
+	InvalidFramePayloadData,
+	/// 1008, the endpoint received a message violating its policy
+	PolicyViolation,
+	/// 1009, the endpoint received a message too big to process
+	MessageTooBig,
+	/// 1010, the client expected the server to negotiate an extension
+	MandatoryExtension,
+	/// 1011, the server encountered an unexpected condition
+	InternalServerError,
+	/// 1012, the server is restarting
+	ServiceRestart,
+	/// 1013, the server is overloaded, the client should try again later
+	TryAgainLater,
+	/// 1014, the server acting as a gateway received an invalid response
+	BadGateway,
+	/// 1015, the TLS handshake failed, must not be sent in a Close frame
+	TlsHandshake,
+	/// Any other status code, e.g. the application codes from 3000 to 4999
+	Other(u16),
+}
+
+impl CloseCode {
+	/// Get the `CloseCode` of a raw status code
+	pub fn from_u16(code: u16) -> CloseCode {
+		match code {
+			1000 => CloseCode::NormalClosure,
+			1001 => CloseCode::GoingAway,
+			1002 => CloseCode::ProtocolError,
+			1003 => CloseCode::UnsupportedData,
+			1005 => CloseCode::NoStatusReceived,
+			1006 => CloseCode::AbnormalClosure,
+			1007 => CloseCode::InvalidFramePayloadData,
+			1008 => CloseCode::PolicyViolation,
+			1009 => CloseCode::MessageTooBig,
+			1010 => CloseCode::MandatoryExtension,
+			1011 => CloseCode::InternalServerError,
+			1012 => CloseCode::ServiceRestart,
+			1013 => CloseCode::TryAgainLater,
+			1014 => CloseCode::BadGateway,
+			1015 => CloseCode::TlsHandshake,
+			code => CloseCode::Other(code),
+		}
+	}
+
+	/// Get the raw status code of this `CloseCode`
+	pub fn to_u16(self) -> u16 {
+		match self {
+			CloseCode::NormalClosure => 1000,
+			CloseCode::GoingAway => 1001,
+			CloseCode::ProtocolError => 1002,
+			CloseCode::UnsupportedData => 1003,
+			CloseCode::NoStatusReceived => 1005,
+			CloseCode::AbnormalClosure => 1006,
+			CloseCode::InvalidFramePayloadData => 1007,
+			CloseCode::PolicyViolation => 1008,
+			CloseCode::MessageTooBig => 1009,
+			CloseCode::MandatoryExtension => 1010,
+			CloseCode::InternalServerError => 1011,
+			CloseCode::ServiceRestart => 1012,
+			CloseCode::TryAgainLater => 1013,
+			CloseCode::BadGateway => 1014,
+			CloseCode::TlsHandshake => 1015,
+			CloseCode::Other(code) => code,
+		}
+	}
+
+	/// Whether this code is reserved for reporting a closed connection
+	/// locally and must never be sent in a Close frame.
+	pub fn is_reserved(self) -> bool {
+		self == CloseCode::NoStatusReceived
+			|| self == CloseCode::AbnormalClosure
+			|| self == CloseCode::TlsHandshake
+	}
+
+	/// Whether this code may be sent in a Close frame, these are the codes
+	/// defined by RFC 6455 and the IANA registry which are not reserved,
+	/// and the codes 3000 to 4999 used by libraries and applications.
+	pub fn is_valid(self) -> bool {
+		matches!(self.to_u16(), 1000..=1003 | 1007..=1014 | 3000..=4999)
+	}
+}
+
+impl From<u16> for CloseCode {
+	fn from(code: u16) -> Self {
+		CloseCode::from_u16(code)
+	}
+}
+
+impl From<CloseCode> for u16 {
+	fn from(code: CloseCode) -> Self {
+		code.to_u16()
+	}
+}
+
 /// Trait representing the ability to convert
 /// self to a `Cow<'a, [u8]>`
 pub trait IntoCowBytes<'a> {
@@ -536,3 +852,226 @@ impl<'a> IntoCowBytes<'a> for Cow<'a, [u8]> {
 		self
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use dataframe::DataFrame;
+	use ws::Message as MessageTrait;
+
+	fn close_frame(code: u16) -> DataFrame {
+		let mut payload = Vec::new();
+		payload.write_u16::<BigEndian>(code).unwrap();
+		payload.extend_from_slice(b"bye");
+		DataFrame::new(true, Opcode::Close, payload)
+	}
+
+	#[test]
+	fn close_code_round_trip() {
+		for code in 0..5000 {
+			assert_eq!(CloseCode::from_u16(code).to_u16(), code);
+		}
+		assert_eq!(CloseCode::from_u16(1000), CloseCode::NormalClosure);
+		assert_eq!(CloseCode::from(4000), CloseCode::Other(4000));
+		assert_eq!(u16::from(CloseCode::MessageTooBig), 1009);
+		assert_eq!(
+			CloseData::new(CloseCode::GoingAway, "").unwrap().code(),
+			CloseCode::GoingAway
+		);
+	}
+
+	#[test]
+	fn reserved_close_codes_are_rejected() {
+		for &code in &[1005, 1006, 1015] {
+			assert!(CloseCode::from_u16(code).is_reserved());
+			assert!(OwnedMessage::from_dataframes(vec![close_frame(code)]).is_err());
+		}
+
+		let message = OwnedMessage::from_dataframes(vec![close_frame(3000)]).unwrap();
+		assert_eq!(
+			message,
+			OwnedMessage::Close(Some(CloseData::new(CloseCode::Other(3000), "bye").unwrap()))
+		);
+	}
+
+	#[test]
+	#[cfg(feature = "sync")]
+	fn invalid_close_payloads() {
+		use receiver::Receiver;
+		use result::WebSocketError;
+		use ws::receiver::Receiver as ReceiverTrait;
+
+		let mut frame = close_frame(1000);
+		frame.data.extend_from_slice(b" \xf0\x28\x8c\xbc");
+		let error = OwnedMessage::from_dataframes(vec![frame.clone()]).unwrap_err();
+		match error {
+			WebSocketError::Utf8Error(_) => (),
+			ref other => panic!("expected a UTF-8 error, got {:?}", other),
+		}
+		assert_eq!(error.close_code(), Some(CloseCode::InvalidFramePayloadData));
+
+		// the same frame off the wire, unmasked as sent by a server
+		let mut wire = Vec::new();
+		frame.write_to(&mut wire, false).unwrap();
+		let error = Receiver::new(false)
+			.recv_message(&mut &wire[..])
+			.unwrap_err();
+		assert_eq!(error.close_code(), Some(CloseCode::InvalidFramePayloadData));
+
+		for &code in &[0, 999, 1004, 1016, 2999, 5000, 65535] {
+			let error = OwnedMessage::from_dataframes(vec![close_frame(code)]).unwrap_err();
+			match error {
+				WebSocketError::ProtocolError(_) => (),
+				ref other => panic!("expected a protocol error for {}, got {:?}", code, other),
+			}
+			assert_eq!(error.close_code(), Some(CloseCode::ProtocolError));
+		}
+	}
+
+	#[test]
+	fn conversions_move_the_payload() {
+		let mut data = Vec::with_capacity(64);
+		data.extend_from_slice(b"payload");
+		let (ptr, capacity) = (data.as_ptr(), data.capacity());
+
+		let data = OwnedMessage::from(data).into_binary().unwrap();
+		assert_eq!((data.as_ptr(), data.capacity()), (ptr, capacity));
+		let data = OwnedMessage::from(data).into_bytes();
+		assert_eq!((data.as_ptr(), data.capacity()), (ptr, capacity));
+
+		let message: OwnedMessage = Message::from(data).into();
+		let data = message.into_text().unwrap_err().into_bytes();
+		assert_eq!((data.as_ptr(), data.capacity()), (ptr, capacity));
+
+		let text = String::from_utf8(data).unwrap();
+		let message: OwnedMessage = Message::from(text).into();
+		let text = message.into_text().unwrap();
+		assert_eq!((text.as_ptr(), text.capacity()), (ptr, capacity));
+		let data = Message::from(text).into_bytes();
+		assert_eq!((data.as_ptr(), data.capacity()), (ptr, capacity));
+		assert_eq!(data, b"payload");
+	}
+
+	#[test]
+	fn dataframes_match_the_serialized_message() {
+		use ws::Message as MessageTrait;
+
+		for message in &[
+			OwnedMessage::Text("text".to_string()),
+			OwnedMessage::Binary(vec![]),
+			OwnedMessage::Close(None),
+			OwnedMessage::Close(Some(CloseData::new(CloseCode::GoingAway, "bye").unwrap())),
+			OwnedMessage::Pong(b"pong".to_vec()),
+		] {
+			let mut wire = Vec::new();
+			message.serialize(&mut wire, false).unwrap();
+			let frame = DataFrame::read_dataframe(&mut &wire[..], false).unwrap();
+			assert_eq!(
+				message.dataframes().unwrap(),
+				::std::slice::from_ref(&frame)
+			);
+			assert_eq!(message.as_borrowed().dataframes().unwrap(), [frame]);
+		}
+	}
+
+	#[test]
+	fn conversions_through_references() {
+		for message in &[
+			OwnedMessage::Text("text".to_string()),
+			OwnedMessage::Binary(vec![0, 1, 2]),
+			OwnedMessage::Close(None),
+			OwnedMessage::Close(Some(CloseData::new(CloseCode::GoingAway, "bye").unwrap())),
+			OwnedMessage::Ping(b"ping".to_vec()),
+			OwnedMessage::Pong(vec![]),
+		] {
+			let borrowed = message.as_borrowed();
+			match borrowed.payload {
+				Cow::Borrowed(_) => (),
+				Cow::Owned(_) => panic!("the payload of {:?} was copied", message),
+			}
+			assert_eq!(borrowed, Message::from(message.clone()));
+			assert_eq!(&OwnedMessage::from(&borrowed), message);
+		}
+
+		let pong = Message::pong(&b"pong"[..]);
+		assert_eq!(
+			OwnedMessage::from(&pong),
+			OwnedMessage::Pong(b"pong".to_vec())
+		);
+		let close = Message::close_because(1000, "done");
+		let data = CloseData::new(CloseCode::NormalClosure, "done").unwrap();
+		assert_eq!(OwnedMessage::from(&close), OwnedMessage::Close(Some(data)));
+	}
+
+	#[test]
+	fn close_data_is_validated() {
+		let reason = "x".repeat(123);
+		let data = CloseData::new(CloseCode::NormalClosure, reason.clone()).unwrap();
+		assert_eq!(OwnedMessage::Close(Some(data)).frame_size(false), 127);
+		match CloseData::new(CloseCode::NormalClosure, reason + "x") {
+			Err(WebSocketError::DataFrameError(_)) => (),
+			other => panic!("the reason is too long, got {:?}", other),
+		}
+		for &code in &[0, 999, 1004, 1005, 1016, 2999, 5000] {
+			assert!(CloseData::new(CloseCode::from_u16(code), "").is_err());
+		}
+		for &code in &[1000, 1003, 1007, 1014, 3000, 4999] {
+			assert!(CloseData::new(CloseCode::from_u16(code), "").is_ok());
+		}
+	}
+
+	#[test]
+	fn received_close_data_is_validated() {
+		let mut payload = vec![0x03, 0xe8];
+		payload.extend_from_slice(&[0xce, 0xbb, 0xff]);
+		let frame = DataFrame::new(true, Opcode::Close, payload);
+		match OwnedMessage::from_dataframes(vec![frame]) {
+			Err(WebSocketError::Utf8Error(_)) => (),
+			other => panic!("the reason is not UTF-8, got {:?}", other),
+		}
+
+		for &code in &[999, 1004, 2000, 5000] {
+			match OwnedMessage::from_dataframes(vec![close_frame(code)]) {
+				Err(WebSocketError::ProtocolError(_)) => (),
+				other => panic!("{} is not a valid close code, got {:?}", code, other),
+			}
+		}
+	}
+
+	#[test]
+	fn close_payload_lengths() {
+		let close = |payload: &[u8]| {
+			OwnedMessage::from_dataframes(vec![DataFrame::new(
+				true,
+				Opcode::Close,
+				payload.to_vec(),
+			)])
+		};
+		assert_eq!(close(&[]).unwrap(), OwnedMessage::Close(None));
+		assert_eq!(
+			close(&[0x03, 0xe8]).unwrap(),
+			OwnedMessage::Close(Some(CloseData {
+				status_code: 1000,
+				reason: String::new(),
+			}))
+		);
+		assert_eq!(
+			close(&[0x03, 0xe8, b'o', b'k']).unwrap(),
+			OwnedMessage::Close(Some(CloseData {
+				status_code: 1000,
+				reason: "ok".to_string(),
+			}))
+		);
+		match close(&[0x03]) {
+			Err(WebSocketError::ProtocolError(_)) => (),
+			other => panic!("a single byte close payload is invalid, got {:?}", other),
+		}
+
+		let mut output = Vec::new();
+		OwnedMessage::Close(None)
+			.serialize(&mut output, false)
+			.unwrap();
+		Message::close().serialize(&mut output, false).unwrap();
+		assert_eq!(output, [0x88, 0x00, 0x88, 0x00]);
+	}
+}
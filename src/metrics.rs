@@ -0,0 +1,152 @@
+//! Counters of the traffic on a connection, e.g. to export them to a monitoring system.
+//!
+//! Every synchronous client keeps `Metrics` which are updated as frames are
+//! sent and received. They are shared by the halves of a split client and can
+//! be read from any thread while the connection is used.
+//!
+//! ```rust,no_run
+//! use websocket::message::OwnedMessage;
+//! use websocket::ClientBuilder;
+//!
+//! let mut client = ClientBuilder::new("ws://127.0.0.1:1234")
+//!     .unwrap()
+//!     .connect_insecure()
+//!     .unwrap();
+//! let metrics = client.metrics();
+//! client.send_message(&OwnedMessage::Text("hello".to_string())).unwrap();
+//! println!("{} messages, {} bytes sent", metrics.messages_sent(), metrics.bytes_sent());
+//! ```
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counters of the messages and frames of a connection, see the module documentation.
+///
+/// Messages are the text and binary messages, counted once their last frame is
+/// sent or received. Bytes are the bytes of all frames on the wire, including
+/// their headers. The counters are only increased, they wrap around on overflow.
+#[derive(Debug, Default)]
+pub struct Metrics {
+	messages_sent: AtomicUsize,
+	messages_received: AtomicUsize,
+	bytes_sent: AtomicUsize,
+	bytes_received: AtomicUsize,
+	pings_sent: AtomicUsize,
+	pings_received: AtomicUsize,
+	pongs_sent: AtomicUsize,
+	pongs_received: AtomicUsize,
+	buffered: AtomicUsize,
+}
+
+impl Metrics {
+	/// New counters which are all 0.
+	pub fn new() -> Self {
+		Metrics::default()
+	}
+
+	/// The number of text and binary messages sent.
+	pub fn messages_sent(&self) -> usize {
+		self.messages_sent.load(Ordering::Relaxed)
+	}
+
+	/// The number of text and binary messages received.
+	pub fn messages_received(&self) -> usize {
+		self.messages_received.load(Ordering::Relaxed)
+	}
+
+	/// The number of bytes sent, frame headers included.
+	pub fn bytes_sent(&self) -> usize {
+		self.bytes_sent.load(Ordering::Relaxed)
+	}
+
+	/// The number of bytes received, frame headers included.
+	pub fn bytes_received(&self) -> usize {
+		self.bytes_received.load(Ordering::Relaxed)
+	}
+
+	/// The number of pings sent.
+	pub fn pings_sent(&self) -> usize {
+		self.pings_sent.load(Ordering::Relaxed)
+	}
+
+	/// The number of pings received.
+	pub fn pings_received(&self) -> usize {
+		self.pings_received.load(Ordering::Relaxed)
+	}
+
+	/// The number of pongs sent.
+	pub fn pongs_sent(&self) -> usize {
+		self.pongs_sent.load(Ordering::Relaxed)
+	}
+
+	/// The number of pongs received.
+	pub fn pongs_received(&self) -> usize {
+		self.pongs_received.load(Ordering::Relaxed)
+	}
+
+	/// The payload bytes of the fragments of an incomplete message which the
+	/// receiver holds until the message's last frame arrives.
+	pub fn reassembly_buffer_len(&self) -> usize {
+		self.buffered.load(Ordering::Relaxed)
+	}
+
+	/// Count a frame of `len` bytes which was sent.
+	#[cfg(feature = "sync")]
+	pub(crate) fn frame_sent(&self, opcode: u8, finished: bool, len: usize) {
+		count(
+			opcode,
+			finished,
+			len,
+			[
+				&self.messages_sent,
+				&self.bytes_sent,
+				&self.pings_sent,
+				&self.pongs_sent,
+			],
+		);
+	}
+
+	/// Count a frame of `len` bytes which was received.
+	#[cfg(feature = "sync")]
+	pub(crate) fn frame_received(&self, opcode: u8, finished: bool, len: usize) {
+		count(
+			opcode,
+			finished,
+			len,
+			[
+				&self.messages_received,
+				&self.bytes_received,
+				&self.pings_received,
+				&self.pongs_received,
+			],
+		);
+	}
+
+	#[cfg(feature = "sync")]
+	pub(crate) fn set_reassembly_buffer_len(&self, len: usize) {
+		self.buffered.store(len, Ordering::Relaxed);
+	}
+}
+
+// the counters are messages, bytes, pings and pongs
+#[cfg(feature = "sync")]
+fn count(opcode: u8, finished: bool, len: usize, counters: [&AtomicUsize; 4]) {
+	counters[1].fetch_add(len, Ordering::Relaxed);
+	let counter = match opcode {
+		0..=2 if finished => counters[0],
+		9 => counters[2],
+		10 => counters[3],
+		_ => return,
+	};
+	counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The size of a frame on the wire with a payload of `len` bytes.
+#[cfg(feature = "sync")]
+pub(crate) fn frame_len(len: usize, masked: bool) -> usize {
+	let length_size = match len {
+		0..=125 => 0,
+		126..=0xFFFF => 2,
+		_ => 8,
+	};
+	2 + length_size + if masked { 4 } else { 0 } + len
+}
@@ -0,0 +1,50 @@
+//! Hooks to watch the frames of a connection, e.g. to debug protocol issues.
+//!
+//! A `FrameObserver` is told about every frame read from or written to a
+//! connection, it can only look at the frames and never change them.
+//! Connections without an observer don't pay for it.
+//!
+//! ```rust,no_run
+//! use std::sync::Arc;
+//! use websocket::dataframe::DataFrame;
+//! use websocket::observer::FrameObserver;
+//! use websocket::ClientBuilder;
+//!
+//! struct Log;
+//!
+//! impl FrameObserver for Log {
+//!     fn on_read(&self, frame: &DataFrame, masked: bool) {
+//!         println!("<- {:?} {} bytes fin={} rsv={:?} masked={}",
+//!             frame.opcode, frame.data.len(), frame.finished, frame.reserved, masked);
+//!     }
+//!
+//!     fn on_write(&self, frame: &DataFrame, masked: bool) {
+//!         println!("-> {:?} {} bytes fin={} rsv={:?} masked={}",
+//!             frame.opcode, frame.data.len(), frame.finished, frame.reserved, masked);
+//!     }
+//! }
+//!
+//! let mut client = ClientBuilder::new("ws://127.0.0.1:1234")
+//!     .unwrap()
+//!     .connect_insecure()
+//!     .unwrap();
+//! client.set_frame_observer(Some(Arc::new(Log)));
+//! ```
+
+use dataframe::DataFrame;
+
+/// Watches the frames of a connection, see the module documentation.
+///
+/// The frames are passed with their payload unmasked, `masked` tells whether
+/// the frame is masked on the wire. Both methods do nothing by default.
+///
+/// An observer is shared by both halves of a split client, which may be used
+/// from different threads.
+pub trait FrameObserver: Send + Sync {
+	/// Called with every frame read from the connection,
+	/// before it is checked against the negotiated extensions.
+	fn on_read(&self, _frame: &DataFrame, _masked: bool) {}
+
+	/// Called with every frame right before it is written to the connection.
+	fn on_write(&self, _frame: &DataFrame, _masked: bool) {}
+}
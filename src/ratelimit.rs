@@ -0,0 +1,191 @@
+//! Rate limiting of the frames received on a connection.
+//!
+//! A `RateLimit` is a token bucket which is refilled at a steady rate, every
+//! received frame takes one token (or one token per payload byte) out of it.
+//! Once the bucket is empty the receiver paces itself: the synchronous receiver
+//! sleeps before reading the next frame and an asynchronous client wrapped in
+//! `async::client::RateLimited` returns `NotReady` until enough tokens are back,
+//! so a client which sends too much is read no faster than its budget allows.
+//!
+//! A limit is set for a single connection, e.g. when accepting a client:
+//!
+//! ```rust,no_run
+//! # use std::time::Duration;
+//! use websocket::ratelimit::RateLimit;
+//! use websocket::sync::Server;
+//!
+//! let mut server = Server::bind("127.0.0.1:1234").unwrap();
+//! let upgrade = server.accept().ok().unwrap();
+//! let limit = RateLimit::frames_per_second(100)
+//!     .burst(500)
+//!     .close_after(Duration::from_secs(10));
+//! let client = upgrade.rate_limit(limit).accept().unwrap();
+//! ```
+
+#[cfg(any(feature = "sync", feature = "async"))]
+use result::{WebSocketError, WebSocketResult};
+use std::time::Duration;
+#[cfg(any(feature = "sync", feature = "async"))]
+use std::time::Instant;
+
+/// The budget of a connection, see the module documentation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+	bytes: bool,
+	rate: u64,
+	burst: u64,
+	close_after: Option<Duration>,
+}
+
+impl RateLimit {
+	/// Allow `frames` frames per second (at least one), and as many in a burst.
+	pub fn frames_per_second(frames: u64) -> Self {
+		RateLimit::new(false, frames)
+	}
+
+	/// Allow `bytes` bytes of frame payload per second (at least one),
+	/// and as many in a burst.
+	pub fn bytes_per_second(bytes: u64) -> Self {
+		RateLimit::new(true, bytes)
+	}
+
+	fn new(bytes: bool, rate: u64) -> Self {
+		let rate = rate.max(1);
+		RateLimit {
+			bytes,
+			rate,
+			burst: rate,
+			close_after: None,
+		}
+	}
+
+	/// How many frames (or bytes) can be received at once before
+	/// the receiver starts pacing itself.
+	pub fn burst(mut self, burst: u64) -> Self {
+		self.burst = burst;
+		self
+	}
+
+	/// Fail with `WebSocketError::RateLimitExceeded` once the peer sent more than
+	/// its budget for `duration` without a break. By default the connection is
+	/// only paced and never given up on.
+	pub fn close_after(mut self, duration: Duration) -> Self {
+		self.close_after = Some(duration);
+		self
+	}
+}
+
+/// The state of a `RateLimit` for one connection.
+#[cfg(any(feature = "sync", feature = "async"))]
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+	limit: RateLimit,
+	// may become negative, the debt is paid before the next frame is read
+	tokens: f64,
+	updated: Instant,
+	exceeded_since: Option<Instant>,
+}
+
+#[cfg(any(feature = "sync", feature = "async"))]
+impl RateLimiter {
+	/// Start with a full bucket.
+	pub(crate) fn new(limit: RateLimit) -> Self {
+		RateLimiter {
+			limit,
+			tokens: limit.burst as f64,
+			updated: Instant::now(),
+			exceeded_since: None,
+		}
+	}
+
+	/// When the next frame can be read, `None` if it can be read right away.
+	pub(crate) fn ready_at(&self) -> Option<Instant> {
+		if self.tokens >= 0.0 {
+			return None;
+		}
+		let wait = Duration::from_secs_f64(-self.tokens / self.limit.rate as f64);
+		Some(self.updated + wait)
+	}
+
+	/// Take the tokens of a frame with a payload of `len` bytes, received at `now`.
+	pub(crate) fn take(&mut self, len: usize, now: Instant) -> WebSocketResult<()> {
+		let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+		let tokens = self.tokens + elapsed * self.limit.rate as f64;
+		self.tokens = tokens.min(self.limit.burst as f64);
+		self.updated = now;
+
+		let cost = if self.limit.bytes { len as f64 } else { 1.0 };
+		if self.tokens >= cost {
+			self.exceeded_since = None;
+		} else {
+			let since = *self.exceeded_since.get_or_insert(now);
+			if let Some(close_after) = self.limit.close_after {
+				if now.saturating_duration_since(since) >= close_after {
+					return Err(WebSocketError::RateLimitExceeded);
+				}
+			}
+		}
+		self.tokens -= cost;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn the_bucket_refills_at_the_rate() {
+		let mut limiter = RateLimiter::new(RateLimit::frames_per_second(10).burst(2));
+		let start = limiter.updated;
+		limiter.take(0, start).unwrap();
+		limiter.take(0, start).unwrap();
+		assert_eq!(limiter.ready_at(), None);
+
+		// a frame over the budget is paid for before the next one is read
+		limiter.take(0, start).unwrap();
+		assert_eq!(limiter.ready_at(), Some(start + Duration::from_millis(100)));
+		limiter.take(0, start + Duration::from_millis(100)).unwrap();
+		assert_eq!(limiter.ready_at(), Some(start + Duration::from_millis(200)));
+
+		// the bucket never holds more than the burst
+		let later = start + Duration::from_secs(60);
+		for _ in 0..2 {
+			limiter.take(0, later).unwrap();
+		}
+		assert_eq!(limiter.ready_at(), None);
+		limiter.take(0, later).unwrap();
+		assert!(limiter.ready_at().is_some());
+	}
+
+	#[test]
+	fn bytes_and_sustained_abuse() {
+		let limit = RateLimit::bytes_per_second(1000).close_after(Duration::from_secs(1));
+		let mut limiter = RateLimiter::new(limit);
+		let start = limiter.updated;
+		limiter.take(1000, start).unwrap();
+		limiter.take(500, start).unwrap();
+		assert_eq!(limiter.ready_at(), Some(start + Duration::from_millis(500)));
+
+		// a peer which keeps over its budget is given up on
+		let mut now = start;
+		while now < start + Duration::from_millis(900) {
+			now += Duration::from_millis(100);
+			limiter.take(500, now).unwrap();
+		}
+		now += Duration::from_millis(200);
+		match limiter.take(500, now) {
+			Err(WebSocketError::RateLimitExceeded) => {}
+			other => panic!("expected the rate limit to be exceeded, got {:?}", other),
+		}
+
+		// a break resets it
+		let mut limiter = RateLimiter::new(limit);
+		limiter.take(1500, start).unwrap();
+		let later = start + Duration::from_secs(5);
+		limiter.take(100, later).unwrap();
+		limiter
+			.take(2000, later + Duration::from_millis(1))
+			.unwrap();
+	}
+}
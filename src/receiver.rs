@@ -1,18 +1,32 @@
 //! The default implementation of a WebSocket Receiver.
 
-use std::io::Read;
+use std::cmp;
 use std::io::Result as IoResult;
+use std::io::{self, Read};
 
 use hyper::buffer::BufReader;
 
-use dataframe::{DataFrame, Opcode};
-use message::OwnedMessage;
+use dataframe::{self, DataFrame, Opcode};
+#[cfg(feature = "deflate")]
+use deflate::Decompressor;
+use message::{Message, OwnedMessage};
+use metrics::{self, Metrics};
+use observer::FrameObserver;
+use ratelimit::{RateLimit, RateLimiter};
 use result::{WebSocketError, WebSocketResult};
+use server::ConnectionSlot;
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
 pub use stream::sync::Shutdown;
 use stream::sync::{AsTcpStream, Stream};
 use ws;
 use ws::receiver::Receiver as ReceiverTrait;
 use ws::receiver::{DataFrameIterator, MessageIterator};
+use ws::util::header::{self as dfh, DataFrameFlags, DataFrameHeader};
+use ws::util::mask;
+use ws::util::utf8::Utf8Validator;
 
 /// This reader bundles an existing stream with a parsing algorithm.
 /// It is used by the client in its `.split()` function as the reading component.
@@ -50,6 +64,26 @@ where
 	pub fn incoming_messages<'a>(&'a mut self) -> MessageIterator<'a, Receiver, BufReader<R>> {
 		self.receiver.incoming_messages(&mut self.stream)
 	}
+
+	/// Starts reading a message piece by piece, see `Receiver::message_reader`.
+	pub fn message_reader<'a>(&'a mut self) -> WebSocketResult<MessageReader<'a, BufReader<R>>> {
+		self.receiver.message_reader(&mut self.stream)
+	}
+
+	/// Reads a single message without copying its payload, see `Receiver::recv_borrowed`.
+	pub fn recv_borrowed<'a>(&'a mut self) -> WebSocketResult<Message<'a>> {
+		self.receiver.recv_borrowed(&mut self.stream)
+	}
+
+	/// Reads and discards the next message, see `Receiver::skip_message`.
+	pub fn skip_message(&mut self) -> WebSocketResult<Vec<OwnedMessage>> {
+		self.receiver.skip_message(&mut self.stream)
+	}
+
+	/// Reads a data frame to forward it, see `Receiver::recv_dataframe_raw`.
+	pub fn recv_dataframe_raw(&mut self) -> WebSocketResult<DataFrame> {
+		self.receiver.recv_dataframe_raw(&mut self.stream)
+	}
 }
 
 impl<S> Reader<S>
@@ -73,16 +107,855 @@ where
 /// DataFrames and Messages.
 pub struct Receiver {
 	buffer: Vec<DataFrame>,
+	partial: Vec<u8>,
+	ahead: ReadAhead,
+	// the payload of the message received with `recv_borrowed`, its opcode
+	// while it is incomplete and whether it was handed out already
+	scratch: Vec<u8>,
+	scratch_opcode: Option<(Opcode, bool)>,
+	scratch_fragments: usize,
+	scratch_done: bool,
 	mask: bool,
+	max_message_size: Option<usize>,
+	max_frame_size: Option<usize>,
+	max_fragments: Option<usize>,
+	utf8: Utf8Validator,
+	reserved_bits: [bool; 3],
+	minimal_lengths: bool,
+	#[cfg(feature = "deflate")]
+	decompressor: Option<Decompressor>,
+	limiter: Option<RateLimiter>,
+	observer: Option<Arc<dyn FrameObserver>>,
+	metrics: Arc<Metrics>,
+	// count the connection for a server with a connection limit or shutdown
+	// trigger, the receiver lives as long as the connection is read from
+	slots: Vec<ConnectionSlot>,
 }
 
 impl Receiver {
-	/// Create a new Receiver using the specified Reader.
+	/// Create a new Receiver, `mask` sets the role it receives frames in.
+	///
+	/// Servers pass `true`, frames sent by clients must be masked.
+	/// Clients pass `false`, frames sent by servers must not be masked.
+	/// Frames breaking this rule fail with `WebSocketError::ProtocolError`.
 	pub fn new(mask: bool) -> Receiver {
 		Receiver {
 			buffer: Vec::new(),
+			partial: Vec::new(),
+			ahead: ReadAhead {
+				buf: Vec::new(),
+				pos: 0,
+				size: 0,
+			},
+			scratch: Vec::new(),
+			scratch_opcode: None,
+			scratch_fragments: 0,
+			scratch_done: false,
 			mask,
+			max_message_size: None,
+			max_frame_size: None,
+			max_fragments: None,
+			utf8: Utf8Validator::new(),
+			reserved_bits: [false; 3],
+			minimal_lengths: false,
+			#[cfg(feature = "deflate")]
+			decompressor: None,
+			limiter: None,
+			observer: None,
+			metrics: Arc::new(Metrics::new()),
+			slots: Vec::new(),
+		}
+	}
+
+	/// Limit the total payload size of a message, including all of its fragments.
+	/// Receiving a bigger message fails with `WebSocketError::MessageTooBig`.
+	/// By default the size of messages is not limited.
+	pub fn set_max_message_size(&mut self, max_message_size: Option<usize>) {
+		self.max_message_size = max_message_size;
+	}
+
+	/// Limit the payload size of a single data frame, the check is done as soon
+	/// as the frame header is read so oversized frames are never buffered.
+	/// By default the size of frames is not limited.
+	pub fn set_max_frame_size(&mut self, max_frame_size: Option<usize>) {
+		self.max_frame_size = max_frame_size;
+	}
+
+	/// Limit the number of frames a single message is sent in, receiving a
+	/// message in more fragments fails with `WebSocketError::MessageTooBig`.
+	/// This catches messages sent as lots of tiny frames, whose total size is
+	/// within `set_max_message_size`. By default the number is not limited.
+	pub fn set_max_fragments(&mut self, max_fragments: Option<usize>) {
+		self.max_fragments = max_fragments;
+	}
+
+	/// Reject frames whose payload length is not encoded in the fewest bytes
+	/// possible with a `WebSocketError::ProtocolError`, as RFC 6455 requires.
+	/// By default such lengths are accepted.
+	pub fn set_minimal_lengths(&mut self, minimal_lengths: bool) {
+		self.minimal_lengths = minimal_lengths;
+	}
+
+	fn too_many_fragments(&self, fragments: usize) -> bool {
+		self.max_fragments.is_some_and(|max| fragments > max)
+	}
+
+	/// Read up to `size` bytes from the stream at once and parse the next frames
+	/// out of them, so a burst of small frames is received with a single read.
+	/// The bytes which were read ahead are kept for the next frames, reads of a
+	/// payload bigger than `size` go to the stream directly.
+	///
+	/// By default (0) every frame is read with the reads it needs, which suits
+	/// buffered streams like the `BufReader` of a client, see
+	/// `ClientBuilder::read_buffer_size`.
+	pub fn set_read_ahead(&mut self, size: usize) {
+		self.ahead.size = size;
+	}
+
+	/// The size of the reads ahead, see `set_read_ahead`.
+	pub fn read_ahead(&self) -> usize {
+		self.ahead.size
+	}
+
+	/// Limit how fast frames are received, see the `ratelimit` module.
+	/// Once the budget is used up reading the next frame waits until enough
+	/// of it is back, this applies to every way of receiving frames and messages.
+	/// By default frames are not limited.
+	pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+		self.limiter = limit.map(RateLimiter::new);
+	}
+
+	/// Tell `observer` about every frame received from now on,
+	/// see the `observer` module.
+	pub fn set_frame_observer(&mut self, observer: Option<Arc<dyn FrameObserver>>) {
+		self.observer = observer;
+	}
+
+	/// The counters of the frames received, see the `metrics` module.
+	pub fn metrics(&self) -> &Arc<Metrics> {
+		&self.metrics
+	}
+
+	/// Count the frames received from now on in `metrics`, e.g. to share them
+	/// with a sender.
+	pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+		self.metrics = metrics;
+	}
+
+	// the payload of an incomplete message which is held until it is finished
+	fn update_reassembly_len(&self) {
+		let scratch = match self.scratch_opcode {
+			Some(_) => self.scratch.len(),
+			None => 0,
+		};
+		let buffered: usize = self.buffer.iter().map(|f| f.data.len()).sum();
+		self.metrics.set_reassembly_buffer_len(scratch + buffered);
+	}
+
+	// waits until the rate limit allows to read the next frame
+	fn pace(&self) {
+		if let Some(ready_at) = self.limiter.as_ref().and_then(RateLimiter::ready_at) {
+			let now = Instant::now();
+			if ready_at > now {
+				thread::sleep(ready_at - now);
+			}
+		}
+	}
+
+	// counts a received frame against the rate limit
+	fn count_frame(&mut self, len: usize) -> WebSocketResult<()> {
+		match self.limiter {
+			Some(ref mut limiter) => limiter.take(len, Instant::now()),
+			None => Ok(()),
+		}
+	}
+
+	fn check_message_size(&mut self, size: usize) -> WebSocketResult<()> {
+		let too_big = match self.max_message_size {
+			Some(max) => size > max,
+			None => false,
+		};
+		if too_big || self.too_many_fragments(self.buffer.len()) {
+			self.buffer.clear();
+			self.utf8 = Utf8Validator::new();
+			return Err(WebSocketError::MessageTooBig);
+		}
+		Ok(())
+	}
+
+	// validate text messages as their fragments arrive, compressed
+	// messages can only be validated once they are inflated
+	fn validate_utf8(&mut self) -> WebSocketResult<()> {
+		let result = match (self.buffer.first(), self.buffer.last()) {
+			(Some(first), Some(last)) if first.opcode == Opcode::Text && !first.reserved[0] => {
+				let result = self.utf8.feed(&last.data);
+				if last.finished {
+					result.and_then(|()| self.utf8.finish())
+				} else {
+					result
+				}
+			}
+			_ => return Ok(()),
+		};
+		result.map_err(|e| {
+			self.utf8 = Utf8Validator::new();
+			self.buffer.clear();
+			e.into()
+		})
+	}
+
+	/// Starts reading the next message piece by piece, the payload of each frame can
+	/// be read from the returned `MessageReader` as soon as the frame has arrived, so
+	/// big messages never have to be held in memory.
+	///
+	/// Control messages which arrive before or in between the frames of the message
+	/// are collected, see `MessageReader::take_control_messages`. A close message
+	/// which arrives before the first frame is returned right away instead, as a
+	/// finished `MessageReader` with the `Close` opcode and the payload of the close
+	/// frame. Text messages are validated frame by frame, a `MessageReader` can not
+	/// read compressed messages.
+	///
+	/// The message must be read to the end (or skipped with `MessageReader::finish`)
+	/// before the next message can be received.
+	///
+	/// ```rust
+	/// # use websocket::dataframe::{DataFrame, Opcode};
+	/// # use websocket::receiver::Receiver;
+	/// # use websocket::ws::dataframe::DataFrame as DataFrameTrait;
+	/// use std::io::Read;
+	///
+	/// # let mut input = Vec::new();
+	/// # DataFrame::new(false, Opcode::Binary, vec![1, 2]).write_to(&mut input, false).unwrap();
+	/// # DataFrame::new(true, Opcode::Continuation, vec![3]).write_to(&mut input, false).unwrap();
+	/// let mut receiver = Receiver::new(false);
+	/// let mut stream = &input[..];
+	///
+	/// let mut message = receiver.message_reader(&mut stream).unwrap();
+	/// assert_eq!(message.opcode(), Opcode::Binary);
+	/// let mut payload = Vec::new();
+	/// message.read_to_end(&mut payload).unwrap();
+	/// assert_eq!(payload, vec![1, 2, 3]);
+	/// ```
+	pub fn message_reader<'a, R>(
+		&'a mut self,
+		reader: &'a mut R,
+	) -> WebSocketResult<MessageReader<'a, R>>
+	where
+		R: Read,
+	{
+		if !self.buffer.is_empty() {
+			return Err(WebSocketError::ProtocolError(
+				"A message is already partially received",
+			));
+		}
+
+		let mut controls = Vec::new();
+		let first = loop {
+			let frame = self.recv_dataframe(reader)?;
+			if frame.opcode as u8 >= 8 && frame.opcode != Opcode::Close {
+				controls.push(<OwnedMessage as ws::Message>::from_dataframes(vec![frame])?);
+				continue;
+			}
+			break frame;
+		};
+		if first.opcode == Opcode::Close {
+			// no message is coming anymore, the close is returned right away
+			let payload = first.data.clone();
+			<OwnedMessage as ws::Message>::from_dataframes(vec![first])?;
+			return Ok(MessageReader {
+				opcode: Opcode::Close,
+				utf8: None,
+				receiver: self,
+				reader,
+				payload,
+				pos: 0,
+				size: 0,
+				fragments: 0,
+				finished: true,
+				controls,
+			});
+		}
+		if first.opcode == Opcode::Continuation {
+			return Err(WebSocketError::ProtocolError(
+				"Unexpected continuation data frame opcode",
+			));
+		}
+		if first.reserved[0] && self.is_decompressing() {
+			return Err(WebSocketError::ProtocolError(
+				"Compressed messages can not be read in pieces",
+			));
 		}
+
+		let mut message = MessageReader {
+			opcode: first.opcode,
+			utf8: if first.opcode == Opcode::Text {
+				Some(Utf8Validator::new())
+			} else {
+				None
+			},
+			receiver: self,
+			reader,
+			payload: Vec::new(),
+			pos: 0,
+			size: 0,
+			fragments: 0,
+			finished: false,
+			controls,
+		};
+		message.next_payload(first)?;
+		Ok(message)
+	}
+
+	/// Receives the next message without copying its payload, the returned message
+	/// borrows a buffer of the receiver which is reused for every message.
+	///
+	/// The fragments of a message are joined in this buffer as well. Only control
+	/// messages which arrive in between the fragments of a message and compressed
+	/// messages own their payload.
+	///
+	/// Like `recv_message` this can be called again after a `WouldBlock` or `TimedOut`
+	/// error, but a message must not be started with one and finished with the other.
+	///
+	/// ```rust
+	/// # use websocket::dataframe::{DataFrame, Opcode};
+	/// # use websocket::receiver::Receiver;
+	/// # use websocket::ws::dataframe::DataFrame as DataFrameTrait;
+	/// use std::borrow::Cow;
+	/// use websocket::message::Type;
+	///
+	/// # let mut input = Vec::new();
+	/// # DataFrame::new(true, Opcode::Binary, vec![1, 2, 3]).write_to(&mut input, false).unwrap();
+	/// let mut receiver = Receiver::new(false);
+	/// let mut stream = &input[..];
+	///
+	/// let message = receiver.recv_borrowed(&mut stream).unwrap();
+	/// assert_eq!(message.opcode, Type::Binary);
+	/// match message.payload {
+	///     Cow::Borrowed(payload) => assert_eq!(payload, &[1, 2, 3]),
+	///     Cow::Owned(_) => unreachable!(),
+	/// }
+	/// ```
+	pub fn recv_borrowed<'a, R>(&'a mut self, reader: &mut R) -> WebSocketResult<Message<'a>>
+	where
+		R: Read,
+	{
+		if !self.buffer.is_empty() {
+			return Err(WebSocketError::ProtocolError(
+				"A message is already partially received",
+			));
+		}
+		if self.scratch_done {
+			self.scratch.clear();
+			self.scratch_done = false;
+		}
+
+		let (opcode, owned) = loop {
+			let start = self.scratch.len();
+			let header = match self.recv_into_scratch(reader) {
+				Err(e) if self.scratch_opcode.is_some() => return Err(incomplete(e)),
+				result => result?,
+			};
+			let opcode = Opcode::from_u8(header.opcode)?;
+			let finished = header.flags.contains(DataFrameFlags::FIN);
+
+			if opcode as u8 >= 8 {
+				if self.scratch_opcode.is_none() {
+					self.scratch_done = true;
+					break (opcode, None);
+				}
+				break (opcode, Some(self.scratch.split_off(start)));
+			}
+
+			let compressed = header.flags.contains(DataFrameFlags::RSV1);
+			let (message_opcode, message_compressed) = match (self.scratch_opcode, opcode) {
+				(None, Opcode::Continuation) => {
+					self.reset_scratch();
+					return Err(WebSocketError::ProtocolError(
+						"Unexpected continuation data frame opcode",
+					));
+				}
+				(None, opcode) => (opcode, compressed),
+				(Some(message), Opcode::Continuation) => message,
+				(Some(_), _) => {
+					self.reset_scratch();
+					return Err(WebSocketError::ProtocolError(
+						"Unexpected data frame opcode",
+					));
+				}
+			};
+			self.scratch_opcode = Some((message_opcode, message_compressed));
+			self.scratch_fragments += 1;
+
+			let too_big = match self.max_message_size {
+				Some(max) => self.scratch.len() > max,
+				None => false,
+			};
+			if too_big || self.too_many_fragments(self.scratch_fragments) {
+				self.reset_scratch();
+				return Err(WebSocketError::MessageTooBig);
+			}
+			if message_opcode == Opcode::Text && !message_compressed {
+				let mut result = self.utf8.feed(&self.scratch[start..]);
+				if finished {
+					result = result.and_then(|()| self.utf8.finish());
+				}
+				if let Err(e) = result {
+					self.reset_scratch();
+					return Err(e.into());
+				}
+			}
+
+			if finished {
+				self.scratch_opcode = None;
+				self.scratch_fragments = 0;
+				self.scratch_done = true;
+				self.update_reassembly_len();
+				if message_compressed {
+					break (message_opcode, Some(self.inflate_scratch()?));
+				}
+				break (message_opcode, None);
+			}
+		};
+
+		match owned {
+			Some(payload) => Message::from_payload(opcode, Cow::Owned(payload)),
+			None => Message::from_payload(opcode, Cow::Borrowed(&self.scratch[..])),
+		}
+	}
+
+	// reads a data frame, its unmasked payload is appended to `scratch`
+	fn recv_into_scratch<R>(&mut self, reader: &mut R) -> WebSocketResult<DataFrameHeader>
+	where
+		R: Read,
+	{
+		self.update_reassembly_len();
+		self.pace();
+		let start = self.scratch.len();
+		let result = {
+			let mut reader = Resume {
+				read: &mut self.partial,
+				pos: 0,
+				inner: &mut Ahead {
+					buffer: &mut self.ahead,
+					inner: reader,
+				},
+			};
+			read_payload(
+				&mut reader,
+				&mut self.scratch,
+				self.mask,
+				self.max_frame_size,
+				self.minimal_lengths,
+			)
+		};
+		match result {
+			Err(WebSocketError::IoError(ref e))
+				if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {}
+			_ => self.partial.clear(),
+		}
+		if result.is_err() {
+			self.scratch.truncate(start);
+		}
+		let header = result?;
+		if let (Some(observer), Some(opcode)) = (self.observer.as_ref(), Opcode::new(header.opcode))
+		{
+			let frame = DataFrame {
+				finished: header.flags.contains(DataFrameFlags::FIN),
+				reserved: reserved_bits(&header),
+				opcode,
+				data: self.scratch[start..].to_vec(),
+			};
+			observer.on_read(&frame, self.mask);
+		}
+		self.metrics.frame_received(
+			header.opcode,
+			header.flags.contains(DataFrameFlags::FIN),
+			metrics::frame_len(header.len as usize, header.mask.is_some()),
+		);
+		if let Err(e) = self.count_frame(header.len as usize) {
+			self.reset_scratch();
+			return Err(e);
+		}
+
+		if let Err(e) = self.check_reserved_bits(header.opcode, &reserved_bits(&header)) {
+			self.reset_scratch();
+			return Err(e);
+		}
+		Ok(header)
+	}
+
+	/// Reads and discards the next message, or the rest of a message which was
+	/// partially received, without buffering its payload. This keeps the
+	/// connection in sync after deciding to ignore a message, e.g. one that can't
+	/// be parsed by the application.
+	///
+	/// The frames are checked like `recv_message` checks them, except that text
+	/// is not validated. The control messages received before or in between the
+	/// frames are returned in the order they arrived, pings still have to be
+	/// answered by the caller. If a close message arrives it is returned last and
+	/// the message is never completed.
+	///
+	/// The payload of a compressed message still goes through the decompressor,
+	/// whose context the following messages may refer to, but the output is
+	/// thrown away.
+	///
+	/// Like `message_reader` this is meant for blocking readers.
+	///
+	/// ```rust
+	/// # use websocket::dataframe::{DataFrame, Opcode};
+	/// # use websocket::receiver::Receiver;
+	/// # use websocket::ws::dataframe::DataFrame as DataFrameTrait;
+	/// use websocket::ws::Receiver as ReceiverTrait;
+	/// use websocket::OwnedMessage;
+	///
+	/// # let mut input = Vec::new();
+	/// # DataFrame::new(true, Opcode::Binary, vec![0; 1024]).write_to(&mut input, false).unwrap();
+	/// # DataFrame::new(true, Opcode::Text, b"next".to_vec()).write_to(&mut input, false).unwrap();
+	/// let mut receiver = Receiver::new(false);
+	/// let mut stream = &input[..];
+	///
+	/// assert!(receiver.skip_message(&mut stream).unwrap().is_empty());
+	/// let message = receiver.recv_message(&mut stream).unwrap();
+	/// assert_eq!(message, OwnedMessage::Text("next".to_string()));
+	/// ```
+	pub fn skip_message<R>(&mut self, reader: &mut R) -> WebSocketResult<Vec<OwnedMessage>>
+	where
+		R: Read,
+	{
+		let mut started = !self.buffer.is_empty() || self.scratch_opcode.is_some();
+		let mut compressed = self.buffer.first().is_some_and(|f| f.reserved[0])
+			|| self
+				.scratch_opcode
+				.is_some_and(|(_, compressed)| compressed);
+		if compressed {
+			self.discard_received()?;
+		}
+		self.buffer.clear();
+		self.reset_scratch();
+
+		let mut controls = Vec::new();
+		loop {
+			let result = self.skip_frame(reader, &mut compressed);
+			let (opcode, finished, control) = if started {
+				result.map_err(incomplete)?
+			} else {
+				result?
+			};
+			if let Some(frame) = control {
+				controls.push(<OwnedMessage as ws::Message>::from_dataframes(vec![frame])?);
+				if opcode == Opcode::Close {
+					return Ok(controls);
+				}
+				continue;
+			}
+			match (started, opcode) {
+				(false, Opcode::Continuation) => {
+					return Err(WebSocketError::ProtocolError(
+						"Unexpected continuation data frame opcode",
+					));
+				}
+				(true, Opcode::Continuation) | (false, _) => {}
+				(true, _) => {
+					return Err(WebSocketError::ProtocolError(
+						"Unexpected data frame opcode",
+					));
+				}
+			}
+			if finished {
+				return Ok(controls);
+			}
+			started = true;
+		}
+	}
+
+	/// Reads a single data frame like `recv_dataframe` to forward it with
+	/// `Sender::send_dataframe_raw`, e.g. by a proxy. The reserved bits of the
+	/// frame are not checked, the extensions using them are negotiated between
+	/// the endpoints the frames are forwarded between.
+	///
+	/// The payload is unmasked, so the frame can be forwarded to an endpoint of
+	/// either role.
+	pub fn recv_dataframe_raw<R>(&mut self, reader: &mut R) -> WebSocketResult<DataFrame>
+	where
+		R: Read,
+	{
+		self.read_dataframe(reader, false)
+	}
+
+	// reads a frame, keeping the bytes of an incomplete frame to resume with them
+	fn read_dataframe<R>(
+		&mut self,
+		reader: &mut R,
+		check_reserved: bool,
+	) -> WebSocketResult<DataFrame>
+	where
+		R: Read,
+	{
+		self.update_reassembly_len();
+		self.pace();
+		let result = {
+			let mut reader = Resume {
+				read: &mut self.partial,
+				pos: 0,
+				inner: &mut Ahead {
+					buffer: &mut self.ahead,
+					inner: reader,
+				},
+			};
+			let (mask, max_frame_size) = (self.mask, self.max_frame_size);
+			dfh::read_header_with(&mut reader, self.minimal_lengths).and_then(|header| {
+				DataFrame::read_payload_with_limit(header, &mut reader, mask, max_frame_size)
+			})
+		};
+		match result {
+			Err(WebSocketError::IoError(ref e))
+				if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {}
+			_ => self.partial.clear(),
+		}
+		let frame = result?;
+		if let Some(ref observer) = self.observer {
+			observer.on_read(&frame, self.mask);
+		}
+		self.metrics.frame_received(
+			frame.opcode as u8,
+			frame.finished,
+			metrics::frame_len(frame.data.len(), self.mask),
+		);
+		self.count_frame(frame.data.len())?;
+		if check_reserved {
+			self.check_reserved_bits(frame.opcode as u8, &frame.reserved)?;
+		}
+
+		Ok(frame)
+	}
+
+	// reads a data frame, only the payload of control frames is kept
+	fn skip_frame<R>(
+		&mut self,
+		inner: &mut R,
+		compressed: &mut bool,
+	) -> WebSocketResult<(Opcode, bool, Option<DataFrame>)>
+	where
+		R: Read,
+	{
+		self.update_reassembly_len();
+		self.pace();
+		let reader = &mut Ahead {
+			buffer: &mut self.ahead,
+			inner: &mut *inner,
+		};
+		let header = dfh::read_header_with(reader, self.minimal_lengths)?;
+		let opcode = Opcode::from_u8(header.opcode)?;
+		dataframe::check_masking(&header, self.mask)?;
+		if let Some(max_len) = self.max_frame_size {
+			if header.len > max_len as u64 {
+				return Err(WebSocketError::DataFrameError(
+					"Data frame payload exceeds the maximum frame size",
+				));
+			}
+		}
+		let finished = header.flags.contains(DataFrameFlags::FIN);
+		let reserved = reserved_bits(&header);
+
+		let control = if opcode as u8 >= 8 {
+			let mut data = Vec::new();
+			read_exactly(&mut reader.take(header.len), header.len, &mut data)?;
+			let frame = DataFrame::read_dataframe_body(header, data, self.mask)?;
+			if let Some(ref observer) = self.observer {
+				observer.on_read(&frame, self.mask);
+			}
+			Some(frame)
+		} else {
+			if opcode != Opcode::Continuation {
+				*compressed = reserved[0];
+			}
+			self.skip_payload(inner, header.len, *compressed, finished)?;
+			None
+		};
+		self.metrics.frame_received(
+			header.opcode,
+			finished,
+			metrics::frame_len(header.len as usize, header.mask.is_some()),
+		);
+		self.count_frame(header.len as usize)?;
+		self.check_reserved_bits(header.opcode, &reserved)?;
+		Ok((opcode, finished, control))
+	}
+
+	fn check_reserved_bits(&self, opcode: u8, reserved: &[bool; 3]) -> WebSocketResult<()> {
+		// permessage-deflate only marks the first frame of a data message
+		if reserved[0] && self.is_decompressing() && (opcode == 0 || opcode >= 8) {
+			return Err(WebSocketError::ProtocolError(
+				"RSV1 set on a control or continuation frame",
+			));
+		}
+		let unexpected = reserved
+			.iter()
+			.zip(self.reserved_bits.iter())
+			.any(|(&set, &allowed)| set && !allowed);
+		if unexpected {
+			return Err(WebSocketError::ProtocolError(
+				"Unsupported reserved bits received",
+			));
+		}
+		Ok(())
+	}
+
+	fn reset_scratch(&mut self) {
+		self.scratch.clear();
+		self.scratch_opcode = None;
+		self.scratch_fragments = 0;
+		self.scratch_done = false;
+		self.utf8 = Utf8Validator::new();
+	}
+
+	// compressed messages are inflated into a new buffer
+	#[cfg(feature = "deflate")]
+	fn inflate_scratch(&mut self) -> WebSocketResult<Vec<u8>> {
+		match self.decompressor {
+			Some(ref mut decompressor) => {
+				decompressor.decompress(&self.scratch, self.max_message_size)
+			}
+			None => Err(WebSocketError::ProtocolError(
+				"Unsupported reserved bits received",
+			)),
+		}
+	}
+
+	#[cfg(not(feature = "deflate"))]
+	fn inflate_scratch(&mut self) -> WebSocketResult<Vec<u8>> {
+		Err(WebSocketError::ProtocolError(
+			"Unsupported reserved bits received",
+		))
+	}
+
+	// the payload of a skipped data frame, which the decompressor has to see
+	// if the message is compressed, so its context stays in sync with the peer
+	#[cfg(feature = "deflate")]
+	fn skip_payload<R>(
+		&mut self,
+		inner: &mut R,
+		len: u64,
+		compressed: bool,
+		finished: bool,
+	) -> WebSocketResult<()>
+	where
+		R: Read,
+	{
+		let reader = &mut Ahead {
+			buffer: &mut self.ahead,
+			inner,
+		};
+		match self.decompressor {
+			Some(ref mut decompressor) if compressed => {
+				let mut buf = [0; 4096];
+				let mut left = len;
+				while left > 0 {
+					let end = cmp::min(left, buf.len() as u64) as usize;
+					let read = match reader.read(&mut buf[..end]) {
+						Ok(0) => {
+							return Err(io::Error::new(
+								io::ErrorKind::UnexpectedEof,
+								"incomplete payload",
+							)
+							.into())
+						}
+						Ok(read) => read,
+						Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+						Err(e) => return Err(e.into()),
+					};
+					decompressor.discard(&buf[..read], false)?;
+					left -= read as u64;
+				}
+				decompressor.discard(&[], finished)
+			}
+			_ => read_exactly(&mut reader.take(len), len, &mut io::sink()),
+		}
+	}
+
+	#[cfg(not(feature = "deflate"))]
+	fn skip_payload<R>(
+		&mut self,
+		inner: &mut R,
+		len: u64,
+		_compressed: bool,
+		_finished: bool,
+	) -> WebSocketResult<()>
+	where
+		R: Read,
+	{
+		let reader = &mut Ahead {
+			buffer: &mut self.ahead,
+			inner,
+		};
+		read_exactly(&mut reader.take(len), len, &mut io::sink())
+	}
+
+	// the fragments of a compressed message received before it is skipped
+	#[cfg(feature = "deflate")]
+	fn discard_received(&mut self) -> WebSocketResult<()> {
+		if let Some(ref mut decompressor) = self.decompressor {
+			for frame in &self.buffer {
+				decompressor.discard(&frame.data, false)?;
+			}
+			if self.scratch_opcode.is_some() {
+				decompressor.discard(&self.scratch, false)?;
+			}
+		}
+		Ok(())
+	}
+
+	#[cfg(not(feature = "deflate"))]
+	fn discard_received(&mut self) -> WebSocketResult<()> {
+		Ok(())
+	}
+
+	#[cfg(feature = "deflate")]
+	fn is_decompressing(&self) -> bool {
+		self.decompressor.is_some()
+	}
+
+	#[cfg(not(feature = "deflate"))]
+	fn is_decompressing(&self) -> bool {
+		false
+	}
+
+	/// Take the bytes of a data frame that was only partially received,
+	/// see `recv_dataframe`, followed by the bytes read ahead of it.
+	pub(crate) fn take_partial_frame(&mut self) -> Vec<u8> {
+		let mut partial = ::std::mem::take(&mut self.partial);
+		partial.extend_from_slice(&self.ahead.buf[self.ahead.pos..]);
+		self.ahead.buf.clear();
+		self.ahead.pos = 0;
+		partial
+	}
+
+	/// Declare which of the reserved bits RSV1, RSV2 and RSV3 are used by
+	/// the negotiated extensions. Receiving a data frame with any other reserved
+	/// bit set is a protocol error. By default no reserved bits are allowed.
+	pub fn set_reserved_bits(&mut self, reserved_bits: [bool; 3]) {
+		self.reserved_bits = reserved_bits;
+	}
+
+	/// Inflate compressed messages received from now on, this should only be
+	/// used once the `permessage-deflate` extension has been negotiated.
+	/// The decompressor consumes the RSV1 bit.
+	#[cfg(feature = "deflate")]
+	pub fn set_decompressor(&mut self, decompressor: Option<Decompressor>) {
+		self.reserved_bits[0] = decompressor.is_some();
+		self.decompressor = decompressor;
+	}
+
+	/// Count the connection against a server's connection limit, or keep it
+	/// registered with a shutdown trigger, until this receiver is dropped.
+	pub(crate) fn hold_slot(&mut self, slot: ConnectionSlot) {
+		self.slots.push(slot);
+	}
+
+	// the close to send once the server of the connection is going away
+	#[cfg(feature = "sync")]
+	pub(crate) fn going_away(&self) -> Option<OwnedMessage> {
+		self.slots.iter().find_map(ConnectionSlot::going_away)
 	}
 }
 
@@ -92,11 +965,15 @@ impl ws::Receiver for Receiver {
 	type M = OwnedMessage;
 
 	/// Reads a single data frame from the remote endpoint.
+	///
+	/// If the reader fails with `WouldBlock`, e.g. because it is nonblocking,
+	/// or `TimedOut` the bytes of the incomplete frame are kept and reading
+	/// resumes with them on the next call.
 	fn recv_dataframe<R>(&mut self, reader: &mut R) -> WebSocketResult<DataFrame>
 	where
 		R: Read,
 	{
-		DataFrame::read_dataframe(reader, self.mask)
+		self.read_dataframe(reader, true)
 	}
 
 	/// Returns the data frames that constitute one message.
@@ -104,6 +981,8 @@ impl ws::Receiver for Receiver {
 	where
 		R: Read,
 	{
+		let mut size: usize = self.buffer.iter().map(|f| f.data.len()).sum();
+
 		let mut finished = if self.buffer.is_empty() {
 			let first = self.recv_dataframe(reader)?;
 
@@ -114,25 +993,35 @@ impl ws::Receiver for Receiver {
 			}
 
 			let finished = first.finished;
+			size += first.data.len();
 			self.buffer.push(first);
+			self.check_message_size(size)?;
+			self.validate_utf8()?;
 			finished
 		} else {
 			false
 		};
 
 		while !finished {
-			let next = self.recv_dataframe(reader)?;
+			let next = self.recv_dataframe(reader).map_err(incomplete)?;
 			finished = next.finished;
 
 			match next.opcode as u8 {
 				// Continuation opcode
-				0 => self.buffer.push(next),
+				0 => {
+					size += next.data.len();
+					self.buffer.push(next);
+					self.check_message_size(size)?;
+					self.validate_utf8()?;
+				}
 				// Control frame
 				8...15 => {
 					return Ok(vec![next]);
 				}
-				// Others
+				// Others, the message in progress is discarded
 				_ => {
+					self.buffer.clear();
+					self.utf8 = Utf8Validator::new();
 					return Err(WebSocketError::ProtocolError(
 						"Unexpected data frame opcode",
 					));
@@ -140,6 +1029,1081 @@ impl ws::Receiver for Receiver {
 			}
 		}
 
-		Ok(::std::mem::replace(&mut self.buffer, Vec::new()))
+		let frames = ::std::mem::replace(&mut self.buffer, Vec::new());
+		self.update_reassembly_len();
+
+		#[cfg(feature = "deflate")]
+		{
+			if let Some(ref mut decompressor) = self.decompressor {
+				// the limit applies to the inflated payload as well
+				return decompressor.decompress_frames(frames, self.max_message_size);
+			}
+		}
+
+		Ok(frames)
+	}
+}
+
+/// A message which is read frame by frame, created by `Receiver::message_reader`.
+///
+/// Reading fails with an `io::Error` of kind `InvalidData` wrapping the
+/// `WebSocketError` if the message is invalid.
+pub struct MessageReader<'a, R>
+where
+	R: Read + 'a,
+{
+	receiver: &'a mut Receiver,
+	reader: &'a mut R,
+	opcode: Opcode,
+	utf8: Option<Utf8Validator>,
+	payload: Vec<u8>,
+	pos: usize,
+	size: usize,
+	fragments: usize,
+	finished: bool,
+	controls: Vec<OwnedMessage>,
+}
+
+impl<'a, R> MessageReader<'a, R>
+where
+	R: Read,
+{
+	/// Whether this is a text or a binary message, or a close message which
+	/// arrived instead of one.
+	pub fn opcode(&self) -> Opcode {
+		self.opcode
+	}
+
+	/// Whether the last frame of the message has been received.
+	pub fn is_finished(&self) -> bool {
+		self.finished
+	}
+
+	/// Takes the control messages which were received before or in between
+	/// the frames of this message, in the order they arrived.
+	///
+	/// Pings still have to be answered by the caller. If a close message arrives
+	/// the message is never completed and further reads fail with an error of kind
+	/// `ConnectionAborted`.
+	pub fn take_control_messages(&mut self) -> Vec<OwnedMessage> {
+		::std::mem::take(&mut self.controls)
+	}
+
+	/// Reads and discards the rest of the message.
+	pub fn finish(&mut self) -> WebSocketResult<()> {
+		while !self.finished {
+			self.next_frame()?;
+		}
+		self.pos = self.payload.len();
+		Ok(())
+	}
+
+	fn next_frame(&mut self) -> WebSocketResult<()> {
+		let frame = self
+			.receiver
+			.recv_dataframe(self.reader)
+			.map_err(incomplete)?;
+		match frame.opcode as u8 {
+			0 => self.next_payload(frame),
+			8..=15 => {
+				let close = frame.opcode == Opcode::Close;
+				self.controls
+					.push(<OwnedMessage as ws::Message>::from_dataframes(vec![frame])?);
+				if close {
+					return Err(io::Error::new(
+						io::ErrorKind::ConnectionAborted,
+						"The connection was closed in the middle of a message",
+					)
+					.into());
+				}
+				Ok(())
+			}
+			_ => Err(WebSocketError::ProtocolError(
+				"Unexpected data frame opcode",
+			)),
+		}
+	}
+
+	fn next_payload(&mut self, frame: DataFrame) -> WebSocketResult<()> {
+		self.size += frame.data.len();
+		self.fragments += 1;
+		if let Some(max) = self.receiver.max_message_size {
+			if self.size > max {
+				return Err(WebSocketError::MessageTooBig);
+			}
+		}
+		if self.receiver.too_many_fragments(self.fragments) {
+			return Err(WebSocketError::MessageTooBig);
+		}
+		if let Some(ref mut utf8) = self.utf8 {
+			utf8.feed(&frame.data)?;
+			if frame.finished {
+				utf8.finish()?;
+			}
+		}
+		self.finished = frame.finished;
+		self.payload = frame.data;
+		self.pos = 0;
+		Ok(())
+	}
+}
+
+impl<'a, R> Read for MessageReader<'a, R>
+where
+	R: Read,
+{
+	fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+		while self.pos == self.payload.len() && !self.finished {
+			self.next_frame().map_err(|e| match e {
+				WebSocketError::IoError(e) => e,
+				e @ WebSocketError::IncompleteMessage => {
+					io::Error::new(io::ErrorKind::UnexpectedEof, e)
+				}
+				e => io::Error::new(io::ErrorKind::InvalidData, e),
+			})?;
+		}
+		let len = cmp::min(buf.len(), self.payload.len() - self.pos);
+		buf[..len].copy_from_slice(&self.payload[self.pos..self.pos + len]);
+		self.pos += len;
+		Ok(len)
+	}
+}
+
+// the connection ended while a message was being received
+fn incomplete(error: WebSocketError) -> WebSocketError {
+	match error {
+		WebSocketError::NoDataAvailable => WebSocketError::IncompleteMessage,
+		WebSocketError::IoError(ref e)
+			if e.kind() == io::ErrorKind::ConnectionReset
+				|| e.kind() == io::ErrorKind::ConnectionAborted =>
+		{
+			WebSocketError::IncompleteMessage
+		}
+		error => error,
+	}
+}
+
+// reads a data frame, appending its unmasked payload to `data`
+fn read_payload<R>(
+	reader: &mut R,
+	data: &mut Vec<u8>,
+	should_be_masked: bool,
+	max_len: Option<usize>,
+	minimal_lengths: bool,
+) -> WebSocketResult<DataFrameHeader>
+where
+	R: Read,
+{
+	let header = dfh::read_header_with(reader, minimal_lengths)?;
+	if let Some(max_len) = max_len {
+		if header.len > max_len as u64 {
+			return Err(WebSocketError::DataFrameError(
+				"Data frame payload exceeds the maximum frame size",
+			));
+		}
+	}
+	dataframe::check_masking(&header, should_be_masked)?;
+
+	let start = data.len();
+	let read = reader.take(header.len).read_to_end(data)?;
+	if (read as u64) < header.len {
+		return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete payload").into());
+	}
+	if let Some(mask) = header.mask {
+		mask::apply_mask(mask, &mut data[start..]);
+	}
+	Ok(header)
+}
+
+fn reserved_bits(header: &DataFrameHeader) -> [bool; 3] {
+	[
+		header.flags.contains(DataFrameFlags::RSV1),
+		header.flags.contains(DataFrameFlags::RSV2),
+		header.flags.contains(DataFrameFlags::RSV3),
+	]
+}
+
+// copies `len` bytes of payload to `writer`
+fn read_exactly<R, W>(reader: &mut R, len: u64, writer: &mut W) -> WebSocketResult<()>
+where
+	R: Read,
+	W: io::Write,
+{
+	if io::copy(reader, writer)? < len {
+		return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete payload").into());
+	}
+	Ok(())
+}
+
+// replays the bytes of a partially read data frame before reading new ones,
+// everything read is kept so the frame can be read again from its start
+struct Resume<'a, R: 'a> {
+	read: &'a mut Vec<u8>,
+	pos: usize,
+	inner: &'a mut R,
+}
+
+impl<'a, R> Read for Resume<'a, R>
+where
+	R: Read,
+{
+	fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+		if self.pos < self.read.len() {
+			let len = cmp::min(buf.len(), self.read.len() - self.pos);
+			buf[..len].copy_from_slice(&self.read[self.pos..self.pos + len]);
+			self.pos += len;
+			return Ok(len);
+		}
+		let len = self.inner.read(buf)?;
+		self.read.extend_from_slice(&buf[..len]);
+		self.pos += len;
+		Ok(len)
+	}
+}
+
+// the bytes read from the stream which were not parsed yet, see `set_read_ahead`
+struct ReadAhead {
+	buf: Vec<u8>,
+	pos: usize,
+	size: usize,
+}
+
+// serves reads from the bytes read ahead, refilling them with a read of up to
+// `size` bytes once they are used up
+struct Ahead<'a, R: 'a> {
+	buffer: &'a mut ReadAhead,
+	inner: &'a mut R,
+}
+
+impl<'a, R> Read for Ahead<'a, R>
+where
+	R: Read,
+{
+	fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+		let ahead = &mut *self.buffer;
+		if ahead.pos == ahead.buf.len() {
+			if buf.len() >= ahead.size {
+				return self.inner.read(buf);
+			}
+			ahead.buf.resize(ahead.size, 0);
+			ahead.pos = 0;
+			match self.inner.read(&mut ahead.buf) {
+				Ok(len) => ahead.buf.truncate(len),
+				Err(e) => {
+					ahead.buf.clear();
+					return Err(e);
+				}
+			}
+		}
+		let len = cmp::min(buf.len(), ahead.buf.len() - ahead.pos);
+		buf[..len].copy_from_slice(&ahead.buf[ahead.pos..ahead.pos + len]);
+		ahead.pos += len;
+		Ok(len)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use ws::dataframe::DataFrame as DataFrameTrait;
+
+	fn wire(frames: &[DataFrame]) -> Vec<u8> {
+		let mut wire = Vec::new();
+		for frame in frames {
+			frame.write_to(&mut wire, false).unwrap();
+		}
+		wire
+	}
+
+	#[test]
+	fn reserved_opcodes_are_rejected() {
+		for &opcode in &[Opcode::NonControl1, Opcode::Control1] {
+			let wire = wire(&[DataFrame::new(true, opcode, b"?".to_vec())]);
+			assert_eq!(wire[0] & 0x0f, opcode as u8);
+			let mut receiver = Receiver::new(false);
+			match receiver.recv_dataframe(&mut &wire[..]) {
+				Err(WebSocketError::ProtocolError(_)) => (),
+				other => panic!("{:?} is reserved, got {:?}", opcode, other),
+			}
+			match receiver.recv_message(&mut &wire[..]) {
+				Err(WebSocketError::ProtocolError(_)) => (),
+				other => panic!("{:?} is reserved, got {:?}", opcode, other),
+			}
+			match receiver.recv_borrowed(&mut &wire[..]) {
+				Err(WebSocketError::ProtocolError(_)) => (),
+				other => panic!("{:?} is reserved, got {:?}", opcode, other),
+			}
+		}
+		for op in 0..16 {
+			let reserved = (3..=7).contains(&op) || op >= 11;
+			assert_eq!(Opcode::from_u8(op).is_err(), reserved);
+		}
+		assert!(Opcode::from_u8(16).is_err());
+	}
+
+	#[test]
+	fn fragments_must_be_in_sequence() {
+		let text = || DataFrame::new(true, Opcode::Text, b"ok".to_vec());
+		let cases = vec![
+			vec![DataFrame::new(true, Opcode::Continuation, vec![]), text()],
+			vec![
+				DataFrame::new(false, Opcode::Text, vec![0xce]),
+				DataFrame::new(true, Opcode::Text, vec![]),
+				text(),
+			],
+		];
+		for frames in cases {
+			let wire = wire(&frames);
+
+			let mut reader = &wire[..];
+			let mut receiver = Receiver::new(false);
+			match receiver.recv_message(&mut reader) {
+				Err(WebSocketError::ProtocolError(_)) => (),
+				other => panic!("expected a protocol error, got {:?}", other),
+			}
+			assert_eq!(
+				receiver.recv_message(&mut reader).unwrap(),
+				OwnedMessage::Text("ok".to_string())
+			);
+
+			let mut reader = &wire[..];
+			let mut receiver = Receiver::new(false);
+			match receiver.recv_borrowed(&mut reader) {
+				Err(WebSocketError::ProtocolError(_)) => (),
+				other => panic!("expected a protocol error, got {:?}", other),
+			}
+			assert_eq!(
+				&*receiver.recv_borrowed(&mut reader).unwrap().payload,
+				b"ok"
+			);
+		}
+	}
+
+	#[test]
+	fn max_message_size_counts_all_fragments() {
+		let wire = wire(&[
+			DataFrame::new(false, Opcode::Binary, vec![0; 6]),
+			DataFrame::new(false, Opcode::Continuation, vec![0; 6]),
+			DataFrame::new(true, Opcode::Continuation, vec![0; 6]),
+		]);
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_max_message_size(Some(17));
+		match receiver.recv_message(&mut &wire[..]) {
+			Err(WebSocketError::MessageTooBig) => (),
+			other => panic!("expected MessageTooBig, got {:?}", other),
+		}
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_max_message_size(Some(18));
+		assert_eq!(
+			receiver.recv_message(&mut &wire[..]).unwrap(),
+			OwnedMessage::Binary(vec![0; 18])
+		);
+	}
+
+	#[test]
+	fn max_fragments_rejects_tiny_frames() {
+		let mut frames = vec![DataFrame::new(false, Opcode::Binary, vec![0])];
+		for i in 1..10_000 {
+			frames.push(DataFrame::new(i == 9_999, Opcode::Continuation, vec![0]));
+		}
+		let wire = wire(&frames);
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_max_message_size(Some(64 * 1024));
+		receiver.set_max_fragments(Some(1_000));
+		match receiver.recv_message(&mut &wire[..]) {
+			Err(WebSocketError::MessageTooBig) => (),
+			other => panic!("expected MessageTooBig, got {:?}", other),
+		}
+		match receiver.recv_borrowed(&mut &wire[..]) {
+			Err(WebSocketError::MessageTooBig) => (),
+			other => panic!("expected MessageTooBig, got {:?}", other.map(|m| m.opcode)),
+		}
+		{
+			let mut reader = &wire[..];
+			let mut message = receiver.message_reader(&mut reader).unwrap();
+			let mut payload = Vec::new();
+			let err = message.read_to_end(&mut payload).unwrap_err();
+			assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+			assert_eq!(payload.len(), 1_000);
+		}
+
+		// a message within the limit is fine
+		let mut receiver = Receiver::new(false);
+		receiver.set_max_fragments(Some(10_000));
+		assert_eq!(
+			receiver.recv_message(&mut &wire[..]).unwrap(),
+			OwnedMessage::Binary(vec![0; 10_000])
+		);
+	}
+
+	#[test]
+	fn max_frame_size_checks_announced_length() {
+		// a binary frame announcing a 2^63 - 1 byte payload
+		let header = [0x82, 127, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+		let mut receiver = Receiver::new(false);
+		receiver.set_max_frame_size(Some(1024));
+		match receiver.recv_dataframe(&mut &header[..]) {
+			Err(WebSocketError::DataFrameError(_)) => (),
+			other => panic!("expected DataFrameError, got {:?}", other),
+		}
+
+		let wire = wire(&[DataFrame::new(true, Opcode::Binary, vec![0; 1024])]);
+		assert_eq!(
+			receiver.recv_dataframe(&mut &wire[..]).unwrap().data.len(),
+			1024
+		);
+	}
+
+	#[test]
+	fn unlimited_frame_size_does_not_trust_length() {
+		let header = [0x82, 127, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+		match Receiver::new(false).recv_dataframe(&mut &header[..]) {
+			Err(WebSocketError::NoDataAvailable) => (),
+			other => panic!("expected NoDataAvailable, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn connection_closed_in_the_middle_of_a_message() {
+		let wire = wire(&[
+			DataFrame::new(false, Opcode::Text, b"hel".to_vec()),
+			DataFrame::new(true, Opcode::Continuation, b"lo".to_vec()),
+		]);
+		// after the first fragment and in the middle of the second one
+		for &end in [5, 8].iter() {
+			match Receiver::new(false).recv_message(&mut &wire[..end]) {
+				Err(WebSocketError::IncompleteMessage) => (),
+				other => panic!("expected IncompleteMessage, got {:?}", other),
+			}
+			match Receiver::new(false).recv_borrowed(&mut &wire[..end]) {
+				Err(WebSocketError::IncompleteMessage) => (),
+				other => panic!("expected IncompleteMessage, got {:?}", other.err()),
+			}
+			let mut receiver = Receiver::new(false);
+			let mut reader = &wire[..end];
+			let mut message = receiver.message_reader(&mut reader).unwrap();
+			let err = message.read_to_end(&mut Vec::new()).unwrap_err();
+			assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+		}
+
+		// a connection closed in between two messages is not an incomplete message
+		match Receiver::new(false).recv_message(&mut &wire[..0]) {
+			Err(WebSocketError::NoDataAvailable) => (),
+			other => panic!("expected NoDataAvailable, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn invalid_utf8_is_rejected_before_the_message_ends() {
+		// the second fragment completes the sequence with an invalid byte,
+		// the final fragment is never read
+		let invalid = wire(&[
+			DataFrame::new(false, Opcode::Text, vec![b'a', 0xe2, 0x82]),
+			DataFrame::new(false, Opcode::Continuation, vec![0x41]),
+		]);
+		match Receiver::new(false).recv_message(&mut &invalid[..]) {
+			Err(WebSocketError::Utf8Error(_)) => (),
+			other => panic!("expected Utf8Error, got {:?}", other),
+		}
+
+		let valid = wire(&[
+			DataFrame::new(false, Opcode::Text, vec![b'a', 0xe2, 0x82]),
+			DataFrame::new(true, Opcode::Continuation, vec![0xac]),
+		]);
+		assert_eq!(
+			Receiver::new(false).recv_message(&mut &valid[..]).unwrap(),
+			OwnedMessage::Text("a\u{20ac}".to_string())
+		);
+	}
+
+	#[test]
+	fn reserved_bits_are_rejected() {
+		for i in 0..3 {
+			let mut frame = DataFrame::new(true, Opcode::Binary, vec![1, 2, 3]);
+			frame.reserved[i] = true;
+			let wire = wire(&[frame.clone()]);
+
+			match Receiver::new(false).recv_dataframe(&mut &wire[..]) {
+				Err(WebSocketError::ProtocolError(_)) => (),
+				other => panic!("expected ProtocolError, got {:?}", other),
+			}
+
+			let mut allowed = [false; 3];
+			allowed[i] = true;
+			let mut receiver = Receiver::new(false);
+			receiver.set_reserved_bits(allowed);
+			assert_eq!(receiver.recv_dataframe(&mut &wire[..]).unwrap(), frame);
+		}
+	}
+
+	#[test]
+	#[cfg(feature = "deflate")]
+	fn rsv1_is_only_allowed_on_the_first_data_frame() {
+		let mut ping = DataFrame::new(true, Opcode::Ping, vec![]);
+		ping.reserved[0] = true;
+		let mut continuation = DataFrame::new(true, Opcode::Continuation, vec![]);
+		continuation.reserved[0] = true;
+		let wires = vec![
+			wire(&[ping]),
+			wire(&[
+				DataFrame::new(false, Opcode::Text, b"a".to_vec()),
+				continuation,
+			]),
+		];
+
+		for wire in wires {
+			for &skip in &[false, true] {
+				let mut receiver = Receiver::new(false);
+				receiver.set_decompressor(Some(Decompressor::new(15, false)));
+				let result = if skip {
+					receiver.skip_message(&mut &wire[..]).map(|_| ())
+				} else {
+					receiver.recv_message(&mut &wire[..]).map(|_| ())
+				};
+				match result {
+					Err(WebSocketError::ProtocolError(_)) => (),
+					other => panic!("expected ProtocolError, got {:?}", other),
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn max_message_size_single_frame() {
+		let wire = wire(&[DataFrame::new(true, Opcode::Text, b"too long".to_vec())]);
+		let mut receiver = Receiver::new(false);
+		receiver.set_max_message_size(Some(4));
+		match receiver.recv_message(&mut &wire[..]) {
+			Err(WebSocketError::MessageTooBig) => (),
+			other => panic!("expected MessageTooBig, got {:?}", other),
+		}
+	}
+
+	#[test]
+	#[cfg(feature = "deflate")]
+	fn max_message_size_stops_inflating() {
+		use deflate::Compressor;
+
+		// 4 MiB of zeros compress to a few KiB
+		let mut compressor = Compressor::new(15, false);
+		let mut bomb = DataFrame::new(
+			true,
+			Opcode::Binary,
+			compressor.compress(&vec![0; 1 << 22]).unwrap(),
+		);
+		bomb.reserved[0] = true;
+		assert!(bomb.data.len() < 1 << 15);
+		let wire = wire(&[bomb]);
+
+		for &borrowed in &[false, true] {
+			let mut receiver = Receiver::new(false);
+			receiver.set_decompressor(Some(Decompressor::new(15, false)));
+			receiver.set_max_message_size(Some(1 << 20));
+			let result = if borrowed {
+				receiver.recv_borrowed(&mut &wire[..]).map(|_| ())
+			} else {
+				receiver.recv_message(&mut &wire[..]).map(|_| ())
+			};
+			match result {
+				Err(WebSocketError::MessageTooBig) => (),
+				other => panic!("expected MessageTooBig, got {:?}", other),
+			}
+		}
+	}
+
+	// hands out the chunks with every other read failing with `WouldBlock`
+	struct Nonblocking(Vec<Vec<u8>>, bool);
+
+	impl Read for Nonblocking {
+		fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+			self.1 = !self.1;
+			if self.1 || self.0.is_empty() {
+				return Err(io::Error::new(io::ErrorKind::WouldBlock, "not ready"));
+			}
+			let len = cmp::min(buf.len(), self.0[0].len());
+			buf[..len].copy_from_slice(&self.0[0][..len]);
+			self.0[0].drain(..len);
+			if self.0[0].is_empty() {
+				self.0.remove(0);
+			}
+			Ok(len)
+		}
+	}
+
+	fn would_block_ref(result: &WebSocketResult<OwnedMessage>) -> bool {
+		match *result {
+			Err(WebSocketError::IoError(ref e)) => e.kind() == io::ErrorKind::WouldBlock,
+			_ => false,
+		}
+	}
+
+	#[test]
+	fn resumes_frames_split_across_reads() {
+		let wire = wire(&[
+			DataFrame::new(false, Opcode::Text, b"split ".to_vec()),
+			DataFrame::new(true, Opcode::Continuation, b"frames".to_vec()),
+		]);
+		// split inside the header and inside both payloads
+		let mut reader = Nonblocking(
+			vec![
+				wire[..1].to_vec(),
+				wire[1..5].to_vec(),
+				wire[5..11].to_vec(),
+				wire[11..].to_vec(),
+			],
+			false,
+		);
+
+		let mut receiver = Receiver::new(false);
+		let mut blocked = 0;
+		let message = loop {
+			match receiver.recv_message(&mut reader) {
+				ref result if would_block_ref(result) => blocked += 1,
+				result => break result.unwrap(),
+			}
+		};
+		assert_eq!(message, OwnedMessage::Text("split frames".to_string()));
+		assert!(blocked >= 4);
+	}
+
+	// hands out the chunks, one per read, and counts the reads
+	struct Chunked(Vec<Vec<u8>>, usize);
+
+	impl Read for Chunked {
+		fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+			self.1 += 1;
+			if self.0.is_empty() {
+				return Ok(0);
+			}
+			let len = cmp::min(buf.len(), self.0[0].len());
+			buf[..len].copy_from_slice(&self.0[0][..len]);
+			self.0[0].drain(..len);
+			if self.0[0].is_empty() {
+				self.0.remove(0);
+			}
+			Ok(len)
+		}
+	}
+
+	#[test]
+	fn read_ahead_parses_several_frames_per_read() {
+		let frames = [
+			DataFrame::new(true, Opcode::Text, b"one".to_vec()),
+			DataFrame::new(true, Opcode::Ping, b"two".to_vec()),
+			DataFrame::new(false, Opcode::Binary, vec![3; 200]),
+			DataFrame::new(true, Opcode::Continuation, vec![4]),
+		];
+		let mut reader = Chunked(vec![wire(&frames)], 0);
+		let mut receiver = Receiver::new(false);
+		receiver.set_read_ahead(4096);
+
+		assert_eq!(
+			receiver.recv_message(&mut reader).unwrap(),
+			OwnedMessage::Text("one".to_string())
+		);
+		assert_eq!(receiver.recv_dataframe(&mut reader).unwrap(), frames[1]);
+		let mut binary = vec![3; 200];
+		binary.push(4);
+		assert_eq!(
+			receiver.recv_message(&mut reader).unwrap(),
+			OwnedMessage::Binary(binary)
+		);
+		assert_eq!(reader.1, 1);
+		assert!(receiver.take_partial_frame().is_empty());
+	}
+
+	#[test]
+	fn read_ahead_resumes_frames_straddling_reads() {
+		let wire = wire(&[
+			DataFrame::new(true, Opcode::Text, b"hello".to_vec()),
+			DataFrame::new(true, Opcode::Text, b"world".to_vec()),
+			DataFrame::new(true, Opcode::Binary, vec![7; 300]),
+		]);
+		// every read ahead ends inside a frame, some of them fail in between
+		let mut receiver = Receiver::new(false);
+		receiver.set_read_ahead(5);
+		let mut reader = Nonblocking(wire.chunks(3).map(|c| c.to_vec()).collect(), false);
+		let mut messages = Vec::new();
+		while messages.len() < 3 {
+			match receiver.recv_message(&mut reader) {
+				ref result if would_block_ref(result) => {}
+				result => messages.push(result.unwrap()),
+			}
+		}
+		assert_eq!(
+			messages,
+			vec![
+				OwnedMessage::Text("hello".to_string()),
+				OwnedMessage::Text("world".to_string()),
+				OwnedMessage::Binary(vec![7; 300]),
+			]
+		);
+
+		// the bytes read ahead are handed back with the partial frame
+		let mut receiver = Receiver::new(false);
+		receiver.set_read_ahead(64);
+		let mut reader = Chunked(vec![wire[..10].to_vec()], 0);
+		assert_eq!(
+			receiver.recv_message(&mut reader).unwrap(),
+			OwnedMessage::Text("hello".to_string())
+		);
+		assert_eq!(receiver.take_partial_frame(), wire[7..10].to_vec());
+	}
+
+	#[test]
+	fn reads_fragmented_messages_in_pieces() {
+		let wire = wire(&[
+			DataFrame::new(true, Opcode::Pong, b"early".to_vec()),
+			DataFrame::new(false, Opcode::Binary, vec![1, 2, 3]),
+			DataFrame::new(false, Opcode::Continuation, vec![]),
+			DataFrame::new(true, Opcode::Ping, b"between".to_vec()),
+			DataFrame::new(false, Opcode::Continuation, vec![4, 5]),
+			DataFrame::new(true, Opcode::Continuation, vec![6]),
+			DataFrame::new(true, Opcode::Text, b"next".to_vec()),
+		]);
+		let mut receiver = Receiver::new(false);
+		let mut reader = &wire[..];
+		{
+			let mut message = receiver.message_reader(&mut reader).unwrap();
+			assert_eq!(message.opcode(), Opcode::Binary);
+			assert_eq!(
+				message.take_control_messages(),
+				vec![OwnedMessage::Pong(b"early".to_vec())]
+			);
+
+			let mut chunk = [0; 2];
+			assert_eq!(message.read(&mut chunk).unwrap(), 2);
+			assert_eq!(chunk, [1, 2]);
+			let mut rest = Vec::new();
+			message.read_to_end(&mut rest).unwrap();
+			assert_eq!(rest, vec![3, 4, 5, 6]);
+			assert!(message.is_finished());
+			assert_eq!(
+				message.take_control_messages(),
+				vec![OwnedMessage::Ping(b"between".to_vec())]
+			);
+		}
+		assert_eq!(
+			receiver.recv_message(&mut reader).unwrap(),
+			OwnedMessage::Text("next".to_string())
+		);
+	}
+
+	#[test]
+	fn message_reader_validates_text() {
+		let wire = wire(&[
+			DataFrame::new(false, Opcode::Text, vec![b'a', 0xC3]),
+			DataFrame::new(true, Opcode::Continuation, vec![0xA9]),
+			DataFrame::new(false, Opcode::Text, b"fine".to_vec()),
+			DataFrame::new(true, Opcode::Continuation, vec![0xFF]),
+		]);
+		let mut receiver = Receiver::new(false);
+		let mut reader = &wire[..];
+
+		let mut text = String::new();
+		receiver
+			.message_reader(&mut reader)
+			.unwrap()
+			.read_to_string(&mut text)
+			.unwrap();
+		assert_eq!(text, "a\u{e9}");
+
+		let mut message = receiver.message_reader(&mut reader).unwrap();
+		let err = message.read_to_end(&mut Vec::new()).unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+		match err
+			.get_ref()
+			.and_then(|e| e.downcast_ref::<WebSocketError>())
+		{
+			Some(&WebSocketError::Utf8Error(_)) => (),
+			e => panic!("expected a UTF-8 error, got {:?}", e),
+		}
+	}
+
+	#[test]
+	fn message_reader_finishes_on_close() {
+		let wire = wire(&[
+			DataFrame::new(false, Opcode::Binary, vec![1]),
+			DataFrame::new(true, Opcode::Close, vec![]),
+		]);
+		let mut receiver = Receiver::new(false);
+		let mut reader = &wire[..];
+		let mut message = receiver.message_reader(&mut reader).unwrap();
+
+		let err = message.read_to_end(&mut Vec::new()).unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+		assert_eq!(
+			message.take_control_messages(),
+			vec![OwnedMessage::Close(None)]
+		);
+	}
+
+	#[test]
+	fn message_reader_returns_an_early_close() {
+		let wire = wire(&[
+			DataFrame::new(true, Opcode::Ping, b"early".to_vec()),
+			DataFrame::new(true, Opcode::Close, vec![0x03, 0xE8, b'b', b'y', b'e']),
+			DataFrame::new(true, Opcode::Text, b"never read".to_vec()),
+		]);
+		let mut receiver = Receiver::new(false);
+		let mut reader = &wire[..];
+		let mut message = receiver.message_reader(&mut reader).unwrap();
+
+		assert_eq!(message.opcode(), Opcode::Close);
+		assert!(message.is_finished());
+		assert_eq!(
+			message.take_control_messages(),
+			vec![OwnedMessage::Ping(b"early".to_vec())]
+		);
+		let mut payload = Vec::new();
+		message.read_to_end(&mut payload).unwrap();
+		assert_eq!(payload, vec![0x03, 0xE8, b'b', b'y', b'e']);
+	}
+
+	#[test]
+	fn skips_fragmented_messages() {
+		let skipped = wire(&[
+			DataFrame::new(false, Opcode::Binary, vec![1; 1000]),
+			DataFrame::new(true, Opcode::Ping, b"between".to_vec()),
+			DataFrame::new(false, Opcode::Continuation, vec![2; 1000]),
+			DataFrame::new(true, Opcode::Continuation, vec![3; 1000]),
+			DataFrame::new(false, Opcode::Text, b"next ".to_vec()),
+			DataFrame::new(true, Opcode::Continuation, b"message".to_vec()),
+		]);
+		let mut reader = &skipped[..];
+		let mut receiver = Receiver::new(false);
+
+		assert_eq!(
+			receiver.skip_message(&mut reader).unwrap(),
+			vec![OwnedMessage::Ping(b"between".to_vec())]
+		);
+		assert_eq!(
+			receiver.recv_message(&mut reader).unwrap(),
+			OwnedMessage::Text("next message".to_string())
+		);
+
+		// the second data message interrupts the skipped one
+		let interrupted = wire(&[
+			DataFrame::new(false, Opcode::Binary, vec![1]),
+			DataFrame::new(true, Opcode::Text, vec![]),
+		]);
+		match receiver.skip_message(&mut &interrupted[..]) {
+			Err(WebSocketError::ProtocolError(_)) => {}
+			other => panic!("expected a protocol error, got {:?}", other),
+		}
+	}
+
+	fn is_borrowed(message: &Message) -> bool {
+		match message.payload {
+			Cow::Borrowed(_) => true,
+			Cow::Owned(_) => false,
+		}
+	}
+
+	#[test]
+	#[cfg(feature = "deflate")]
+	fn skipped_compressed_messages_keep_the_context() {
+		use deflate::Compressor;
+
+		// every message refers back to the ones before it
+		let text = "context takeover, ".repeat(20);
+		let mut compressor = Compressor::new(15, false);
+		let mut frames = Vec::new();
+		for _ in 0..4 {
+			let compressed = compressor.compress(text.as_bytes()).unwrap();
+			let (head, tail) = compressed.split_at(compressed.len() / 2);
+			let mut first = DataFrame::new(false, Opcode::Text, head.to_vec());
+			first.reserved[0] = true;
+			frames.push(first);
+			frames.push(DataFrame::new(true, Opcode::Continuation, tail.to_vec()));
+		}
+		let wire = wire(&frames);
+		let mut receiver = Receiver::new(false);
+		receiver.set_decompressor(Some(Decompressor::new(15, false)));
+
+		let mut reader = &wire[..];
+		assert!(receiver.skip_message(&mut reader).unwrap().is_empty());
+		let message = OwnedMessage::Text(text.clone());
+		assert_eq!(receiver.recv_message(&mut reader).unwrap(), message);
+
+		// a message skipped after its first fragment was received
+		let first = wire.len() - reader.len();
+		let split = first + frames[4].frame_size(false);
+		let mut reader = Nonblocking(
+			vec![wire[first..split].to_vec(), wire[split..].to_vec()],
+			false,
+		);
+		while receiver.recv_borrowed(&mut reader).is_err() {
+			if reader.0.len() == 1 {
+				break;
+			}
+		}
+		assert_eq!(reader.0.len(), 1);
+		let mut rest = &reader.0[0][..];
+		assert!(receiver.skip_message(&mut rest).unwrap().is_empty());
+		assert_eq!(receiver.recv_message(&mut rest).unwrap(), message);
+	}
+
+	#[test]
+	fn borrows_fragmented_messages() {
+		let wire = wire(&[
+			DataFrame::new(true, Opcode::Pong, b"early".to_vec()),
+			DataFrame::new(false, Opcode::Text, b"borrowed ".to_vec()),
+			DataFrame::new(true, Opcode::Ping, b"between".to_vec()),
+			DataFrame::new(true, Opcode::Continuation, b"text".to_vec()),
+			DataFrame::new(true, Opcode::Close, b"\x03\xe8bye".to_vec()),
+		]);
+		let mut reader = &wire[..];
+		let mut receiver = Receiver::new(false);
+
+		let message = receiver.recv_borrowed(&mut reader).unwrap();
+		assert_eq!(message, Message::pong(&b"early"[..]));
+		assert!(is_borrowed(&message));
+
+		// the ping arrives while the text is buffered
+		let message = receiver.recv_borrowed(&mut reader).unwrap();
+		assert_eq!(message, Message::ping(&b"between"[..]));
+		assert!(!is_borrowed(&message));
+
+		let message = receiver.recv_borrowed(&mut reader).unwrap();
+		assert_eq!(message, Message::text("borrowed text"));
+		assert!(is_borrowed(&message));
+
+		let message = receiver.recv_borrowed(&mut reader).unwrap();
+		assert_eq!(message, Message::close_because(1000, "bye"));
+		assert!(is_borrowed(&message));
+	}
+
+	#[test]
+	fn borrowed_messages_are_validated() {
+		let invalid = wire(&[
+			DataFrame::new(false, Opcode::Text, vec![0xce]),
+			DataFrame::new(true, Opcode::Continuation, vec![0xff]),
+			DataFrame::new(true, Opcode::Continuation, vec![]),
+		]);
+		let mut reader = &invalid[..];
+		let mut receiver = Receiver::new(false);
+		assert!(receiver.recv_borrowed(&mut reader).is_err());
+		match receiver.recv_borrowed(&mut reader) {
+			Err(WebSocketError::ProtocolError(_)) => (),
+			_ => panic!("a message can not start with a continuation"),
+		}
+
+		let big = wire(&[DataFrame::new(true, Opcode::Binary, vec![0; 8])]);
+		receiver.set_max_message_size(Some(7));
+		match receiver.recv_borrowed(&mut &big[..]) {
+			Err(WebSocketError::MessageTooBig) => (),
+			_ => panic!("the message is too big"),
+		}
+	}
+
+	#[test]
+	fn resumes_borrowed_messages() {
+		let mut frames = Vec::new();
+		DataFrame::new(false, Opcode::Binary, vec![1, 2, 3])
+			.write_to(&mut frames, true)
+			.unwrap();
+		DataFrame::new(true, Opcode::Continuation, vec![4, 5])
+			.write_to(&mut frames, true)
+			.unwrap();
+		let chunks = frames.chunks(3).map(|c| c.to_vec()).collect();
+		let mut reader = Nonblocking(chunks, false);
+
+		let mut receiver = Receiver::new(true);
+		let mut blocked = 0;
+		loop {
+			match receiver.recv_borrowed(&mut reader) {
+				Err(WebSocketError::IoError(ref e)) if e.kind() == io::ErrorKind::WouldBlock => {
+					blocked += 1;
+				}
+				result => {
+					assert_eq!(result.unwrap(), Message::binary(vec![1, 2, 3, 4, 5]));
+					break;
+				}
+			}
+		}
+		assert!(blocked >= 7);
+	}
+
+	#[test]
+	fn masking_follows_the_role() {
+		let frame = DataFrame::new(true, Opcode::Text, b"hello".to_vec());
+		// (frame is masked, receiving as a server)
+		for &(masked, server) in &[(true, false), (false, true)] {
+			let mut bytes = Vec::new();
+			frame.write_to(&mut bytes, masked).unwrap();
+
+			let results = vec![
+				Receiver::new(server)
+					.recv_dataframe(&mut &bytes[..])
+					.map(|_| ()),
+				Receiver::new(server)
+					.recv_message(&mut &bytes[..])
+					.map(|_| ()),
+				Receiver::new(server)
+					.recv_borrowed(&mut &bytes[..])
+					.map(|_| ()),
+			];
+			for result in results {
+				match result {
+					Err(WebSocketError::ProtocolError(_)) => (),
+					other => panic!("expected ProtocolError, got {:?}", other),
+				}
+			}
+
+			let mut receiver = Receiver::new(masked);
+			assert_eq!(
+				receiver.recv_message(&mut &bytes[..]).unwrap(),
+				OwnedMessage::Text("hello".to_string())
+			);
+		}
+	}
+
+	#[test]
+	fn rate_limit_paces_frames() {
+		use std::time::Duration;
+
+		let frames: Vec<_> = (0..30)
+			.map(|i| DataFrame::new(true, Opcode::Binary, vec![i]))
+			.collect();
+		let wire = wire(&frames);
+		let limit = RateLimit::frames_per_second(100).burst(10);
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_rate_limit(Some(limit));
+		let mut stream = &wire[..];
+		let start = Instant::now();
+		for i in 0..30 {
+			let message = receiver.recv_message(&mut stream).unwrap();
+			assert_eq!(message, OwnedMessage::Binary(vec![i]));
+		}
+		// the first 10 frames are a burst, the other 20 come at most 100 per second
+		let elapsed = start.elapsed();
+		assert!(elapsed >= Duration::from_millis(180), "took {:?}", elapsed);
+
+		// borrowed messages are paced the same way
+		let mut receiver = Receiver::new(false);
+		receiver.set_rate_limit(Some(limit));
+		let mut stream = &wire[..];
+		let start = Instant::now();
+		for _ in 0..15 {
+			receiver.recv_borrowed(&mut stream).unwrap();
+		}
+		assert!(start.elapsed() >= Duration::from_millis(40));
+
+		// a peer which never lets up is given up on
+		let mut receiver = Receiver::new(false);
+		receiver.set_rate_limit(Some(limit.close_after(Duration::from_millis(50))));
+		let mut stream = &wire[..];
+		let error = (0..30)
+			.map(|_| receiver.recv_message(&mut stream))
+			.find(Result::is_err);
+		match error {
+			Some(Err(WebSocketError::RateLimitExceeded)) => (),
+			other => panic!("expected the rate limit to be exceeded, got {:?}", other),
+		}
 	}
 }
@@ -2,6 +2,7 @@
 
 pub use hyper::status::StatusCode;
 use hyper::Error as HttpError;
+use message::CloseCode;
 use server::upgrade::HyperIntoWsError;
 use std::convert::From;
 use std::error::Error;
@@ -33,7 +34,8 @@ pub mod async {
 /// Represents a WebSocket error
 #[derive(Debug)]
 pub enum WebSocketError {
-	/// A WebSocket protocol error
+	/// A WebSocket protocol error,
+	/// the connection should be closed with status code 1002
 	ProtocolError(&'static str),
 	/// Invalid WebSocket request error
 	RequestError(&'static str),
@@ -45,6 +47,17 @@ pub enum WebSocketError {
 	StatusCodeError(StatusCode),
 	/// No data available
 	NoDataAvailable,
+	/// The connection ended in the middle of a fragmented message,
+	/// the fragments received so far are discarded
+	IncompleteMessage,
+	/// A received message exceeded the maximum message size,
+	/// the connection should be closed with status code 1009
+	MessageTooBig,
+	/// The peer kept sending more than its `RateLimit` allows,
+	/// the connection should be closed with status code 1008
+	RateLimitExceeded,
+	/// A read or write on the stream did not complete before its timeout
+	Timeout,
 	/// An input/output error
 	IoError(io::Error),
 	/// An HTTP parsing error
@@ -62,15 +75,54 @@ pub enum WebSocketError {
 	/// an ssl handshake interruption
 	#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
 	TlsHandshakeInterruption,
+	/// The TLS session with the server failed while connecting,
+	/// e.g. the server rejected the client certificate
+	#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
+	TlsSessionError(io::Error),
 	/// A UTF-8 error
 	Utf8Error(Utf8Error),
 }
 
+impl WebSocketError {
+	/// The status code to fail the connection with after receiving something
+	/// which caused this error, `None` for errors which are not the peer's fault
+	/// or which leave nothing to send a close to.
+	///
+	/// Violations of the protocol are answered with `1002`, text and close
+	/// reasons which are not valid UTF-8 with `1007`, messages over the size
+	/// limit with `1009` and peers over their rate limit with `1008`.
+	///
+	///```
+	///# use websocket::message::CloseCode;
+	///# use websocket::result::WebSocketError;
+	///let error = WebSocketError::from(std::str::from_utf8(b"\xff").unwrap_err());
+	///assert_eq!(error.close_code(), Some(CloseCode::InvalidFramePayloadData));
+	///```
+	pub fn close_code(&self) -> Option<CloseCode> {
+		match *self {
+			WebSocketError::ProtocolError(_) | WebSocketError::DataFrameError(_) => {
+				Some(CloseCode::ProtocolError)
+			}
+			WebSocketError::Utf8Error(_) => Some(CloseCode::InvalidFramePayloadData),
+			WebSocketError::MessageTooBig => Some(CloseCode::MessageTooBig),
+			WebSocketError::RateLimitExceeded => Some(CloseCode::PolicyViolation),
+			_ => None,
+		}
+	}
+}
+
 impl fmt::Display for WebSocketError {
 	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
 		fmt.write_str("WebSocketError: ")?;
 		fmt.write_str(self.description())?;
-		Ok(())
+		match *self {
+			WebSocketError::ProtocolError(detail)
+			| WebSocketError::RequestError(detail)
+			| WebSocketError::ResponseError(detail)
+			| WebSocketError::DataFrameError(detail) => write!(fmt, ": {}", detail),
+			WebSocketError::StatusCodeError(status) => write!(fmt, ": {}", status),
+			_ => Ok(()),
+		}
 	}
 }
 
@@ -82,6 +134,10 @@ impl Error for WebSocketError {
 			WebSocketError::ResponseError(_) => "WebSocket response error",
 			WebSocketError::DataFrameError(_) => "WebSocket data frame error",
 			WebSocketError::NoDataAvailable => "No data available",
+			WebSocketError::IncompleteMessage => "Connection ended in the middle of a message",
+			WebSocketError::MessageTooBig => "Message too big",
+			WebSocketError::RateLimitExceeded => "Rate limit exceeded",
+			WebSocketError::Timeout => "Operation timed out",
 			WebSocketError::IoError(_) => "I/O failure",
 			WebSocketError::HttpError(_) => "HTTP failure",
 			WebSocketError::UrlError(_) => "URL failure",
@@ -91,19 +147,23 @@ impl Error for WebSocketError {
 			WebSocketError::TlsHandshakeFailure => "TLS Handshake failure",
 			#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
 			WebSocketError::TlsHandshakeInterruption => "TLS Handshake interrupted",
+			#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
+			WebSocketError::TlsSessionError(_) => "TLS session failure",
 			WebSocketError::Utf8Error(_) => "UTF-8 failure",
 			WebSocketError::WebSocketUrlError(_) => "WebSocket URL failure",
 			WebSocketError::StatusCodeError(_) => "Received unexpected status code",
 		}
 	}
 
-	fn cause(&self) -> Option<&Error> {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
 		match *self {
 			WebSocketError::IoError(ref error) => Some(error),
 			WebSocketError::HttpError(ref error) => Some(error),
 			WebSocketError::UrlError(ref error) => Some(error),
 			#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
 			WebSocketError::TlsError(ref error) => Some(error),
+			#[cfg(any(feature = "sync-ssl", feature = "async-ssl", feature = "sync-rustls"))]
+			WebSocketError::TlsSessionError(ref error) => Some(error),
 			WebSocketError::Utf8Error(ref error) => Some(error),
 			WebSocketError::WebSocketUrlError(ref error) => Some(error),
 			_ => None,
@@ -186,6 +246,9 @@ impl From<HyperIntoWsError> for WebSocketError {
 			NoUpgradeHeader => ProtocolError("Missing Upgrade WebSocket header"),
 			NoWsConnectionHeader => ProtocolError("Invalid Connection WebSocket header"),
 			NoConnectionHeader => ProtocolError("Missing Connection WebSocket header"),
+			InvalidProtocolHeader => ProtocolError("Invalid Sec-WebSocket-Protocol header"),
+			TooManyConnections => ProtocolError("Too many open connections"),
+			ShutDown => ProtocolError("The server was shut down"),
 		}
 	}
 }
@@ -218,3 +281,53 @@ impl Error for WSUrlErrorKind {
 		}
 	}
 }
+
+#[cfg(all(test, feature = "sync"))]
+mod tests {
+	use super::*;
+	use receiver::Receiver;
+	use std::io::Read;
+	use ws::receiver::Receiver as ReceiverTrait;
+
+	struct Broken;
+
+	impl Read for Broken {
+		fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+			Err(io::Error::new(
+				io::ErrorKind::ConnectionReset,
+				"reset by peer",
+			))
+		}
+	}
+
+	#[test]
+	fn transport_and_protocol_errors_are_distinct() {
+		let error = Receiver::new(false).recv_message(&mut Broken).unwrap_err();
+		match error {
+			WebSocketError::IoError(ref e) if e.kind() == io::ErrorKind::ConnectionReset => (),
+			ref other => panic!("expected an I/O error, got {:?}", other),
+		}
+		assert_eq!(error.to_string(), "WebSocketError: I/O failure");
+		assert_eq!(error.source().unwrap().to_string(), "reset by peer");
+
+		// a masked frame sent to a client
+		let frame = [0x81, 0x80, 1, 2, 3, 4];
+		let error = Receiver::new(false)
+			.recv_message(&mut &frame[..])
+			.unwrap_err();
+		match error {
+			WebSocketError::ProtocolError(_) => (),
+			ref other => panic!("expected a protocol error, got {:?}", other),
+		}
+		assert_eq!(
+			error.to_string(),
+			"WebSocketError: WebSocket protocol error: Masked data frame received from a server"
+		);
+		assert!(error.source().is_none());
+
+		match WebSocketError::from(HyperIntoWsError::NoSecWsKeyHeader) {
+			WebSocketError::ProtocolError("Missing Sec-WebSocket-Key header") => (),
+			other => panic!("expected a protocol error, got {:?}", other),
+		}
+	}
+}
@@ -1,13 +1,21 @@
 //! The default implementation of a WebSocket Sender.
 
-use result::WebSocketResult;
+use dataframe::{DataFrame as OwnedDataFrame, Opcode};
+#[cfg(feature = "deflate")]
+use deflate::Compressor;
+use metrics::Metrics;
+use observer::FrameObserver;
+use result::{WebSocketError, WebSocketResult};
+use std::io;
 use std::io::Result as IoResult;
 use std::io::Write;
+use std::sync::Arc;
 use stream::sync::AsTcpStream;
 pub use stream::sync::Shutdown;
 use ws;
 use ws::dataframe::DataFrame;
 use ws::sender::Sender as SenderTrait;
+use ws::util::mask::{self, MaskGenerator};
 
 /// A writer that bundles a stream with a serializer to send the messages.
 /// This is used in the client's `.split()` function as the writing component.
@@ -34,12 +42,41 @@ where
 	}
 
 	/// Sends a single message to the remote endpoint.
+	///
+	/// The message is written and the stream is flushed before this returns,
+	/// together with the messages buffered by `send_message_buffered`.
 	pub fn send_message<M>(&mut self, message: &M) -> WebSocketResult<()>
 	where
 		M: ws::Message,
 	{
 		self.sender.send_message(&mut self.stream, message)
 	}
+
+	/// Buffers a message until the next `flush`, see `Sender::send_message_buffered`.
+	pub fn send_message_buffered<M>(&mut self, message: &M) -> WebSocketResult<()>
+	where
+		M: ws::Message,
+	{
+		self.sender.send_message_buffered(message)
+	}
+
+	/// Writes the buffered messages and flushes the stream.
+	pub fn flush(&mut self) -> WebSocketResult<()> {
+		self.sender.flush(&mut self.stream)
+	}
+
+	/// Forwards a data frame as it is, see `Sender::send_dataframe_raw`.
+	pub fn send_dataframe_raw(&mut self, frame: &OwnedDataFrame) -> WebSocketResult<()> {
+		self.sender.send_dataframe_raw(&mut self.stream, frame)
+	}
+
+	/// Starts a message which is sent piece by piece, see `Sender::message_writer`.
+	pub fn message_writer<'a>(
+		&'a mut self,
+		opcode: Opcode,
+	) -> WebSocketResult<MessageWriter<'a, W>> {
+		self.sender.message_writer(&mut self.stream, opcode)
+	}
 }
 
 impl<S> Writer<S>
@@ -59,16 +96,420 @@ where
 	}
 }
 
+/// Sends the same messages to many connections, e.g. the subscribers of a
+/// pub/sub server.
+///
+/// Servers don't mask their frames, so a message is encoded once and the same
+/// bytes are written to every connection. Writers which mask, fragment or
+/// compress their messages send them with `Writer::send_message` instead.
+///
+/// ```rust
+/// # use websocket::sync::stream::DuplexStream;
+/// # use websocket::receiver::Receiver;
+/// # use websocket::ws::Receiver as ReceiverTrait;
+/// use websocket::sender::{Broadcast, Sender, Writer};
+/// use websocket::OwnedMessage;
+///
+/// # let (first, first_peer) = DuplexStream::pair();
+/// # let (second, second_peer) = DuplexStream::pair();
+/// let writers = vec![first, second]
+///     .into_iter()
+///     .map(|stream| Writer { stream, sender: Sender::new(false) })
+///     .collect();
+/// let mut broadcast = Broadcast::new(writers);
+///
+/// let failed = broadcast.send(&OwnedMessage::Text("news".to_string())).unwrap();
+/// assert!(failed.is_empty());
+/// # for mut peer in vec![first_peer, second_peer] {
+/// #     let message = Receiver::new(false).recv_message(&mut peer).unwrap();
+/// #     assert_eq!(message, OwnedMessage::Text("news".to_string()));
+/// # }
+/// ```
+pub struct Broadcast<W> {
+	/// The connections messages are sent to
+	pub writers: Vec<Writer<W>>,
+}
+
+impl<W> Broadcast<W>
+where
+	W: Write,
+{
+	/// Create a broadcast sending to `writers`.
+	pub fn new(writers: Vec<Writer<W>>) -> Self {
+		Broadcast { writers }
+	}
+
+	/// Sends a message to all connections.
+	///
+	/// A failing connection doesn't stop the message from being sent to the others,
+	/// the index in `writers` and the error of every connection that failed are returned.
+	/// This only fails if the message can't be encoded at all.
+	pub fn send<M>(&mut self, message: &M) -> WebSocketResult<Vec<(usize, WebSocketError)>>
+	where
+		M: ws::Message,
+	{
+		let mut encoded = Vec::with_capacity(message.message_size(false));
+		message.serialize(&mut encoded, false)?;
+
+		let mut failed = Vec::new();
+		for (i, writer) in self.writers.iter_mut().enumerate() {
+			let result = if writer.sender.mask
+				|| writer.sender.transforms_messages()
+				|| writer.sender.has_buffered()
+				|| writer.sender.high_water_mark.is_some()
+			{
+				writer.send_message(message)
+			} else {
+				writer.sender.send_encoded(&mut writer.stream, &encoded)
+			};
+			if let Err(e) = result {
+				failed.push((i, e));
+			}
+		}
+		Ok(failed)
+	}
+}
+
 /// A Sender that wraps a Writer and provides a default implementation using
 /// DataFrames and Messages.
 pub struct Sender {
 	mask: bool,
+	fragment_size: Option<usize>,
+	write_buffer: Vec<u8>,
+	write_buffer_size: usize,
+	#[cfg(feature = "deflate")]
+	compressor: Option<Compressor>,
+	observer: Option<Arc<dyn FrameObserver>>,
+	mask_generator: Option<Box<dyn MaskGenerator>>,
+	high_water_mark: Option<usize>,
+	metrics: Arc<Metrics>,
+	close_sent: bool,
 }
 
 impl Sender {
 	/// Create a new WebSocketSender using the specified Writer.
 	pub fn new(mask: bool) -> Sender {
-		Sender { mask }
+		Sender {
+			mask,
+			fragment_size: None,
+			write_buffer: Vec::new(),
+			write_buffer_size: 0,
+			#[cfg(feature = "deflate")]
+			compressor: None,
+			observer: None,
+			mask_generator: None,
+			high_water_mark: None,
+			metrics: Arc::new(Metrics::new()),
+			close_sent: false,
+		}
+	}
+
+	/// Split data messages whose payload is larger than `size` bytes into
+	/// several frames of at most `size` bytes, `None` (the default) or `Some(0)`
+	/// sends every message as a single frame.
+	///
+	/// Control messages are never fragmented. If compression is enabled the
+	/// compressed payload is fragmented.
+	pub fn set_fragment_size(&mut self, size: Option<usize>) {
+		self.fragment_size = size.filter(|&size| size > 0);
+	}
+
+	/// The largest payload sent in a single frame, see `set_fragment_size`.
+	pub fn fragment_size(&self) -> Option<usize> {
+		self.fragment_size
+	}
+
+	/// Encode outgoing frames into a buffer of `size` bytes kept by the sender,
+	/// 0 (the default) encodes every frame into a buffer of its own.
+	///
+	/// The frames of a message which fit into the buffer together (e.g. the fragments
+	/// of a fragmented or compressed message) are handed to the writer with a
+	/// single `write_all`. Frames are written before `send_message` returns either way.
+	/// A bigger buffer saves allocations and writes for more messages, but the sender
+	/// holds on to `size` bytes of memory. Frames which don't fit are encoded on their own.
+	///
+	/// To join several messages into one write use `send_message_buffered`.
+	pub fn set_write_buffer_size(&mut self, size: usize) {
+		self.write_buffer_size = size;
+		// messages sent with `send_message_buffered` may be waiting in the buffer
+		if self.write_buffer.is_empty() {
+			self.write_buffer = Vec::with_capacity(size);
+		}
+	}
+
+	/// The size of the write buffer, see `set_write_buffer_size`.
+	pub fn write_buffer_size(&self) -> usize {
+		self.write_buffer_size
+	}
+
+	/// Encodes a message into the write buffer without writing anything,
+	/// the message is written by the next `flush` or when another message is sent.
+	///
+	/// `send_message` writes the message and flushes the writer before returning,
+	/// which is what should be done before waiting for a reply. Buffering a few
+	/// messages and flushing once writes all of them with a single `write_all`.
+	/// The buffer grows to fit every message buffered, whatever its size is.
+	///
+	/// ```rust
+	/// # use websocket::message::OwnedMessage;
+	/// # use websocket::sender::Sender;
+	/// let mut sender = Sender::new(false);
+	/// let mut stream = Vec::new();
+	/// for i in 0..3 {
+	///     let message = OwnedMessage::Text(i.to_string());
+	///     sender.send_message_buffered(&message).unwrap();
+	/// }
+	/// assert!(stream.is_empty());
+	/// sender.flush(&mut stream).unwrap();
+	/// assert_eq!(stream.len(), 9);
+	/// ```
+	pub fn send_message_buffered<M>(&mut self, message: &M) -> WebSocketResult<()>
+	where
+		M: ws::Message,
+	{
+		if !self.transforms_messages() {
+			let start = self.write_buffer.len();
+			message.serialize(&mut self.write_buffer, self.mask)?;
+			let first = self.write_buffer.get(start).cloned();
+			self.count_serialized(first, self.write_buffer.len() - start);
+			return Ok(());
+		}
+		for frame in self.message_frames(message)? {
+			self.encode_frame(&frame)?;
+		}
+		Ok(())
+	}
+
+	/// Writes the messages waiting in the write buffer and flushes the writer.
+	///
+	/// A failing write drops the messages which were buffered, unless a
+	/// high-water mark is set and the writer would block, see
+	/// `set_write_high_water_mark`.
+	pub fn flush<W>(&mut self, writer: &mut W) -> WebSocketResult<()>
+	where
+		W: Write,
+	{
+		self.flush_buffer(writer)?;
+		writer.flush()?;
+		Ok(())
+	}
+
+	/// Whether messages sent with `send_message_buffered`, or queued because
+	/// of a high-water mark, are waiting to be written.
+	pub fn has_buffered(&self) -> bool {
+		!self.write_buffer.is_empty()
+	}
+
+	/// Queue the frames sent from now on in the write buffer and write only as much
+	/// of them as the writer takes, for writers in nonblocking mode. `None` (the
+	/// default) writes every message completely, blocking or failing halfway.
+	///
+	/// A message or frame is queued and `Ok` is returned as soon as the writer
+	/// won't take any more, even if a part of it is still waiting. The next
+	/// `send_message`, `send_dataframe` or `flush` writes the queued bytes first,
+	/// frames are never torn apart. Once more than `limit` bytes are waiting,
+	/// sending fails with an `IoError` of kind `WouldBlock` if the writer doesn't
+	/// take enough of them: the message was not queued and can be sent again
+	/// later. `flush` fails the same way while anything is left.
+	///
+	/// ```rust,no_run
+	/// # use std::io::ErrorKind;
+	/// # use websocket::message::OwnedMessage;
+	/// # use websocket::result::WebSocketError;
+	/// use websocket::ClientBuilder;
+	///
+	/// let mut client = ClientBuilder::new("ws://127.0.0.1:1234")
+	///     .unwrap()
+	///     .connect_insecure()
+	///     .unwrap();
+	/// client.set_nonblocking(true).unwrap();
+	/// client.set_write_high_water_mark(Some(1 << 20));
+	/// match client.send_message(&OwnedMessage::Binary(vec![0; 1 << 16])) {
+	///     Ok(()) => {}
+	///     Err(WebSocketError::IoError(ref e)) if e.kind() == ErrorKind::WouldBlock => {
+	///         // the peer is slow, keep the message or drop it
+	///     }
+	///     Err(e) => panic!("{}", e),
+	/// }
+	/// ```
+	pub fn set_write_high_water_mark(&mut self, limit: Option<usize>) {
+		self.high_water_mark = limit;
+	}
+
+	/// The most bytes queued before sending fails, see `set_write_high_water_mark`.
+	pub fn write_high_water_mark(&self) -> Option<usize> {
+		self.high_water_mark
+	}
+
+	/// The number of bytes waiting in the write buffer.
+	pub fn buffered_len(&self) -> usize {
+		self.write_buffer.len()
+	}
+
+	/// Whether a close frame was sent (or buffered) through this sender.
+	pub fn close_sent(&self) -> bool {
+		self.close_sent
+	}
+
+	/// Starts a text or binary message (depending on `opcode`) which is
+	/// written piece by piece, without having the whole message in memory.
+	///
+	/// Every non-empty `write` to the returned `MessageWriter` is sent as one frame,
+	/// the message ends with `MessageWriter::finish` or when it is dropped.
+	/// Wrap it in a `BufWriter` to avoid sending lots of small frames.
+	/// Messages written this way are never compressed and it is up to the caller
+	/// to only write valid UTF-8 to a text message.
+	///
+	/// While the message is being written no other message can be sent through
+	/// this sender, control messages can be sent in between the frames with
+	/// `MessageWriter::send_control`.
+	///
+	/// ```rust
+	/// # use websocket::dataframe::Opcode;
+	/// # use websocket::sender::Sender;
+	/// use std::io::Write;
+	///
+	/// let mut sender = Sender::new(false);
+	/// let mut stream = Vec::new();
+	/// {
+	///     let mut message = sender.message_writer(&mut stream, Opcode::Binary).unwrap();
+	///     message.write_all(b"Hello, ").unwrap();
+	///     message.write_all(b"World!").unwrap();
+	///     message.finish().unwrap();
+	/// }
+	/// ```
+	pub fn message_writer<'a, W>(
+		&'a mut self,
+		writer: &'a mut W,
+		opcode: Opcode,
+	) -> WebSocketResult<MessageWriter<'a, W>>
+	where
+		W: Write,
+	{
+		if opcode != Opcode::Text && opcode != Opcode::Binary {
+			return Err(WebSocketError::ProtocolError(
+				"Only text and binary messages can be written in pieces",
+			));
+		}
+		self.flush_buffer(writer)?;
+		Ok(MessageWriter {
+			writer,
+			mask: self.mask,
+			opcode,
+			finished: false,
+			observer: self.observer.as_ref(),
+			mask_generator: &mut self.mask_generator,
+			metrics: &self.metrics,
+			close_sent: &mut self.close_sent,
+		})
+	}
+
+	/// Writes `frame` as it is, e.g. to forward a frame received from another
+	/// connection with `Receiver::recv_dataframe_raw`. Its reserved bits are kept
+	/// and it is neither compressed nor fragmented, the payload is only masked
+	/// if this sender masks its frames, whatever the frame was received with.
+	///
+	/// The frame is written after the buffered frames without going through the
+	/// write buffer, and `writer` is flushed.
+	///
+	/// ```rust
+	/// # use websocket::dataframe::DataFrame;
+	/// # use websocket::receiver::Receiver;
+	/// # use websocket::sender::Sender;
+	/// // a masked frame of a client, "hi" with the key [1, 2, 3, 4]
+	/// let input = [0x81, 0x82, 1, 2, 3, 4, b'h' ^ 1, b'i' ^ 2];
+	/// // the server's end reads it and forwards it to another server as a client
+	/// let mut receiver = Receiver::new(true);
+	/// let mut sender = Sender::new(true);
+	/// let mut output = Vec::new();
+	///
+	/// let frame = receiver.recv_dataframe_raw(&mut &input[..]).unwrap();
+	/// sender.send_dataframe_raw(&mut output, &frame).unwrap();
+	/// let forwarded = DataFrame::read_dataframe(&mut &output[..], true).unwrap();
+	/// assert_eq!(forwarded, frame);
+	/// ```
+	pub fn send_dataframe_raw<W>(
+		&mut self,
+		writer: &mut W,
+		frame: &OwnedDataFrame,
+	) -> WebSocketResult<()>
+	where
+		W: Write,
+	{
+		self.flush_buffer(writer)?;
+		if let Some(ref observer) = self.observer {
+			observe(&**observer, frame, self.mask)?;
+		}
+		let masking_key = masking_key(self.mask, &mut self.mask_generator);
+		write_frame(writer, frame, masking_key)?;
+		let size = frame.frame_size(self.mask);
+		self.count_sent(frame.opcode as u8, frame.finished, size);
+		Ok(writer.flush()?)
+	}
+
+	/// Tell `observer` about every frame sent from now on, see the `observer` module.
+	///
+	/// Messages are split into their frames to pass them to the observer,
+	/// so the frames are encoded one at a time while it is set.
+	pub fn set_frame_observer(&mut self, observer: Option<Arc<dyn FrameObserver>>) {
+		self.observer = observer;
+	}
+
+	/// The counters of the frames sent, see the `metrics` module.
+	pub fn metrics(&self) -> &Arc<Metrics> {
+		&self.metrics
+	}
+
+	/// Count the frames sent from now on in `metrics`, e.g. to share them
+	/// with a receiver.
+	pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+		self.metrics = metrics;
+	}
+
+	/// Mask the frames sent from now on with keys from `generator` instead of
+	/// random ones, `None` goes back to random keys. This does nothing if the
+	/// sender doesn't mask its frames.
+	///
+	/// Messages are split into their frames like with a frame observer.
+	///
+	/// ```rust
+	/// # use websocket::message::OwnedMessage;
+	/// # use websocket::sender::Sender;
+	/// # use websocket::ws::sender::Sender as SenderTrait;
+	/// use websocket::ws::util::mask::MaskGenerator;
+	///
+	/// struct Zeros;
+	///
+	/// impl MaskGenerator for Zeros {
+	///     fn next_mask(&mut self) -> [u8; 4] {
+	///         [0; 4]
+	///     }
+	/// }
+	///
+	/// let mut sender = Sender::new(true);
+	/// sender.set_mask_generator(Some(Box::new(Zeros)));
+	/// let mut stream = Vec::new();
+	/// sender.send_message(&mut stream, &OwnedMessage::Text("hi".to_string())).unwrap();
+	/// assert_eq!(stream, [0x81, 0x82, 0, 0, 0, 0, b'h', b'i']);
+	/// ```
+	pub fn set_mask_generator(&mut self, generator: Option<Box<dyn MaskGenerator>>) {
+		self.mask_generator = generator;
+	}
+
+	/// Compress all data messages sent from now on, this should only be
+	/// used once the `permessage-deflate` extension has been negotiated.
+	#[cfg(feature = "deflate")]
+	pub fn set_compressor(&mut self, compressor: Option<Compressor>) {
+		self.compressor = compressor;
+	}
+
+	/// Send data messages smaller than `threshold` bytes uncompressed,
+	/// see `Compressor::set_threshold`. This does nothing if compression is not enabled.
+	#[cfg(feature = "deflate")]
+	pub fn set_compression_threshold(&mut self, threshold: usize) {
+		if let Some(ref mut compressor) = self.compressor {
+			compressor.set_threshold(threshold);
+		}
 	}
 }
 
@@ -76,4 +517,921 @@ impl ws::Sender for Sender {
 	fn is_masked(&self) -> bool {
 		self.mask
 	}
+
+	fn send_dataframe<D, W>(&mut self, writer: &mut W, dataframe: &D) -> WebSocketResult<()>
+	where
+		D: DataFrame,
+		W: Write,
+	{
+		if let Some(limit) = self.high_water_mark {
+			return self.send_queued(writer, limit, |sender| sender.encode_frame(dataframe));
+		}
+		self.buffer_frame(writer, dataframe)?;
+		self.flush(writer)
+	}
+
+	fn send_message<M, W>(&mut self, writer: &mut W, message: &M) -> WebSocketResult<()>
+	where
+		M: ws::Message,
+		W: Write,
+	{
+		if let Some(limit) = self.high_water_mark {
+			return self.send_queued(writer, limit, |sender| {
+				sender.send_message_buffered(message)
+			});
+		}
+		// messages waiting in the buffer are written together with this one
+		if self.has_buffered() {
+			self.send_message_buffered(message)?;
+			return self.flush(writer);
+		}
+		if !self.transforms_messages() {
+			let mut counted = Counted {
+				inner: writer,
+				first: None,
+				len: 0,
+			};
+			let result = message.serialize(&mut counted, self.mask);
+			self.count_serialized(counted.first, counted.len);
+			result?;
+			counted.inner.flush()?;
+			return Ok(());
+		}
+
+		// every frame gets its own masking key
+		for frame in self.message_frames(message)? {
+			self.buffer_frame(writer, &frame)?;
+		}
+		self.flush(writer)
+	}
+}
+
+impl Sender {
+	// the frames of a message, compressed and fragmented as configured
+	fn message_frames<M>(&mut self, message: &M) -> WebSocketResult<Vec<OwnedDataFrame>>
+	where
+		M: ws::Message,
+	{
+		let mut frames = message.dataframes()?;
+
+		#[cfg(feature = "deflate")]
+		{
+			if let Some(ref mut compressor) = self.compressor {
+				frames = compressor.compress_frames(frames)?;
+			}
+		}
+		if let Some(size) = self.fragment_size {
+			frames = fragment(frames, size);
+		}
+		Ok(frames)
+	}
+
+	// frames are joined in the write buffer until it is full
+	fn buffer_frame<D, W>(&mut self, writer: &mut W, frame: &D) -> WebSocketResult<()>
+	where
+		D: DataFrame,
+		W: Write,
+	{
+		let size = frame.frame_size(self.mask);
+		if self.write_buffer.len() + size > self.write_buffer_size {
+			self.flush_buffer(writer)?;
+		}
+		if size > self.write_buffer_size {
+			if let Some(ref observer) = self.observer {
+				observe(&**observer, frame, self.mask)?;
+			}
+			let masking_key = masking_key(self.mask, &mut self.mask_generator);
+			write_frame(writer, frame, masking_key)?;
+			self.count_sent(frame.opcode(), frame.is_last(), size);
+			return Ok(());
+		}
+		self.encode_frame(frame)
+	}
+
+	fn encode_frame<D>(&mut self, frame: &D) -> WebSocketResult<()>
+	where
+		D: DataFrame,
+	{
+		if let Some(ref observer) = self.observer {
+			observe(&**observer, frame, self.mask)?;
+		}
+		let masking_key = masking_key(self.mask, &mut self.mask_generator);
+		frame.encode_with_key(&mut self.write_buffer, masking_key)?;
+		let size = frame.frame_size(self.mask);
+		self.count_sent(frame.opcode(), frame.is_last(), size);
+		Ok(())
+	}
+
+	// writes a message serialized as a single unmasked frame, e.g. by a broadcast
+	fn send_encoded<W>(&mut self, writer: &mut W, encoded: &[u8]) -> WebSocketResult<()>
+	where
+		W: Write,
+	{
+		let mut counted = Counted {
+			inner: writer,
+			first: None,
+			len: 0,
+		};
+		let result = counted.write_all(encoded);
+		self.count_serialized(counted.first, counted.len);
+		result?;
+		Ok(counted.inner.flush()?)
+	}
+
+	// counts a message serialized as a single frame starting with `first`
+	fn count_serialized(&mut self, first: Option<u8>, len: usize) {
+		if let Some(first) = first {
+			self.count_sent(first & 0x0F, first & 0x80 != 0, len);
+		}
+	}
+
+	fn count_sent(&mut self, opcode: u8, finished: bool, len: usize) {
+		if opcode == Opcode::Close as u8 {
+			self.close_sent = true;
+		}
+		self.metrics.frame_sent(opcode, finished, len);
+	}
+
+	// queues what `encode` adds to the write buffer unless more than `limit`
+	// bytes are still waiting after writing them, then writes what it can
+	fn send_queued<W, F>(&mut self, writer: &mut W, limit: usize, encode: F) -> WebSocketResult<()>
+	where
+		W: Write,
+		F: FnOnce(&mut Sender) -> WebSocketResult<()>,
+	{
+		if self.write_buffer.len() > limit {
+			if let Err(e) = self.write_queued(writer) {
+				if !is_would_block(&e) || self.write_buffer.len() > limit {
+					return Err(e);
+				}
+			}
+		}
+		encode(self)?;
+		match self.write_queued(writer) {
+			Ok(()) => Ok(writer.flush()?),
+			Err(ref e) if is_would_block(e) => Ok(()),
+			Err(e) => Err(e),
+		}
+	}
+
+	// writes as much of the write buffer as the writer takes, keeping the rest
+	// if it would block, anything else drops the buffer like `flush_buffer`
+	fn write_queued<W>(&mut self, writer: &mut W) -> WebSocketResult<()>
+	where
+		W: Write,
+	{
+		let mut written = 0;
+		let result = loop {
+			if written == self.write_buffer.len() {
+				break Ok(());
+			}
+			match writer.write(&self.write_buffer[written..]) {
+				Ok(0) => {
+					break Err(io::Error::new(
+						io::ErrorKind::WriteZero,
+						"failed to write the queued frames",
+					));
+				}
+				Ok(n) => written += n,
+				Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+				Err(e) => break Err(e),
+			}
+		};
+		match result {
+			Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+				self.write_buffer.drain(..written);
+			}
+			_ => self.write_buffer.clear(),
+		}
+		Ok(result?)
+	}
+
+	fn flush_buffer<W>(&mut self, writer: &mut W) -> WebSocketResult<()>
+	where
+		W: Write,
+	{
+		if self.high_water_mark.is_some() {
+			return self.write_queued(writer);
+		}
+		if !self.write_buffer.is_empty() {
+			let result = writer.write_all(&self.write_buffer);
+			self.write_buffer.clear();
+			result?;
+		}
+		Ok(())
+	}
+
+	#[cfg(feature = "deflate")]
+	fn transforms_messages(&self) -> bool {
+		self.fragment_size.is_some()
+			|| self.compressor.is_some()
+			|| self.observer.is_some()
+			|| self.mask_generator.is_some()
+	}
+
+	#[cfg(not(feature = "deflate"))]
+	fn transforms_messages(&self) -> bool {
+		self.fragment_size.is_some() || self.observer.is_some() || self.mask_generator.is_some()
+	}
+}
+
+// remembers the first byte and the number of bytes written
+struct Counted<'a, W: 'a> {
+	inner: &'a mut W,
+	first: Option<u8>,
+	len: usize,
+}
+
+impl<'a, W> Write for Counted<'a, W>
+where
+	W: Write,
+{
+	fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+		let len = self.inner.write(buf)?;
+		if self.first.is_none() && len > 0 {
+			self.first = Some(buf[0]);
+		}
+		self.len += len;
+		Ok(len)
+	}
+
+	// big payloads are written together with their header
+	fn write_vectored(&mut self, bufs: &[io::IoSlice]) -> IoResult<usize> {
+		let len = self.inner.write_vectored(bufs)?;
+		if self.first.is_none() && len > 0 {
+			self.first = bufs.iter().find(|buf| !buf.is_empty()).map(|buf| buf[0]);
+		}
+		self.len += len;
+		Ok(len)
+	}
+
+	fn flush(&mut self) -> IoResult<()> {
+		self.inner.flush()
+	}
+}
+
+fn is_would_block(error: &WebSocketError) -> bool {
+	match *error {
+		WebSocketError::IoError(ref e) => e.kind() == io::ErrorKind::WouldBlock,
+		_ => false,
+	}
+}
+
+fn masking_key(mask: bool, generator: &mut Option<Box<dyn MaskGenerator>>) -> Option<[u8; 4]> {
+	if !mask {
+		return None;
+	}
+	Some(match *generator {
+		Some(ref mut generator) => generator.next_mask(),
+		None => mask::gen_mask(),
+	})
+}
+
+fn write_frame<D, W>(writer: &mut W, frame: &D, masking_key: Option<[u8; 4]>) -> WebSocketResult<()>
+where
+	D: DataFrame,
+	W: Write,
+{
+	if masking_key.is_none() {
+		return frame.write_to(writer, false);
+	}
+	let mut data = Vec::with_capacity(frame.frame_size(true));
+	frame.encode_with_key(&mut data, masking_key)?;
+	writer.write_all(&data)?;
+	Ok(())
+}
+
+// hands an owned copy of a frame about to be written to the observer
+fn observe<D>(observer: &dyn FrameObserver, frame: &D, mask: bool) -> WebSocketResult<()>
+where
+	D: DataFrame,
+{
+	// invalid opcodes fail once the frame is written
+	let opcode = match Opcode::new(frame.opcode()) {
+		Some(opcode) => opcode,
+		None => return Ok(()),
+	};
+	let mut data = Vec::with_capacity(frame.size());
+	frame.write_payload(&mut data)?;
+	let frame = OwnedDataFrame {
+		finished: frame.is_last(),
+		reserved: *frame.reserved(),
+		opcode,
+		data,
+	};
+	observer.on_write(&frame, mask);
+	Ok(())
+}
+
+/// A message which is being sent frame by frame, created by `Sender::message_writer`.
+///
+/// Dropping it finishes the message, ignoring any error.
+pub struct MessageWriter<'a, W>
+where
+	W: Write + 'a,
+{
+	writer: &'a mut W,
+	mask: bool,
+	opcode: Opcode,
+	finished: bool,
+	observer: Option<&'a Arc<dyn FrameObserver>>,
+	mask_generator: &'a mut Option<Box<dyn MaskGenerator>>,
+	metrics: &'a Metrics,
+	close_sent: &'a mut bool,
+}
+
+impl<'a, W> MessageWriter<'a, W>
+where
+	W: Write,
+{
+	/// Sends a control message (close, ping or pong) in between two frames
+	/// of this message.
+	pub fn send_control<D>(&mut self, frame: &D) -> WebSocketResult<()>
+	where
+		D: DataFrame,
+	{
+		if frame.opcode() < 8 {
+			return Err(WebSocketError::ProtocolError(
+				"Only control frames can be sent in the middle of a message",
+			));
+		}
+		if let Some(observer) = self.observer {
+			observe(&**observer, frame, self.mask)?;
+		}
+		let masking_key = masking_key(self.mask, self.mask_generator);
+		write_frame(self.writer, frame, masking_key)?;
+		if frame.opcode() == Opcode::Close as u8 {
+			*self.close_sent = true;
+		}
+		self.metrics
+			.frame_sent(frame.opcode(), frame.is_last(), frame.frame_size(self.mask));
+		Ok(())
+	}
+
+	/// Sends the last frame of the message.
+	pub fn finish(mut self) -> WebSocketResult<()> {
+		self.finish_message()
+	}
+
+	fn send_frame(&mut self, finished: bool, data: Vec<u8>) -> WebSocketResult<()> {
+		let frame = OwnedDataFrame::new(finished, self.opcode, data);
+		if let Some(observer) = self.observer {
+			observer.on_write(&frame, self.mask);
+		}
+		let masking_key = masking_key(self.mask, self.mask_generator);
+		write_frame(self.writer, &frame, masking_key)?;
+		self.metrics
+			.frame_sent(frame.opcode as u8, finished, frame.frame_size(self.mask));
+		self.opcode = Opcode::Continuation;
+		Ok(())
+	}
+
+	fn finish_message(&mut self) -> WebSocketResult<()> {
+		if self.finished {
+			return Ok(());
+		}
+		self.finished = true;
+		self.send_frame(true, Vec::new())?;
+		self.writer.flush()?;
+		Ok(())
+	}
+}
+
+impl<'a, W> Write for MessageWriter<'a, W>
+where
+	W: Write,
+{
+	fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+		if buf.is_empty() {
+			return Ok(0);
+		}
+		match self.send_frame(false, buf.to_vec()) {
+			Ok(()) => Ok(buf.len()),
+			Err(WebSocketError::IoError(e)) => Err(e),
+			Err(e) => Err(io::Error::other(e.to_string())),
+		}
+	}
+
+	fn flush(&mut self) -> IoResult<()> {
+		self.writer.flush()
+	}
+}
+
+impl<'a, W> Drop for MessageWriter<'a, W>
+where
+	W: Write,
+{
+	fn drop(&mut self) {
+		let _ = self.finish_message();
+	}
+}
+
+// splits the payloads of data frames into chunks of at most `size` bytes,
+// only the first frame of a message keeps its opcode and reserved bits
+fn fragment(frames: Vec<OwnedDataFrame>, size: usize) -> Vec<OwnedDataFrame> {
+	let mut fragments = Vec::with_capacity(frames.len());
+	for frame in frames {
+		if frame.opcode as u8 >= 8 || frame.data.len() <= size {
+			fragments.push(frame);
+			continue;
+		}
+		let chunks = frame.data.chunks(size);
+		let count = chunks.len();
+		for (i, chunk) in chunks.enumerate() {
+			let last = i + 1 == count;
+			let mut fragment = if i == 0 {
+				OwnedDataFrame::new(last && frame.finished, frame.opcode, chunk.to_vec())
+			} else {
+				OwnedDataFrame::new(last && frame.finished, Opcode::Continuation, chunk.to_vec())
+			};
+			if i == 0 {
+				fragment.reserved = frame.reserved;
+			}
+			fragments.push(fragment);
+		}
+	}
+	fragments
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use message::OwnedMessage;
+	use receiver::Receiver;
+	use ws::receiver::Receiver as ReceiverTrait;
+	use ws::util::header::{read_header, DataFrameFlags};
+	use ws::Message;
+
+	#[test]
+	fn fragments_large_messages() {
+		let payload: Vec<u8> = (0..10_000).map(|i| i as u8).collect();
+		let message = OwnedMessage::Binary(payload.clone());
+		let mut sender = Sender::new(true);
+		sender.set_fragment_size(Some(4096));
+		let mut output = Vec::new();
+		sender.send_message(&mut output, &message).unwrap();
+		sender
+			.send_message(&mut output, &OwnedMessage::Ping(payload[..100].to_vec()))
+			.unwrap();
+
+		let mut reader = &output[..];
+		let mut headers = Vec::new();
+		while !reader.is_empty() {
+			let header = read_header(&mut reader).unwrap();
+			reader = &reader[header.len as usize..];
+			headers.push(header);
+		}
+		let shape: Vec<_> = headers
+			.iter()
+			.map(|h| (h.opcode, h.flags.contains(DataFrameFlags::FIN), h.len))
+			.collect();
+		assert_eq!(
+			shape,
+			vec![
+				(2, false, 4096),
+				(0, false, 4096),
+				(0, true, 1808),
+				(9, true, 100)
+			]
+		);
+		assert!(headers.iter().all(|h| h.mask.is_some()));
+		assert!(headers[0].mask != headers[1].mask);
+
+		let mut receiver = Receiver::new(true);
+		let mut reader = &output[..];
+		assert_eq!(receiver.recv_message(&mut reader).unwrap(), message);
+	}
+
+	// counts up from 1, so every frame gets the next four bytes as its key
+	struct CountingMask(u8);
+
+	impl MaskGenerator for CountingMask {
+		fn next_mask(&mut self) -> [u8; 4] {
+			let key = [self.0 + 1, self.0 + 2, self.0 + 3, self.0 + 4];
+			self.0 += 4;
+			key
+		}
+	}
+
+	#[test]
+	fn mask_generator_gives_reproducible_output() {
+		let mut sender = Sender::new(true);
+		sender.set_mask_generator(Some(Box::new(CountingMask(0))));
+		let mut output = Vec::new();
+		sender
+			.send_message(&mut output, &OwnedMessage::Text("Hello".to_string()))
+			.unwrap();
+		sender.set_fragment_size(Some(2));
+		sender
+			.send_message(&mut output, &OwnedMessage::Binary(vec![0xff, 0xff, 0xff]))
+			.unwrap();
+		{
+			let mut message = sender.message_writer(&mut output, Opcode::Binary).unwrap();
+			message.write_all(&[0]).unwrap();
+			message.finish().unwrap();
+		}
+
+		// "Hello" masked with 1 2 3 4
+		let mut expected = vec![0x81, 0x85, 1, 2, 3, 4];
+		expected.extend_from_slice(&[b'H' ^ 1, b'e' ^ 2, b'l' ^ 3, b'l' ^ 4, b'o' ^ 1]);
+		// two fragments, one key each
+		expected.extend_from_slice(&[0x02, 0x82, 5, 6, 7, 8, 0xff ^ 5, 0xff ^ 6]);
+		expected.extend_from_slice(&[0x80, 0x81, 9, 10, 11, 12, 0xff ^ 9]);
+		// written in pieces
+		expected.extend_from_slice(&[0x02, 0x81, 13, 14, 15, 16, 13]);
+		expected.extend_from_slice(&[0x80, 0x80, 17, 18, 19, 20]);
+		assert_eq!(output, expected);
+
+		// a server doesn't mask, whatever the generator
+		let mut sender = Sender::new(false);
+		sender.set_mask_generator(Some(Box::new(CountingMask(0))));
+		let mut output = Vec::new();
+		sender
+			.send_message(&mut output, &OwnedMessage::Text("Hi".to_string()))
+			.unwrap();
+		assert_eq!(output, vec![0x81, 0x02, b'H', b'i']);
+	}
+
+	#[test]
+	fn forwarded_frames_are_masked_again() {
+		// a compressed fragment received by a server, masked with 9 9 9 9
+		let input = [0x42, 0x83, 9, 9, 9, 9, 1 ^ 9, 2 ^ 9, 3 ^ 9];
+		let mut receiver = Receiver::new(true);
+		assert!(receiver.recv_dataframe(&mut &input[..]).is_err());
+		let frame = receiver.recv_dataframe_raw(&mut &input[..]).unwrap();
+		assert_eq!(frame.reserved, [true, false, false]);
+		assert!(!frame.finished);
+
+		// forwarded by a client with its own key
+		let mut sender = Sender::new(true);
+		sender.set_mask_generator(Some(Box::new(CountingMask(0))));
+		let mut output = Vec::new();
+		sender.send_dataframe_raw(&mut output, &frame).unwrap();
+		assert_eq!(output, vec![0x42, 0x83, 1, 2, 3, 4, 1 ^ 1, 2 ^ 2, 3 ^ 3]);
+
+		// and by a server without one
+		let mut output = Vec::new();
+		Sender::new(false)
+			.send_dataframe_raw(&mut output, &frame)
+			.unwrap();
+		assert_eq!(output, vec![0x42, 0x03, 1, 2, 3]);
+	}
+
+	#[test]
+	fn small_messages_are_not_fragmented() {
+		let mut sender = Sender::new(false);
+		sender.set_fragment_size(Some(0));
+		assert_eq!(sender.fragment_size(), None);
+		sender.set_fragment_size(Some(16));
+
+		let message = OwnedMessage::Text("sixteen bytes!!!".to_string());
+		let mut output = Vec::new();
+		sender.send_message(&mut output, &message).unwrap();
+		let mut expected = Vec::new();
+		message.serialize(&mut expected, false).unwrap();
+		assert_eq!(output, expected);
+	}
+
+	#[cfg(feature = "deflate")]
+	#[test]
+	fn fragments_compressed_messages() {
+		use deflate::Decompressor;
+
+		let text: String = (0..2000).map(|i| format!("{} ", i)).collect();
+		let message = OwnedMessage::Text(text);
+		let mut sender = Sender::new(false);
+		sender.set_compressor(Some(Compressor::new(15, false)));
+		sender.set_fragment_size(Some(512));
+		let mut output = Vec::new();
+		sender.send_message(&mut output, &message).unwrap();
+
+		let mut reader = &output[..];
+		let first = read_header(&mut reader).unwrap();
+		assert!(first.flags.contains(DataFrameFlags::RSV1));
+		assert!(!first.flags.contains(DataFrameFlags::FIN));
+		let second = read_header(&mut &reader[first.len as usize..]).unwrap();
+		assert!(!second.flags.contains(DataFrameFlags::RSV1));
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_decompressor(Some(Decompressor::new(15, false)));
+		let mut reader = &output[..];
+		assert_eq!(receiver.recv_message(&mut reader).unwrap(), message);
+	}
+
+	#[test]
+	fn streams_messages_in_pieces() {
+		let mut sender = Sender::new(true);
+		let mut output = Vec::new();
+		{
+			let mut message = sender.message_writer(&mut output, Opcode::Text).unwrap();
+			message.write_all(b"streamed ").unwrap();
+			message
+				.send_control(&OwnedMessage::Ping(b"in between".to_vec()))
+				.unwrap();
+			message.write_all(b"in ").unwrap();
+			message.write_all(b"").unwrap();
+			message.write_all(b"pieces").unwrap();
+			message.finish().unwrap();
+		}
+		{
+			// dropping the writer ends the message too
+			let mut message = sender.message_writer(&mut output, Opcode::Binary).unwrap();
+			message.write_all(&[1, 2, 3]).unwrap();
+		}
+
+		let mut receiver = Receiver::new(true);
+		let mut reader = &output[..];
+		let frames: Vec<_> = (0..6)
+			.map(|_| receiver.recv_dataframe(&mut reader).unwrap())
+			.map(|f| (f.opcode, f.finished))
+			.collect();
+		assert_eq!(
+			frames,
+			vec![
+				(Opcode::Text, false),
+				(Opcode::Ping, true),
+				(Opcode::Continuation, false),
+				(Opcode::Continuation, false),
+				(Opcode::Continuation, true),
+				(Opcode::Binary, false),
+			]
+		);
+
+		let mut reader = &output[..];
+		let messages: Vec<_> = (0..3)
+			.map(|_| receiver.recv_message(&mut reader).unwrap())
+			.collect();
+		assert_eq!(
+			messages,
+			vec![
+				OwnedMessage::Ping(b"in between".to_vec()),
+				OwnedMessage::Text("streamed in pieces".to_string()),
+				OwnedMessage::Binary(vec![1, 2, 3]),
+			]
+		);
+	}
+
+	#[test]
+	fn only_data_messages_are_streamed() {
+		let mut sender = Sender::new(false);
+		let mut output = Vec::new();
+		assert!(sender.message_writer(&mut output, Opcode::Ping).is_err());
+		let mut message = sender.message_writer(&mut output, Opcode::Binary).unwrap();
+		assert!(message
+			.send_control(&OwnedMessage::Text("data".to_string()))
+			.is_err());
+	}
+
+	// records the size of every write
+	struct Writes(Vec<usize>, Vec<u8>);
+
+	impl Write for Writes {
+		fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+			self.0.push(buf.len());
+			self.1.extend_from_slice(buf);
+			Ok(buf.len())
+		}
+
+		fn flush(&mut self) -> IoResult<()> {
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn buffered_messages_are_written_at_once() {
+		let messages: Vec<_> = (0..3)
+			.map(|i| OwnedMessage::Text(format!("message {}", i)))
+			.collect();
+		let mut sender = Sender::new(true);
+		let mut writes = Writes(Vec::new(), Vec::new());
+		for message in &messages {
+			sender.send_message_buffered(message).unwrap();
+		}
+		assert!(writes.0.is_empty());
+		assert!(sender.has_buffered());
+		sender.flush(&mut writes).unwrap();
+		assert_eq!(writes.0.len(), 1);
+		assert!(!sender.has_buffered());
+
+		// messages sent right away go after the buffered ones
+		let ping = OwnedMessage::Ping(b"ping".to_vec());
+		sender.send_message_buffered(&messages[0]).unwrap();
+		sender.send_message(&mut writes, &ping).unwrap();
+		assert_eq!(writes.0.len(), 2);
+
+		let mut receiver = Receiver::new(true);
+		let mut reader = &writes.1[..];
+		for message in messages.iter().chain(vec![&messages[0], &ping]) {
+			assert_eq!(&receiver.recv_message(&mut reader).unwrap(), message);
+		}
+		assert!(reader.is_empty());
+
+		// and the writer is flushed
+		let mut stream = io::BufWriter::new(Vec::new());
+		sender.send_message(&mut stream, &ping).unwrap();
+		assert_eq!(stream.get_ref().len(), 10);
+	}
+
+	#[test]
+	fn high_water_mark_stops_blocking_writes() {
+		use socket2::SockRef;
+		use std::net::{TcpListener, TcpStream};
+		use std::thread;
+		use std::time::Duration;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		let (peer, _) = listener.accept().unwrap();
+		SockRef::from(&stream).set_send_buffer_size(4096).unwrap();
+		stream.set_nonblocking(true).unwrap();
+
+		let mut sender = Sender::new(false);
+		sender.set_write_high_water_mark(Some(1024));
+		let big = OwnedMessage::Binary(vec![7; 1 << 22]);
+		let small = OwnedMessage::Text("after".to_string());
+		// the big message is taken, but the socket can't write all of it
+		sender.send_message(&mut stream, &big).unwrap();
+		assert!(sender.buffered_len() > 1024);
+		for _ in 0..2 {
+			match sender.send_message(&mut stream, &small) {
+				Err(WebSocketError::IoError(ref e)) if e.kind() == io::ErrorKind::WouldBlock => (),
+				other => panic!("expected the send to block, got {:?}", other),
+			}
+		}
+
+		let reader = thread::spawn(move || {
+			let mut peer = peer;
+			let mut receiver = Receiver::new(false);
+			let first = receiver.recv_message(&mut peer).unwrap();
+			let second = receiver.recv_message(&mut peer).unwrap();
+			(first, second)
+		});
+		loop {
+			match sender.flush(&mut stream) {
+				Ok(()) => break,
+				Err(ref e) if is_would_block(e) => thread::sleep(Duration::from_millis(1)),
+				Err(e) => panic!("{}", e),
+			}
+		}
+		assert!(!sender.has_buffered());
+		sender.send_message(&mut stream, &small).unwrap();
+		while sender.has_buffered() {
+			let _ = sender.flush(&mut stream);
+		}
+		assert_eq!(reader.join().unwrap(), (big, small));
+	}
+
+	// takes a few bytes at a time, counting the vectored writes
+	struct Trickle(Vec<u8>, usize);
+
+	impl Write for Trickle {
+		fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+			let n = buf.len().min(7);
+			self.0.extend_from_slice(&buf[..n]);
+			Ok(n)
+		}
+
+		fn write_vectored(&mut self, bufs: &[io::IoSlice]) -> IoResult<usize> {
+			self.1 += 1;
+			let mut written = 0;
+			for buf in bufs {
+				let n = buf.len().min(7 - written);
+				self.0.extend_from_slice(&buf[..n]);
+				written += n;
+			}
+			Ok(written)
+		}
+
+		fn flush(&mut self) -> IoResult<()> {
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn big_payloads_are_written_with_the_header() {
+		let payload: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+		let messages = vec![
+			OwnedMessage::Binary(payload.clone()),
+			OwnedMessage::Text(String::from_utf8(vec![b'a'; 20_000]).unwrap()),
+		];
+		let mut sender = Sender::new(false);
+		let mut trickle = Trickle(Vec::new(), 0);
+		for message in &messages {
+			sender.send_message(&mut trickle, message).unwrap();
+		}
+		// a 10 and a 4 byte header, 7 bytes at a time
+		assert_eq!(trickle.1, 2 + 1);
+
+		let mut expected = Vec::new();
+		for message in &messages {
+			message.encode_into(&mut expected, false).unwrap();
+		}
+		assert!(trickle.0 == expected);
+		let mut receiver = Receiver::new(false);
+		let mut reader = &trickle.0[..];
+		for message in &messages {
+			assert_eq!(&receiver.recv_message(&mut reader).unwrap(), message);
+		}
+
+		// small and masked payloads are encoded in one buffer
+		let mut trickle = Trickle(Vec::new(), 0);
+		sender
+			.send_message(&mut trickle, &OwnedMessage::Binary(vec![1; 100]))
+			.unwrap();
+		Sender::new(true)
+			.send_message(&mut trickle, &messages[0])
+			.unwrap();
+		assert_eq!(trickle.1, 0);
+	}
+
+	#[test]
+	fn write_buffer_joins_frames() {
+		let big = OwnedMessage::Binary(vec![7; 100]);
+		let small = OwnedMessage::Ping(b"ping".to_vec());
+		let mut sender = Sender::new(true);
+		sender.set_fragment_size(Some(40));
+
+		let mut writes = Writes(Vec::new(), Vec::new());
+		sender.send_message(&mut writes, &big).unwrap();
+		assert_eq!(writes.0, vec![46, 46, 26]);
+
+		sender.set_write_buffer_size(64);
+		assert_eq!(sender.write_buffer_size(), 64);
+		sender.send_message(&mut writes, &big).unwrap();
+		assert_eq!(writes.0[3..], [46, 46, 26]);
+
+		sender.set_write_buffer_size(200);
+		sender.send_message(&mut writes, &big).unwrap();
+		sender.send_message(&mut writes, &small).unwrap();
+		sender.send_dataframe(&mut writes, &small).unwrap();
+		assert_eq!(writes.0[6..], [118, 10, 10]);
+
+		sender.set_write_buffer_size(16);
+		sender.send_message(&mut writes, &big).unwrap();
+		assert_eq!(writes.0[9..], [46, 46, 26]);
+
+		let mut receiver = Receiver::new(true);
+		let mut reader = &writes.1[..];
+		for _ in 0..3 {
+			assert_eq!(receiver.recv_message(&mut reader).unwrap(), big);
+		}
+		assert_eq!(receiver.recv_message(&mut reader).unwrap(), small);
+		assert_eq!(receiver.recv_message(&mut reader).unwrap(), small);
+		assert_eq!(receiver.recv_message(&mut reader).unwrap(), big);
+		assert!(reader.is_empty());
+	}
+
+	#[test]
+	fn broadcast_encodes_once() {
+		use stream::sync::DuplexStream;
+
+		let message = OwnedMessage::Binary(vec![1, 2, 3]);
+		let mut peers = Vec::new();
+		let mut writers = Vec::new();
+		for i in 0..4 {
+			let (stream, peer) = DuplexStream::pair();
+			// the third one is a client's writer, which masks its frames
+			writers.push(Writer {
+				stream,
+				sender: Sender::new(i == 2),
+			});
+			peers.push((peer, i == 2));
+		}
+		// the second connection is gone
+		peers.remove(1);
+
+		let mut broadcast = Broadcast::new(writers);
+		let failed = broadcast.send(&message).unwrap();
+		assert_eq!(failed.len(), 1);
+		match failed[0] {
+			(1, WebSocketError::IoError(ref e)) => assert_eq!(e.kind(), io::ErrorKind::BrokenPipe),
+			ref other => panic!("expected the second writer to fail, got {:?}", other),
+		}
+
+		let failed = broadcast
+			.send(&OwnedMessage::Text("two".to_string()))
+			.unwrap();
+		assert_eq!(failed.len(), 1);
+		let failed = broadcast.send(&OwnedMessage::Close(None)).unwrap();
+		assert_eq!(failed.len(), 1);
+		// writers taking the fast path are accounted for like the others
+		for (i, writer) in broadcast.writers.iter().enumerate() {
+			if i != 1 {
+				assert!(writer.sender.close_sent());
+				assert_eq!(writer.sender.metrics().messages_sent(), 2);
+			}
+		}
+		for (mut peer, masked) in peers {
+			let mut receiver = Receiver::new(masked);
+			assert_eq!(receiver.recv_message(&mut peer).unwrap(), message);
+			assert_eq!(
+				receiver.recv_message(&mut peer).unwrap(),
+				OwnedMessage::Text("two".to_string())
+			);
+			assert_eq!(
+				receiver.recv_message(&mut peer).unwrap(),
+				OwnedMessage::Close(None)
+			);
+		}
+	}
 }
@@ -2,13 +2,14 @@
 use bytes::BytesMut;
 use futures;
 use futures::{Future, Stream};
-use server::upgrade::async::{IntoWs, Upgrade};
+use server::upgrade::async::{into_ws_accepting, Upgrade};
 use server::InvalidConnection;
 use server::{NoTlsAcceptor, OptionalTlsAcceptor, WsServer};
 use std;
 use std::io;
 use std::net::SocketAddr;
 use std::net::ToSocketAddrs;
+use stream::TcpOptions;
 pub use tokio_reactor::Handle;
 use tokio_tcp::{TcpListener, TcpStream};
 
@@ -49,6 +50,12 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 		Ok(Server {
 			listener: TcpListener::from_std(tcp, handle)?,
 			ssl_acceptor: NoTlsAcceptor,
+			tcp: TcpOptions::default(),
+			#[cfg(feature = "sync")]
+			limit: None,
+			#[cfg(feature = "sync")]
+			shutdown: None,
+			versions: None,
 		})
 	}
 
@@ -62,19 +69,23 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 	/// (https://github.com/cyderize/rust-websocket/blob/master/examples/async-server.rs)
 	/// example for a good echo server example.
 	pub fn incoming(self) -> Incoming<TcpStream> {
+		let tcp = self.tcp;
+		let versions = self.versions().to_vec();
 		let future = self
 			.listener
 			.incoming()
-			.and_then(|s| s.peer_addr().map(|a| (s, a)))
+			.and_then(move |s| {
+				tcp.apply_async(&s)
+					.and_then(|()| s.peer_addr().map(|a| (s, a)))
+			})
 			.map_err(|e| InvalidConnection {
 				stream: None,
 				parsed: None,
 				buffer: None,
 				error: e.into(),
 			})
-			.and_then(|(stream, a)| {
-				let handshake = stream
-					.into_ws()
+			.and_then(move |(stream, a)| {
+				let handshake = into_ws_accepting(stream, versions.clone())
 					.map_err(|(stream, req, buf, err)| InvalidConnection {
 						stream: Some(stream),
 						parsed: req,
@@ -107,6 +118,12 @@ impl WsServer<TlsAcceptor, TcpListener> {
 		Ok(Server {
 			listener: TcpListener::from_std(tcp, handle)?,
 			ssl_acceptor: acceptor,
+			tcp: TcpOptions::default(),
+			#[cfg(feature = "sync")]
+			limit: None,
+			#[cfg(feature = "sync")]
+			shutdown: None,
+			versions: None,
 		})
 	}
 
@@ -120,11 +137,16 @@ impl WsServer<TlsAcceptor, TcpListener> {
 	/// (https://github.com/cyderize/rust-websocket/blob/master/examples/async-server.rs)
 	/// example for a good echo server example.
 	pub fn incoming(self) -> Incoming<TlsStream<TcpStream>> {
+		let versions = self.versions().to_vec();
 		let acceptor = TlsAcceptorExt::from(self.ssl_acceptor);
+		let tcp = self.tcp;
 		let future = self
 			.listener
 			.incoming()
-			.and_then(|s| s.peer_addr().map(|a| (s, a)))
+			.and_then(move |s| {
+				tcp.apply_async(&s)
+					.and_then(|()| s.peer_addr().map(|a| (s, a)))
+			})
 			.map_err(|e| InvalidConnection {
 				stream: None,
 				parsed: None,
@@ -132,6 +154,7 @@ impl WsServer<TlsAcceptor, TcpListener> {
 				error: e.into(),
 			})
 			.and_then(move |(stream, a)| {
+				let versions = versions.clone();
 				let handshake = acceptor
 					.accept(stream)
 					.map_err(|e| {
@@ -144,8 +167,7 @@ impl WsServer<TlsAcceptor, TcpListener> {
 						}
 					})
 					.and_then(move |stream| {
-						stream
-							.into_ws()
+						into_ws_accepting(stream, versions)
 							.map_err(|(stream, req, buf, err)| InvalidConnection {
 								stream: Some(stream),
 								parsed: req,
@@ -1,10 +1,20 @@
 //! Provides an implementation of a WebSocket server
 #[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
 use native_tls::TlsAcceptor;
+#[cfg(all(feature = "sync-rustls", not(feature = "native-tls")))]
+use tls::TlsAcceptor;
 
 use self::upgrade::{HyperIntoWsError, Request};
 use std::fmt::{Debug, Formatter, Result as FmtResult};
+#[cfg(feature = "sync")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "sync")]
+use std::sync::Arc;
+#[cfg(any(feature = "sync", feature = "async"))]
+use std::time::Duration;
 use stream::Stream;
+#[cfg(any(feature = "sync", feature = "async"))]
+use stream::TcpOptions;
 
 pub mod upgrade;
 
@@ -14,6 +24,18 @@ pub mod async;
 #[cfg(feature = "sync")]
 pub mod sync;
 
+#[cfg(any(feature = "sync", feature = "async"))]
+pub mod shutdown;
+
+#[cfg(feature = "sync")]
+use self::shutdown::ShutdownTrigger;
+#[cfg(any(feature = "sync", feature = "async"))]
+use self::upgrade::DEFAULT_VERSIONS;
+#[cfg(any(feature = "sync", feature = "async"))]
+use header::WebSocketVersion;
+#[cfg(feature = "sync")]
+use message::OwnedMessage;
+
 /// Marker struct for a struct not being secure
 #[derive(Clone)]
 pub struct NoTlsAcceptor;
@@ -23,7 +45,11 @@ pub struct NoTlsAcceptor;
 /// is running over SSL or not.
 pub trait OptionalTlsAcceptor {}
 impl OptionalTlsAcceptor for NoTlsAcceptor {}
-#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+#[cfg(any(
+	feature = "sync-ssl",
+	feature = "async-ssl",
+	all(feature = "sync-rustls", not(feature = "native-tls"))
+))]
 impl OptionalTlsAcceptor for TlsAcceptor {}
 
 /// When a sever tries to accept a connection many things can go wrong.
@@ -108,4 +134,140 @@ where
 	listener: L,
 	/// The SSL acceptor given to the server
 	pub ssl_acceptor: S,
+	tcp: TcpOptions,
+	#[cfg(feature = "sync")]
+	limit: Option<ConnectionLimit>,
+	#[cfg(feature = "sync")]
+	shutdown: Option<ShutdownTrigger>,
+	#[cfg(any(feature = "sync", feature = "async"))]
+	versions: Option<Vec<WebSocketVersion>>,
+}
+
+#[cfg(any(feature = "sync", feature = "async"))]
+impl<S, L> WsServer<S, L>
+where
+	S: OptionalTlsAcceptor,
+{
+	/// Whether to set `TCP_NODELAY` on accepted connections, so small messages
+	/// are sent right away instead of being held back by Nagle's algorithm.
+	///
+	/// This is on by default, older versions left the system's default
+	/// (Nagle's algorithm on). It is set as soon as a connection is accepted,
+	/// before its handshake is read, and has no effect on Unix sockets.
+	///
+	/// ```rust,no_run
+	/// # use websocket::sync::Server;
+	/// let server = Server::bind("127.0.0.1:1234").unwrap().nodelay(false);
+	/// ```
+	pub fn nodelay(mut self, nodelay: bool) -> Self {
+		self.tcp.nodelay = nodelay;
+		self
+	}
+
+	/// Enable `SO_KEEPALIVE` on accepted connections, the system starts probing
+	/// a client once its connection was idle for `idle`. `None` (the default)
+	/// leaves the option off.
+	pub fn keepalive(mut self, idle: Option<Duration>) -> Self {
+		self.tcp.keepalive = idle;
+		self
+	}
+
+	/// Accept any of the websocket `versions` instead of only version 13,
+	/// e.g. for interoperability tests with clients of draft versions. Requests
+	/// for other versions are answered with `426 Upgrade Required` listing them.
+	///
+	/// The connections are spoken to as RFC 6455 describes whatever the version.
+	///
+	/// ```rust,no_run
+	/// # use websocket::sync::Server;
+	/// let server = Server::bind("127.0.0.1:1234").unwrap().accept_versions(vec![13, 8]);
+	/// ```
+	pub fn accept_versions<I, V>(mut self, versions: I) -> Self
+	where
+		I: IntoIterator<Item = V>,
+		V: Into<WebSocketVersion>,
+	{
+		self.versions = Some(versions.into_iter().map(Into::into).collect());
+		self
+	}
+
+	fn versions(&self) -> &[WebSocketVersion] {
+		self.versions.as_deref().unwrap_or(DEFAULT_VERSIONS)
+	}
+}
+
+/// The number of connections a server keeps open at the same time,
+/// shared by all handles of the server.
+#[cfg(feature = "sync")]
+#[derive(Clone, Debug)]
+pub(crate) struct ConnectionLimit {
+	max: usize,
+	open: Arc<AtomicUsize>,
+}
+
+#[cfg(feature = "sync")]
+impl ConnectionLimit {
+	fn new(max: usize) -> Self {
+		ConnectionLimit {
+			max,
+			open: Arc::new(AtomicUsize::new(0)),
+		}
+	}
+
+	// counts a new connection, unless the limit is reached
+	fn acquire(&self) -> Option<ConnectionSlot> {
+		let mut open = self.open.load(Ordering::SeqCst);
+		loop {
+			if open >= self.max {
+				return None;
+			}
+			match self
+				.open
+				.compare_exchange(open, open + 1, Ordering::SeqCst, Ordering::SeqCst)
+			{
+				Ok(_) => return Some(ConnectionSlot(Slot::Counted(self.open.clone()))),
+				Err(current) => open = current,
+			}
+		}
+	}
+}
+
+/// A connection counted against a server's `ConnectionLimit` or registered
+/// with a `ShutdownTrigger`, until this is dropped.
+#[cfg(feature = "sync")]
+#[derive(Debug)]
+pub(crate) struct ConnectionSlot(Slot);
+
+#[cfg(feature = "sync")]
+#[derive(Debug)]
+enum Slot {
+	Counted(Arc<AtomicUsize>),
+	Registered(ShutdownTrigger, usize),
+}
+
+#[cfg(feature = "sync")]
+impl ConnectionSlot {
+	fn registered(trigger: ShutdownTrigger, id: usize) -> Self {
+		ConnectionSlot(Slot::Registered(trigger, id))
+	}
+
+	// the close to send once the server is going away
+	pub(crate) fn going_away(&self) -> Option<OwnedMessage> {
+		match self.0 {
+			Slot::Registered(ref trigger, _) => trigger.going_away(),
+			_ => None,
+		}
+	}
+}
+
+#[cfg(feature = "sync")]
+impl Drop for ConnectionSlot {
+	fn drop(&mut self) {
+		match self.0 {
+			Slot::Counted(ref open) => {
+				open.fetch_sub(1, Ordering::SeqCst);
+			}
+			Slot::Registered(ref trigger, id) => trigger.unregister(id),
+		}
+	}
 }
@@ -0,0 +1,235 @@
+//! Stopping a server, e.g. when the process is told to exit.
+//!
+//! A `ShutdownTrigger` is taken from a synchronous server before its accept
+//! loop starts and can be fired from any thread: the loop over `incoming` (or
+//! the server itself) ends and `accept` fails with `HyperIntoWsError::ShutDown`.
+//! The clients accepted since the trigger was taken can be told the server is
+//! going away with a `1001` close, which each client sends itself through its
+//! sender.
+//!
+//! ```rust,no_run
+//! # use std::thread;
+//! use websocket::sync::Server;
+//!
+//! let mut server = Server::bind("127.0.0.1:1234").unwrap();
+//! let trigger = server.shutdown_trigger().unwrap();
+//! let stop = trigger.clone();
+//! thread::spawn(move || {
+//!     // wait for a signal, a command, ...
+//!     stop.shutdown();
+//!     stop.close_connections("restarting").unwrap();
+//! });
+//!
+//! for upgrade in server.incoming().filter_map(Result::ok) {
+//!     let mut client = upgrade.accept().unwrap();
+//!     // the client sends the close once `recv_message` fails
+//!     thread::spawn(move || while let Ok(message) = client.recv_message() {
+//!         // ...
+//!     });
+//! }
+//! ```
+//!
+//! An asynchronous server is stopped by dropping the future which drives it.
+//! `ShutdownTrigger::signal` resolves once the trigger is fired, so that future
+//! can be `select`ed with it, a trigger made with `ShutdownTrigger::new` is not
+//! tied to any server.
+
+#[cfg(feature = "async")]
+use futures::task::{self, Task};
+#[cfg(feature = "async")]
+use futures::{Async, Future, Poll};
+use message::{CloseCode, CloseData, OwnedMessage};
+use result::WebSocketResult;
+#[cfg(feature = "sync")]
+use server::ConnectionSlot;
+use std::collections::HashMap;
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Stops a server, see the module documentation.
+///
+/// The trigger can be cloned and shared between threads, firing it
+/// more than once does nothing.
+#[derive(Clone, Debug)]
+pub struct ShutdownTrigger {
+	state: Arc<State>,
+}
+
+#[derive(Debug)]
+struct State {
+	shut_down: AtomicBool,
+	// connected to when shutting down, so a blocking accept returns
+	wake: Option<SocketAddr>,
+	connections: Mutex<Connections>,
+	going_away: Mutex<Option<CloseData>>,
+	#[cfg(feature = "async")]
+	tasks: Mutex<Vec<Task>>,
+}
+
+#[derive(Debug, Default)]
+struct Connections {
+	#[cfg(feature = "sync")]
+	next: usize,
+	streams: HashMap<usize, TcpStream>,
+}
+
+impl ShutdownTrigger {
+	/// A trigger which stops nothing on its own, e.g. to end an asynchronous
+	/// accept loop with `signal`.
+	pub fn new() -> Self {
+		ShutdownTrigger::waking(None)
+	}
+
+	/// A trigger for a server listening on `address`.
+	pub(crate) fn waking(address: Option<SocketAddr>) -> Self {
+		ShutdownTrigger {
+			state: Arc::new(State {
+				shut_down: AtomicBool::new(false),
+				wake: address,
+				connections: Mutex::new(Connections::default()),
+				going_away: Mutex::new(None),
+				#[cfg(feature = "async")]
+				tasks: Mutex::new(Vec::new()),
+			}),
+		}
+	}
+
+	/// Stop accepting connections, the connections accepted already stay open.
+	pub fn shutdown(&self) {
+		if self.state.shut_down.swap(true, Ordering::SeqCst) {
+			return;
+		}
+		if let Some(mut address) = self.state.wake {
+			if address.ip().is_unspecified() {
+				address.set_ip(match address {
+					SocketAddr::V4(_) => [127, 0, 0, 1].into(),
+					SocketAddr::V6(_) => [0, 0, 0, 0, 0, 0, 0, 1].into(),
+				});
+			}
+			let _ = TcpStream::connect(address);
+		}
+		#[cfg(feature = "async")]
+		{
+			for task in self.state.tasks.lock().unwrap().drain(..) {
+				task.notify();
+			}
+		}
+	}
+
+	/// Whether `shutdown` was called.
+	pub fn is_shut_down(&self) -> bool {
+		self.state.shut_down.load(Ordering::SeqCst)
+	}
+
+	/// Tell the clients accepted since the trigger was taken which are still
+	/// open that the server is going away with a `1001` close with `reason`.
+	/// This fails if `reason` doesn't fit into a close frame.
+	///
+	/// The receiving side of every connection is shut down, so the reads of the
+	/// threads handling the clients end. A client whose `recv_message` or
+	/// `recv_dataframe` fails from then on sends the close through its own sender
+	/// (unless it sent one already) before returning the error, so the close
+	/// never ends up in the middle of another message. A client which was split
+	/// only stops receiving, its `Writer` can send the close, see `going_away`.
+	pub fn close_connections(&self, reason: &str) -> WebSocketResult<()> {
+		let close = CloseData::new(CloseCode::GoingAway, reason)?;
+		*self.state.going_away.lock().unwrap() = Some(close);
+
+		let streams: Vec<_> = {
+			let mut connections = self.state.connections.lock().unwrap();
+			connections.streams.drain().collect()
+		};
+		for (_, stream) in streams {
+			// the client may be gone already
+			let _ = stream.shutdown(Shutdown::Read);
+		}
+		Ok(())
+	}
+
+	/// The close message passed to `close_connections`, once it was called.
+	pub fn going_away(&self) -> Option<OwnedMessage> {
+		let close = self.state.going_away.lock().unwrap().clone()?;
+		Some(OwnedMessage::Close(Some(close)))
+	}
+
+	/// A future which resolves once `shutdown` is called, e.g. to stop an
+	/// asynchronous server with `select`.
+	#[cfg(feature = "async")]
+	pub fn signal(&self) -> ShutdownSignal {
+		ShutdownSignal {
+			state: self.state.clone(),
+		}
+	}
+
+	/// Keep a handle of `stream` to shut it down with `close_connections`,
+	/// until the returned slot is dropped.
+	#[cfg(feature = "sync")]
+	pub(crate) fn register(&self, stream: &TcpStream) -> Option<ConnectionSlot> {
+		let stream = stream.try_clone().ok()?;
+		let mut connections = self.state.connections.lock().unwrap();
+		let id = connections.next;
+		connections.next += 1;
+		connections.streams.insert(id, stream);
+		Some(ConnectionSlot::registered(self.clone(), id))
+	}
+
+	#[cfg(feature = "sync")]
+	pub(crate) fn unregister(&self, id: usize) {
+		self.state.connections.lock().unwrap().streams.remove(&id);
+	}
+}
+
+impl Default for ShutdownTrigger {
+	fn default() -> Self {
+		ShutdownTrigger::new()
+	}
+}
+
+/// Resolves once a server is shut down, see `ShutdownTrigger::signal`.
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct ShutdownSignal {
+	state: Arc<State>,
+}
+
+#[cfg(feature = "async")]
+impl Future for ShutdownSignal {
+	type Item = ();
+	type Error = ();
+
+	fn poll(&mut self) -> Poll<(), ()> {
+		if self.state.shut_down.load(Ordering::SeqCst) {
+			return Ok(Async::Ready(()));
+		}
+		{
+			let mut tasks = self.state.tasks.lock().unwrap();
+			if !tasks.iter().any(Task::will_notify_current) {
+				tasks.push(task::current());
+			}
+		}
+		// the trigger may have been fired before the task was stored
+		if self.state.shut_down.load(Ordering::SeqCst) {
+			return Ok(Async::Ready(()));
+		}
+		Ok(Async::NotReady)
+	}
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+	use super::*;
+	use std::thread;
+
+	#[test]
+	fn signal_resolves_once_shut_down() {
+		let trigger = ShutdownTrigger::new();
+		let signal = trigger.signal();
+		let stop = trigger.clone();
+		let waiter = thread::spawn(move || signal.wait());
+		stop.shutdown();
+		waiter.join().unwrap().unwrap();
+		// a signal taken afterwards is ready right away
+		trigger.signal().wait().unwrap();
+	}
+}
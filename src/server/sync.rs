@@ -1,12 +1,23 @@
 //! Provides an implementation of a WebSocket server
+use hyper::header::Headers;
+use hyper::status::StatusCode;
 #[cfg(feature = "sync-ssl")]
 use native_tls::{TlsAcceptor, TlsStream};
-use server::upgrade::sync::{Buffer, IntoWs, Upgrade};
+use server::shutdown::ShutdownTrigger;
+use server::upgrade::sync::{into_ws_accepting, Buffer, Upgrade};
 pub use server::upgrade::{HyperIntoWsError, Request};
-use server::{InvalidConnection, NoTlsAcceptor, OptionalTlsAcceptor, WsServer};
+use server::{ConnectionLimit, InvalidConnection, NoTlsAcceptor, OptionalTlsAcceptor, WsServer};
 use std::convert::Into;
 use std::io;
 use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{SocketAddr as UnixSocketAddr, UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+use stream::sync::{AsTcpStream, Stream};
+use stream::TcpOptions;
+#[cfg(all(feature = "sync-rustls", not(feature = "native-tls")))]
+use tls::{self, TlsAcceptor, TlsServerStream};
 
 #[cfg(feature = "async")]
 use server::async;
@@ -28,6 +39,120 @@ pub type AcceptResult<S> = Result<Upgrade<S>, InvalidConnection<S, Buffer>>;
 /// a WebSocketClient, so if needed, an alternative server implementation can be used.
 pub type Server<S> = WsServer<S, TcpListener>;
 
+/// A WebSocket server listening on a Unix domain socket, e.g. behind
+/// a reverse proxy on the same machine.
+#[cfg(unix)]
+pub type UnixServer = WsServer<NoTlsAcceptor, UnixListener>;
+
+impl<S, L> WsServer<S, L>
+where
+	S: OptionalTlsAcceptor,
+{
+	/// Keep at most `max` connections open at the same time, further upgrade
+	/// requests are answered with `503 Service Unavailable` and `accept`
+	/// fails with `HyperIntoWsError::TooManyConnections`.
+	///
+	/// A connection stays open until its `Upgrade`, or the `Client` it is accepted as,
+	/// is dropped. If the client is split its `Reader` keeps the connection open.
+	/// Handles made with `try_clone` share the limit, it is not kept by `into_async`.
+	///
+	/// ```rust,no_run
+	/// # use websocket::sync::Server;
+	/// let server = Server::bind("127.0.0.1:1234").unwrap().max_connections(10_000);
+	/// ```
+	pub fn max_connections(mut self, max: usize) -> Self {
+		self.limit = Some(ConnectionLimit::new(max));
+		self
+	}
+
+	// counts an upgrade request against the connection limit, or answers it
+	// right away and gives back the stream if there is no room left
+	fn admit<T>(&self, upgrade: Upgrade<T>) -> Result<Upgrade<T>, T>
+	where
+		T: Stream,
+	{
+		let limit = match self.limit {
+			Some(ref limit) => limit,
+			None => return Ok(upgrade),
+		};
+		if let Some(slot) = limit.acquire() {
+			return Ok(upgrade.hold_slot(slot));
+		}
+		match upgrade.reject_with_response(StatusCode::ServiceUnavailable, &Headers::new(), b"") {
+			Ok(stream) => Err(stream),
+			Err((stream, _)) => Err(stream),
+		}
+	}
+
+	// keeps the connection registered with the shutdown trigger, if there is one
+	fn register<T>(&self, upgrade: Upgrade<T>) -> Upgrade<T>
+	where
+		T: Stream + AsTcpStream,
+	{
+		let slot = self
+			.shutdown
+			.as_ref()
+			.and_then(|trigger| trigger.register(upgrade.stream.as_tcp()));
+		match slot {
+			Some(slot) => upgrade.hold_slot(slot),
+			None => upgrade,
+		}
+	}
+
+	fn is_shut_down(&self) -> bool {
+		self.shutdown
+			.as_ref()
+			.is_some_and(ShutdownTrigger::is_shut_down)
+	}
+}
+
+/// The upgrade requests sent to a server, see `WsServer::incoming`.
+pub struct Incoming<'a, S, L>
+where
+	S: OptionalTlsAcceptor + 'a,
+	L: 'a,
+{
+	server: &'a mut WsServer<S, L>,
+}
+
+impl<'a, S, L> Iterator for Incoming<'a, S, L>
+where
+	S: OptionalTlsAcceptor,
+	WsServer<S, L>: Iterator,
+{
+	type Item = <WsServer<S, L> as Iterator>::Item;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.server.next()
+	}
+}
+
+fn too_many_connections<T>(stream: T) -> InvalidConnection<T, Buffer>
+where
+	T: Stream,
+{
+	InvalidConnection {
+		stream: Some(stream),
+		parsed: None,
+		buffer: None,
+		error: HyperIntoWsError::TooManyConnections,
+	}
+}
+
+// ends the iteration over a server once it is shut down
+fn unless_shut_down<S>(result: AcceptResult<S>) -> Option<AcceptResult<S>>
+where
+	S: Stream,
+{
+	match result {
+		Err(InvalidConnection {
+			error: HyperIntoWsError::ShutDown,
+			..
+		}) => None,
+		result => Some(result),
+	}
+}
+
 /// Synchronous methods for creating a server and accepting incoming connections.
 impl<S> WsServer<S, TcpListener>
 where
@@ -38,6 +163,50 @@ where
 		self.listener.local_addr()
 	}
 
+	/// An iterator over the upgrade requests sent to the server, which
+	/// keeps the server around unlike iterating over the server itself.
+	///
+	/// Requests are taken one at a time: every call to `next` waits for a
+	/// connection and reads its request, like `accept`. Nothing is answered
+	/// until the `Upgrade` is accepted or rejected, so it can be inspected and
+	/// handed over to whatever thread, pool or event loop should handle it.
+	/// Dropping an `Upgrade` closes its connection.
+	///
+	/// ```rust,no_run
+	/// # use std::thread;
+	/// use websocket::sync::Server;
+	///
+	/// let mut server = Server::bind("127.0.0.1:1234").unwrap();
+	/// for upgrade in server.incoming().filter_map(Result::ok) {
+	///     if upgrade.origin() != Some("https://example.com") {
+	///         let _ = upgrade.reject();
+	///         continue;
+	///     }
+	///     thread::spawn(move || {
+	///         let mut client = upgrade.accept().unwrap();
+	///         // ...
+	///     });
+	/// }
+	/// ```
+	pub fn incoming<'a>(&'a mut self) -> Incoming<'a, S, TcpListener> {
+		Incoming { server: self }
+	}
+
+	/// A trigger to stop this server from another thread, see the `shutdown` module.
+	///
+	/// Once the trigger is fired `accept` fails with `HyperIntoWsError::ShutDown`,
+	/// waking up if it is waiting for a connection, and iterating over the server
+	/// ends. Every call returns the same trigger. Handles made with `try_clone`
+	/// afterwards share it, note that only one waiting `accept` is woken up.
+	pub fn shutdown_trigger(&mut self) -> io::Result<ShutdownTrigger> {
+		if let Some(ref trigger) = self.shutdown {
+			return Ok(trigger.clone());
+		}
+		let trigger = ShutdownTrigger::waking(Some(self.local_addr()?));
+		self.shutdown = Some(trigger.clone());
+		Ok(trigger)
+	}
+
 	/// Changes whether the Server is in nonblocking mode.
 	/// NOTE: It is strongly encouraged to use the `websocket::async` module instead
 	/// of this. It provides high level APIs for creating asynchronous servers.
@@ -80,6 +249,21 @@ where
 		self.listener.set_nonblocking(nonblocking)
 	}
 
+	// waits for the next connection, unless the server is shut down
+	fn next_stream(&self) -> Result<TcpStream, HyperIntoWsError> {
+		if self.is_shut_down() {
+			return Err(HyperIntoWsError::ShutDown);
+		}
+		let stream = self.listener.accept().and_then(|(s, _)| {
+			self.tcp.apply(&s)?;
+			Ok(s)
+		});
+		if self.is_shut_down() {
+			return Err(HyperIntoWsError::ShutDown);
+		}
+		Ok(stream?)
+	}
+
 	/// Turns an existing synchronous server into an asynchronous one.
 	/// This will only work if the stream used for this server `S` already implements
 	/// `AsyncRead + AsyncWrite`. Useful if you would like some blocking things to happen
@@ -89,6 +273,10 @@ where
 		Ok(WsServer {
 			listener: AsyncTcpListener::from_std(self.listener, handle)?,
 			ssl_acceptor: self.ssl_acceptor,
+			tcp: self.tcp,
+			limit: None,
+			shutdown: None,
+			versions: self.versions,
 		})
 	}
 }
@@ -142,19 +330,23 @@ impl WsServer<TlsAcceptor, TcpListener> {
 		Ok(Server {
 			listener: TcpListener::bind(&addr)?,
 			ssl_acceptor: acceptor,
+			tcp: TcpOptions::default(),
+			limit: None,
+			shutdown: None,
+			versions: None,
 		})
 	}
 
 	/// Wait for and accept an incoming WebSocket connection, returning a WebSocketRequest
 	pub fn accept(&mut self) -> AcceptResult<TlsStream<TcpStream>> {
-		let stream = match self.listener.accept() {
-			Ok(s) => s.0,
+		let stream = match self.next_stream() {
+			Ok(s) => s,
 			Err(e) => {
 				return Err(InvalidConnection {
 					stream: None,
 					parsed: None,
 					buffer: None,
-					error: HyperIntoWsError::Io(e),
+					error: e,
 				});
 			}
 		};
@@ -171,8 +363,11 @@ impl WsServer<TlsAcceptor, TcpListener> {
 			}
 		};
 
-		match stream.into_ws() {
-			Ok(u) => Ok(u),
+		match into_ws_accepting(stream, self.versions()) {
+			Ok(u) => {
+				let upgrade = self.admit(u).map_err(too_many_connections)?;
+				Ok(self.register(upgrade))
+			}
 			Err((s, r, b, e)) => Err(InvalidConnection {
 				stream: Some(s),
 				parsed: r,
@@ -188,7 +383,76 @@ impl Iterator for WsServer<TlsAcceptor, TcpListener> {
 	type Item = AcceptResult<TlsStream<TcpStream>>;
 
 	fn next(&mut self) -> Option<<Self as Iterator>::Item> {
-		Some(self.accept())
+		unless_shut_down(self.accept())
+	}
+}
+
+/// Synchronous methods for creating a `rustls` server and accepting incoming connections.
+#[cfg(all(feature = "sync-rustls", not(feature = "native-tls")))]
+impl WsServer<TlsAcceptor, TcpListener> {
+	/// Bind this Server to this socket, utilising the given `rustls` configuration.
+	/// See the `tls` module for how to create one.
+	pub fn bind_secure<A>(addr: A, acceptor: TlsAcceptor) -> io::Result<Self>
+	where
+		A: ToSocketAddrs,
+	{
+		Ok(Server {
+			listener: TcpListener::bind(&addr)?,
+			ssl_acceptor: acceptor,
+			tcp: TcpOptions::default(),
+			limit: None,
+			shutdown: None,
+			versions: None,
+		})
+	}
+
+	/// Wait for and accept an incoming WebSocket connection, returning a WebSocketRequest
+	pub fn accept(&mut self) -> AcceptResult<TlsServerStream<TcpStream>> {
+		let stream = match self.next_stream() {
+			Ok(s) => s,
+			Err(e) => {
+				return Err(InvalidConnection {
+					stream: None,
+					parsed: None,
+					buffer: None,
+					error: e,
+				});
+			}
+		};
+
+		let stream = match tls::accept(&self.ssl_acceptor, stream) {
+			Ok(s) => s,
+			Err(err) => {
+				return Err(InvalidConnection {
+					stream: None,
+					parsed: None,
+					buffer: None,
+					error: err.into(),
+				});
+			}
+		};
+
+		match into_ws_accepting(stream, self.versions()) {
+			Ok(u) => {
+				let upgrade = self.admit(u).map_err(too_many_connections)?;
+				Ok(self.register(upgrade))
+			}
+			Err((s, r, b, e)) => Err(InvalidConnection {
+				stream: Some(s),
+				parsed: r,
+				buffer: b,
+				error: e,
+			}),
+		}
+	}
+}
+
+#[cfg(all(feature = "sync-rustls", not(feature = "native-tls")))]
+impl Iterator for WsServer<TlsAcceptor, TcpListener> {
+	type Item = AcceptResult<TlsServerStream<TcpStream>>;
+
+	fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+		unless_shut_down(self.accept())
 	}
 }
 
@@ -223,25 +487,32 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 		Ok(Server {
 			listener: TcpListener::bind(&addr)?,
 			ssl_acceptor: NoTlsAcceptor,
+			tcp: TcpOptions::default(),
+			limit: None,
+			shutdown: None,
+			versions: None,
 		})
 	}
 
 	/// Wait for and accept an incoming WebSocket connection, returning a WebSocketRequest
 	pub fn accept(&mut self) -> AcceptResult<TcpStream> {
-		let stream = match self.listener.accept() {
-			Ok(s) => s.0,
+		let stream = match self.next_stream() {
+			Ok(s) => s,
 			Err(e) => {
 				return Err(InvalidConnection {
 					stream: None,
 					parsed: None,
 					buffer: None,
-					error: e.into(),
+					error: e,
 				});
 			}
 		};
 
-		match stream.into_ws() {
-			Ok(u) => Ok(u),
+		match into_ws_accepting(stream, self.versions()) {
+			Ok(u) => {
+				let upgrade = self.admit(u).map_err(too_many_connections)?;
+				Ok(self.register(upgrade))
+			}
 			Err((s, r, b, e)) => Err(InvalidConnection {
 				stream: Some(s),
 				parsed: r,
@@ -257,6 +528,10 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 		Ok(Server {
 			listener: inner,
 			ssl_acceptor: self.ssl_acceptor.clone(),
+			tcp: self.tcp,
+			limit: self.limit.clone(),
+			shutdown: self.shutdown.clone(),
+			versions: self.versions.clone(),
 		})
 	}
 }
@@ -264,6 +539,92 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 impl Iterator for WsServer<NoTlsAcceptor, TcpListener> {
 	type Item = AcceptResult<TcpStream>;
 
+	fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+		unless_shut_down(self.accept())
+	}
+}
+
+#[cfg(unix)]
+impl WsServer<NoTlsAcceptor, UnixListener> {
+	/// Bind this Server to the Unix domain socket at `path`
+	///
+	/// ```no_run
+	/// use websocket::sync::server::UnixServer;
+	///
+	/// let mut server = UnixServer::bind("/run/chat/ws.sock").unwrap();
+	/// let client = server.accept().ok().unwrap().accept().unwrap();
+	/// ```
+	pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+		Ok(WsServer {
+			listener: UnixListener::bind(path)?,
+			ssl_acceptor: NoTlsAcceptor,
+			tcp: TcpOptions::default(),
+			limit: None,
+			shutdown: None,
+			versions: None,
+		})
+	}
+
+	/// Get the socket address of this server
+	pub fn local_addr(&self) -> io::Result<UnixSocketAddr> {
+		self.listener.local_addr()
+	}
+
+	/// An iterator over the upgrade requests sent to the server,
+	/// see `Server::incoming`.
+	pub fn incoming<'a>(&'a mut self) -> Incoming<'a, NoTlsAcceptor, UnixListener> {
+		Incoming { server: self }
+	}
+
+	/// Changes whether the Server is in nonblocking mode, see `Server::set_nonblocking`.
+	pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+		self.listener.set_nonblocking(nonblocking)
+	}
+
+	/// Wait for and accept an incoming WebSocket connection, returning a WebSocketRequest
+	// the error hands back the stream, like the TCP servers' `accept`
+	#[allow(clippy::result_large_err)]
+	pub fn accept(&mut self) -> AcceptResult<UnixStream> {
+		let stream = match self.listener.accept() {
+			Ok(s) => s.0,
+			Err(e) => {
+				return Err(InvalidConnection {
+					stream: None,
+					parsed: None,
+					buffer: None,
+					error: e.into(),
+				});
+			}
+		};
+
+		match into_ws_accepting(stream, self.versions()) {
+			Ok(u) => self.admit(u).map_err(too_many_connections),
+			Err((s, r, b, e)) => Err(InvalidConnection {
+				stream: Some(s),
+				parsed: r,
+				buffer: b,
+				error: e,
+			}),
+		}
+	}
+
+	/// Create a new independently owned handle to the underlying socket.
+	pub fn try_clone(&self) -> io::Result<Self> {
+		Ok(WsServer {
+			listener: self.listener.try_clone()?,
+			ssl_acceptor: NoTlsAcceptor,
+			tcp: TcpOptions::default(),
+			limit: None,
+			shutdown: None,
+			versions: self.versions.clone(),
+		})
+	}
+}
+
+#[cfg(unix)]
+impl Iterator for WsServer<NoTlsAcceptor, UnixListener> {
+	type Item = AcceptResult<UnixStream>;
+
 	fn next(&mut self) -> Option<<Self as Iterator>::Item> {
 		Some(self.accept())
 	}
@@ -296,4 +657,251 @@ mod tests {
 			},
 		}
 	}
+
+	#[test]
+	fn client_version_is_sent() {
+		use super::*;
+		use client::builder::ClientBuilder;
+		use header::WebSocketVersion;
+		use std::thread;
+
+		let mut server = Server::bind("127.0.0.1:0")
+			.unwrap()
+			.accept_versions(vec![13, 8]);
+		let addr = server.local_addr().unwrap();
+		let accepting = thread::spawn(move || {
+			let mut versions = Vec::new();
+			for _ in 0..2 {
+				let upgrade = server.accept().ok().unwrap();
+				versions.push(upgrade.version().cloned());
+				upgrade.accept().unwrap();
+			}
+			versions
+		});
+
+		let url = format!("ws://{}", addr);
+		ClientBuilder::new(&url)
+			.unwrap()
+			.connect_insecure()
+			.unwrap();
+		ClientBuilder::new(&url)
+			.unwrap()
+			.version(8)
+			.connect_insecure()
+			.unwrap();
+		assert_eq!(
+			accepting.join().unwrap(),
+			[
+				Some(WebSocketVersion::WebSocket13),
+				Some(WebSocketVersion::Unknown("8".to_string())),
+			]
+		);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn unix_socket_echo() {
+		use super::*;
+		use client::builder::ClientBuilder;
+		use message::OwnedMessage;
+		use std::fs;
+		use std::process;
+		use std::thread;
+
+		let path = ::std::env::temp_dir().join(format!("websocket-test-{}.sock", process::id()));
+		let _ = fs::remove_file(&path);
+		let mut server = UnixServer::bind(&path).unwrap();
+		let peer = thread::spawn(move || {
+			let upgrade = server.accept().ok().unwrap();
+			assert_eq!(
+				upgrade.request.headers.get_raw("Host"),
+				Some(&[b"localhost".to_vec()][..])
+			);
+			assert_eq!(upgrade.uri(), "/chat");
+			let mut client = upgrade.accept().ok().unwrap();
+			let message = client.recv_message().unwrap();
+			client.send_message(&message).unwrap();
+		});
+
+		let mut client = ClientBuilder::new("ws://localhost/chat")
+			.unwrap()
+			.connect_unix(&path)
+			.unwrap();
+		let message = OwnedMessage::Text("over a unix socket".to_string());
+		client.send_message(&message).unwrap();
+		assert_eq!(client.recv_message().unwrap(), message);
+		peer.join().unwrap();
+		fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn incoming_requests_one_at_a_time() {
+		use super::*;
+		use client::builder::ClientBuilder;
+		use result::WebSocketError;
+		use std::thread;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+		let peer = thread::spawn(move || {
+			let mut decisions = Vec::new();
+			for upgrade in server.incoming().take(2) {
+				let upgrade = upgrade.ok().unwrap();
+				let protocols = upgrade.protocols().to_vec();
+				if protocols.contains(&"chat".to_string()) {
+					upgrade.use_protocol("chat").accept().ok().unwrap();
+				} else {
+					upgrade.reject().ok().unwrap();
+				}
+				decisions.push(protocols);
+			}
+			// the server is still there afterwards
+			server.local_addr().unwrap();
+			decisions
+		});
+
+		let client = ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.add_protocol("chat")
+			.connect_insecure()
+			.unwrap();
+		assert_eq!(client.protocols(), ["chat".to_string()]);
+		match ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.add_protocol("other")
+			.connect_insecure()
+		{
+			Err(WebSocketError::StatusCodeError(StatusCode::BadRequest)) => {}
+			other => panic!("expected 400, got {:?}", other.map(|_| ())),
+		}
+		assert_eq!(
+			peer.join().unwrap(),
+			vec![vec!["chat".to_string()], vec!["other".to_string()]]
+		);
+	}
+
+	#[test]
+	fn shutdown_closes_the_connections() {
+		use super::*;
+		use client::builder::ClientBuilder;
+		use message::{CloseData, OwnedMessage};
+		use std::thread;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+		let trigger = server.shutdown_trigger().unwrap();
+		let peer = thread::spawn(move || {
+			let mut handlers = Vec::new();
+			for upgrade in server.incoming() {
+				let mut client = upgrade.ok().unwrap().accept().ok().unwrap();
+				// the read ends once the connections are closed
+				handlers.push(thread::spawn(move || {
+					assert!(client.recv_message().is_err());
+				}));
+			}
+			assert!(server.accept().is_err());
+			handlers
+		});
+
+		let mut client = ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.connect_insecure()
+			.unwrap();
+		trigger.shutdown();
+		trigger.shutdown();
+		trigger.close_connections("bye").unwrap();
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Close(Some(CloseData {
+				status_code: 1001,
+				reason: "bye".to_string(),
+			}))
+		);
+		let handlers = peer.join().unwrap();
+		assert_eq!(handlers.len(), 1);
+		for handler in handlers {
+			handler.join().unwrap();
+		}
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	#[test]
+	fn shutdown_closes_tls_connections() {
+		use super::*;
+		use client::builder::ClientBuilder;
+		use message::{CloseData, OwnedMessage};
+		use native_tls::Identity;
+		use std::thread;
+
+		let identity = include_bytes!("../client/fixtures/ws.example.test.p12");
+		let identity = Identity::from_pkcs12(identity, "websocket").unwrap();
+		let acceptor = TlsAcceptor::new(identity).unwrap();
+		let mut server = Server::bind_secure("127.0.0.1:0", acceptor).unwrap();
+		let addr = server.local_addr().unwrap();
+		let trigger = server.shutdown_trigger().unwrap();
+		let handler = thread::spawn(move || {
+			let mut client = server.accept().ok().unwrap().accept().ok().unwrap();
+			assert!(client.recv_message().is_err());
+		});
+
+		let mut client = ClientBuilder::new(&format!("wss://{}", addr))
+			.unwrap()
+			.danger_accept_invalid_certs(true)
+			.connect_secure(None)
+			.unwrap();
+		trigger.shutdown();
+		trigger.close_connections("bye").unwrap();
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Close(Some(CloseData {
+				status_code: 1001,
+				reason: "bye".to_string(),
+			}))
+		);
+		handler.join().unwrap();
+	}
+
+	#[test]
+	fn max_connections() {
+		use super::*;
+		use client::builder::ClientBuilder;
+		use result::WebSocketError;
+		use std::sync::mpsc::channel;
+		use std::thread;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap().max_connections(2);
+		let addr = server.local_addr().unwrap();
+		let (tx, rx) = channel();
+		thread::spawn(move || loop {
+			let result = server
+				.accept()
+				.map(|upgrade| upgrade.accept().ok().unwrap());
+			if tx.send(result.map_err(|e| e.error)).is_err() {
+				break;
+			}
+		});
+		let connect = || {
+			ClientBuilder::new(&format!("ws://{}", addr))
+				.unwrap()
+				.connect_insecure()
+		};
+
+		let _first = connect().unwrap();
+		let _second = connect().unwrap();
+		let first_peer = rx.recv().unwrap().unwrap();
+		let _second_peer = rx.recv().unwrap().unwrap();
+
+		match connect() {
+			Err(WebSocketError::StatusCodeError(StatusCode::ServiceUnavailable)) => {}
+			other => panic!("expected 503, got {:?}", other.map(|_| ())),
+		}
+		match rx.recv().unwrap() {
+			Err(HyperIntoWsError::TooManyConnections) => {}
+			other => panic!("expected too many connections, got {:?}", other.map(|_| ())),
+		}
+
+		drop(first_peer);
+		let _third = connect().unwrap();
+		assert!(rx.recv().unwrap().is_ok());
+	}
 }
@@ -5,7 +5,9 @@
 //! an intermediate struct called `Upgrade` and the `Upgrade` struct itself.
 //! The `Upgrade` struct is used to inspect details of the websocket connection
 //! (e.g. what protocols it wants to use) and decide whether to accept or reject it.
-use super::{validate, HyperIntoWsError, Request, WsUpgrade};
+use super::{
+	error_response, validate_versions, HyperIntoWsError, Request, WsUpgrade, DEFAULT_VERSIONS,
+};
 use bytes::BytesMut;
 use client::async::ClientNew;
 use codec::http::HttpServerCodec;
@@ -13,9 +15,11 @@ use codec::ws::{Context, MessageCodec};
 use futures::sink::Send as SinkSend;
 use futures::Stream as StreamTrait;
 use futures::{Future, Sink};
-use hyper::header::Headers;
+use header::WebSocketVersion;
+use hyper::header::{Connection, ContentLength, Headers};
 use hyper::http::h1::Incoming;
 use hyper::status::StatusCode;
+use message::OwnedMessage;
 use std::io::{self, ErrorKind};
 use stream::async::Stream;
 use tokio_codec::{Decoder, Framed, FramedParts};
@@ -87,11 +91,13 @@ where
 
 	fn internal_accept(mut self, custom_headers: Option<&Headers>) -> ClientNew<S> {
 		let status = self.prepare_headers(custom_headers);
+		let codec = self.message_codec();
 		let WsUpgrade {
 			headers,
 			stream,
 			request,
 			buffer,
+			..
 		} = self;
 
 		let mut parts = FramedParts::new(stream, HttpServerCodec);
@@ -105,7 +111,6 @@ where
 				headers: headers.clone(),
 			})
 			.map(move |s| {
-				let codec = MessageCodec::default(Context::Server);
 				let client = update_framed_codec(s, codec);
 				(client, headers)
 			})
@@ -113,34 +118,106 @@ where
 		Box::new(future)
 	}
 
+	// the codec of the accepted client, compressing if deflate was accepted
+	#[cfg(feature = "deflate")]
+	fn message_codec(&self) -> MessageCodec<OwnedMessage> {
+		let mut codec = MessageCodec::default(Context::Server);
+		if let Some(config) = self.accepted_deflate() {
+			codec.use_deflate(&config);
+		}
+		codec
+	}
+
+	#[cfg(not(feature = "deflate"))]
+	fn message_codec(&self) -> MessageCodec<OwnedMessage> {
+		MessageCodec::default(Context::Server)
+	}
+
 	/// Asynchronously send a rejection message and deconstruct `self`
 	/// into it's original stream. The stream being returned is framed with the
 	/// `HttpServerCodec` since that was used to send the rejection message.
 	pub fn reject(self) -> SinkSend<Framed<S, HttpServerCodec>> {
-		self.internal_reject(None)
+		self.internal_reject(None, StatusCode::BadRequest)
+	}
+
+	/// Asynchronously reject the request with `status`, e.g. `Unauthorized`
+	/// once a lookup of the client's credentials failed. Like `reject` this
+	/// deconstructs `self` into the stream framed with the `HttpServerCodec`.
+	///
+	/// Unless the headers of the upgrade set them already, the response
+	/// contains `Connection: close` and `Content-Length: 0`, the stream should
+	/// be dropped once it was sent.
+	///
+	/// ```rust,no_run
+	/// # extern crate hyper;
+	/// # extern crate tokio;
+	/// # extern crate websocket;
+	/// use hyper::status::StatusCode;
+	/// use websocket::async::futures::future::{self, Either};
+	/// use websocket::async::futures::{Future, Stream};
+	/// use websocket::async::Server;
+	///
+	/// # fn main() {
+	/// // stands in for a database query
+	/// fn is_authorized(token: Option<Vec<u8>>) -> impl Future<Item = bool, Error = ()> {
+	///     future::ok(token.is_some_and(|token| token == b"Bearer secret"))
+	/// }
+	///
+	/// let server = Server::bind("127.0.0.1:1234", &tokio::reactor::Handle::default()).unwrap();
+	/// let connections = server
+	///     .incoming()
+	///     .map_err(|_| ())
+	///     .for_each(|(upgrade, _)| {
+	///         let token = upgrade.request.headers.get_raw("Authorization").map(|t| t[0].clone());
+	///         let handshake = is_authorized(token).and_then(move |authorized| {
+	///             if authorized {
+	///                 Either::A(upgrade.accept().map(|_| ()).map_err(|_| ()))
+	///             } else {
+	///                 let rejected = upgrade.reject_with_status(StatusCode::Unauthorized);
+	///                 Either::B(rejected.map(|_| ()).map_err(|_| ()))
+	///             }
+	///         });
+	///         tokio::spawn(handshake);
+	///         Ok(())
+	///     });
+	/// tokio::run(connections);
+	/// # }
+	/// ```
+	pub fn reject_with_status(self, status: StatusCode) -> SinkSend<Framed<S, HttpServerCodec>> {
+		self.internal_reject(None, status)
 	}
 
 	/// Asynchronously send a rejection message with custom headers and
 	/// deconstruct `self` into it's original stream.
 	///  The stream being returned is framed with the
 	/// `HttpServerCodec` since that was used to send the rejection message.
+	///
+	/// `Connection: close` and `Content-Length: 0` are only added when `headers`
+	/// don't set these headers, e.g. to write a body after the response.
 	pub fn reject_with(self, headers: &Headers) -> SinkSend<Framed<S, HttpServerCodec>> {
-		self.internal_reject(Some(headers))
+		self.internal_reject(Some(headers), StatusCode::BadRequest)
 	}
 
 	fn internal_reject(
 		mut self,
 		headers: Option<&Headers>,
+		status: StatusCode,
 	) -> SinkSend<Framed<S, HttpServerCodec>> {
 		if let Some(custom) = headers {
 			self.headers.extend(custom.iter());
 		}
+		if !self.headers.has::<Connection>() {
+			self.headers.set(Connection::close());
+		}
+		if !self.headers.has::<ContentLength>() {
+			self.headers.set(ContentLength(0));
+		}
 		let mut parts = FramedParts::new(self.stream, HttpServerCodec);
 		parts.read_buf = self.buffer;
 		let duplex = Framed::from_parts(parts);
 		duplex.send(Incoming {
 			version: self.request.version,
-			subject: StatusCode::BadRequest,
+			subject: status,
 			headers: self.headers,
 		})
 	}
@@ -217,34 +294,208 @@ where
 	type Error = (S, Option<Request>, BytesMut, HyperIntoWsError);
 
 	fn into_ws(self) -> Box<Future<Item = Upgrade<Self::Stream>, Error = Self::Error> + Send> {
-		let future = HttpServerCodec
-			.framed(self)
-			.into_future()
-			.map_err(|(e, s)| {
-				let FramedParts { io, read_buf, .. } = s.into_parts();
-				(io, None, read_buf, e.into())
-			})
-			.and_then(|(m, s)| {
-				let FramedParts { io, read_buf, .. } = s.into_parts();
-				if let Some(msg) = m {
-					match validate(&msg.subject.0, msg.version, &msg.headers) {
-						Ok(()) => Ok((msg, io, read_buf)),
-						Err(e) => Err((io, Some(msg), read_buf, e)),
+		into_ws_accepting(self, DEFAULT_VERSIONS.to_vec())
+	}
+}
+
+// like `IntoWs::into_ws` for a stream, but any of `versions` is accepted as
+// the websocket version of the request
+pub(crate) fn into_ws_accepting<S>(
+	stream: S,
+	versions: Vec<WebSocketVersion>,
+) -> Box<dyn Future<Item = Upgrade<S>, Error = <S as IntoWs>::Error> + Send>
+where
+	S: Stream + Send + 'static,
+{
+	// the rejected stream is boxed while it is passed along the futures
+	let future = HttpServerCodec
+		.framed(stream)
+		.into_future()
+		.map_err(|(e, s)| {
+			let FramedParts { io, read_buf, .. } = s.into_parts();
+			Box::new((io, None, read_buf, e.into()))
+		})
+		.and_then(move |(m, s)| {
+			let FramedParts {
+				mut io, read_buf, ..
+			} = s.into_parts();
+			if let Some(msg) = m {
+				match validate_versions(&msg.subject.0, msg.version, &msg.headers, &versions) {
+					Ok(()) => Ok((msg, io, read_buf)),
+					Err(e) => {
+						// best effort, the response is small enough to fit into
+						// the socket's buffer of a fresh connection
+						if let Some(response) =
+							error_response(msg.version, &msg.headers, &e, &versions)
+						{
+							let _ = io.write_all(response.as_bytes());
+						}
+						Err(Box::new((io, Some(msg), read_buf, e)))
 					}
-				} else {
-					let err = HyperIntoWsError::Io(io::Error::new(
-						ErrorKind::ConnectionReset,
-						"Connection dropped before handshake could be read",
-					));
-					Err((io, None, read_buf, err))
 				}
+			} else {
+				let err = HyperIntoWsError::Io(io::Error::new(
+					ErrorKind::ConnectionReset,
+					"Connection dropped before handshake could be read",
+				));
+				Err(Box::new((io, None, read_buf, err)))
+			}
+		})
+		.map_err(|rejected| *rejected)
+		.map(|(m, stream, buffer)| WsUpgrade {
+			headers: Headers::new(),
+			stream,
+			request: m,
+			buffer,
+			#[cfg(feature = "sync")]
+			slots: Vec::new(),
+			#[cfg(feature = "sync")]
+			rate_limit: None,
+			#[cfg(feature = "sync")]
+			strict: false,
+		});
+	Box::new(future)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use client::builder::ClientBuilder;
+	use futures::future::{self, Either};
+	use futures::Stream;
+	use hyper::header::Headers;
+	use result::WebSocketError;
+	use server::async::Server;
+	use tokio::reactor::Handle;
+	use tokio::runtime::Builder;
+
+	#[test]
+	fn rejects_after_looking_at_the_request() {
+		let mut runtime = Builder::new().build().unwrap();
+		let server = Server::bind("127.0.0.1:0", &Handle::default()).unwrap();
+		let addr = server.local_addr().unwrap();
+		let handshakes = server
+			.incoming()
+			.take(2)
+			.map_err(|e| panic!("invalid connection: {:?}", e.error))
+			.for_each(|(upgrade, _)| {
+				let token = upgrade
+					.request
+					.headers
+					.get_raw("X-Token")
+					.map(|t| t[0].clone());
+				// decided later, like after a database lookup
+				future::lazy(move || Ok(token == Some(b"secret".to_vec()))).and_then(
+					move |authorized| {
+						if authorized {
+							Either::A(upgrade.accept().map(|_| ()))
+						} else {
+							let rejected = upgrade.reject_with_status(StatusCode::Unauthorized);
+							Either::B(rejected.map(|_| ()).map_err(Into::into))
+						}
+					},
+				)
 			})
-			.map(|(m, stream, buffer)| WsUpgrade {
-				headers: Headers::new(),
-				stream,
-				request: m,
-				buffer,
+			.map_err(|e: WebSocketError| panic!("handshake failed: {:?}", e));
+		runtime.spawn(handshakes);
+
+		let connect = |token: &str| {
+			let mut headers = Headers::new();
+			headers.set_raw("X-Token", vec![token.as_bytes().to_vec()]);
+			ClientBuilder::new(&format!("ws://{}", addr))
+				.unwrap()
+				.custom_headers(&headers)
+				.async_connect_insecure()
+		};
+		match runtime.block_on(connect("guessed")) {
+			Err(WebSocketError::StatusCodeError(StatusCode::Unauthorized)) => (),
+			other => panic!("expected 401, got {:?}", other.map(|_| ())),
+		}
+		assert!(runtime.block_on(connect("secret")).is_ok());
+	}
+
+	#[test]
+	fn server_accepts_the_configured_versions() {
+		let mut runtime = Builder::new().build().unwrap();
+		let server = Server::bind("127.0.0.1:0", &Handle::default())
+			.unwrap()
+			.accept_versions(vec![13, 8]);
+		let addr = server.local_addr().unwrap();
+		let handshakes = server
+			.incoming()
+			// requests for other versions were answered already
+			.then(|upgrade| Ok::<_, ()>(upgrade.ok()))
+			.filter_map(|upgrade| upgrade)
+			.take(1)
+			.for_each(|(upgrade, _)| {
+				assert_eq!(upgrade.version(), Some(&WebSocketVersion::from(8)));
+				upgrade
+					.accept()
+					.map(|_| ())
+					.map_err(|e| panic!("handshake failed: {:?}", e))
 			});
-		Box::new(future)
+		runtime.spawn(handshakes);
+
+		let connect = |version| {
+			ClientBuilder::new(&format!("ws://{}", addr))
+				.unwrap()
+				.version(version)
+				.async_connect_insecure()
+		};
+		match runtime.block_on(connect(7)) {
+			Err(WebSocketError::StatusCodeError(StatusCode::UpgradeRequired)) => (),
+			other => panic!("expected 426, got {:?}", other.map(|_| ())),
+		}
+		assert!(runtime.block_on(connect(8)).is_ok());
+	}
+
+	#[test]
+	fn reject_with_keeps_the_given_headers() {
+		use std::io::{Read, Write};
+		use std::net::TcpStream;
+		use std::thread;
+
+		let mut runtime = Builder::new().build().unwrap();
+		let server = Server::bind("127.0.0.1:0", &Handle::default()).unwrap();
+		let addr = server.local_addr().unwrap();
+		let client = thread::spawn(move || {
+			let mut stream = TcpStream::connect(addr).unwrap();
+			stream
+				.write_all(
+					b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+					  Connection: Upgrade\r\nSec-WebSocket-Version: 13\r\n\
+					  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+				)
+				.unwrap();
+			let mut response = String::new();
+			stream.read_to_string(&mut response).unwrap();
+			response
+		});
+		let reject = server
+			.incoming()
+			.into_future()
+			.map_err(|_| panic!("invalid connection"))
+			.and_then(|(upgrade, _)| {
+				let (upgrade, _) = upgrade.unwrap();
+				let mut headers = Headers::new();
+				headers.set(Connection::keep_alive());
+				headers.set(ContentLength(5));
+				upgrade.reject_with(&headers)
+			})
+			.and_then(|framed| {
+				let mut stream = framed.into_inner();
+				stream.write_all(b"nope!").map(|()| stream)
+			});
+		drop(runtime.block_on(reject).unwrap());
+
+		let response = client.join().unwrap();
+		assert!(
+			response.contains("Connection: keep-alive\r\n"),
+			"{}",
+			response
+		);
+		assert!(response.contains("Content-Length: 5\r\n"), "{}", response);
+		assert!(!response.contains("close"), "{}", response);
+		assert!(response.ends_with("\r\n\r\nnope!"), "{}", response);
 	}
 }
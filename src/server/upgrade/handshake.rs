@@ -0,0 +1,211 @@
+//! Parse websocket handshakes from raw bytes and build their responses,
+//! without reading from or writing to a socket.
+//!
+//! This is useful to test handshakes or when the first bytes of a connection
+//! were already read by some other protocol.
+//!
+//! ```rust
+//! use websocket::server::upgrade::Handshake;
+//!
+//! let request = b"GET /chat HTTP/1.1\r\n\
+//!                 Host: example.com\r\n\
+//!                 Upgrade: websocket\r\n\
+//!                 Connection: Upgrade\r\n\
+//!                 Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+//!                 Sec-WebSocket-Version: 13\r\n\r\n";
+//!
+//! // the request is incomplete until the empty line is received
+//! assert!(Handshake::parse_request(&request[..20]).unwrap().is_none());
+//!
+//! let (handshake, consumed) = Handshake::parse_request(request).unwrap().unwrap();
+//! assert_eq!(consumed, request.len());
+//! assert_eq!(handshake.uri(), "/chat");
+//!
+//! let response = String::from_utf8(handshake.build_response()).unwrap();
+//! assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+//! ```
+use super::{set_upgrade_headers, validate, HyperIntoWsError, Request};
+use header::extensions::Extension;
+use header::{WebSocketExtensions, WebSocketKey, WebSocketProtocol, WebSocketVersion};
+
+use hyper::buffer::BufReader;
+use hyper::header::Headers;
+use hyper::http::h1::parse_request;
+use hyper::status::StatusCode;
+
+// the longest handshake request that is waited for, like hyper's own limit
+const MAX_REQUEST_SIZE: usize = 8192 + 4096 * 100;
+
+/// A validated websocket handshake request.
+///
+/// The response headers can be extended before the response is built,
+/// e.g. with the protocol chosen from `protocols()`.
+#[derive(Debug)]
+pub struct Handshake {
+	/// The handshake request.
+	pub request: Request,
+	/// Extra headers sent in the handshake response.
+	pub headers: Headers,
+}
+
+impl Handshake {
+	/// Parse and validate a handshake request from the start of `bytes`.
+	///
+	/// Returns `Ok(None)` if `bytes` does not contain the whole request yet,
+	/// otherwise the handshake and the number of bytes it took up, any bytes after
+	/// that already belong to the websocket connection.
+	pub fn parse_request(bytes: &[u8]) -> Result<Option<(Handshake, usize)>, HyperIntoWsError> {
+		let len = match bytes.windows(4).position(|w| w == b"\r\n\r\n") {
+			Some(p) => p + 4,
+			None if bytes.len() > MAX_REQUEST_SIZE => {
+				return Err(HyperIntoWsError::Parsing(::hyper::Error::TooLarge));
+			}
+			None => return Ok(None),
+		};
+
+		let mut reader = BufReader::with_capacity(&bytes[..len], len);
+		let request = parse_request(&mut reader)?;
+		validate(&request.subject.0, request.version, &request.headers)?;
+
+		let handshake = Handshake {
+			request,
+			headers: Headers::new(),
+		};
+		Ok(Some((handshake, len)))
+	}
+
+	/// Select a protocol to use in the handshake response.
+	pub fn use_protocol<P>(mut self, protocol: P) -> Self
+	where
+		P: Into<String>,
+	{
+		upsert_header!(self.headers; WebSocketProtocol; {
+			Some(protos) => protos.0.push(protocol.into()),
+			None => WebSocketProtocol(vec![protocol.into()])
+		});
+		self
+	}
+
+	/// Select an extension to use in the handshake response.
+	pub fn use_extension(mut self, extension: Extension) -> Self {
+		upsert_header!(self.headers; WebSocketExtensions; {
+			Some(protos) => protos.0.push(extension),
+			None => WebSocketExtensions(vec![extension])
+		});
+		self
+	}
+
+	/// The client's websocket key.
+	pub fn key(&self) -> &[u8; 16] {
+		// NOTE: validated requests always have a key
+		self.request
+			.headers
+			.get::<WebSocketKey>()
+			.unwrap()
+			.as_bytes()
+	}
+
+	/// A list of protocols requested from the client.
+	pub fn protocols(&self) -> &[String] {
+		self.request
+			.headers
+			.get::<WebSocketProtocol>()
+			.map(|p| p.0.as_slice())
+			.unwrap_or(&[])
+	}
+
+	/// A list of extensions requested from the client.
+	pub fn extensions(&self) -> &[Extension] {
+		self.request
+			.headers
+			.get::<WebSocketExtensions>()
+			.map(|e| e.0.as_slice())
+			.unwrap_or(&[])
+	}
+
+	/// The client's websocket version, if it sent one.
+	pub fn version(&self) -> Option<&WebSocketVersion> {
+		self.request.headers.get::<WebSocketVersion>()
+	}
+
+	/// The original request URI.
+	pub fn uri(&self) -> String {
+		format!("{}", self.request.subject.1)
+	}
+
+	/// The raw `101 Switching Protocols` response accepting this handshake,
+	/// ready to be sent to the client.
+	pub fn build_response(&self) -> Vec<u8> {
+		let mut headers = self.headers.clone();
+		let key = self.request.headers.get::<WebSocketKey>().unwrap();
+		set_upgrade_headers(&mut headers, key);
+		format!(
+			"{} {}\r\n{}\r\n",
+			self.request.version,
+			StatusCode::SwitchingProtocols,
+			headers
+		)
+		.into_bytes()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const REQUEST: &[u8] = b"GET /chat HTTP/1.1\r\n\
+	                         Host: server.example.com\r\n\
+	                         Upgrade: websocket\r\n\
+	                         Connection: Upgrade\r\n\
+	                         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+	                         Sec-WebSocket-Protocol: chat, superchat\r\n\
+	                         Sec-WebSocket-Extensions: permessage-deflate\r\n\
+	                         Sec-WebSocket-Version: 13\r\n\r\n";
+
+	#[test]
+	fn valid_request() {
+		let mut bytes = REQUEST.to_vec();
+		bytes.extend_from_slice(b"\x81\x00");
+		let (handshake, consumed) = Handshake::parse_request(&bytes).unwrap().unwrap();
+		assert_eq!(consumed, REQUEST.len());
+		assert_eq!(handshake.key(), b"the sample nonce");
+		assert_eq!(handshake.protocols(), ["chat", "superchat"]);
+		assert_eq!(handshake.extensions()[0].name, "permessage-deflate");
+		assert_eq!(handshake.version(), Some(&WebSocketVersion::WebSocket13));
+
+		let response = handshake.use_protocol("chat").build_response();
+		let response = String::from_utf8(response).unwrap();
+		assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+		assert!(response.contains("Sec-WebSocket-Protocol: chat\r\n"));
+		assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+		assert!(response.ends_with("\r\n\r\n"));
+	}
+
+	#[test]
+	fn truncated_request() {
+		for len in 0..REQUEST.len() {
+			match Handshake::parse_request(&REQUEST[..len]) {
+				Ok(None) => (),
+				_ => panic!("{} bytes are not a whole request", len),
+			}
+		}
+	}
+
+	#[test]
+	fn malformed_request() {
+		match Handshake::parse_request(b"GET /chat HTTP/1.1\r\nHost: a\r\n\r\n") {
+			Err(HyperIntoWsError::NoSecWsKeyHeader) => (),
+			_ => panic!("a request without a key is not a handshake"),
+		}
+		match Handshake::parse_request(b"GET\x01/ HTTP/1.1\r\n\r\n") {
+			Err(HyperIntoWsError::Parsing(_)) => (),
+			_ => panic!("an invalid request line must not parse"),
+		}
+		let mut huge = b"GET / HTTP/1.1\r\nX: ".to_vec();
+		huge.resize(MAX_REQUEST_SIZE + 1, b'x');
+		match Handshake::parse_request(&huge) {
+			Err(HyperIntoWsError::Parsing(::hyper::Error::TooLarge)) => (),
+			_ => panic!("a handshake can not be endless"),
+		}
+	}
+}
@@ -1,15 +1,21 @@
 //! Allows you to take an existing request or stream of data and convert it into a
 //! WebSocket client.
+#[cfg(feature = "deflate")]
+use deflate::DeflateConfig;
 use header::extensions::Extension;
-use header::{
-	Origin, WebSocketAccept, WebSocketExtensions, WebSocketKey, WebSocketProtocol, WebSocketVersion,
-};
+use header::{Origin, WebSocketExtensions, WebSocketKey, WebSocketProtocol, WebSocketVersion};
+#[cfg(feature = "sync")]
+use ratelimit::RateLimit;
+#[cfg(feature = "sync")]
+use server::ConnectionSlot;
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
 use std::io;
 use stream::Stream;
 
 use hyper::header::{Connection, ConnectionOption, Headers, Protocol, ProtocolName, Upgrade};
+#[cfg(any(feature = "sync", feature = "async"))]
+use hyper::header::{ContentLength, ContentType};
 use hyper::http::h1::Incoming;
 use hyper::method::Method;
 use hyper::status::StatusCode;
@@ -22,6 +28,11 @@ use hyper::version::HttpVersion;
 #[cfg(feature = "async")]
 pub mod async;
 
+#[cfg(any(feature = "sync", feature = "async"))]
+pub mod handshake;
+#[cfg(any(feature = "sync", feature = "async"))]
+pub use self::handshake::Handshake;
+
 #[cfg(feature = "sync")]
 pub mod sync;
 
@@ -50,6 +61,14 @@ where
 	pub request: Request,
 	/// Some buffered data from the stream, if it exists.
 	pub buffer: B,
+	// count the connection for a server with a connection limit or shutdown trigger
+	#[cfg(feature = "sync")]
+	slots: Vec<ConnectionSlot>,
+	// applies to the accepted client
+	#[cfg(feature = "sync")]
+	rate_limit: Option<RateLimit>,
+	#[cfg(feature = "sync")]
+	strict: bool,
 }
 
 impl<S, B> WsUpgrade<S, B>
@@ -115,7 +134,10 @@ where
 
 	/// The client's websocket accept key.
 	pub fn key(&self) -> Option<&[u8; 16]> {
-		self.request.headers.get::<WebSocketKey>().map(|k| &k.0)
+		self.request
+			.headers
+			.get::<WebSocketKey>()
+			.map(|k| k.as_bytes())
 	}
 
 	/// The client's websocket version.
@@ -133,6 +155,38 @@ where
 		self.request.headers.get::<Origin>().map(|o| &o.0 as &str)
 	}
 
+	/// Accept the `permessage-deflate` extension if the client offered it, in which
+	/// case the accepted client will compress its messages.
+	/// If the client did not offer any acceptable parameters this does nothing.
+	#[cfg(feature = "deflate")]
+	pub fn enable_deflate(self) -> Self {
+		self.enable_deflate_with(&DeflateConfig::default())
+	}
+
+	/// Accept the `permessage-deflate` extension like `enable_deflate`, using `config`
+	/// as the server's preferences. The negotiated parameters are sent back
+	/// to the client and used by the accepted client.
+	///
+	/// With `server_no_context_takeover` and a small `server_max_window_bits`
+	/// the memory kept by every connection to compress its messages is bounded.
+	/// Asking the client not to take over its context or to use a small window as well
+	/// does the same for the memory needed to decompress the client's messages.
+	#[cfg(feature = "deflate")]
+	pub fn enable_deflate_with(self, config: &DeflateConfig) -> Self {
+		match config.negotiate(self.extensions()) {
+			Some(config) => self.use_extension(config.response()),
+			None => self,
+		}
+	}
+
+	// the parameters sent back to the client by `enable_deflate`
+	#[cfg(all(any(feature = "sync", feature = "async"), feature = "deflate"))]
+	fn accepted_deflate(&self) -> Option<DeflateConfig> {
+		self.headers
+			.get::<WebSocketExtensions>()
+			.and_then(|e| e.0.iter().filter_map(DeflateConfig::from_extension).next())
+	}
+
 	#[cfg(feature = "sync")]
 	fn send(&mut self, status: StatusCode) -> io::Result<()> {
 		let data = format!(
@@ -151,27 +205,36 @@ where
 		// NOTE: we know there is a key because this is a valid request
 		// i.e. to construct this you must go through the validate function
 		let key = self.request.headers.get::<WebSocketKey>().unwrap();
-		self.headers.set(WebSocketAccept::new(key));
-		self.headers
-			.set(Connection(vec![ConnectionOption::ConnectionHeader(
-				UniCase("Upgrade".to_string()),
-			)]));
-		self.headers
-			.set(Upgrade(vec![Protocol::new(ProtocolName::WebSocket, None)]));
+		set_upgrade_headers(&mut self.headers, key);
 
 		StatusCode::SwitchingProtocols
 	}
 }
 
+// the headers of a successful handshake response
+fn set_upgrade_headers(headers: &mut Headers, key: &WebSocketKey) {
+	headers.set(key.accept());
+	headers.set(Connection(vec![ConnectionOption::ConnectionHeader(
+		UniCase("Upgrade".to_string()),
+	)]));
+	headers.set(Upgrade(vec![Protocol::new(ProtocolName::WebSocket, None)]));
+}
+
 /// Errors that can occur when one tries to upgrade a connection to a
 /// websocket connection.
+///
+/// When a handshake lacks one of the required headers the client is told which
+/// one with a `400 Bad Request`, unless the request has neither a
+/// `Sec-WebSocket-*` header nor asks to upgrade to a `websocket`. Such a request
+/// is left unanswered so it can be handled as a plain HTTP request.
 #[derive(Debug)]
 pub enum HyperIntoWsError {
 	/// The HTTP method in a valid websocket upgrade request must be GET
 	MethodNotGet,
 	/// Currently HTTP 2 is not supported
 	UnsupportedHttpVersion,
-	/// Currently only WebSocket13 is supported (RFC6455)
+	/// Currently only WebSocket13 is supported (RFC6455), when upgrading a stream
+	/// the client is told so with a `426 Upgrade Required` response
 	UnsupportedWebsocketVersion,
 	/// A websocket upgrade request must contain a key
 	NoSecWsKeyHeader,
@@ -183,6 +246,14 @@ pub enum HyperIntoWsError {
 	NoWsConnectionHeader,
 	/// A websocket upgrade request must contain a `Connection` header
 	NoConnectionHeader,
+	/// A websocket upgrade request's `Sec-WebSocket-Protocol` header must list
+	/// protocols which are HTTP tokens, the client is told so with a `400 Bad Request`
+	InvalidProtocolHeader,
+	/// The server already has as many open connections as it allows, the client
+	/// is told so with a `503 Service Unavailable` response
+	TooManyConnections,
+	/// The server was stopped with its `ShutdownTrigger`
+	ShutDown,
 	/// IO error from reading the underlying socket
 	Io(io::Error),
 	/// Error while parsing an incoming request
@@ -207,6 +278,9 @@ impl Error for HyperIntoWsError {
 			NoUpgradeHeader => "Missing Upgrade WebSocket header",
 			NoWsConnectionHeader => "Invalid Connection WebSocket header",
 			NoConnectionHeader => "Missing Connection WebSocket header",
+			InvalidProtocolHeader => "Invalid Sec-WebSocket-Protocol header",
+			TooManyConnections => "Too many open connections",
+			ShutDown => "The server was shut down",
 			Io(ref e) => e.description(),
 			Parsing(ref e) => e.description(),
 		}
@@ -243,12 +317,80 @@ impl From<::codec::http::HttpCodecError> for HyperIntoWsError {
 	}
 }
 
+// the answer to an invalid request which is sent right away: an unsupported
+// websocket version is answered listing the supported versions (RFC 6455
+// section 4.4), a malformed protocol header with a `400 Bad Request`, and so
+// is a handshake missing a header, naming the header in the body
+#[cfg(any(feature = "sync", feature = "async"))]
+fn error_response(
+	version: HttpVersion,
+	request: &Headers,
+	error: &HyperIntoWsError,
+	versions: &[WebSocketVersion],
+) -> Option<String> {
+	use self::HyperIntoWsError::*;
+	let mut headers = Headers::new();
+	let mut body = String::new();
+	let status = match *error {
+		UnsupportedWebsocketVersion => {
+			let versions: Vec<_> = versions.iter().map(ToString::to_string).collect();
+			headers.set_raw(
+				"Sec-WebSocket-Version",
+				vec![versions.join(", ").into_bytes()],
+			);
+			StatusCode::UpgradeRequired
+		}
+		InvalidProtocolHeader => StatusCode::BadRequest,
+		NoSecWsKeyHeader | NoUpgradeHeader | NoWsUpgradeHeader | NoConnectionHeader
+		| NoWsConnectionHeader
+			if is_handshake_attempt(request) =>
+		{
+			headers.set(ContentType::plaintext());
+			body = error.to_string();
+			StatusCode::BadRequest
+		}
+		_ => return None,
+	};
+	headers.set(Connection::close());
+	headers.set(ContentLength(body.len() as u64));
+	Some(format!("{} {}\r\n{}\r\n{}", version, status, headers, body))
+}
+
+// a request with a websocket header or asking to upgrade to a websocket,
+// other requests may be meant to be answered as plain HTTP requests
+#[cfg(any(feature = "sync", feature = "async"))]
+fn is_handshake_attempt(headers: &Headers) -> bool {
+	let websocket_header = headers.iter().any(|header| {
+		let prefix = header.name().get(..14);
+		prefix.is_some_and(|prefix| prefix.eq_ignore_ascii_case("Sec-WebSocket-"))
+	});
+	let websocket_upgrade = headers
+		.get::<Upgrade>()
+		.is_some_and(|upgrade| upgrade.0.iter().any(|u| u.name == ProtocolName::WebSocket));
+	websocket_header || websocket_upgrade
+}
+
+/// The websocket versions a server accepts unless told otherwise.
+#[cfg(any(feature = "sync", feature = "async"))]
+pub const DEFAULT_VERSIONS: &[WebSocketVersion] = &[WebSocketVersion::WebSocket13];
+
 #[cfg(any(feature = "sync", feature = "async"))]
 /// Check whether an incoming request is a valid WebSocket upgrade attempt.
 pub fn validate(
 	method: &Method,
 	version: HttpVersion,
 	headers: &Headers,
+) -> Result<(), HyperIntoWsError> {
+	validate_versions(method, version, headers, DEFAULT_VERSIONS)
+}
+
+/// Like `validate`, but any of `versions` is accepted as the websocket version.
+#[cfg(any(feature = "sync", feature = "async"))]
+pub fn validate_versions(
+	method: &Method,
+	version: HttpVersion,
+	headers: &Headers,
+	versions: &[WebSocketVersion],
 ) -> Result<(), HyperIntoWsError> {
 	if *method != Method::Get {
 		return Err(HyperIntoWsError::MethodNotGet);
@@ -259,7 +401,7 @@ pub fn validate(
 	}
 
 	if let Some(version) = headers.get::<WebSocketVersion>() {
-		if version != &WebSocketVersion::WebSocket13 {
+		if !versions.contains(version) {
 			return Err(HyperIntoWsError::UnsupportedWebsocketVersion);
 		}
 	}
@@ -268,6 +410,13 @@ pub fn validate(
 		return Err(HyperIntoWsError::NoSecWsKeyHeader);
 	}
 
+	// a header which is present but can't be parsed is malformed
+	if headers.get_raw("Sec-WebSocket-Protocol").is_some()
+		&& headers.get::<WebSocketProtocol>().is_none()
+	{
+		return Err(HyperIntoWsError::InvalidProtocolHeader);
+	}
+
 	match headers.get() {
 		Some(&Upgrade(ref upgrade)) => {
 			if upgrade.iter().all(|u| u.name != ProtocolName::WebSocket) {
@@ -1,13 +1,19 @@
 //! Allows you to take an existing request or stream of data and convert it into a
 //! WebSocket client.
 use client::sync::Client;
-use server::upgrade::{validate, HyperIntoWsError, Request, WsUpgrade};
-use std::io;
-use std::net::TcpStream;
-use stream::sync::{AsTcpStream, Stream};
+use header::WebSocketVersion;
+use ratelimit::RateLimit;
+use server::upgrade::{
+	error_response, validate, validate_versions, HyperIntoWsError, Request, WsUpgrade,
+	DEFAULT_VERSIONS,
+};
+use server::ConnectionSlot;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream};
+use stream::sync::{AsTcpStream, SocketAddrs, Stream};
 
 use hyper::buffer::BufReader;
-use hyper::header::Headers;
+use hyper::header::{Connection, ContentLength, Headers};
 use hyper::http::h1::parse_request;
 use hyper::http::h1::Incoming;
 use hyper::net::NetworkStream;
@@ -61,6 +67,96 @@ where
 		self.internal_accept(Some(custom_headers))
 	}
 
+	/// Accept the handshake request using the subprotocol chosen by `select`,
+	/// which is given the protocols requested by the client in order of preference.
+	/// If `select` returns `None` no subprotocol is sent back to the client.
+	///
+	/// Choosing a protocol that was not requested by the client fails
+	/// with an `InvalidInput` error and no response is sent.
+	pub fn accept_with_protocol<F>(self, select: F) -> Result<Client<S>, (S, io::Error)>
+	where
+		F: FnOnce(&[String]) -> Option<String>,
+	{
+		let protocol = match select(self.protocols()) {
+			Some(protocol) => protocol,
+			None => return self.accept(),
+		};
+		if !self.protocols().contains(&protocol) {
+			return Err((
+				self.stream,
+				io::Error::new(
+					io::ErrorKind::InvalidInput,
+					"Selected protocol was not requested by the client",
+				),
+			));
+		}
+		self.use_protocol(protocol).accept()
+	}
+
+	/// Accept the handshake request if `allow` returns true for the `Origin`
+	/// header of the request, otherwise reject it with `403 Forbidden`.
+	///
+	/// Browsers always send the origin of the page opening the connection,
+	/// sandboxed documents send the string `"null"`. Other clients usually don't
+	/// send the header at all, in which case `allow` is given `None`.
+	///
+	/// A rejected request fails with a `PermissionDenied` error after the response
+	/// has been sent.
+	///
+	/// ```rust,no_run
+	/// # use websocket::sync::Server;
+	/// let mut server = Server::bind("127.0.0.1:1234").unwrap();
+	/// let upgrade = server.accept().ok().unwrap();
+	/// let client = upgrade.accept_if_origin(|origin| origin == Some("https://example.com"));
+	/// ```
+	pub fn accept_if_origin<F>(self, allow: F) -> Result<Client<S>, (S, io::Error)>
+	where
+		F: FnOnce(Option<&str>) -> bool,
+	{
+		if allow(self.origin()) {
+			return self.accept();
+		}
+		let stream = self.internal_reject(None, StatusCode::Forbidden, b"")?;
+		Err((
+			stream,
+			io::Error::new(
+				io::ErrorKind::PermissionDenied,
+				"The origin of the request is not allowed",
+			),
+		))
+	}
+
+	/// Limit how fast the accepted client may send frames, see `Client::set_rate_limit`.
+	pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+		self.rate_limit = Some(limit);
+		self
+	}
+
+	/// Enforce every requirement of RFC 6455 on the accepted client, see
+	/// `Client::set_strict`. The checks can be turned off one by one on the client.
+	/// The asynchronous `WsUpgrade` has no such setting, its codec does not know
+	/// these checks.
+	///
+	/// ```rust,no_run
+	/// # use websocket::sync::Server;
+	/// let server = Server::bind("127.0.0.1:9002").unwrap();
+	/// for upgrade in server.filter_map(Result::ok) {
+	///     let client = upgrade.strict(true).accept();
+	/// #   drop(client);
+	/// }
+	/// ```
+	pub fn strict(mut self, strict: bool) -> Self {
+		self.strict = strict;
+		self
+	}
+
+	/// Count the connection against a server's connection limit, or keep it
+	/// registered with a shutdown trigger, until the accepted client is dropped.
+	pub(crate) fn hold_slot(mut self, slot: ConnectionSlot) -> Self {
+		self.slots.push(slot);
+		self
+	}
+
 	fn internal_accept(mut self, headers: Option<&Headers>) -> Result<Client<S>, (S, io::Error)> {
 		let status = self.prepare_headers(headers);
 
@@ -68,30 +164,91 @@ where
 			return Err((self.stream, e));
 		}
 
+		#[cfg(feature = "deflate")]
+		let deflate = self.accepted_deflate();
+
 		let stream = match self.buffer {
 			Some(Buffer { buf, pos, cap }) => BufReader::from_parts(self.stream, buf, pos, cap),
 			None => BufReader::new(self.stream),
 		};
 
-		Ok(Client::unchecked(stream, self.headers, false, true))
+		let mut client = Client::unchecked(stream, self.headers, false, true);
+		for slot in self.slots {
+			client.hold_slot(slot);
+		}
+		client.set_rate_limit(self.rate_limit);
+		if self.strict {
+			client.set_strict(true);
+		}
+		#[cfg(feature = "deflate")]
+		{
+			if let Some(config) = deflate {
+				client.use_deflate(&config);
+			}
+		}
+		Ok(client)
 	}
 
 	/// Reject the client's request to make a websocket connection.
 	pub fn reject(self) -> Result<S, (S, io::Error)> {
-		self.internal_reject(None)
+		self.internal_reject(None, StatusCode::BadRequest, b"")
 	}
 
 	/// Reject the client's request to make a websocket connection
 	/// and send extra headers.
 	pub fn reject_with(self, headers: &Headers) -> Result<S, (S, io::Error)> {
-		self.internal_reject(Some(headers))
+		self.internal_reject(Some(headers), StatusCode::BadRequest, b"")
+	}
+
+	/// Reject the client's request to make a websocket connection
+	/// with a custom status, extra headers and a response body,
+	/// e.g. to tell a browser why its credentials were refused.
+	///
+	/// The response always contains the `Connection: close` and
+	/// `Content-Length` headers, the returned stream should be dropped
+	/// once the response was sent.
+	///
+	/// ```rust,no_run
+	/// # extern crate hyper;
+	/// # extern crate websocket;
+	/// # fn main() {
+	/// use hyper::header::{ContentType, Headers};
+	/// use hyper::status::StatusCode;
+	/// use websocket::sync::Server;
+	///
+	/// let mut server = Server::bind("127.0.0.1:1234").unwrap();
+	/// let upgrade = server.accept().ok().unwrap();
+	/// let mut headers = Headers::new();
+	/// headers.set(ContentType::json());
+	/// let body = br#"{"error":"invalid token"}"#;
+	/// upgrade.reject_with_response(StatusCode::Unauthorized, &headers, body).unwrap();
+	/// # }
+	/// ```
+	pub fn reject_with_response(
+		self,
+		status: StatusCode,
+		headers: &Headers,
+		body: &[u8],
+	) -> Result<S, (S, io::Error)> {
+		self.internal_reject(Some(headers), status, body)
 	}
 
-	fn internal_reject(mut self, headers: Option<&Headers>) -> Result<S, (S, io::Error)> {
+	fn internal_reject(
+		mut self,
+		headers: Option<&Headers>,
+		status: StatusCode,
+		body: &[u8],
+	) -> Result<S, (S, io::Error)> {
 		if let Some(custom) = headers {
 			self.headers.extend(custom.iter());
 		}
-		match self.send(StatusCode::BadRequest) {
+		self.headers.set(Connection::close());
+		self.headers.set(ContentLength(body.len() as u64));
+		let result = self
+			.send(status)
+			.and_then(|()| self.stream.write_all(body))
+			.and_then(|()| self.stream.flush());
+		match result {
 			Ok(()) => Ok(self.stream),
 			Err(e) => Err((self.stream, e)),
 		}
@@ -109,6 +266,26 @@ where
 	}
 }
 
+impl<S, B> WsUpgrade<S, B>
+where
+	S: Stream + SocketAddrs,
+{
+	/// The address of the client requesting the upgrade, this can be used to
+	/// decide whether to accept the connection. Streams which are not backed by
+	/// a `TcpStream` fail with an error of kind `Unsupported`.
+	/// See [`TcpStream::peer_addr`]
+	/// (https://doc.rust-lang.org/std/net/struct.TcpStream.html#method.peer_addr).
+	pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+		self.stream.peer_addr()
+	}
+
+	/// See [`TcpStream::local_addr`]
+	/// (https://doc.rust-lang.org/std/net/struct.TcpStream.html#method.local_addr).
+	pub fn local_addr(&self) -> io::Result<SocketAddr> {
+		self.stream.local_addr()
+	}
+}
+
 /// Trait to take a stream or similar and attempt to recover the start of a
 /// websocket handshake from it.
 /// Should be used when a stream might contain a request for a websocket session.
@@ -163,25 +340,48 @@ where
 	type Error = (S, Option<Request>, Option<Buffer>, HyperIntoWsError);
 
 	fn into_ws(self) -> Result<Upgrade<Self::Stream>, Self::Error> {
-		let mut reader = BufReader::new(self);
-		let request = parse_request(&mut reader);
+		into_ws_accepting(self, DEFAULT_VERSIONS)
+	}
+}
 
-		let (stream, buf, pos, cap) = reader.into_parts();
-		let buffer = Some(Buffer { buf, cap, pos });
+/// Like `IntoWs::into_ws` for a stream, but any of `versions` is accepted as
+/// the websocket version of the request.
+pub(crate) fn into_ws_accepting<S>(
+	stream: S,
+	versions: &[WebSocketVersion],
+) -> Result<Upgrade<S>, <S as IntoWs>::Error>
+where
+	S: Stream,
+{
+	let mut reader = BufReader::new(stream);
+	let request = parse_request(&mut reader);
 
-		let request = match request {
-			Ok(r) => r,
-			Err(e) => return Err((stream, None, buffer, e.into())),
-		};
+	let (mut stream, buf, pos, cap) = reader.into_parts();
+	let buffer = Some(Buffer { buf, cap, pos });
 
-		match validate(&request.subject.0, request.version, &request.headers) {
-			Ok(_) => Ok(WsUpgrade {
-				headers: Headers::new(),
-				stream,
-				request,
-				buffer,
-			}),
-			Err(e) => Err((stream, Some(request), buffer, e)),
+	let request = match request {
+		Ok(r) => r,
+		Err(e) => return Err((stream, None, buffer, e.into())),
+	};
+
+	match validate_versions(
+		&request.subject.0,
+		request.version,
+		&request.headers,
+		versions,
+	) {
+		Ok(_) => Ok(WsUpgrade {
+			headers: Headers::new(),
+			stream,
+			request,
+			buffer,
+			slots: Vec::new(),
+			rate_limit: None,
+			strict: false,
+		}),
+		Err(e) => {
+			reject_invalid(&mut stream, &request, &e, versions);
+			Err((stream, Some(request), buffer, e))
 		}
 	}
 }
@@ -193,19 +393,81 @@ where
 	type Stream = S;
 	type Error = (S, Request, HyperIntoWsError);
 
-	fn into_ws(self) -> Result<Upgrade<Self::Stream>, Self::Error> {
+	fn into_ws(mut self) -> Result<Upgrade<Self::Stream>, Self::Error> {
 		match validate(&self.1.subject.0, self.1.version, &self.1.headers) {
 			Ok(_) => Ok(WsUpgrade {
 				headers: Headers::new(),
 				stream: self.0,
 				request: self.1,
 				buffer: None,
+				slots: Vec::new(),
+				rate_limit: None,
+				strict: false,
 			}),
-			Err(e) => Err((self.0, self.1, e)),
+			Err(e) => {
+				reject_invalid(&mut self.0, &self.1, &e, DEFAULT_VERSIONS);
+				Err((self.0, self.1, e))
+			}
 		}
 	}
 }
 
+/// Accept a websocket handshake that was already parsed, e.g. by your own hyper
+/// routing, and answer it on `stream`.
+///
+/// The request must be a valid handshake: a `GET` request with the `Upgrade: websocket`
+/// and `Connection: Upgrade` headers, a `Sec-WebSocket-Key` and version 13, the
+/// `Sec-WebSocket-Accept` header of the response is computed from the key.
+/// If the request is not a handshake or the response could not be sent the stream
+/// is returned along with the error.
+/// A handshake which is missing a header or asks for an unsupported version has
+/// already been answered with a `400 Bad Request` or `426 Upgrade Required` then,
+/// so nothing else must be written to the stream. Other requests, e.g. ones which
+/// are not a handshake attempt at all, are left unanswered, see `HyperIntoWsError`.
+///
+/// ```rust,no_run
+/// # extern crate hyper;
+/// # extern crate websocket;
+/// # fn main() {
+/// use hyper::buffer::BufReader;
+/// use hyper::http::h1::parse_request;
+/// use std::net::TcpListener;
+/// use websocket::sync::server::upgrade::accept_request;
+///
+/// let listener = TcpListener::bind("127.0.0.1:1234").unwrap();
+/// let (stream, _) = listener.accept().unwrap();
+/// let mut reader = BufReader::new(stream);
+/// let request = parse_request(&mut reader).unwrap();
+/// let mut client = accept_request(reader.into_inner(), request).ok().unwrap();
+/// # }
+/// ```
+pub fn accept_request<S>(stream: S, request: Request) -> Result<Client<S>, (S, HyperIntoWsError)>
+where
+	S: Stream,
+{
+	match RequestStreamPair(stream, request).into_ws() {
+		Ok(upgrade) => upgrade.accept().map_err(|(s, e)| (s, e.into())),
+		Err((stream, _, e)) => Err((stream, e)),
+	}
+}
+
+// some invalid requests are answered right away, the error
+// is returned either way so a failed write is not reported
+fn reject_invalid<S>(
+	stream: &mut S,
+	request: &Request,
+	error: &HyperIntoWsError,
+	versions: &[WebSocketVersion],
+) where
+	S: Write,
+{
+	if let Some(response) = error_response(request.version, &request.headers, error, versions) {
+		let _ = stream
+			.write_all(response.as_bytes())
+			.and_then(|()| stream.flush());
+	}
+}
+
 /// Upgrade a hyper connection to a websocket one.
 ///
 /// A hyper request is implicitly defined as a stream from other `impl`s of Stream.
@@ -272,6 +534,376 @@ impl<'a, 'b> IntoWs for HyperRequest<'a, 'b> {
 				headers,
 				subject: (method, uri),
 			},
+			slots: Vec::new(),
+			rate_limit: None,
+			strict: false,
 		})
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use header::WebSocketProtocol;
+	use result::WebSocketError;
+	use std::io::Cursor;
+	use stream::sync::ReadWritePair;
+
+	type TestStream = ReadWritePair<Cursor<Vec<u8>>, Cursor<Vec<u8>>>;
+
+	fn upgrade(protocols: &str) -> Upgrade<TestStream> {
+		upgrade_with(&format!("Sec-WebSocket-Protocol: {}\r\n", protocols))
+	}
+
+	// an upgrade request with some extra header lines
+	fn upgrade_with(headers: &str) -> Upgrade<TestStream> {
+		let request = format!(
+			"GET / HTTP/1.1\r\n\
+			 Host: localhost\r\n\
+			 Upgrade: websocket\r\n\
+			 Connection: Upgrade\r\n\
+			 Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+			 Sec-WebSocket-Version: 13\r\n\
+			 {}\r\n",
+			headers
+		);
+		let stream = ReadWritePair(Cursor::new(request.into_bytes()), Cursor::new(Vec::new()));
+		match stream.into_ws() {
+			Ok(upgrade) => upgrade,
+			Err(_) => panic!("invalid upgrade request"),
+		}
+	}
+
+	#[test]
+	fn accept_with_selected_protocol() {
+		let upgrade = upgrade("chat, superchat");
+		let client = match upgrade.accept_with_protocol(|offered| {
+			assert_eq!(offered, &["chat".to_string(), "superchat".to_string()]);
+			Some("chat".to_string())
+		}) {
+			Ok(client) => client,
+			Err((_, e)) => panic!("{}", e),
+		};
+		assert_eq!(
+			client.headers().get::<WebSocketProtocol>(),
+			Some(&WebSocketProtocol(vec!["chat".to_string()]))
+		);
+	}
+
+	#[test]
+	fn accept_without_matching_protocol() {
+		let client = match upgrade("chat, superchat")
+			.accept_with_protocol(|offered| offered.iter().find(|p| *p == "graphql-ws").cloned())
+		{
+			Ok(client) => client,
+			Err((_, e)) => panic!("{}", e),
+		};
+		assert!(client.headers().get::<WebSocketProtocol>().is_none());
+
+		match upgrade("chat").accept_with_protocol(|_| Some("graphql-ws".to_string())) {
+			Err((stream, ref e)) if e.kind() == io::ErrorKind::InvalidInput => {
+				assert!(stream.1.into_inner().is_empty());
+			}
+			_ => panic!("a protocol that was not offered must be refused"),
+		}
+	}
+
+	fn response(stream: TestStream) -> String {
+		String::from_utf8(stream.1.into_inner()).unwrap()
+	}
+
+	#[test]
+	fn accept_allowed_origin() {
+		let upgrade = upgrade_with("Origin: https://example.com\r\n");
+		let allowed = |origin: Option<&str>| origin == Some("https://example.com");
+		match upgrade.accept_if_origin(allowed) {
+			Ok(client) => {
+				let (stream, _) = client.into_stream();
+				assert!(response(stream).starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+			}
+			Err((_, e)) => panic!("{}", e),
+		}
+	}
+
+	#[test]
+	fn reject_disallowed_origin() {
+		for origin in &["Origin: https://evil.example\r\n", "Origin: null\r\n"] {
+			let upgrade = upgrade_with(origin);
+			match upgrade.accept_if_origin(|origin| origin == Some("https://example.com")) {
+				Err((stream, ref e)) if e.kind() == io::ErrorKind::PermissionDenied => {
+					let response = response(stream);
+					assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+					assert!(response.contains("Connection: close\r\n"));
+				}
+				_ => panic!("{} must be rejected", origin),
+			}
+		}
+	}
+
+	#[test]
+	fn accept_if_origin_without_origin() {
+		let mut seen = Some("unset");
+		let result = upgrade("chat").accept_if_origin(|origin| {
+			seen = origin.map(|_| "set");
+			false
+		});
+		assert_eq!(seen, None);
+		assert!(result.is_err());
+
+		assert!(upgrade("chat")
+			.accept_if_origin(|origin| origin.is_none())
+			.is_ok());
+	}
+
+	#[test]
+	fn unsupported_version_is_answered_with_426() {
+		let request = "GET / HTTP/1.1\r\n\
+		               Host: localhost\r\n\
+		               Upgrade: websocket\r\n\
+		               Connection: Upgrade\r\n\
+		               Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+		               Sec-WebSocket-Version: 8\r\n\r\n";
+		let stream = ReadWritePair(Cursor::new(request.into()), Cursor::new(Vec::new()));
+		match stream.into_ws() {
+			Err((stream, Some(_), _, HyperIntoWsError::UnsupportedWebsocketVersion)) => {
+				assert_eq!(
+					response(stream),
+					"HTTP/1.1 426 Upgrade Required\r\n\
+					 Sec-WebSocket-Version: 13\r\n\
+					 Connection: close\r\n\
+					 Content-Length: 0\r\n\r\n"
+				);
+			}
+			_ => panic!("version 8 must be refused"),
+		}
+	}
+
+	#[test]
+	fn accepted_versions_are_configurable() {
+		let request = |version| {
+			let request = format!(
+				"GET / HTTP/1.1\r\n\
+				 Host: localhost\r\n\
+				 Upgrade: websocket\r\n\
+				 Connection: Upgrade\r\n\
+				 Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+				 Sec-WebSocket-Version: {}\r\n\r\n",
+				version
+			);
+			ReadWritePair(Cursor::new(request.into_bytes()), Cursor::new(Vec::new()))
+		};
+		let versions = [WebSocketVersion::WebSocket13, WebSocketVersion::from(8)];
+
+		let upgrade = into_ws_accepting(request(8), &versions).ok().unwrap();
+		assert_eq!(upgrade.version(), Some(&WebSocketVersion::from(8)));
+		match into_ws_accepting(request(7), &versions) {
+			Err((stream, Some(_), _, HyperIntoWsError::UnsupportedWebsocketVersion)) => {
+				assert_eq!(
+					response(stream),
+					"HTTP/1.1 426 Upgrade Required\r\n\
+					 Sec-WebSocket-Version: 13, 8\r\n\
+					 Connection: close\r\n\
+					 Content-Length: 0\r\n\r\n"
+				);
+			}
+			_ => panic!("version 7 must be refused"),
+		}
+	}
+
+	#[test]
+	fn malformed_protocol_headers_are_answered_with_400() {
+		assert_eq!(
+			upgrade("chat,superchat, v2.chat_json").protocols(),
+			&["chat", "superchat", "v2.chat_json"]
+		);
+
+		for protocols in &["chat, super chat", "chat, v1/json", "\"chat\"", "", " , "] {
+			let request = format!(
+				"GET / HTTP/1.1\r\n\
+				 Host: localhost\r\n\
+				 Upgrade: websocket\r\n\
+				 Connection: Upgrade\r\n\
+				 Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+				 Sec-WebSocket-Version: 13\r\n\
+				 Sec-WebSocket-Protocol: {}\r\n\r\n",
+				protocols
+			);
+			let stream = ReadWritePair(Cursor::new(request.into_bytes()), Cursor::new(Vec::new()));
+			match stream.into_ws() {
+				Err((stream, Some(_), _, HyperIntoWsError::InvalidProtocolHeader)) => {
+					assert_eq!(
+						response(stream),
+						"HTTP/1.1 400 Bad Request\r\n\
+						 Connection: close\r\n\
+						 Content-Length: 0\r\n\r\n"
+					);
+				}
+				_ => panic!("{:?} must be refused", protocols),
+			}
+		}
+	}
+
+	#[test]
+	fn reject_with_status_and_body() {
+		let mut headers = Headers::new();
+		headers.set_raw("Content-Type", vec![b"application/json".to_vec()]);
+		let body = br#"{"error":"invalid token"}"#;
+		let stream = upgrade_with("")
+			.reject_with_response(StatusCode::Unauthorized, &headers, body)
+			.ok()
+			.unwrap();
+		assert_eq!(
+			response(stream),
+			"HTTP/1.1 401 Unauthorized\r\n\
+			 Content-Type: application/json\r\n\
+			 Connection: close\r\n\
+			 Content-Length: 25\r\n\r\n\
+			 {\"error\":\"invalid token\"}"
+		);
+	}
+
+	fn parse(request: &str) -> Request {
+		let mut reader = BufReader::new(Cursor::new(request.as_bytes()));
+		parse_request(&mut reader).unwrap()
+	}
+
+	#[test]
+	fn accept_parsed_request() {
+		let request = parse(
+			"GET /chat HTTP/1.1\r\n\
+			 Host: localhost\r\n\
+			 Upgrade: websocket\r\n\
+			 Connection: keep-alive, Upgrade\r\n\
+			 Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+			 Sec-WebSocket-Version: 13\r\n\r\n",
+		);
+		let stream = ReadWritePair(Cursor::new(Vec::new()), Cursor::new(Vec::new()));
+		let client = accept_request(stream, request).ok().unwrap();
+		let response = response(client.into_stream().0);
+		assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+		assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+	}
+
+	#[test]
+	fn accept_request_without_upgrade() {
+		let request = parse(
+			"GET /chat HTTP/1.1\r\n\
+			 Host: localhost\r\n\
+			 Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+			 Sec-WebSocket-Version: 13\r\n\r\n",
+		);
+		let stream = ReadWritePair(Cursor::new(Vec::new()), Cursor::new(Vec::new()));
+		match accept_request(stream, request) {
+			Err((stream, HyperIntoWsError::NoUpgradeHeader)) => {
+				assert!(response(stream).starts_with("HTTP/1.1 400 Bad Request\r\n"));
+			}
+			_ => panic!("a request without upgrade must not be upgraded"),
+		}
+
+		let request = parse("GET /chat HTTP/1.1\r\nHost: localhost\r\n\r\n");
+		let stream = ReadWritePair(Cursor::new(Vec::new()), Cursor::new(Vec::new()));
+		match accept_request(stream, request) {
+			Err((stream, HyperIntoWsError::NoSecWsKeyHeader)) => {
+				assert_eq!(response(stream), "");
+			}
+			_ => panic!("a plain request must not be upgraded"),
+		}
+	}
+
+	#[test]
+	fn missing_headers_are_answered_with_400() {
+		let handshake = [
+			"Upgrade: websocket\r\n",
+			"Connection: Upgrade\r\n",
+			"Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n",
+		];
+		let cases = [
+			(0, "Missing Upgrade WebSocket header"),
+			(1, "Missing Connection WebSocket header"),
+			(2, "Missing Sec-WebSocket-Key header"),
+		];
+		for &(missing, message) in &cases {
+			let mut request = "GET / HTTP/1.1\r\nHost: localhost\r\n".to_string();
+			for (i, header) in handshake.iter().enumerate() {
+				if i != missing {
+					request.push_str(header);
+				}
+			}
+			request.push_str("Sec-WebSocket-Version: 13\r\n\r\n");
+			let stream = ReadWritePair(Cursor::new(request.into_bytes()), Cursor::new(Vec::new()));
+			match stream.into_ws() {
+				Err((stream, Some(_), _, e)) => {
+					assert_eq!(
+						response(stream),
+						format!(
+							"HTTP/1.1 400 Bad Request\r\n\
+							 Content-Type: text/plain; charset=utf-8\r\n\
+							 Connection: close\r\n\
+							 Content-Length: {}\r\n\r\n{}",
+							message.len(),
+							message
+						)
+					);
+					match WebSocketError::from(e) {
+						WebSocketError::ProtocolError(m) => assert_eq!(m, message),
+						e => panic!("expected a protocol error, got {:?}", e),
+					}
+				}
+				_ => panic!("a request without {:?} must be refused", handshake[missing]),
+			}
+		}
+	}
+
+	#[cfg(feature = "deflate")]
+	#[test]
+	fn deflate_without_context_takeover() {
+		use deflate::{Decompressor, DeflateConfig};
+		use message::OwnedMessage;
+		use ws::util::header::{read_header, DataFrameFlags};
+
+		let config = DeflateConfig {
+			server_no_context_takeover: true,
+			client_no_context_takeover: true,
+			server_max_window_bits: 10,
+			client_max_window_bits: 10,
+		};
+		let upgrade = upgrade_with(
+			"Sec-WebSocket-Extensions: permessage-deflate; client_max_window_bits\r\n",
+		);
+		let mut client = match upgrade.enable_deflate_with(&config).accept() {
+			Ok(client) => client,
+			Err((_, e)) => panic!("{}", e),
+		};
+		let text = "a message, ".repeat(8);
+		let message = OwnedMessage::Text(text.clone());
+		for _ in 0..3 {
+			client.send_message(&message).unwrap();
+		}
+
+		let (stream, _) = client.into_stream();
+		let written = stream.1.into_inner();
+		let end = written.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+		let response = String::from_utf8(written[..end].to_vec()).unwrap();
+		assert!(response.contains(
+			"Sec-WebSocket-Extensions: permessage-deflate; server_no_context_takeover; \
+			 client_no_context_takeover; server_max_window_bits=10; client_max_window_bits=10\r\n"
+		));
+
+		// every message can be inflated on its own
+		let mut frames = &written[end..];
+		let mut payloads = Vec::new();
+		while !frames.is_empty() {
+			let header = read_header(&mut frames).unwrap();
+			assert!(header.flags.contains(DataFrameFlags::RSV1));
+			let (payload, rest) = frames.split_at(header.len as usize);
+			let inflated = Decompressor::new(10, false)
+				.decompress(payload, None)
+				.unwrap();
+			assert_eq!(inflated, text.as_bytes());
+			payloads.push(payload.to_vec());
+			frames = rest;
+		}
+		assert_eq!(payloads.len(), 3);
+		assert!(payloads.iter().all(|p| *p == payloads[0]));
+	}
+}
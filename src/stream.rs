@@ -2,6 +2,8 @@
 
 use std::fmt::Arguments;
 use std::io::{self, Read, Write};
+#[cfg(any(feature = "sync", feature = "async"))]
+use std::time::Duration;
 
 /// Represents a stream that can be read from, and written to.
 /// This is an abstraction around readable and writable things to be able
@@ -9,6 +11,27 @@ use std::io::{self, Read, Write};
 pub trait Stream: Read + Write {}
 impl<S> Stream for S where S: Read + Write {}
 
+/// The socket options set on every TCP connection a client opens or a server
+/// accepts, see `ClientBuilder::nodelay` and `ClientBuilder::keepalive`.
+#[cfg(any(feature = "sync", feature = "async"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct TcpOptions {
+	/// Set `TCP_NODELAY`, on by default.
+	pub nodelay: bool,
+	/// Set `SO_KEEPALIVE` with this idle time, off by default.
+	pub keepalive: Option<Duration>,
+}
+
+#[cfg(any(feature = "sync", feature = "async"))]
+impl Default for TcpOptions {
+	fn default() -> Self {
+		TcpOptions {
+			nodelay: true,
+			keepalive: None,
+		}
+	}
+}
+
 /// If you would like to combine an input stream and an output stream into a single
 /// stream to talk websockets over then this is the struct for you!
 ///
@@ -80,6 +103,14 @@ pub mod async {
 	pub trait Stream: AsyncRead + AsyncWrite {}
 	impl<S> Stream for S where S: AsyncRead + AsyncWrite {}
 
+	impl super::TcpOptions {
+		/// Set the options on a connected stream.
+		pub(crate) fn apply_async(&self, stream: &TcpStream) -> io::Result<()> {
+			stream.set_nodelay(self.nodelay)?;
+			stream.set_keepalive(self.keepalive)
+		}
+	}
+
 	impl<R, W> AsyncRead for ReadWritePair<R, W>
 	where
 		R: AsyncRead,
@@ -104,10 +135,20 @@ pub mod sync {
 	pub use super::ReadWritePair;
 	#[cfg(feature = "sync-ssl")]
 	pub use native_tls::TlsStream;
+	use socket2::{SockRef, TcpKeepalive};
+	use std::collections::VecDeque;
 	use std::io::{self, Read, Write};
 	pub use std::net::Shutdown;
+	use std::net::SocketAddr;
 	pub use std::net::TcpStream;
 	use std::ops::Deref;
+	#[cfg(unix)]
+	pub use std::os::unix::net::UnixStream;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::sync::{Arc, Condvar, Mutex};
+	use std::time::{Duration, Instant};
+	#[cfg(all(feature = "sync-rustls", not(feature = "native-tls")))]
+	pub use tls::{TlsServerStream, TlsStream};
 
 	pub use super::Stream;
 
@@ -155,6 +196,28 @@ pub mod sync {
 		}
 	}
 
+	#[cfg(unix)]
+	impl Splittable for UnixStream {
+		type Reader = UnixStream;
+		type Writer = UnixStream;
+
+		fn split(self) -> io::Result<(UnixStream, UnixStream)> {
+			self.try_clone().map(|s| (s, self))
+		}
+	}
+
+	impl super::TcpOptions {
+		/// Set the options on a connected stream.
+		pub(crate) fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+			stream.set_nodelay(self.nodelay)?;
+			let socket = SockRef::from(stream);
+			match self.keepalive {
+				Some(idle) => socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(idle)),
+				None => socket.set_keepalive(false),
+			}
+		}
+	}
+
 	/// The ability access a borrow to an underlying TcpStream,
 	/// so one can set options on the stream such as `nonblocking`.
 	pub trait AsTcpStream {
@@ -175,6 +238,20 @@ pub mod sync {
 		}
 	}
 
+	#[cfg(all(feature = "sync-rustls", not(feature = "native-tls")))]
+	impl AsTcpStream for TlsStream<TcpStream> {
+		fn as_tcp(&self) -> &TcpStream {
+			self.get_ref()
+		}
+	}
+
+	#[cfg(all(feature = "sync-rustls", not(feature = "native-tls")))]
+	impl AsTcpStream for TlsServerStream<TcpStream> {
+		fn as_tcp(&self) -> &TcpStream {
+			self.get_ref()
+		}
+	}
+
 	impl<T> AsTcpStream for Box<T>
 	where
 		T: AsTcpStream + ?Sized,
@@ -183,4 +260,282 @@ pub mod sync {
 			self.deref().as_tcp()
 		}
 	}
+
+	/// The addresses of the socket behind a stream. Every `AsTcpStream` has them,
+	/// other transports fail with an error of kind `Unsupported`. A custom stream
+	/// can implement this trait without any methods to get these errors.
+	pub trait SocketAddrs {
+		/// The address of the remote end of the connection.
+		fn peer_addr(&self) -> io::Result<SocketAddr> {
+			Err(not_a_socket())
+		}
+
+		/// The address of the local end of the connection.
+		fn local_addr(&self) -> io::Result<SocketAddr> {
+			Err(not_a_socket())
+		}
+	}
+
+	impl<T> SocketAddrs for T
+	where
+		T: AsTcpStream + ?Sized,
+	{
+		fn peer_addr(&self) -> io::Result<SocketAddr> {
+			self.as_tcp().peer_addr()
+		}
+
+		fn local_addr(&self) -> io::Result<SocketAddr> {
+			self.as_tcp().local_addr()
+		}
+	}
+
+	// Unix sockets have addresses, but not ones of the internet
+	#[cfg(unix)]
+	impl SocketAddrs for UnixStream {}
+
+	impl<R, W> SocketAddrs for ReadWritePair<R, W>
+	where
+		R: Read,
+		W: Write,
+	{
+	}
+
+	impl SocketAddrs for DuplexStream {}
+
+	fn not_a_socket() -> io::Error {
+		io::Error::new(
+			io::ErrorKind::Unsupported,
+			"the stream is not backed by a TCP socket",
+		)
+	}
+
+	// one direction of a `DuplexStream`
+	#[derive(Default)]
+	struct Pipe {
+		buffer: Mutex<(VecDeque<u8>, bool)>,
+		readable: Condvar,
+	}
+
+	impl Pipe {
+		fn close(&self) {
+			let mut buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+			buffer.1 = true;
+			self.readable.notify_all();
+		}
+	}
+
+	/// An in-memory stream connected to another one, what is written to one end
+	/// can be read from the other. This allows to speak websockets without any
+	/// sockets, e.g. in tests.
+	///
+	/// Reads block until data is available or the other end was dropped, which
+	/// lets reads return `Ok(0)` and writes fail with `BrokenPipe`.
+	/// Cloning a stream gives another handle to the same end, which is only
+	/// closed once all of its handles are dropped.
+	///
+	/// ```rust
+	/// use std::thread;
+	/// use websocket::sync::stream::DuplexStream;
+	/// use websocket::sync::server::upgrade::IntoWs;
+	/// use websocket::{ClientBuilder, OwnedMessage};
+	///
+	/// let (client, server) = DuplexStream::pair();
+	/// let peer = thread::spawn(move || {
+	///     let mut server = server.into_ws().ok().unwrap().accept().ok().unwrap();
+	///     let message = server.recv_message().unwrap();
+	///     server.send_message(&message).unwrap();
+	/// });
+	///
+	/// let mut client = ClientBuilder::new("ws://localhost").unwrap()
+	///     .connect_on(client)
+	///     .unwrap();
+	/// client.send_message(&OwnedMessage::Text("ping".to_string())).unwrap();
+	/// assert_eq!(client.recv_message().unwrap(), OwnedMessage::Text("ping".to_string()));
+	/// # peer.join().unwrap();
+	/// ```
+	pub struct DuplexStream {
+		incoming: Arc<Pipe>,
+		outgoing: Arc<Pipe>,
+		handles: Arc<AtomicUsize>,
+		read_timeout: Option<Duration>,
+	}
+
+	impl DuplexStream {
+		/// Create two streams connected to each other.
+		pub fn pair() -> (DuplexStream, DuplexStream) {
+			let first = Arc::new(Pipe::default());
+			let second = Arc::new(Pipe::default());
+			let a = DuplexStream {
+				incoming: first.clone(),
+				outgoing: second.clone(),
+				handles: Arc::new(AtomicUsize::new(1)),
+				read_timeout: None,
+			};
+			let b = DuplexStream {
+				incoming: second,
+				outgoing: first,
+				handles: Arc::new(AtomicUsize::new(1)),
+				read_timeout: None,
+			};
+			(a, b)
+		}
+
+		/// Reads fail with `TimedOut` if no data arrived within `timeout`,
+		/// `None` (the default) waits forever.
+		pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+			self.read_timeout = timeout;
+		}
+
+		/// The read timeout of this handle.
+		pub fn read_timeout(&self) -> Option<Duration> {
+			self.read_timeout
+		}
+
+		/// Close the given directions of the stream for all handles of this end.
+		pub fn shutdown(&self, how: Shutdown) {
+			if how != Shutdown::Write {
+				self.incoming.close();
+			}
+			if how != Shutdown::Read {
+				self.outgoing.close();
+			}
+		}
+	}
+
+	impl Clone for DuplexStream {
+		fn clone(&self) -> Self {
+			self.handles.fetch_add(1, Ordering::SeqCst);
+			DuplexStream {
+				incoming: self.incoming.clone(),
+				outgoing: self.outgoing.clone(),
+				handles: self.handles.clone(),
+				read_timeout: self.read_timeout,
+			}
+		}
+	}
+
+	impl Drop for DuplexStream {
+		fn drop(&mut self) {
+			if self.handles.fetch_sub(1, Ordering::SeqCst) == 1 {
+				self.shutdown(Shutdown::Both);
+			}
+		}
+	}
+
+	impl Read for DuplexStream {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			let deadline = self.read_timeout.map(|t| Instant::now() + t);
+			let pipe = &self.incoming;
+			let mut buffer = pipe.buffer.lock().unwrap_or_else(|e| e.into_inner());
+			while buffer.0.is_empty() && !buffer.1 && !buf.is_empty() {
+				buffer = match deadline {
+					Some(deadline) => {
+						let now = Instant::now();
+						if now >= deadline {
+							return Err(io::Error::new(io::ErrorKind::TimedOut, "read timed out"));
+						}
+						let wait = pipe.readable.wait_timeout(buffer, deadline - now);
+						wait.unwrap_or_else(|e| e.into_inner()).0
+					}
+					None => pipe
+						.readable
+						.wait(buffer)
+						.unwrap_or_else(|e| e.into_inner()),
+				};
+			}
+			let len = buf.len().min(buffer.0.len());
+			for (byte, b) in buf.iter_mut().zip(buffer.0.drain(..len)) {
+				*byte = b;
+			}
+			Ok(len)
+		}
+	}
+
+	impl Write for DuplexStream {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			let pipe = &self.outgoing;
+			let mut buffer = pipe.buffer.lock().unwrap_or_else(|e| e.into_inner());
+			if buffer.1 {
+				return Err(io::Error::new(
+					io::ErrorKind::BrokenPipe,
+					"the stream was closed",
+				));
+			}
+			buffer.0.extend(buf);
+			pipe.readable.notify_all();
+			Ok(buf.len())
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	impl Splittable for DuplexStream {
+		type Reader = DuplexStream;
+		type Writer = DuplexStream;
+
+		fn split(self) -> io::Result<(DuplexStream, DuplexStream)> {
+			Ok((self.clone(), self))
+		}
+	}
+
+	#[cfg(test)]
+	mod tests {
+		use super::*;
+		use client::builder::ClientBuilder;
+		use message::OwnedMessage;
+		use server::upgrade::sync::IntoWs;
+		use std::thread;
+
+		#[test]
+		fn echo_over_pipes() {
+			let (client, server) = DuplexStream::pair();
+			let peer = thread::spawn(move || {
+				let upgrade = server.into_ws().ok().unwrap();
+				let mut server = upgrade.accept().ok().unwrap();
+				loop {
+					match server.recv_message().unwrap() {
+						OwnedMessage::Close(data) => {
+							server.send_message(&OwnedMessage::Close(data)).unwrap();
+							return;
+						}
+						message => server.send_message(&message).unwrap(),
+					}
+				}
+			});
+
+			let client = ClientBuilder::new("ws://localhost/echo")
+				.unwrap()
+				.connect_on(client)
+				.unwrap();
+			let (mut receiver, mut sender) = client.split().unwrap();
+			let message = OwnedMessage::Binary(vec![7; 70_000]);
+			sender.send_message(&message).unwrap();
+			assert_eq!(receiver.recv_message().unwrap(), message);
+			sender.send_message(&OwnedMessage::Close(None)).unwrap();
+			assert_eq!(receiver.recv_message().unwrap(), OwnedMessage::Close(None));
+			peer.join().unwrap();
+
+			// the server's end is gone
+			assert_eq!(receiver.stream.read(&mut [0; 1]).unwrap(), 0);
+		}
+
+		#[test]
+		fn read_timeout() {
+			let (mut a, mut b) = DuplexStream::pair();
+			a.set_read_timeout(Some(Duration::from_millis(10)));
+			let err = a.read(&mut [0; 4]).unwrap_err();
+			assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+			b.write_all(b"data").unwrap();
+			let mut buf = [0; 8];
+			assert_eq!(a.read(&mut buf).unwrap(), 4);
+			assert_eq!(&buf[..4], b"data");
+
+			drop(a);
+			let err = b.write(b"gone").unwrap_err();
+			assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+		}
+	}
 }
@@ -0,0 +1,122 @@
+//! Connecting a client and a server in memory, e.g. for tests.
+//!
+//! The two ends talk over a `DuplexStream`, so no sockets are bound and tests
+//! can't be disturbed by ports in use, firewalls or slow networks. The real
+//! opening handshake is run, including checking the `Sec-WebSocket-Accept` key,
+//! so both ends negotiate protocols and extensions like they would over TCP.
+//!
+//! ```rust
+//! use std::thread;
+//! use websocket::testing;
+//! use websocket::OwnedMessage;
+//!
+//! let (mut client, mut server) = testing::pair().unwrap();
+//! let echo = thread::spawn(move || {
+//!     let message = server.recv_message().unwrap();
+//!     server.send_message(&message).unwrap();
+//! });
+//!
+//! let message = OwnedMessage::Text("hello".to_string());
+//! client.send_message(&message).unwrap();
+//! assert_eq!(client.recv_message().unwrap(), message);
+//! echo.join().unwrap();
+//! ```
+
+use client::builder::ClientBuilder;
+use client::sync::Client;
+use result::WebSocketResult;
+use server::upgrade::sync::{IntoWs, Upgrade};
+use std::io;
+use std::panic;
+use std::thread;
+use stream::sync::DuplexStream;
+
+/// A client connected to `ws://localhost/` and the server's end of the
+/// connection, the server accepts the handshake as it is.
+pub fn pair() -> WebSocketResult<(Client<DuplexStream>, Client<DuplexStream>)> {
+	let mut builder = ClientBuilder::new("ws://localhost/")?;
+	connect(&mut builder, Upgrade::accept)
+}
+
+/// Run the handshake described by `builder` against a server which answers
+/// the upgrade request with `accept`, e.g. to pick a protocol or to reject the
+/// request. The client and the server's end of the connection are returned.
+///
+/// The server runs on another thread until the handshake is over, if it
+/// rejects the request the error of the client is returned.
+pub fn connect<F>(
+	builder: &mut ClientBuilder,
+	accept: F,
+) -> WebSocketResult<(Client<DuplexStream>, Client<DuplexStream>)>
+where
+	F: FnOnce(Upgrade<DuplexStream>) -> Result<Client<DuplexStream>, (DuplexStream, io::Error)>,
+	F: Send + 'static,
+{
+	let (client, server) = DuplexStream::pair();
+	let server = thread::spawn(move || -> WebSocketResult<Client<DuplexStream>> {
+		let upgrade = server.into_ws().map_err(|(_, _, _, e)| e)?;
+		accept(upgrade).map_err(|(_, e)| e.into())
+	});
+
+	let client = builder.connect_on(client);
+	let server = server.join().unwrap_or_else(|e| panic::resume_unwind(e));
+	let client = client?;
+	Ok((client, server?))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use client::builder::ClientBuilder;
+	use hyper::status::StatusCode;
+	use message::OwnedMessage;
+	use result::WebSocketError;
+
+	#[test]
+	fn handshake_and_echo() {
+		let mut builder = ClientBuilder::new("ws://localhost/chat")
+			.unwrap()
+			.add_protocol("chat");
+		let (mut client, mut server) = connect(&mut builder, |upgrade| {
+			assert_eq!(upgrade.request.subject.1.to_string(), "/chat");
+			upgrade.use_protocol("chat").accept()
+		})
+		.unwrap();
+		assert_eq!(client.protocols(), ["chat".to_string()]);
+
+		let echo = thread::spawn(move || loop {
+			match server.recv_message().unwrap() {
+				OwnedMessage::Close(data) => {
+					server.send_message(&OwnedMessage::Close(data)).unwrap();
+					return;
+				}
+				message => server.send_message(&message).unwrap(),
+			}
+		});
+		for message in &[
+			OwnedMessage::Text("hello".to_string()),
+			OwnedMessage::Binary(vec![1; 100_000]),
+		] {
+			client.send_message(message).unwrap();
+			assert_eq!(&client.recv_message().unwrap(), message);
+		}
+		client.send_message(&OwnedMessage::Close(None)).unwrap();
+		assert_eq!(client.recv_message().unwrap(), OwnedMessage::Close(None));
+		echo.join().unwrap();
+	}
+
+	#[test]
+	fn rejected_handshake() {
+		let mut builder = ClientBuilder::new("ws://localhost/").unwrap();
+		match connect(&mut builder, |upgrade| {
+			let stream = upgrade.reject().ok().unwrap();
+			Err((
+				stream,
+				io::Error::new(io::ErrorKind::ConnectionRefused, "rejected"),
+			))
+		}) {
+			Err(WebSocketError::StatusCodeError(StatusCode::BadRequest)) => {}
+			other => panic!("expected 400, got {:?}", other.map(|_| ())),
+		}
+	}
+}
@@ -0,0 +1,309 @@
+//! TLS for synchronous connections backed by `rustls`.
+//!
+//! This is used when the `sync-rustls` feature is enabled and `native-tls` is not,
+//! if `native-tls` is enabled (through `sync-ssl` or `async-ssl`) it takes precedence
+//! and this module is not compiled.
+//!
+//! The types here replace their `native-tls` counterparts in the synchronous API,
+//! e.g. `ClientBuilder::connect` takes an optional `TlsConnector` and
+//! `sync::Server::bind_secure` takes a `TlsAcceptor`.
+//!
+//! ```rust,no_run
+//! # extern crate websocket;
+//! # fn main() {
+//! use std::sync::Arc;
+//! use websocket::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+//! use websocket::rustls::ServerConfig;
+//! use websocket::sync::Server;
+//! # let certs: Vec<CertificateDer<'static>> = vec![];
+//! # let key: PrivateKeyDer<'static> = unimplemented!();
+//!
+//! let config = ServerConfig::builder()
+//!     .with_no_client_auth()
+//!     .with_single_cert(certs, key)
+//!     .unwrap();
+//!
+//! let server = Server::bind_secure("127.0.0.1:1234", Arc::new(config)).unwrap();
+//! # }
+//! ```
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, ring, WebPkiSupportedAlgorithms};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{
+	ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, ServerConfig,
+	ServerConnection, SignatureScheme, StreamOwned,
+};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+/// The configuration used to open TLS connections to servers.
+pub type TlsConnector = Arc<ClientConfig>;
+
+/// The configuration used to accept TLS connections from clients.
+pub type TlsAcceptor = Arc<ServerConfig>;
+
+/// A TLS connection to a server.
+pub type TlsStream<S> = StreamOwned<ClientConnection, S>;
+
+/// A TLS connection accepted from a client.
+pub type TlsServerStream<S> = StreamOwned<ServerConnection, S>;
+
+/// Create the configuration used when no `TlsConnector` is given, servers
+/// are verified against the Mozilla root certificates from `webpki-roots`.
+///
+/// **Warning:** with `accept_invalid_certs` the server's certificate is not
+/// verified at all, see `ClientBuilder::danger_accept_invalid_certs`.
+pub fn default_connector(accept_invalid_certs: bool) -> io::Result<TlsConnector> {
+	client_connector(accept_invalid_certs, None)
+}
+
+/// Create the configuration used when no `TlsConnector` is given like
+/// `default_connector`, presenting the PEM encoded client certificate chain
+/// and private key `identity` to servers which ask for one.
+pub fn client_connector(
+	accept_invalid_certs: bool,
+	identity: Option<(&[u8], &[u8])>,
+) -> io::Result<TlsConnector> {
+	let provider = Arc::new(ring::default_provider());
+	let builder = ClientConfig::builder_with_provider(provider.clone())
+		.with_safe_default_protocol_versions()
+		.map_err(tls_error)?;
+	let builder = if accept_invalid_certs {
+		let verifier = AcceptAnyCert(provider.signature_verification_algorithms);
+		builder
+			.dangerous()
+			.with_custom_certificate_verifier(Arc::new(verifier))
+	} else {
+		let roots = RootCertStore {
+			roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+		};
+		builder.with_root_certificates(roots)
+	};
+	let config = match identity {
+		Some((cert_chain, key)) => {
+			let cert_chain = CertificateDer::pem_slice_iter(cert_chain)
+				.collect::<Result<Vec<_>, _>>()
+				.map_err(pem_error)?;
+			let key = PrivateKeyDer::from_pem_slice(key).map_err(pem_error)?;
+			builder
+				.with_client_auth_cert(cert_chain, key)
+				.map_err(tls_error)?
+		}
+		None => builder.with_no_client_auth(),
+	};
+	Ok(Arc::new(config))
+}
+
+/// Open a TLS connection to the server `domain` over `stream`,
+/// the handshake is completed before this returns.
+pub fn connect<S>(connector: TlsConnector, domain: &str, mut stream: S) -> io::Result<TlsStream<S>>
+where
+	S: Read + Write,
+{
+	// IPv6 hosts are bracketed in URLs
+	let domain = domain.trim_start_matches('[').trim_end_matches(']');
+	let name = ServerName::try_from(domain.to_string())
+		.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+	let mut connection = ClientConnection::new(connector, name).map_err(tls_error)?;
+	if connection.is_handshaking() {
+		connection.complete_io(&mut stream)?;
+	}
+	Ok(StreamOwned::new(connection, stream))
+}
+
+/// Accept a TLS connection from a client over `stream`,
+/// the handshake is completed before this returns.
+pub fn accept<S>(acceptor: &TlsAcceptor, mut stream: S) -> io::Result<TlsServerStream<S>>
+where
+	S: Read + Write,
+{
+	let mut connection = ServerConnection::new(acceptor.clone()).map_err(tls_error)?;
+	if connection.is_handshaking() {
+		connection.complete_io(&mut stream)?;
+	}
+	Ok(StreamOwned::new(connection, stream))
+}
+
+fn tls_error(err: rustls::Error) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+fn pem_error(err: rustls::pki_types::pem::Error) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidInput, err)
+}
+
+/// Whether an error of a TLS stream was caused by the TLS session
+/// rather than the underlying stream.
+pub fn is_session_error(err: &io::Error) -> bool {
+	err.get_ref()
+		.is_some_and(|inner| inner.is::<rustls::Error>())
+}
+
+// verifies handshake signatures but trusts any certificate
+#[derive(Debug)]
+struct AcceptAnyCert(WebPkiSupportedAlgorithms);
+
+impl ServerCertVerifier for AcceptAnyCert {
+	fn verify_server_cert(
+		&self,
+		_end_entity: &CertificateDer<'_>,
+		_intermediates: &[CertificateDer<'_>],
+		_server_name: &ServerName<'_>,
+		_ocsp_response: &[u8],
+		_now: UnixTime,
+	) -> Result<ServerCertVerified, rustls::Error> {
+		Ok(ServerCertVerified::assertion())
+	}
+
+	fn verify_tls12_signature(
+		&self,
+		message: &[u8],
+		cert: &CertificateDer<'_>,
+		dss: &DigitallySignedStruct,
+	) -> Result<HandshakeSignatureValid, rustls::Error> {
+		crypto::verify_tls12_signature(message, cert, dss, &self.0)
+	}
+
+	fn verify_tls13_signature(
+		&self,
+		message: &[u8],
+		cert: &CertificateDer<'_>,
+		dss: &DigitallySignedStruct,
+	) -> Result<HandshakeSignatureValid, rustls::Error> {
+		crypto::verify_tls13_signature(message, cert, dss, &self.0)
+	}
+
+	fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+		self.0.supported_schemes()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use client::builder::{ClientBuilder, TlsIdentity};
+	use message::OwnedMessage;
+	use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+	use server::sync::Server;
+	use std::net::SocketAddr;
+	use std::thread;
+
+	fn certificate() -> CertificateDer<'static> {
+		CertificateDer::from(&include_bytes!("client/fixtures/ws.example.test.der")[..])
+	}
+
+	// an echo server with a certificate for `ws.example.test`
+	fn echo_server() -> SocketAddr {
+		let key = include_bytes!("client/fixtures/ws.example.test.key.der");
+		let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(&key[..]));
+		let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+			.with_safe_default_protocol_versions()
+			.unwrap()
+			.with_no_client_auth()
+			.with_single_cert(vec![certificate()], key.clone_key())
+			.unwrap();
+		let mut server = Server::bind_secure("127.0.0.1:0", Arc::new(config)).unwrap();
+		let addr = server.local_addr().unwrap();
+		thread::spawn(move || loop {
+			let mut client = match server.accept() {
+				Ok(upgrade) => upgrade.accept().unwrap(),
+				Err(_) => continue,
+			};
+			let message = client.recv_message().unwrap();
+			client.send_message(&message).unwrap();
+		});
+		addr
+	}
+
+	fn echo(builder: &mut ClientBuilder, connector: Option<TlsConnector>) {
+		let mut client = builder.connect(connector).unwrap();
+		let message = OwnedMessage::Text("hello over rustls".to_string());
+		client.send_message(&message).unwrap();
+		assert_eq!(client.recv_message().unwrap(), message);
+	}
+
+	#[test]
+	fn round_trip() {
+		let addr = echo_server();
+		let mut roots = RootCertStore::empty();
+		roots.add(certificate()).unwrap();
+		let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+			.with_safe_default_protocol_versions()
+			.unwrap()
+			.with_root_certificates(roots)
+			.with_no_client_auth();
+		let connector = Arc::new(config);
+
+		let mut builder = ClientBuilder::new(&format!("wss://{}", addr))
+			.unwrap()
+			.tls_domain("ws.example.test");
+		echo(&mut builder, Some(connector.clone()));
+
+		// the certificate is not valid for the IP address
+		let mut builder = builder.clone().tls_domain(addr.ip().to_string());
+		assert!(builder.connect(Some(connector)).is_err());
+	}
+
+	#[test]
+	fn accept_invalid_certs() {
+		let addr = echo_server();
+		let mut builder = ClientBuilder::new(&format!("wss://{}", addr)).unwrap();
+		assert!(builder.connect(None).is_err());
+
+		let mut builder = builder.danger_accept_invalid_certs(true);
+		echo(&mut builder, None);
+	}
+
+	#[test]
+	fn client_certificate() {
+		use result::WebSocketError;
+		use rustls::server::WebPkiClientVerifier;
+
+		let client_cert = include_bytes!("client/fixtures/client.example.test.pem");
+		let client_key = include_bytes!("client/fixtures/client.example.test.key.pem");
+		let mut roots = RootCertStore::empty();
+		roots
+			.add(CertificateDer::from_pem_slice(client_cert).unwrap())
+			.unwrap();
+		let provider = Arc::new(ring::default_provider());
+		let verifier =
+			WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+				.build()
+				.unwrap();
+		let key = include_bytes!("client/fixtures/ws.example.test.key.der");
+		let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(&key[..]));
+		let config = ServerConfig::builder_with_provider(provider)
+			.with_safe_default_protocol_versions()
+			.unwrap()
+			.with_client_cert_verifier(verifier)
+			.with_single_cert(vec![certificate()], key)
+			.unwrap();
+		let mut server = Server::bind_secure("127.0.0.1:0", Arc::new(config)).unwrap();
+		let addr = server.local_addr().unwrap();
+		thread::spawn(move || loop {
+			let mut client = match server.accept() {
+				Ok(upgrade) => upgrade.accept().unwrap(),
+				Err(_) => continue,
+			};
+			let message = client.recv_message().unwrap();
+			client.send_message(&message).unwrap();
+		});
+
+		let builder = ClientBuilder::new(&format!("wss://{}", addr))
+			.unwrap()
+			.danger_accept_invalid_certs(true);
+		let identity = TlsIdentity::from_pem(client_cert, client_key);
+		echo(&mut builder.clone().identity(identity), None);
+
+		match builder.clone().connect(None) {
+			Err(WebSocketError::TlsSessionError(_)) => {}
+			other => panic!(
+				"expected the certificate to be required, got {:?}",
+				other.err()
+			),
+		}
+	}
+}
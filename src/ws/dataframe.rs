@@ -3,11 +3,15 @@
 //! optimize the memory footprint of a dataframe for their
 //! own needs, and be able to use custom dataframes quickly
 use result::WebSocketResult;
-use std::io::Write;
+use std::io::{self, IoSlice, Write};
 use ws::util::header as dfh;
 use ws::util::mask;
 use ws::util::mask::Masker;
 
+// unmasked payloads of at least this many bytes are written along with
+// the header instead of being copied into one buffer with it
+const VECTORED_WRITE_SIZE: usize = 16 * 1024;
+
 /// A generic DataFrame. Every dataframe should be able to
 /// provide these methods. (If the payload is not known in advance then
 /// rewrite the write_payload method)
@@ -49,45 +53,100 @@ pub trait DataFrame {
 	/// Takes the payload out into a vec
 	fn take_payload(self) -> Vec<u8>;
 
+	/// The payload, if it is kept in a single buffer. By default the payload is
+	/// only available through `write_payload`.
+	///
+	/// Big unmasked payloads which are available are written with the header in a
+	/// single vectored write by `write_to`, without copying them.
+	fn payload_slice(&self) -> Option<&[u8]> {
+		None
+	}
+
 	/// Writes a DataFrame to a Writer.
+	///
+	/// The frame is encoded into a single buffer which is written with one `write_all`,
+	/// except for unmasked frames with a big payload from `payload_slice`.
 	fn write_to(&self, writer: &mut Write, mask: bool) -> WebSocketResult<()> {
-		let mut flags = dfh::DataFrameFlags::empty();
-		if self.is_last() {
-			flags.insert(dfh::DataFrameFlags::FIN);
-		}
-		{
-			let reserved = self.reserved();
-			if reserved[0] {
-				flags.insert(dfh::DataFrameFlags::RSV1);
-			}
-			if reserved[1] {
-				flags.insert(dfh::DataFrameFlags::RSV2);
-			}
-			if reserved[2] {
-				flags.insert(dfh::DataFrameFlags::RSV3);
+		if !mask && self.size() >= VECTORED_WRITE_SIZE {
+			if let Some(payload) = self.payload_slice() {
+				let mut header = Vec::with_capacity(10);
+				dfh::write_header(&mut header, frame_header(self, None))?;
+				write_all_vectored(writer, &header, payload)?;
+				return Ok(());
 			}
 		}
+		let mut data = Vec::<u8>::with_capacity(self.frame_size(mask));
+		self.encode_into(&mut data, mask)?;
+		writer.write_all(data.as_slice())?;
+		Ok(())
+	}
 
+	/// Appends the encoded DataFrame, i.e. what `write_to` writes, to a buffer.
+	fn encode_into(&self, data: &mut Vec<u8>, mask: bool) -> WebSocketResult<()> {
 		let masking_key = if mask { Some(mask::gen_mask()) } else { None };
+		self.encode_with_key(data, masking_key)
+	}
 
-		let header = dfh::DataFrameHeader {
-			flags,
-			opcode: self.opcode() as u8,
-			mask: masking_key,
-			len: self.size() as u64,
-		};
-
-		let mut data = Vec::<u8>::new();
-		dfh::write_header(&mut data, header)?;
+	/// Appends the encoded DataFrame to a buffer like `encode_into`,
+	/// masked with `masking_key` if there is one.
+	fn encode_with_key(
+		&self,
+		data: &mut Vec<u8>,
+		masking_key: Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		dfh::write_header(data, frame_header(self, masking_key))?;
 
 		match masking_key {
 			Some(mask) => {
-				let mut masker = Masker::new(mask, &mut data);
+				let mut masker = Masker::new(mask, data);
 				self.write_payload(&mut masker)?
 			}
-			None => self.write_payload(&mut data)?,
+			None => self.write_payload(data)?,
 		};
-		writer.write_all(data.as_slice())?;
 		Ok(())
 	}
 }
+
+fn frame_header<D>(frame: &D, masking_key: Option<[u8; 4]>) -> dfh::DataFrameHeader
+where
+	D: DataFrame + ?Sized,
+{
+	let mut flags = dfh::DataFrameFlags::empty();
+	if frame.is_last() {
+		flags.insert(dfh::DataFrameFlags::FIN);
+	}
+	{
+		let reserved = frame.reserved();
+		if reserved[0] {
+			flags.insert(dfh::DataFrameFlags::RSV1);
+		}
+		if reserved[1] {
+			flags.insert(dfh::DataFrameFlags::RSV2);
+		}
+		if reserved[2] {
+			flags.insert(dfh::DataFrameFlags::RSV3);
+		}
+	}
+
+	dfh::DataFrameHeader {
+		flags,
+		opcode: frame.opcode(),
+		mask: masking_key,
+		len: frame.size() as u64,
+	}
+}
+
+// like `write_all` for the header followed by the payload
+fn write_all_vectored(writer: &mut dyn Write, header: &[u8], payload: &[u8]) -> io::Result<()> {
+	let mut written = 0;
+	while written < header.len() {
+		let slices = [IoSlice::new(&header[written..]), IoSlice::new(payload)];
+		match writer.write_vectored(&slices) {
+			Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+			Ok(n) => written += n,
+			Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+			Err(e) => return Err(e),
+		}
+	}
+	writer.write_all(&payload[written - header.len()..])
+}
@@ -2,6 +2,7 @@
 //!
 //! See the `ws` module documentation for more information.
 
+use dataframe::DataFrame;
 use result::WebSocketResult;
 use std::io::Write;
 use ws::dataframe::DataFrame as DataFrameable;
@@ -16,4 +17,19 @@ pub trait Message: Sized {
 
 	/// Attempt to form a message from a series of data frames
 	fn from_dataframes<D: DataFrameable>(frames: Vec<D>) -> WebSocketResult<Self>;
+
+	/// The data frames this message is sent in, e.g. to compress or fragment them.
+	///
+	/// By default the message is serialized and its frames are read back,
+	/// implementations which know their frames can build them directly.
+	fn dataframes(&self) -> WebSocketResult<Vec<DataFrame>> {
+		let mut buffer = Vec::with_capacity(self.message_size(false));
+		self.serialize(&mut buffer, false)?;
+		let mut reader = &buffer[..];
+		let mut frames = Vec::new();
+		while !reader.is_empty() {
+			frames.push(DataFrame::read_dataframe(&mut reader, false)?);
+		}
+		Ok(frames)
+	}
 }
@@ -2,6 +2,39 @@
 //!
 //! Also provides iterators over data frames and messages.
 //! See the `ws` module documentation for more information.
+//!
+//! Code written against the `Receiver` trait works with any implementation,
+//! e.g. the default `receiver::Receiver` or a mock in tests:
+//!
+//! ```rust
+//! # extern crate websocket;
+//! # fn main() {
+//! use std::io::Read;
+//! use websocket::ws::Receiver;
+//! use websocket::{OwnedMessage, WebSocketResult};
+//! # use websocket::dataframe::{DataFrame, Opcode};
+//! # use websocket::ws::dataframe::DataFrame as DataFrameTrait;
+//!
+//! // the next text message, other messages are skipped
+//! fn next_text<Recv, R>(receiver: &mut Recv, reader: &mut R) -> WebSocketResult<String>
+//! where
+//!     Recv: Receiver<M = OwnedMessage>,
+//!     R: Read,
+//! {
+//!     loop {
+//!         if let OwnedMessage::Text(text) = receiver.recv_message(reader)? {
+//!             return Ok(text);
+//!         }
+//!     }
+//! }
+//!
+//! # let mut input = Vec::new();
+//! # DataFrame::new(true, Opcode::Binary, vec![1]).write_to(&mut input, false).unwrap();
+//! # DataFrame::new(true, Opcode::Text, b"hello".to_vec()).write_to(&mut input, false).unwrap();
+//! let mut receiver = websocket::receiver::Receiver::new(false);
+//! assert_eq!(next_text(&mut receiver, &mut &input[..]).unwrap(), "hello");
+//! # }
+//! ```
 
 use result::WebSocketResult;
 use std::io::Read;
@@ -103,3 +136,86 @@ where
 		Some(self.inner.recv_message(self.reader))
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use dataframe::{DataFrame as OwnedDataFrame, Opcode};
+	use message::OwnedMessage;
+	use result::WebSocketError;
+	use std::collections::VecDeque;
+
+	// hands out prepared frames and never touches the reader
+	struct MockReceiver {
+		frames: VecDeque<OwnedDataFrame>,
+	}
+
+	impl Receiver for MockReceiver {
+		type F = OwnedDataFrame;
+
+		type M = OwnedMessage;
+
+		fn recv_dataframe<R>(&mut self, _reader: &mut R) -> WebSocketResult<OwnedDataFrame>
+		where
+			R: Read,
+		{
+			self.frames
+				.pop_front()
+				.ok_or(WebSocketError::NoDataAvailable)
+		}
+
+		fn recv_message_dataframes<R>(
+			&mut self,
+			reader: &mut R,
+		) -> WebSocketResult<Vec<OwnedDataFrame>>
+		where
+			R: Read,
+		{
+			let mut frames = vec![self.recv_dataframe(reader)?];
+			while !frames[frames.len() - 1].finished {
+				frames.push(self.recv_dataframe(reader)?);
+			}
+			Ok(frames)
+		}
+	}
+
+	fn messages<Recv>(receiver: &mut Recv) -> Vec<Recv::M>
+	where
+		Recv: Receiver,
+	{
+		receiver
+			.incoming_messages(&mut ::std::io::empty())
+			.take_while(Result::is_ok)
+			.map(Result::unwrap)
+			.collect()
+	}
+
+	#[test]
+	fn generic_code_runs_over_a_mock() {
+		let frames = vec![
+			OwnedDataFrame::new(false, Opcode::Text, b"hel".to_vec()),
+			OwnedDataFrame::new(true, Opcode::Continuation, b"lo".to_vec()),
+			OwnedDataFrame::new(true, Opcode::Ping, vec![1, 2]),
+		];
+		let mut receiver = MockReceiver {
+			frames: frames.clone().into_iter().collect(),
+		};
+		assert_eq!(
+			messages(&mut receiver),
+			vec![
+				OwnedMessage::Text("hello".to_string()),
+				OwnedMessage::Ping(vec![1, 2]),
+			]
+		);
+
+		let mut receiver = MockReceiver {
+			frames: frames.clone().into_iter().collect(),
+		};
+		let received: Vec<_> = receiver
+			.incoming_dataframes(&mut ::std::io::empty())
+			.take(3)
+			.map(Result::unwrap)
+			.collect();
+		assert_eq!(received, frames);
+	}
+}
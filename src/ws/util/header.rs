@@ -2,6 +2,7 @@
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use result::{WebSocketError, WebSocketResult};
+use std::convert::TryFrom;
 use std::io::{Read, Write};
 
 bitflags! {
@@ -70,7 +71,24 @@ pub fn write_header(writer: &mut Write, header: DataFrameHeader) -> WebSocketRes
 }
 
 /// Reads a data frame header.
+///
+/// Lengths which are not encoded in the fewest bytes possible (e.g. a length of 5
+/// in the 16 bit form) are accepted, see `read_header_with` to reject them.
+/// A 64 bit length with its most significant bit set is a `ProtocolError`, one
+/// which doesn't fit into a `usize` on this platform is a `DataFrameError`.
 pub fn read_header<R>(reader: &mut R) -> WebSocketResult<DataFrameHeader>
+where
+	R: Read,
+{
+	read_header_with(reader, false)
+}
+
+/// Reads a data frame header like `read_header`, if `minimal_lengths` is set a
+/// length which is not encoded in the fewest bytes possible is a `ProtocolError`.
+pub fn read_header_with<R>(
+	reader: &mut R,
+	minimal_lengths: bool,
+) -> WebSocketResult<DataFrameHeader>
 where
 	R: Read,
 {
@@ -80,28 +98,10 @@ where
 	let flags = DataFrameFlags::from_bits_truncate(byte0);
 	let opcode = byte0 & 0x0F;
 
-	let len = match byte1 & 0x7F {
-		0...125 => u64::from(byte1 & 0x7F),
-		126 => {
-			let len = u64::from(reader.read_u16::<BigEndian>()?);
-			if len <= 125 {
-				return Err(WebSocketError::DataFrameError("Invalid data frame length"));
-			}
-			len
-		}
-		127 => {
-			let len = reader.read_u64::<BigEndian>()?;
-			if len <= 65535 {
-				return Err(WebSocketError::DataFrameError("Invalid data frame length"));
-			}
-			len
-		}
-		_ => unreachable!(),
-	};
-
+	// control frames must fit in a single frame with a 7 bit length
 	if opcode >= 8 {
-		if len >= 126 {
-			return Err(WebSocketError::DataFrameError(
+		if byte1 & 0x7F >= 126 {
+			return Err(WebSocketError::ProtocolError(
 				"Control frame length too long",
 			));
 		}
@@ -112,6 +112,37 @@ where
 		}
 	}
 
+	let len = match byte1 & 0x7F {
+		0...125 => u64::from(byte1 & 0x7F),
+		126 => u64::from(reader.read_u16::<BigEndian>()?),
+		127 => {
+			let len = reader.read_u64::<BigEndian>()?;
+			if len & (1 << 63) != 0 {
+				return Err(WebSocketError::ProtocolError(
+					"Most significant bit of the payload length is set",
+				));
+			}
+			len
+		}
+		_ => unreachable!(),
+	};
+	let minimal = match byte1 & 0x7F {
+		126 => len >= 126,
+		127 => len > 0xFFFF,
+		_ => true,
+	};
+	if minimal_lengths && !minimal {
+		return Err(WebSocketError::ProtocolError(
+			"Payload length is not encoded in the fewest bytes",
+		));
+	}
+	// the payload is kept in memory, which can't be done on 32 bit platforms
+	if usize::try_from(len).is_err() {
+		return Err(WebSocketError::DataFrameError(
+			"Data frame payload is too long for this platform",
+		));
+	}
+
 	let mask = if byte1 & 0x80 == 0x80 {
 		Some([
 			reader.read_u8()?,
@@ -192,6 +223,78 @@ mod tests {
 		assert_eq!(&obtained[..], &expected[..]);
 	}
 
+	#[test]
+	fn test_read_header_invalid_control_frames() {
+		// a ping with a 16 bit length
+		let header = [0x89, 0x7E, 0x00, 0x7E];
+		match read_header(&mut &header[..]) {
+			Err(WebSocketError::ProtocolError(_)) => (),
+			other => panic!("expected ProtocolError, got {:?}", other),
+		}
+
+		// a close frame with a 64 bit length
+		let header = [0x88, 0x7F, 0, 0, 0, 0, 0, 1, 0, 0];
+		match read_header(&mut &header[..]) {
+			Err(WebSocketError::ProtocolError(_)) => (),
+			other => panic!("expected ProtocolError, got {:?}", other),
+		}
+
+		// a pong without FIN
+		let header = [0x0A, 0x05];
+		match read_header(&mut &header[..]) {
+			Err(WebSocketError::ProtocolError(_)) => (),
+			other => panic!("expected ProtocolError, got {:?}", other),
+		}
+
+		// the largest allowed control frame
+		let header = [0x89, 0x7D];
+		assert_eq!(read_header(&mut &header[..]).unwrap().len, 125);
+	}
+
+	#[test]
+	fn test_read_header_64_bit_lengths() {
+		// the most significant bit must not be set
+		let header = [0x82, 0x7F, 0x80, 0, 0, 0, 0, 0, 0, 1];
+		match read_header(&mut &header[..]) {
+			Err(WebSocketError::ProtocolError(_)) => (),
+			other => panic!("expected ProtocolError, got {:?}", other),
+		}
+
+		// 4 GiB and more only fit on 64 bit platforms
+		let header = [0x82, 0x7F, 0, 0, 0, 1, 0, 0, 0, 0];
+		let result = read_header(&mut &header[..]);
+		if cfg!(target_pointer_width = "64") {
+			assert_eq!(result.unwrap().len, 1 << 32);
+		} else {
+			match result {
+				Err(WebSocketError::DataFrameError(_)) => (),
+				other => panic!("expected DataFrameError, got {:?}", other),
+			}
+		}
+		let header = [0x82, 0x7F, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+		assert_eq!(
+			read_header(&mut &header[..]).is_ok(),
+			cfg!(target_pointer_width = "64")
+		);
+
+		// lengths are not required to be encoded in as few bytes as possible
+		let header = [0x82, 0x7E, 0, 5];
+		assert_eq!(read_header(&mut &header[..]).unwrap().len, 5);
+		let header = [0x82, 0x7F, 0, 0, 0, 0, 0, 0, 0, 5];
+		assert_eq!(read_header(&mut &header[..]).unwrap().len, 5);
+		for header in &[
+			&[0x82, 0x7E, 0, 5][..],
+			&[0x82, 0x7F, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF],
+		] {
+			match read_header_with(&mut &header[..], true) {
+				Err(WebSocketError::ProtocolError(_)) => (),
+				other => panic!("expected ProtocolError, got {:?}", other),
+			}
+		}
+		let header = [0x82, 0x7E, 0, 126];
+		assert_eq!(read_header_with(&mut &header[..], true).unwrap().len, 126);
+	}
+
 	#[bench]
 	fn bench_read_header(b: &mut test::Bencher) {
 		let header = vec![0x42u8, 0xFE, 0x02, 0x00, 0x02, 0x04, 0x08, 0x10];
@@ -1,5 +1,9 @@
 //! Utility functions for masking data frame payload data
-use rand;
+//!
+//! `apply_mask` masks a payload in place and `mask_data` returns a masked copy,
+//! they are the routines senders and receivers use for every frame. Masking
+//! twice with the same key gives back the original data, so both also unmask.
+use rand::{self, Rng};
 use std::io::Result as IoResult;
 use std::io::Write;
 
@@ -25,12 +29,15 @@ impl<'w> Masker<'w> {
 
 impl<'w> Write for Masker<'w> {
 	fn write(&mut self, data: &[u8]) -> IoResult<usize> {
-		let mut buf = Vec::with_capacity(data.len());
-		for &byte in data.iter() {
-			buf.push(byte ^ self.key[self.pos]);
-			self.pos = (self.pos + 1) % self.key.len();
-		}
-		self.end.write(&buf)
+		let mut buf = data.to_vec();
+		let mut key = self.key;
+		key.rotate_left(self.pos);
+		apply_mask(key, &mut buf);
+		// the key position must follow what the endpoint took,
+		// so all of the data is written before returning
+		self.end.write_all(&buf)?;
+		self.pos = (self.pos + data.len()) % self.key.len();
+		Ok(data.len())
 	}
 
 	fn flush(&mut self) -> IoResult<()> {
@@ -40,24 +47,93 @@ impl<'w> Write for Masker<'w> {
 
 /// Generates a random masking key
 pub fn gen_mask() -> [u8; 4] {
-	rand::random()
+	gen_mask_with(&mut rand::thread_rng())
 }
 
-/// Masks data to send to a server and writes
-pub fn mask_data(mask: [u8; 4], data: &[u8]) -> Vec<u8> {
-	let mut out = Vec::with_capacity(data.len());
-	let zip_iter = data.iter().zip(mask.iter().cycle());
-	for (&buf_item, &key_item) in zip_iter {
-		out.push(buf_item ^ key_item);
+/// Generates a masking key using the given random number generator
+pub fn gen_mask_with<R: Rng>(rng: &mut R) -> [u8; 4] {
+	rng.gen()
+}
+
+/// A source of masking keys for the frames a client sends.
+///
+/// Keys are random by default, a generator which isn't can be used to
+/// get the same bytes on the wire every time, e.g. in tests.
+/// Servers don't mask their frames and never use it.
+pub trait MaskGenerator: Send {
+	/// The masking key of the next frame
+	fn next_mask(&mut self) -> [u8; 4];
+}
+
+/// Generates random masking keys with the thread's random number generator,
+/// which is what senders do unless they are given another `MaskGenerator`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ThreadRngMask;
+
+impl MaskGenerator for ThreadRngMask {
+	fn next_mask(&mut self) -> [u8; 4] {
+		gen_mask()
 	}
+}
+
+/// Masks or unmasks a copy of `data`, see `apply_mask`.
+pub fn mask_data(mask: [u8; 4], data: &[u8]) -> Vec<u8> {
+	let mut out = data.to_vec();
+	apply_mask(mask, &mut out);
 	out
 }
 
-#[cfg(all(feature = "nightly", test))]
+/// Masks or unmasks `data` in place, the first byte is XORed with the
+/// first byte of the key.
+///
+/// Eight bytes are processed at a time, which is much faster than
+/// going byte by byte for large payloads.
+///
+/// ```rust
+/// use websocket::ws::util::mask::apply_mask;
+///
+/// let key = [1, 2, 3, 4];
+/// let mut data = *b"payload";
+/// apply_mask(key, &mut data);
+/// assert_eq!(data[0], b'p' ^ 1);
+/// apply_mask(key, &mut data);
+/// assert_eq!(&data, b"payload");
+/// ```
+pub fn apply_mask(mask: [u8; 4], data: &mut [u8]) {
+	let word = u64::from_ne_bytes([
+		mask[0], mask[1], mask[2], mask[3], mask[0], mask[1], mask[2], mask[3],
+	]);
+	let mut chunks = data.chunks_exact_mut(8);
+	for chunk in &mut chunks {
+		let mut bytes = [0u8; 8];
+		bytes.copy_from_slice(chunk);
+		let masked = u64::from_ne_bytes(bytes) ^ word;
+		chunk.copy_from_slice(&masked.to_ne_bytes());
+	}
+	// the chunks end on a multiple of the key length,
+	// so the remainder starts with the first byte of the key
+	let remainder = chunks.into_remainder();
+	for (byte, key) in remainder.iter_mut().zip(mask.iter().cycle()) {
+		*byte ^= key;
+	}
+}
+
+#[cfg(test)]
 mod tests {
 	use super::*;
+	use rand::rngs::StdRng;
+	use rand::SeedableRng;
+	#[cfg(feature = "nightly")]
 	use test;
 
+	// the byte by byte loop `apply_mask` replaced
+	fn mask_bytes(mask: [u8; 4], data: &[u8]) -> Vec<u8> {
+		data.iter()
+			.zip(mask.iter().cycle())
+			.map(|(byte, key)| byte ^ key)
+			.collect()
+	}
+
 	#[test]
 	fn test_mask_data() {
 		let key = [1u8, 2u8, 3u8, 4u8];
@@ -70,6 +146,40 @@ mod tests {
 		assert_eq!(obtained, expected);
 	}
 
+	#[test]
+	fn mask_round_trips() {
+		let mut rng = StdRng::seed_from_u64(7);
+		let lengths = (0..=67).chain(vec![1000, 4093, 65_536, 65_539]);
+		for len in lengths {
+			let original: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+			let key = gen_mask_with(&mut rng);
+
+			let mut data = original.clone();
+			apply_mask(key, &mut data);
+			assert_eq!(data, mask_bytes(key, &original), "length {}", len);
+			apply_mask(key, &mut data);
+			assert_eq!(data, original, "length {}", len);
+
+			// the key carries on across writes
+			let mut out = Vec::new();
+			{
+				let mut masker = Masker::new(key, &mut out);
+				for part in original.chunks(7) {
+					masker.write_all(part).unwrap();
+				}
+			}
+			assert_eq!(out, mask_bytes(key, &original), "length {}", len);
+		}
+	}
+
+	#[test]
+	fn test_gen_mask_with() {
+		let mut first = StdRng::seed_from_u64(42);
+		let mut second = StdRng::seed_from_u64(42);
+		assert_eq!(gen_mask_with(&mut first), gen_mask_with(&mut second));
+	}
+
+	#[cfg(feature = "nightly")]
 	#[bench]
 	fn bench_mask_data(b: &mut test::Bencher) {
 		let buffer = b"The quick brown fox jumps over the lazy dog";
@@ -80,6 +190,7 @@ mod tests {
 		});
 	}
 
+	#[cfg(feature = "nightly")]
 	#[bench]
 	fn bench_gen_mask(b: &mut test::Bencher) {
 		b.iter(|| {
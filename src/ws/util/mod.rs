@@ -2,6 +2,7 @@
 
 pub mod header;
 pub mod mask;
+pub mod utf8;
 
 use std::str::from_utf8;
 use std::str::Utf8Error;
@@ -15,6 +16,15 @@ pub fn bytes_to_string(data: &[u8]) -> Result<String, Utf8Error> {
 	Ok(utf8.to_string())
 }
 
+/// Compares two byte slices in constant time, i.e. the time taken
+/// only depends on the length of the slices and not on their contents.
+pub(crate) fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+	if a.len() != b.len() {
+		return false;
+	}
+	a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 /// Updates codec of Framed
 #[cfg(feature = "async")]
 pub fn update_framed_codec<S, B, A>(framed: Framed<S, B>, codec: A) -> Framed<S, A> {
@@ -24,3 +34,16 @@ pub fn update_framed_codec<S, B, A>(framed: Framed<S, B>, codec: A) -> Framed<S,
 	new_parts.write_buf = old_parts.write_buf;
 	Framed::from_parts(new_parts)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_ct_eq() {
+		assert!(ct_eq(b"", b""));
+		assert!(ct_eq(b"accept", b"accept"));
+		assert!(!ct_eq(b"accept", b"accepT"));
+		assert!(!ct_eq(b"accept", b"accepted"));
+	}
+}
@@ -0,0 +1,102 @@
+//! Incremental UTF-8 validation of fragmented text messages
+use std::str::{from_utf8, Utf8Error};
+
+/// Validates UTF-8 data which arrives in chunks, e.g. the fragments of a
+/// text message, so invalid data can be rejected before the whole message
+/// has been received. Multi-byte sequences may be split between chunks.
+#[derive(Debug, Clone, Default)]
+pub struct Utf8Validator {
+	// the start of a multi-byte sequence at the end of the last chunk
+	incomplete: Vec<u8>,
+}
+
+impl Utf8Validator {
+	/// Create a validator expecting the start of a new text.
+	pub fn new() -> Utf8Validator {
+		Utf8Validator::default()
+	}
+
+	/// Validate the next chunk of the text.
+	pub fn feed(&mut self, mut data: &[u8]) -> Result<(), Utf8Error> {
+		// finish the sequence started by the last chunk first
+		while !self.incomplete.is_empty() {
+			if data.is_empty() {
+				return Ok(());
+			}
+			self.incomplete.push(data[0]);
+			data = &data[1..];
+
+			match from_utf8(&self.incomplete) {
+				Ok(_) => self.incomplete.clear(),
+				Err(e) if e.error_len().is_none() => (),
+				Err(e) => {
+					self.incomplete.clear();
+					return Err(e);
+				}
+			}
+		}
+
+		match from_utf8(data) {
+			Ok(_) => Ok(()),
+			Err(e) if e.error_len().is_none() => {
+				self.incomplete.extend_from_slice(&data[e.valid_up_to()..]);
+				Ok(())
+			}
+			Err(e) => Err(e),
+		}
+	}
+
+	/// Check that the text does not end in the middle of a multi-byte sequence,
+	/// the validator can then be reused for a new text.
+	pub fn finish(&mut self) -> Result<(), Utf8Error> {
+		let result = from_utf8(&self.incomplete).map(|_| ());
+		self.incomplete.clear();
+		result
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn validate(chunks: &[&[u8]]) -> Result<(), Utf8Error> {
+		let mut validator = Utf8Validator::new();
+		for chunk in chunks {
+			validator.feed(chunk)?;
+		}
+		validator.finish()
+	}
+
+	#[test]
+	fn sequences_split_between_chunks() {
+		let text = "κόσμε, \u{10348} and \u{20ac}".as_bytes();
+		for i in 0..text.len() {
+			for j in i..text.len() {
+				assert!(validate(&[&text[..i], &text[i..j], &text[j..]]).is_ok());
+			}
+		}
+	}
+
+	#[test]
+	fn invalid_data_fails_early() {
+		let mut validator = Utf8Validator::new();
+		assert!(validator.feed(b"valid ").is_ok());
+		assert!(validator.feed(&[0xce]).is_ok());
+		assert!(validator.feed(&[0xba, 0xe1, 0xbd]).is_ok());
+		// not a continuation byte
+		assert!(validator.feed(&[0x41]).is_err());
+
+		assert!(validate(&[&[0xff]]).is_err());
+		assert!(validate(&[b"abc", &[0xed, 0xa0], &[0x80]]).is_err());
+	}
+
+	#[test]
+	fn incomplete_text_fails_on_finish() {
+		let mut validator = Utf8Validator::new();
+		assert!(validator.feed(&[0xf0, 0x90, 0x8d]).is_ok());
+		assert!(validator.finish().is_err());
+		// the validator is ready for the next text
+		assert!(validator.feed(b"next").is_ok());
+		assert!(validator.finish().is_ok());
+	}
+}